@@ -36,7 +36,7 @@ fn main() -> Result<(), SessionError> {
     let mut data: Vec<u8> = vec![0; 16 * 1024];
     rng.fill_bytes(&mut data);
 
-    let encrypted_bytes = sesh1.encrypt(data.clone());
+    let encrypted_bytes = sesh1.encrypt(data.clone())?;
 
     match sesh2.decrypt(encrypted_bytes.clone()) {
         Ok(_) => {
@@ -49,7 +49,7 @@ fn main() -> Result<(), SessionError> {
 
     info!("Now attempting message modification...");
 
-    let mut tampered_bytes = sesh1.encrypt(data.clone());
+    let mut tampered_bytes = sesh1.encrypt(data.clone())?;
     tamper_with(&mut tampered_bytes, 1);
 
     match sesh2.decrypt(tampered_bytes) {
@@ -90,18 +90,16 @@ fn main() -> Result<(), SessionError> {
 
     match lb.decrypt(tampered_bytes) {
         Ok(_) => (),
-        Err(e) => match e {
-            LockedBoxError::MacMismatch => {
-                info!("MAC mismatch! Data was tampered with! (expected)")
-            },
-            _ => error!("Wrong error received")
+        Err(LockedBoxError::MacMismatch) => {
+            info!("MAC mismatch! Data was tampered with! (expected)")
         },
+        Err(_) => error!("Wrong error received"),
     };
 
     Ok(())
 }
 
-fn tamper_with(bytes: &mut Vec<u8>, many_times: usize) {
+fn tamper_with(bytes: &mut [u8], many_times: usize) {
     let mut rng = thread_rng();
     let mut index;
 
@@ -0,0 +1,56 @@
+use std::{env, fs, process};
+
+use rand::thread_rng;
+use tracing::info;
+
+use xc220b3::{fileformat::{self, FileError}, EncodedPoint, PublicKey, SecretKey};
+
+// usage:
+//   file keygen <secret key file>                 write a new secret key, print its public key
+//   file seal <in> <out> <public key hex>...      encrypt to one or more recipients
+//   file open <in> <out> <secret key file>...     decrypt with whichever secret key it's for
+fn main() -> Result<(), FileError> {
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["keygen", secret_path] => {
+            let secret = SecretKey::random(&mut thread_rng());
+            fs::write(secret_path, hex::encode(secret.to_be_bytes()))?;
+            println!("{}", hex::encode(EncodedPoint::from(secret.public_key())));
+            Ok(())
+        }
+        ["seal", path_in, path_out, ref recipients @ ..] if !recipients.is_empty() => {
+            let recipients: Vec<PublicKey> = recipients
+                .iter()
+                .map(|pk| hex::decode(pk).ok().and_then(|pk| PublicKey::from_sec1_bytes(&pk).ok()))
+                .collect::<Option<_>>()
+                .unwrap_or_else(|| usage("recipients must be hex SEC1 public keys"));
+            fileformat::seal(path_in, path_out, &recipients)?;
+            info!("sealed {} to {} recipient(s)", path_out, recipients.len());
+            Ok(())
+        }
+        ["open", path_in, path_out, ref secret_paths @ ..] if !secret_paths.is_empty() => {
+            let mut secrets = Vec::with_capacity(secret_paths.len());
+            for secret_path in secret_paths {
+                let secret = hex::decode(fs::read_to_string(secret_path)?.trim())
+                    .ok()
+                    .and_then(|secret| SecretKey::from_be_bytes(&secret).ok())
+                    .unwrap_or_else(|| usage("secret key files must hold a hex secp256k1 secret key"));
+                secrets.push(secret);
+            }
+            fileformat::open_with_keys(path_in, path_out, &secrets)?;
+            info!("opened {}", path_out);
+            Ok(())
+        }
+        _ => usage("expected keygen, seal or open"),
+    }
+}
+
+fn usage(problem: &str) -> ! {
+    eprintln!("{}", problem);
+    eprintln!("usage: file keygen <secret key file>");
+    eprintln!("       file seal <in> <out> <public key hex>...");
+    eprintln!("       file open <in> <out> <secret key file>...");
+    process::exit(2);
+}
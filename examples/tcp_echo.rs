@@ -0,0 +1,86 @@
+use std::{env, net::{TcpListener, TcpStream}, thread};
+
+use rand::thread_rng;
+use tracing::{error, info};
+
+use xc220b3::{framing::{read_frame, write_frame}, PublicKeyBytes, Session, SessionError};
+
+// usage:
+//   tcp_echo server [addr]   run an echo server
+//   tcp_echo client [addr]   send a few messages to a server
+//   tcp_echo                 both, over loopback
+fn main() -> Result<(), SessionError> {
+    tracing_subscriber::fmt::init();
+
+    let mut args = env::args().skip(1);
+    let mode = args.next();
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:7220".to_string());
+
+    match mode.as_deref() {
+        Some("server") => {
+            let listener = TcpListener::bind(&addr)?;
+            info!("listening on {}", addr);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream) {
+                        error!("connection failed: {:?}", e);
+                    }
+                });
+            }
+            Ok(())
+        }
+        Some("client") => client(TcpStream::connect(&addr)?),
+        _ => {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let addr = listener.local_addr()?;
+            let server = thread::spawn(move || serve(listener.accept()?.0));
+
+            client(TcpStream::connect(addr)?)?;
+            server.join().expect("server thread panicked")
+        }
+    }
+}
+
+// both sides send their compressed public key as the first frame, then every
+// frame is an encrypted record
+fn handshake(stream: &mut TcpStream) -> Result<Session, SessionError> {
+    let mut session = Session::new(&mut thread_rng());
+
+    write_frame(stream, &session.public_key_sec1_compressed()?)?;
+    let peer_pk = PublicKeyBytes::from_sec1(&read_frame(stream)?)?;
+    session.set_sym_key(peer_pk.point())?;
+
+    Ok(session)
+}
+
+fn serve(mut stream: TcpStream) -> Result<(), SessionError> {
+    let mut session = handshake(&mut stream)?;
+    info!("server: session ready with {}", stream.peer_addr()?);
+
+    loop {
+        let record = match read_frame(&mut stream) {
+            Ok(record) => record,
+            // client hung up
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let plain = session.decrypt(record)?;
+        info!("server: echoing {} bytes", plain.len());
+        write_frame(&mut stream, &session.encrypt(plain)?)?;
+    }
+}
+
+fn client(mut stream: TcpStream) -> Result<(), SessionError> {
+    let mut session = handshake(&mut stream)?;
+    info!("client: session ready with {}", stream.peer_addr()?);
+
+    for msg in ["Hello", "", "a slightly longer message to echo back"] {
+        write_frame(&mut stream, &session.encrypt(msg.as_bytes().to_vec())?)?;
+        let echoed = session.decrypt(read_frame(&mut stream)?)?;
+        assert_eq!(echoed, msg.as_bytes());
+        info!("client: got {:?} back", String::from_utf8_lossy(&echoed));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+// xc220b3's handshake (see handshake.rs there) with every message in a
+// fixed array or a buffer of the caller's:
+//
+//   client -> server   config || count (1) || versions || client pk [|| extensions]
+//   server -> client   selected version (1) || server pk [|| extensions]
+//   client -> server   record(transcript hash || certificate)
+//   server -> client   record(transcript hash)
+//
+// this side offers and selects version 3 alone, sends xc220b3's default
+// config and takes no other, and sends no extensions. ones the peer sends
+// are checked for layout as xc220b3 checks them and otherwise skipped, so a
+// server here answers to any server name. identity keys aren't supported, a
+// peer sending its key signed is refused as malformed
+//
+// framing the messages over a byte stream is the caller's: xc220b3's
+// write_frame puts a big-endian u32 length in front of each
+
+use k256::ecdh::EphemeralSecret;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::PublicKey;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::protocol::{self, CONFIG_LEN, DEFAULT_CONFIG, SIGNED_PUBLIC_KEY_LEN, TRANSCRIPT_LEN};
+use crate::record;
+use crate::session::{Role, Session, SessionError};
+
+/// Length of a compressed SEC1 public key, as this side sends its own.
+pub const PUBLIC_KEY_LEN: usize = 33;
+/// Length of the client's first message.
+pub const HELLO_LEN: usize = CONFIG_LEN + 2 + PUBLIC_KEY_LEN;
+/// Length of the server's answer to it.
+pub const RESPONSE_LEN: usize = 1 + PUBLIC_KEY_LEN;
+/// Length of the server's last message, the record of the transcript hash.
+pub const REPLY_LEN: usize = Session::record_len(TRANSCRIPT_LEN);
+
+// xc220b3's SessionConfig::default().to_bytes()
+const CONFIG: [u8; CONFIG_LEN] = DEFAULT_CONFIG.to_bytes();
+
+/// The length of the client's confirmation when it presents a
+/// `certificate_len` byte certificate.
+pub const fn confirmation_len(certificate_len: usize) -> usize {
+    Session::record_len(TRANSCRIPT_LEN + certificate_len)
+}
+
+/// Where in the exchange a handshake failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakePhase {
+    /// The client's config and public key.
+    Hello,
+    /// The server's public key, and deriving the session keys.
+    KeyExchange,
+    /// The encrypted transcript hashes, and the certificate.
+    Confirm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// The peer sent something that isn't a handshake message, or a key
+    /// that isn't a point on the curve, or is signed.
+    Malformed(HandshakePhase),
+    /// The client's config isn't xc220b3's default.
+    ConfigMismatch,
+    /// The client doesn't offer version 3.
+    NoCommonVersion,
+    /// The verifier refused the client's certificate.
+    BadCertificate,
+    /// The peer's confirmation didn't open, or was for a different
+    /// transcript.
+    ConfirmationMismatch,
+    /// As [`SessionError::BufferTooSmall`].
+    BufferTooSmall(usize),
+}
+
+/// The client's side, from the hello to the session.
+pub struct ClientHandshake {
+    secret: EphemeralSecret,
+    hello: [u8; HELLO_LEN],
+}
+
+impl ClientHandshake {
+    /// A fresh key pair from `rng`, and the hello offering its public key.
+    pub fn new(rng: &mut (impl CryptoRng + RngCore)) -> ClientHandshake {
+        let secret = EphemeralSecret::random(rng);
+        let mut hello = [0u8; HELLO_LEN];
+        hello[..CONFIG_LEN].copy_from_slice(&CONFIG);
+        hello[CONFIG_LEN] = 1;
+        hello[CONFIG_LEN + 1] = record::VERSION;
+        hello[CONFIG_LEN + 2..].copy_from_slice(secret.public_key().to_encoded_point(true).as_bytes());
+        ClientHandshake { secret, hello }
+    }
+
+    /// The first message, to send the server.
+    pub fn hello(&self) -> &[u8; HELLO_LEN] {
+        &self.hello
+    }
+
+    /// Takes the server's response and keys the session, writing the
+    /// confirmation, which carries `certificate` for the server's verifier,
+    /// to the start of `out`. `out` must be at least [`confirmation_len`]
+    /// long.
+    pub fn confirm(
+        self,
+        response: &[u8],
+        certificate: &[u8],
+        out: &mut [u8],
+    ) -> Result<(ClientConfirmation, usize), HandshakeError> {
+        let phase = HandshakePhase::KeyExchange;
+        let (&version, rest) = response.split_first().ok_or(HandshakeError::Malformed(phase))?;
+        if version != record::VERSION {
+            return Err(HandshakeError::Malformed(phase));
+        }
+        let theirs = split_key(rest).ok_or(HandshakeError::Malformed(phase))?;
+        let ours = self.hello[CONFIG_LEN + 2..].try_into().unwrap();
+        let mut session = agree(&self.secret, ours, theirs).ok_or(HandshakeError::Malformed(phase))?;
+        let transcript = protocol::transcript(&self.hello, response);
+
+        let written = session.seal_parts_into(&[&transcript, certificate], out).map_err(buffer_error)?;
+        Ok((ClientConfirmation { session, transcript }, written))
+    }
+}
+
+/// A client's handshake waiting for the server's reply.
+pub struct ClientConfirmation {
+    session: Session,
+    transcript: [u8; TRANSCRIPT_LEN],
+}
+
+impl ClientConfirmation {
+    /// Checks the server's reply, and gives the ready session.
+    pub fn finish(mut self, reply: &[u8]) -> Result<Session, HandshakeError> {
+        let mut theirs = [0u8; TRANSCRIPT_LEN];
+        match reply.len() == REPLY_LEN && self.session.open_into(reply, &mut theirs).is_ok() {
+            true if theirs == self.transcript => Ok(self.session),
+            _ => Err(HandshakeError::ConfirmationMismatch),
+        }
+    }
+}
+
+/// The server's side, from the client's hello to the session.
+pub struct ServerHandshake {
+    session: Session,
+    transcript: [u8; TRANSCRIPT_LEN],
+    response: [u8; RESPONSE_LEN],
+}
+
+impl ServerHandshake {
+    /// Takes the client's hello, keys the session with a fresh key pair
+    /// from `rng`, and gives the handshake holding the response to send.
+    pub fn respond(rng: &mut (impl CryptoRng + RngCore), hello: &[u8]) -> Result<ServerHandshake, HandshakeError> {
+        let phase = HandshakePhase::Hello;
+        if hello.len() <= CONFIG_LEN + 1 {
+            return Err(HandshakeError::Malformed(phase));
+        }
+        let (config, rest) = hello.split_at(CONFIG_LEN);
+        if config != CONFIG {
+            return Err(HandshakeError::ConfigMismatch);
+        }
+        let count = rest[0] as usize;
+        if count == 0 || rest.len() <= 1 + count {
+            return Err(HandshakeError::Malformed(phase));
+        }
+        let (versions, rest) = rest[1..].split_at(count);
+        let theirs = split_key(rest).ok_or(HandshakeError::Malformed(phase))?;
+        if !versions.contains(&record::VERSION) {
+            return Err(HandshakeError::NoCommonVersion);
+        }
+
+        let secret = EphemeralSecret::random(rng);
+        let mut response = [0u8; RESPONSE_LEN];
+        response[0] = record::VERSION;
+        response[1..].copy_from_slice(secret.public_key().to_encoded_point(true).as_bytes());
+        let session = agree(&secret, response[1..].try_into().unwrap(), theirs).ok_or(HandshakeError::Malformed(phase))?;
+        Ok(ServerHandshake { session, transcript: protocol::transcript(hello, &response), response })
+    }
+
+    /// The message to send the client.
+    pub fn response(&self) -> &[u8; RESPONSE_LEN] {
+        &self.response
+    }
+
+    /// Checks the client's confirmation and has `verifier` check its
+    /// certificate, giving the ready session along with the reply to send.
+    /// The confirmation is opened into `plain`, which must be at least its
+    /// length less [`OVERHEAD`](crate::OVERHEAD), and the certificate is
+    /// left there after the first [`TRANSCRIPT_LEN`] bytes.
+    pub fn confirm(
+        mut self,
+        confirmation: &[u8],
+        plain: &mut [u8],
+        verifier: impl FnOnce(&[u8]) -> bool,
+    ) -> Result<(Session, [u8; REPLY_LEN]), HandshakeError> {
+        let plain_len = match self.session.open_into(confirmation, plain) {
+            Ok(plain_len) => plain_len,
+            Err(SessionError::BufferTooSmall(len)) => return Err(HandshakeError::BufferTooSmall(len)),
+            Err(_) => return Err(HandshakeError::ConfirmationMismatch),
+        };
+        let confirmation = &plain[..plain_len];
+        if confirmation.len() < TRANSCRIPT_LEN || confirmation[..TRANSCRIPT_LEN] != self.transcript {
+            return Err(HandshakeError::ConfirmationMismatch);
+        }
+        if !verifier(&confirmation[TRANSCRIPT_LEN..]) {
+            return Err(HandshakeError::BadCertificate);
+        }
+
+        let mut reply = [0u8; REPLY_LEN];
+        self.session.seal_into(&self.transcript, &mut reply).map_err(buffer_error)?;
+        Ok((self.session, reply))
+    }
+}
+
+// the peer's key as it was sent, after checking the layout of whatever
+// follows it. either SEC1 form is taken
+fn split_key(bytes: &[u8]) -> Option<&[u8]> {
+    let len = match (bytes.first(), bytes.get(33)) {
+        (Some(0x04), _) => 65,
+        (Some(0x02 | 0x03), Some(0x02 | 0x03)) => SIGNED_PUBLIC_KEY_LEN,
+        (Some(0x02 | 0x03), _) => PUBLIC_KEY_LEN,
+        _ => return None,
+    };
+    // the extensions are read for their layout and none are kept
+    if len == SIGNED_PUBLIC_KEY_LEN || bytes.len() < len || !protocol::read_extensions(&bytes[len..], |_, _| ()) {
+        return None;
+    }
+    Some(&bytes[..len])
+}
+
+// keys a session as xc220b3's set_sym_key does from version 3: the keys
+// bound to both compressed public keys, the initiator's (the lower) first
+fn agree(secret: &EphemeralSecret, ours: &[u8; PUBLIC_KEY_LEN], theirs: &[u8]) -> Option<Session> {
+    let theirs = PublicKey::from_sec1_bytes(theirs).ok()?;
+    let canonical = theirs.to_encoded_point(true);
+    let canonical: &[u8; PUBLIC_KEY_LEN] = canonical.as_bytes().try_into().ok()?;
+    let (role, initiator, responder) = match ours.cmp(canonical) {
+        core::cmp::Ordering::Less => (Role::Initiator, ours, canonical),
+        core::cmp::Ordering::Greater => (Role::Responder, canonical, ours),
+        // that's our own key
+        core::cmp::Ordering::Equal => return None,
+    };
+    let shared = secret.diffie_hellman(&theirs);
+    // no early exit, how much of it is zero isn't anyone's business
+    if shared.raw_secret_bytes().iter().fold(0u8, |acc, byte| acc | byte) == 0 {
+        return None;
+    }
+    let mut bound = [0u8; 2 * PUBLIC_KEY_LEN];
+    bound[..PUBLIC_KEY_LEN].copy_from_slice(initiator);
+    bound[PUBLIC_KEY_LEN..].copy_from_slice(responder);
+    Some(Session::new(role, shared.raw_secret_bytes(), &bound))
+}
+
+// sealing a handshake record can only fail for want of room
+fn buffer_error(e: SessionError) -> HandshakeError {
+    match e {
+        SessionError::BufferTooSmall(len) => HandshakeError::BufferTooSmall(len),
+        _ => HandshakeError::Malformed(HandshakePhase::Confirm),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+
+    #[test]
+    fn test_hellos_are_checked() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let hello = *ClientHandshake::new(&mut rng).hello();
+        assert!(ServerHandshake::respond(&mut rng, &hello).is_ok());
+
+        let mut other = hello;
+        other[20] = 0;
+        assert_eq!(ServerHandshake::respond(&mut rng, &other).err(), Some(HandshakeError::ConfigMismatch));
+        other = hello;
+        other[CONFIG_LEN + 1] = 2;
+        assert_eq!(ServerHandshake::respond(&mut rng, &other).err(), Some(HandshakeError::NoCommonVersion));
+        for len in [0, CONFIG_LEN + 1, HELLO_LEN - 1] {
+            let malformed = Some(HandshakeError::Malformed(HandshakePhase::Hello));
+            assert_eq!(ServerHandshake::respond(&mut rng, &hello[..len]).err(), malformed);
+        }
+    }
+
+    #[test]
+    fn test_own_key_is_refused() {
+        let mut rng = ChaCha20Rng::seed_from_u64(2);
+        let client = ClientHandshake::new(&mut rng);
+        let mut response = [0u8; RESPONSE_LEN];
+        response[0] = record::VERSION;
+        response[1..].copy_from_slice(&client.hello()[CONFIG_LEN + 2..]);
+        let mut out = [0u8; confirmation_len(0)];
+        let malformed = HandshakeError::Malformed(HandshakePhase::KeyExchange);
+        assert_eq!(client.confirm(&response, &[], &mut out).err(), Some(malformed));
+    }
+}
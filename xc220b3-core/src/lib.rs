@@ -0,0 +1,36 @@
+//! The handshake and records of xc220b3's default session, for targets
+//! with no allocator. A [`ClientHandshake`] or [`ServerHandshake`] here
+//! talks to xc220b3's `handshake::client_sync` and `server_sync` (or its
+//! async ones) on their default `HandshakeConfig`, and the [`Session`] it
+//! gives seals and opens the same version 3 ordered records.
+//!
+//! Every message is a fixed array or is written into a buffer of the
+//! caller's, and [`Session::seal_record`] and [`Session::open_record`] give
+//! a `heapless::Vec` of a capacity fixed at compile time. Nothing
+//! allocates: the crate doesn't link `alloc`, and none of its dependencies
+//! are built with `std` or `alloc`. It has no unsafe code of its own either,
+//! the cipher being RustCrypto's XChaCha20 rather than xc220b3's.
+//!
+//! What it leaves out, and refuses from the peer, is everything past
+//! sending data: heartbeats, close and rekey records, renegotiation,
+//! messages with ids, identity keys and extensions of its own.
+//!
+//! What the two crates have to agree on, the labels, the config encoding,
+//! the record header and the KDF, is in [`protocol`], which xc220b3 builds
+//! against as well, so there's one copy of it.
+
+#![no_std]
+#![forbid(unsafe_code)]
+
+mod handshake;
+pub mod protocol;
+mod record;
+mod session;
+
+pub use handshake::{
+    confirmation_len, ClientConfirmation, ClientHandshake, HandshakeError, HandshakePhase, ServerHandshake, HELLO_LEN,
+    PUBLIC_KEY_LEN, REPLY_LEN, RESPONSE_LEN,
+};
+pub use protocol::{CONFIG_LEN, TAG_LEN, TRANSCRIPT_LEN};
+pub use record::OVERHEAD;
+pub use session::{Session, SessionError, MAX_MESSAGE_LEN, REKEY_AFTER_MESSAGES};
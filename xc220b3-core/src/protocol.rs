@@ -0,0 +1,289 @@
+// what xc220b3 and this crate have to agree on byte for byte, kept in one
+// place that both build against: xc220b3 depends on this crate for it, so
+// neither has a copy to drift. none of it allocates
+//
+// the record layout and the MAC input are explained in record.rs of both
+// crates, the handshake messages in handshake.rs. the cipher itself isn't
+// here, xc220b3 has its own XChaCha20 and this crate RustCrypto's
+
+/// Records from before the header was authenticated.
+pub const V1: u8 = 1;
+/// Records laid out as today's, from sessions whose keys weren't bound to
+/// the public keys.
+pub const V2: u8 = 2;
+/// Records of sessions keyed with both public keys bound in, the newest.
+pub const V3: u8 = 3;
+/// The header byte: the version, and the flags below.
+pub const HEADER_LEN: usize = 1;
+/// The key epoch's parity.
+pub const EPOCH_BIT: u8 = 0x80;
+pub const HEARTBEAT_BIT: u8 = 0x40;
+pub const CLOSE_BIT: u8 = 0x20;
+/// A message sealed with an id, both bits above together a rekey record.
+pub const ID_BIT: u8 = 0x10;
+/// The bits of the header byte that aren't the version.
+pub const FLAG_BITS: u8 = EPOCH_BIT | HEARTBEAT_BIT | CLOSE_BIT | ID_BIT;
+/// An obfuscated record's salt, in place of the header.
+pub const SALT_LEN: usize = 16;
+/// An ordered record's le64 sequence number, after the header byte.
+pub const SEQ_LEN: usize = 8;
+/// The default cipher's tag, which is also XChaCha20's nonce.
+pub const TAG_LEN: usize = 24;
+
+/// The default largest message, generous for any single message but small
+/// enough that one hostile record can't exhaust memory.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+/// The most records sent under one key, far below where the counter could
+/// wrap.
+pub const REKEY_AFTER_MESSAGES: u64 = 1 << 48;
+/// The default plaintext bytes per chunk of a stream.
+pub const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+/// The XChaCha20-BLAKE3 suite's id.
+pub const SUITE_XCHACHA20_BLAKE3: u8 = 1;
+/// The BLAKE3 KDF's id.
+pub const KDF_BLAKE3: u8 = 1;
+/// The HKDF-SHA256 KDF's id.
+pub const KDF_HKDF_SHA256: u8 = 2;
+
+pub const KDF_CONTEXT: &str = "xc220b3 2022-10 session kdf";
+/// The label of the key the initiator seals with.
+pub const INITIATOR_TO_RESPONDER: &[u8] = b"xc220b3 initiator to responder";
+/// The label of the key the responder seals with.
+pub const RESPONDER_TO_INITIATOR: &[u8] = b"xc220b3 responder to initiator";
+
+pub const TRANSCRIPT_CONTEXT: &str = "xc220b3 2022-10 handshake transcript";
+/// Length of a transcript hash.
+pub const TRANSCRIPT_LEN: usize = 32;
+/// The first byte of a handshake message's extensions area.
+pub const EXTENSIONS_MARKER: u8 = 0xe5;
+/// Most entries a handshake message's extensions can hold.
+pub const MAX_EXTENSIONS: usize = 16;
+/// Most bytes a handshake message's extensions can take up, each entry's
+/// type and length included.
+pub const MAX_EXTENSIONS_LEN: usize = 1024;
+/// A compressed key, then a compressed identity key and its signature.
+pub const SIGNED_PUBLIC_KEY_LEN: usize = 33 + 33 + 64;
+
+/// Length of an encoded [`ConfigFields`].
+pub const CONFIG_LEN: usize = 25;
+const CONFIG_ENCODING_VERSION: u8 = 3;
+pub const FLAG_OBFUSCATE: u8 = 1;
+pub const FLAG_ORDERED: u8 = 2;
+pub const FLAG_HIDE_LENGTHS: u8 = 4;
+const CONFIG_FLAGS: u8 = FLAG_OBFUSCATE | FLAG_ORDERED | FLAG_HIDE_LENGTHS;
+
+/// xc220b3's `SessionConfig` as it's encoded, the suite and KDF by id.
+///
+/// `version (1) || suite (1) || kdf (1) || tag_len (1) || le64(max_message_len) || le64(rekey_after) || flags (1) || le32(stream_chunk_len)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigFields {
+    pub suite: u8,
+    pub kdf: u8,
+    pub tag_len: u8,
+    pub max_message_len: u64,
+    pub rekey_after: u64,
+    /// [`FLAG_OBFUSCATE`], [`FLAG_ORDERED`] and [`FLAG_HIDE_LENGTHS`].
+    pub flags: u8,
+    pub stream_chunk_len: u32,
+}
+
+/// xc220b3's default config.
+pub const DEFAULT_CONFIG: ConfigFields = ConfigFields {
+    suite: SUITE_XCHACHA20_BLAKE3,
+    kdf: KDF_BLAKE3,
+    tag_len: TAG_LEN as u8,
+    max_message_len: DEFAULT_MAX_MESSAGE_LEN as u64,
+    rekey_after: REKEY_AFTER_MESSAGES,
+    flags: FLAG_ORDERED,
+    stream_chunk_len: STREAM_CHUNK_LEN as u32,
+};
+
+impl ConfigFields {
+    pub const fn to_bytes(&self) -> [u8; CONFIG_LEN] {
+        let mut out = [0u8; CONFIG_LEN];
+        out[0] = CONFIG_ENCODING_VERSION;
+        out[1] = self.suite;
+        out[2] = self.kdf;
+        out[3] = self.tag_len;
+        let max_message_len = self.max_message_len.to_le_bytes();
+        let rekey_after = self.rekey_after.to_le_bytes();
+        let mut i = 0;
+        while i < 8 {
+            out[4 + i] = max_message_len[i];
+            out[12 + i] = rekey_after[i];
+            i += 1;
+        }
+        out[20] = self.flags;
+        let stream_chunk_len = self.stream_chunk_len.to_le_bytes();
+        let mut i = 0;
+        while i < 4 {
+            out[21 + i] = stream_chunk_len[i];
+            i += 1;
+        }
+        out
+    }
+
+    /// Reverses [`ConfigFields::to_bytes`], or `None` for anything it didn't
+    /// write: another length or encoding version, or an unknown flag. The
+    /// fields themselves aren't checked.
+    pub fn from_bytes(bytes: &[u8]) -> Option<ConfigFields> {
+        if bytes.len() != CONFIG_LEN || bytes[0] != CONFIG_ENCODING_VERSION || bytes[20] & !CONFIG_FLAGS != 0 {
+            return None;
+        }
+        Some(ConfigFields {
+            suite: bytes[1],
+            kdf: bytes[2],
+            tag_len: bytes[3],
+            max_message_len: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            rekey_after: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+            flags: bytes[20],
+            stream_chunk_len: u32::from_le_bytes(bytes[21..25].try_into().unwrap()),
+        })
+    }
+}
+
+/// A header byte of `version` for a record of key epoch `epoch`.
+pub const fn header_byte(version: u8, epoch: u64) -> u8 {
+    match epoch % 2 {
+        0 => version,
+        _ => version | EPOCH_BIT,
+    }
+}
+
+/// The sequence number in an ordered record's header.
+pub fn seq(header: &[u8]) -> u64 {
+    u64::from_le_bytes(header[HEADER_LEN..HEADER_LEN + SEQ_LEN].try_into().unwrap())
+}
+
+/// What the cipher binds under the tag for a record with `header`, fed to
+/// `out` a piece at a time. From version 2 that's
+///
+/// `le64(header len) || header || le64(aad len) || aad || le64(plaintext len)`
+///
+/// and a version 1 record binds the aad alone.
+pub fn authenticated_data(header: &[u8], aad: &[u8], plain_len: usize, mut out: impl FnMut(&[u8])) {
+    if header.len() == HEADER_LEN && header[0] & !FLAG_BITS == V1 {
+        out(aad);
+        return;
+    }
+    out(&(header.len() as u64).to_le_bytes());
+    out(header);
+    out(&(aad.len() as u64).to_le_bytes());
+    out(aad);
+    out(&(plain_len as u64).to_le_bytes());
+}
+
+/// The default cipher's MAC input for the concatenation of `parts` under
+/// `key` with `aad`, fed to `update` a piece at a time for BLAKE3, whose
+/// 24 byte XOF output over it is the tag:
+///
+/// `plaintext || key [|| aad || le64(aad len)]`
+///
+/// the aad length-suffixed so the plaintext/aad boundary can't be moved,
+/// and an empty aad left out, length and all.
+pub fn mac_input(key: &[u8], parts: &[&[u8]], aad: &[u8], mut update: impl FnMut(&[u8])) {
+    for part in parts {
+        update(part);
+    }
+    update(key);
+    if !aad.is_empty() {
+        update(aad);
+        update(&(aad.len() as u64).to_le_bytes());
+    }
+}
+
+/// The BLAKE3 KDF with a `transcript` or `label`: under a key derived from
+/// the shared secret in [`KDF_CONTEXT`], the label length-prefixed so it
+/// and the transcript can't bleed into each other. With neither, xc220b3
+/// keys by the plain hash of the secret, as version 1 sessions always have.
+pub fn derive(shared_secret: &[u8], transcript: &[u8], label: &[u8], out: &mut [u8]) {
+    let key = zeroize::Zeroizing::new(blake3::derive_key(KDF_CONTEXT, shared_secret));
+    let mut b3 = blake3::Hasher::new_keyed(&key);
+    b3.update(&(label.len() as u64).to_le_bytes());
+    b3.update(label);
+    b3.update(transcript);
+    b3.finalize_xof().fill(out);
+}
+
+/// The hash both ends confirm a handshake with, over the client's hello and
+/// the server's response as sent.
+pub fn transcript(hello: &[u8], response: &[u8]) -> [u8; TRANSCRIPT_LEN] {
+    let mut b3 = blake3::Hasher::new_derive_key(TRANSCRIPT_CONTEXT);
+    b3.update(&(hello.len() as u64).to_le_bytes());
+    b3.update(hello);
+    b3.update(response);
+    *b3.finalize().as_bytes()
+}
+
+/// Reads a handshake message's extensions area, handing each entry's type
+/// and data to `entry`, and gives whether it was well formed: empty, or
+///
+/// `0xe5 || le16(length) || entries`, each `le16(type) || le16(data length) || data`
+///
+/// with from one to [`MAX_EXTENSIONS_LEN`] bytes of at most
+/// [`MAX_EXTENSIONS`] entries, no type twice. An empty area is written as
+/// none at all, so there's one way to send it. Entries before a fault have
+/// already been handed over.
+pub fn read_extensions<'a>(bytes: &'a [u8], mut entry: impl FnMut(u16, &'a [u8])) -> bool {
+    let Some((&marker, rest)) = bytes.split_first() else {
+        return true;
+    };
+    let Some(len) = rest.get(..2) else {
+        return false;
+    };
+    let len = u16::from_le_bytes(len.try_into().unwrap()) as usize;
+    if marker != EXTENSIONS_MARKER || len == 0 || len > MAX_EXTENSIONS_LEN || rest.len() != 2 + len {
+        return false;
+    }
+    let mut kinds = [0u16; MAX_EXTENSIONS];
+    let mut count = 0;
+    let mut entries = &rest[2..];
+    while let [k0, k1, l0, l1, rest @ ..] = entries {
+        let kind = u16::from_le_bytes([*k0, *k1]);
+        let data_len = u16::from_le_bytes([*l0, *l1]) as usize;
+        if rest.len() < data_len || count == MAX_EXTENSIONS || kinds[..count].contains(&kind) {
+            return false;
+        }
+        kinds[count] = kind;
+        count += 1;
+        let (data, after) = rest.split_at(data_len);
+        entry(kind, data);
+        entries = after;
+    }
+    entries.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trips() {
+        let bytes = DEFAULT_CONFIG.to_bytes();
+        assert_eq!(ConfigFields::from_bytes(&bytes), Some(DEFAULT_CONFIG));
+        let mut other = bytes;
+        other[20] |= 0x80;
+        assert_eq!(ConfigFields::from_bytes(&other), None);
+        other = bytes;
+        other[0] = 2;
+        assert_eq!(ConfigFields::from_bytes(&other), None);
+        assert_eq!(ConfigFields::from_bytes(&bytes[..CONFIG_LEN - 1]), None);
+    }
+
+    #[test]
+    fn test_extensions_are_read_when_well_formed() {
+        let read = |bytes: &[u8]| {
+            let mut entries = heapless::Vec::<(u16, usize), MAX_EXTENSIONS>::new();
+            read_extensions(bytes, |kind, data| entries.push((kind, data.len())).unwrap()).then_some(entries)
+        };
+        assert_eq!(read(&[]).unwrap(), []);
+        assert_eq!(read(&[EXTENSIONS_MARKER, 5, 0, 1, 0, 1, 0, b'x']).unwrap(), [(1, 1)]);
+        assert_eq!(read(&[EXTENSIONS_MARKER, 8, 0, 1, 0, 0, 0, 2, 0, 0, 0]).unwrap(), [(1, 0), (2, 0)]);
+        // empty, cut short, one type twice, another marker
+        assert_eq!(read(&[EXTENSIONS_MARKER, 0, 0]), None);
+        assert_eq!(read(&[EXTENSIONS_MARKER, 5, 0, 1, 0, 2, 0, b'x']), None);
+        assert_eq!(read(&[EXTENSIONS_MARKER, 8, 0, 1, 0, 0, 0, 1, 0, 0, 0]), None);
+        assert_eq!(read(&[0xe4, 4, 0, 1, 0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,122 @@
+// records exactly as xc220b3's default session seals them, version 3 and
+// ordered (see record.rs there for why they're laid out so):
+//
+//   version (1) || le64(sequence number) || ciphertext || tag (24)
+//
+// the cipher is XChaCha20 keyed with the direction's key and nonced with
+// the tag, which is BLAKE3's 24 byte XOF output over
+//
+//   plaintext || key || aad || le64(aad len)
+//
+// where the aad is the header the tag covers, bound as
+//
+//   le64(header len) || header || le64(0) || le64(plaintext len)
+//
+// the zero being the length of an aad of the caller's, which this crate
+// never has. only data records of the first key epoch are sealed here, so
+// the header byte is the version alone. the layouts are protocol's, shared
+// with xc220b3
+
+use blake3::Hasher;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::{Key, XChaCha20, XNonce};
+use subtle::ConstantTimeEq;
+
+use crate::protocol::{self, FLAG_BITS, SEQ_LEN, TAG_LEN};
+use crate::session::SessionError;
+
+pub(crate) const VERSION: u8 = protocol::V3;
+// the header byte and the sequence number
+pub(crate) const HEADER_LEN: usize = protocol::HEADER_LEN + SEQ_LEN;
+/// Bytes a record adds on top of its plaintext.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+// the le64 lengths around the header, the (empty) aad and the plaintext
+const AUTHENTICATED_LEN: usize = 8 + HEADER_LEN + 8 + 8;
+
+pub(crate) type Header = [u8; HEADER_LEN];
+
+pub(crate) fn header(seq: u64) -> Header {
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = VERSION;
+    header[1..].copy_from_slice(&seq.to_le_bytes());
+    header
+}
+
+pub(crate) fn seq(header: &[u8]) -> u64 {
+    protocol::seq(header)
+}
+
+/// Checks the structure of `record` and splits it into its header and what
+/// the cipher made, before any cryptography is done.
+pub(crate) fn split_header(record: &[u8]) -> Result<(&[u8], &[u8]), SessionError> {
+    if record.len() < OVERHEAD {
+        return Err(SessionError::InvalidLength(record.len()));
+    }
+    // the epoch and record kinds xc220b3 marks there, none of which this
+    // crate sends or takes
+    if record[0] & !FLAG_BITS != VERSION {
+        return Err(SessionError::UnsupportedVersion(record[0]));
+    }
+    if record[0] != VERSION {
+        return Err(SessionError::UnsupportedRecord(record[0]));
+    }
+    Ok(record.split_at(HEADER_LEN))
+}
+
+fn authenticated_data(header: &[u8], plain_len: usize) -> [u8; AUTHENTICATED_LEN] {
+    let mut out = [0u8; AUTHENTICATED_LEN];
+    let mut at = 0;
+    protocol::authenticated_data(header, &[], plain_len, |piece| {
+        out[at..at + piece.len()].copy_from_slice(piece);
+        at += piece.len();
+    });
+    out
+}
+
+fn mac(key: &[u8; 32], parts: &[&[u8]], aad: &[u8]) -> [u8; TAG_LEN] {
+    let mut b3 = Hasher::new();
+    protocol::mac_input(key, parts, aad, |piece| {
+        b3.update(piece);
+    });
+    let mut tag = [0u8; TAG_LEN];
+    b3.finalize_xof().fill(&mut tag);
+    tag
+}
+
+/// Seals the concatenation of `parts` under `header` into `out`, which is
+/// exactly the record's length.
+pub(crate) fn seal(key: &[u8; 32], header: &Header, parts: &[&[u8]], out: &mut [u8]) {
+    let plain_len = out.len() - OVERHEAD;
+    let tag = mac(key, parts, &authenticated_data(header, plain_len));
+    let (front, rest) = out.split_at_mut(HEADER_LEN);
+    let (ciphertext, tail) = rest.split_at_mut(plain_len);
+    front.copy_from_slice(header);
+
+    // the keystream carries on across parts
+    let mut at = 0;
+    for part in parts {
+        ciphertext[at..at + part.len()].copy_from_slice(part);
+        at += part.len();
+    }
+    XChaCha20::new(Key::from_slice(key), XNonce::from_slice(&tag)).apply_keystream(ciphertext);
+    tail.copy_from_slice(&tag);
+}
+
+/// Opens what [`split_header`] split off a record into `out`, which is
+/// exactly the plaintext's length, and zeroes `out` again if it doesn't
+/// authenticate.
+pub(crate) fn open(key: &[u8; 32], header: &[u8], sealed: &[u8], out: &mut [u8]) -> Result<(), SessionError> {
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    out.copy_from_slice(ciphertext);
+    XChaCha20::new(Key::from_slice(key), XNonce::from_slice(tag)).apply_keystream(out);
+
+    // masked to itself or to zeroes either way, as xc220b3 does, rather
+    // than only wiped when it's bad
+    let good: bool = mac(key, &[out], &authenticated_data(header, out.len())).ct_eq(tag).into();
+    let mask = core::hint::black_box(good as u8).wrapping_neg();
+    out.iter_mut().for_each(|byte| *byte &= mask);
+    match good {
+        true => Ok(()),
+        false => Err(SessionError::MacMismatch),
+    }
+}
@@ -0,0 +1,263 @@
+use core::fmt;
+
+use zeroize::Zeroize;
+
+use crate::protocol::{self, INITIATOR_TO_RESPONDER, RESPONDER_TO_INITIATOR};
+use crate::record::{self, OVERHEAD};
+
+/// The largest message a session takes, xc220b3's default.
+pub const MAX_MESSAGE_LEN: usize = protocol::DEFAULT_MAX_MESSAGE_LEN;
+/// Records one key seals before xc220b3 would want it rekeyed, which this
+/// crate doesn't do, so a session ends there.
+pub const REKEY_AFTER_MESSAGES: u64 = protocol::REKEY_AFTER_MESSAGES;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionError {
+    /// The record is well formed but failed authentication, it was tampered
+    /// with or sealed under a different key.
+    MacMismatch,
+    /// Too short to be a record.
+    InvalidLength(usize),
+    /// The record isn't of version 3, the header byte is given.
+    UnsupportedVersion(u8),
+    /// A version 3 record of a kind this crate doesn't take: a heartbeat,
+    /// close, rekey or id record, or one from a renegotiated epoch. The
+    /// header byte is given.
+    UnsupportedRecord(u8),
+    /// The record isn't the next one the peer sealed, it was reordered,
+    /// replayed or one before it was dropped.
+    OutOfOrder { expected: u64, received: u64 },
+    MessageTooLarge(usize),
+    /// The buffer given can't hold what would be written to it, which needs
+    /// the given length. Nothing was sealed or opened.
+    BufferTooSmall(usize),
+    /// [`REKEY_AFTER_MESSAGES`] records have been sealed.
+    CounterExhausted,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::MacMismatch => write!(f, "record failed authentication"),
+            SessionError::InvalidLength(len) => write!(f, "{} bytes is too short for a record", len),
+            SessionError::UnsupportedVersion(byte) => write!(f, "record header {:#04x} isn't version 3", byte),
+            SessionError::UnsupportedRecord(byte) => write!(f, "record header {:#04x} isn't a data record", byte),
+            SessionError::OutOfOrder { expected, received } => {
+                write!(f, "expected record {}, received record {}", expected, received)
+            }
+            SessionError::MessageTooLarge(len) => write!(f, "{} byte message is over the limit", len),
+            SessionError::BufferTooSmall(len) => write!(f, "buffer too small, {} bytes needed", len),
+            SessionError::CounterExhausted => write!(f, "record counter exhausted"),
+        }
+    }
+}
+
+/// Which end of the session, set by the public keys: the side with the
+/// lower compressed key is the initiator, as in xc220b3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    Initiator,
+    Responder,
+}
+
+/// A keyed session, which seals records into and opens them from buffers
+/// the caller owns. Made by a [handshake](crate::ClientHandshake), and the
+/// peer can be a session of xc220b3's on its defaults.
+///
+/// The keys are zeroized when it's dropped.
+pub struct Session {
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    sent: u64,
+    received: u64,
+}
+
+impl Session {
+    // keys both directions as xc220b3's Blake3Kdf does, binding `transcript`
+    pub(crate) fn new(role: Role, shared: &[u8], transcript: &[u8]) -> Session {
+        let (send_label, recv_label) = match role {
+            Role::Initiator => (INITIATOR_TO_RESPONDER, RESPONDER_TO_INITIATOR),
+            Role::Responder => (RESPONDER_TO_INITIATOR, INITIATOR_TO_RESPONDER),
+        };
+        Session {
+            send_key: derive(shared, transcript, send_label),
+            recv_key: derive(shared, transcript, recv_label),
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    /// Length of the record `plaintext_len` bytes seal into.
+    pub const fn record_len(plaintext_len: usize) -> usize {
+        plaintext_len + OVERHEAD
+    }
+
+    /// Seals `plain` into a record at the start of `out`, giving its
+    /// length. `out` must be at least [`Session::record_len`] long, if it
+    /// isn't the error is [`SessionError::BufferTooSmall`] and nothing is
+    /// sent.
+    pub fn seal_into(&mut self, plain: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        self.seal_parts_into(&[plain], out)
+    }
+
+    /// [`Session::seal_into`] over the concatenation of `parts`, without
+    /// concatenating them first.
+    pub fn seal_parts_into(&mut self, parts: &[&[u8]], out: &mut [u8]) -> Result<usize, SessionError> {
+        let plain_len: usize = parts.iter().map(|part| part.len()).sum();
+        if plain_len > MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge(plain_len));
+        }
+        let record_len = Session::record_len(plain_len);
+        if out.len() < record_len {
+            return Err(SessionError::BufferTooSmall(record_len));
+        }
+        // never wrap, a record sealed twice under one number is a replay
+        // the peer would take
+        self.sent = match self.sent.checked_add(1) {
+            Some(sent) if sent <= REKEY_AFTER_MESSAGES => sent,
+            _ => return Err(SessionError::CounterExhausted),
+        };
+        record::seal(&self.send_key, &record::header(self.sent), parts, &mut out[..record_len]);
+        Ok(record_len)
+    }
+
+    /// Opens the next record from the peer into the start of `out`, giving
+    /// the plaintext's length. `out` must be at least the record's length
+    /// less [`OVERHEAD`](crate::OVERHEAD), if it isn't the error is
+    /// [`SessionError::BufferTooSmall`] and the record isn't looked at.
+    /// Whatever was written to `out` is zeroed again if the record is
+    /// refused, and a refused record isn't counted, so the next one can
+    /// still be opened.
+    pub fn open_into(&mut self, record: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        let plain_len = record.len().saturating_sub(OVERHEAD);
+        if out.len() < plain_len {
+            return Err(SessionError::BufferTooSmall(plain_len));
+        }
+        let (header, sealed) = record::split_header(record)?;
+        if plain_len > MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge(plain_len));
+        }
+        let expected = self.received + 1;
+        if record::seq(header) != expected {
+            return Err(SessionError::OutOfOrder { expected, received: record::seq(header) });
+        }
+        record::open(&self.recv_key, header, sealed, &mut out[..plain_len])?;
+        self.received = expected;
+        Ok(plain_len)
+    }
+
+    /// [`Session::seal_into`] giving the record in a `heapless::Vec` of
+    /// capacity `N`, [`SessionError::BufferTooSmall`] if it doesn't fit.
+    pub fn seal_record<const N: usize>(&mut self, plain: &[u8]) -> Result<heapless::Vec<u8, N>, SessionError> {
+        let mut out = heapless::Vec::new();
+        let record_len = Session::record_len(plain.len());
+        out.resize(record_len, 0).map_err(|_| SessionError::BufferTooSmall(record_len))?;
+        self.seal_into(plain, &mut out)?;
+        Ok(out)
+    }
+
+    /// [`Session::open_into`] giving the plaintext in a `heapless::Vec` of
+    /// capacity `N`, [`SessionError::BufferTooSmall`] if it doesn't fit.
+    pub fn open_record<const N: usize>(&mut self, record: &[u8]) -> Result<heapless::Vec<u8, N>, SessionError> {
+        let mut out = heapless::Vec::new();
+        let plain_len = record.len().saturating_sub(OVERHEAD);
+        out.resize(plain_len, 0).map_err(|_| SessionError::BufferTooSmall(plain_len))?;
+        self.open_into(record, &mut out)?;
+        Ok(out)
+    }
+
+    /// Records sealed so far, the last one's sequence number.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Records opened so far.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.send_key.zeroize();
+        self.recv_key.zeroize();
+    }
+}
+
+fn derive(shared: &[u8], transcript: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    protocol::derive(shared, transcript, label, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pair() -> (Session, Session) {
+        let shared = [7u8; 32];
+        (Session::new(Role::Initiator, &shared, b"transcript"), Session::new(Role::Responder, &shared, b"transcript"))
+    }
+
+    #[test]
+    fn test_records_roundtrip_in_order() {
+        let (mut ours, mut theirs) = pair();
+        let mut record = [0u8; 64];
+        let mut plain = [0u8; 31];
+        for message in [&b"hello"[..], b"", &[0x5a; 31]] {
+            let written = ours.seal_into(message, &mut record).unwrap();
+            assert_eq!(written, message.len() + OVERHEAD);
+            let read = theirs.open_into(&record[..written], &mut plain).unwrap();
+            assert_eq!(&plain[..read], message);
+        }
+        assert_eq!((ours.sent(), theirs.received()), (3, 3));
+    }
+
+    #[test]
+    fn test_refused_records_arent_counted() {
+        let (mut ours, mut theirs) = pair();
+        let first = ours.seal_record::<64>(b"first").unwrap();
+        let second = ours.seal_record::<64>(b"second").unwrap();
+        assert_eq!(theirs.open_record::<16>(&second), Err(SessionError::OutOfOrder { expected: 1, received: 2 }));
+
+        let mut tampered = first.clone();
+        // into the ciphertext, past the header
+        tampered[record::HEADER_LEN] ^= 1;
+        let mut plain = [0xffu8; 5];
+        assert_eq!(theirs.open_into(&tampered, &mut plain), Err(SessionError::MacMismatch));
+        assert_eq!(plain, [0; 5]);
+
+        assert_eq!(&theirs.open_record::<16>(&first).unwrap()[..], b"first");
+        assert_eq!(&theirs.open_record::<16>(&second).unwrap()[..], b"second");
+    }
+
+    #[test]
+    fn test_structure_is_checked_first() {
+        let (mut ours, mut theirs) = pair();
+        let record = ours.seal_record::<64>(b"hello").unwrap();
+        let mut plain = [0u8; 16];
+        assert_eq!(theirs.open_into(&record[..OVERHEAD - 1], &mut plain), Err(SessionError::InvalidLength(OVERHEAD - 1)));
+        assert_eq!(theirs.open_into(&record, &mut plain[..4]), Err(SessionError::BufferTooSmall(5)));
+
+        let mut other = record.clone();
+        other[0] = 2;
+        assert_eq!(theirs.open_into(&other, &mut plain), Err(SessionError::UnsupportedVersion(2)));
+        // a heartbeat's flag
+        other[0] = 0x43;
+        assert_eq!(theirs.open_into(&other, &mut plain), Err(SessionError::UnsupportedRecord(0x43)));
+        assert_eq!(theirs.open_into(&record, &mut plain), Ok(5));
+    }
+
+    #[test]
+    fn test_buffers_too_small_send_nothing() {
+        let (mut ours, mut theirs) = pair();
+        assert_eq!(ours.seal_record::<8>(b"hello"), Err(SessionError::BufferTooSmall(5 + OVERHEAD)));
+        let mut small = [0u8; OVERHEAD];
+        assert_eq!(ours.seal_into(b"hello", &mut small), Err(SessionError::BufferTooSmall(5 + OVERHEAD)));
+        assert_eq!(ours.sent(), 0);
+
+        let record = ours.seal_record::<64>(b"hello").unwrap();
+        assert_eq!(theirs.open_record::<4>(&record), Err(SessionError::BufferTooSmall(5)));
+        assert_eq!(&theirs.open_record::<5>(&record).unwrap()[..], b"hello");
+    }
+}
@@ -0,0 +1,94 @@
+// this crate's handshake and records against xc220b3's own, over a socket
+// with xc220b3's framing, each way round
+
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use xc220b3::framing::{read_frame, write_frame};
+use xc220b3::handshake::{client_sync, server_sync, HandshakeConfig};
+use xc220b3::SessionConfig;
+use xc220b3_core::{confirmation_len, ClientHandshake, ServerHandshake, CONFIG_LEN, OVERHEAD, TRANSCRIPT_LEN};
+
+const CERTIFICATE: &[u8] = b"device 7";
+const MESSAGES: [&[u8]; 3] = [b"hello", b"", &[0x5a; 1000]];
+
+fn connected() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    (client, listener.accept().unwrap().0)
+}
+
+// opens each record from the other side and seals it back
+fn echo(mut session: xc220b3::Session, mut io: TcpStream) {
+    for _ in MESSAGES {
+        let plain = session.decrypt(read_frame(&mut io).unwrap()).unwrap();
+        write_frame(&mut io, &session.encrypt(plain).unwrap()).unwrap();
+    }
+}
+
+fn converse(session: &mut xc220b3_core::Session, io: &mut TcpStream) {
+    let mut record = [0u8; 1000 + OVERHEAD];
+    let mut plain = [0u8; 1000];
+    for message in MESSAGES {
+        let written = session.seal_into(message, &mut record).unwrap();
+        write_frame(io, &record[..written]).unwrap();
+        let read = session.open_into(&read_frame(io).unwrap(), &mut plain).unwrap();
+        assert_eq!(&plain[..read], message);
+    }
+}
+
+#[test]
+fn test_hello_carries_the_default_config() {
+    let client = ClientHandshake::new(&mut ChaCha20Rng::seed_from_u64(1));
+    assert_eq!(client.hello()[..CONFIG_LEN], SessionConfig::default().to_bytes());
+}
+
+#[test]
+fn test_client_against_xc220b3_server() {
+    let (mut io, theirs) = connected();
+    let peer = thread::spawn(move || {
+        let verifier = |certificate: &[u8]| certificate == CERTIFICATE;
+        let (session, io) = server_sync(theirs, &HandshakeConfig::default(), &verifier).unwrap();
+        assert_eq!(session.negotiated_version(), 3);
+        echo(session, io);
+    });
+
+    let client = ClientHandshake::new(&mut ChaCha20Rng::seed_from_u64(2));
+    write_frame(&mut io, client.hello()).unwrap();
+    let mut confirmation = [0u8; confirmation_len(CERTIFICATE.len())];
+    let (client, written) = client.confirm(&read_frame(&mut io).unwrap(), CERTIFICATE, &mut confirmation).unwrap();
+    write_frame(&mut io, &confirmation[..written]).unwrap();
+    let mut session = client.finish(&read_frame(&mut io).unwrap()).unwrap();
+
+    converse(&mut session, &mut io);
+    peer.join().unwrap();
+}
+
+#[test]
+fn test_server_against_xc220b3_client() {
+    let (ours, mut io) = connected();
+    let peer = thread::spawn(move || {
+        // the server name goes out as an extension, which is skipped
+        let config = HandshakeConfig {
+            certificate: CERTIFICATE.to_vec(),
+            server_name: Some("device.local".to_string()),
+            ..HandshakeConfig::default()
+        };
+        let (session, io) = client_sync(ours, &config).unwrap();
+        assert_eq!(session.negotiated_version(), 3);
+        echo(session, io);
+    });
+
+    let mut rng = ChaCha20Rng::seed_from_u64(3);
+    let server = ServerHandshake::respond(&mut rng, &read_frame(&mut io).unwrap()).unwrap();
+    write_frame(&mut io, server.response()).unwrap();
+    let mut plain = [0u8; TRANSCRIPT_LEN + CERTIFICATE.len()];
+    let confirmation = read_frame(&mut io).unwrap();
+    let (mut session, reply) = server.confirm(&confirmation, &mut plain, |certificate| certificate == CERTIFICATE).unwrap();
+    write_frame(&mut io, &reply).unwrap();
+
+    converse(&mut session, &mut io);
+    peer.join().unwrap();
+}
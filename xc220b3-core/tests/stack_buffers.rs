@@ -0,0 +1,77 @@
+// a whole handshake and conversation with nothing but stack buffers. the
+// test crate is no_std itself, so neither it nor the crate under test can
+// reach for an allocation, only the harness around it links std
+
+#![no_std]
+#![forbid(unsafe_code)]
+
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use xc220b3_core::{
+    confirmation_len, ClientHandshake, HandshakeError, ServerHandshake, Session, SessionError, OVERHEAD, TRANSCRIPT_LEN,
+};
+
+const CERTIFICATE: &[u8] = b"device 7";
+
+fn handshake(rng: &mut ChaCha20Rng) -> (Session, Session) {
+    let client = ClientHandshake::new(rng);
+    let server = ServerHandshake::respond(rng, client.hello()).unwrap();
+
+    let mut confirmation = [0u8; confirmation_len(CERTIFICATE.len())];
+    let (client, written) = client.confirm(server.response(), CERTIFICATE, &mut confirmation).unwrap();
+    assert_eq!(written, confirmation.len());
+
+    let mut plain = [0u8; TRANSCRIPT_LEN + CERTIFICATE.len()];
+    let (server, reply) = server.confirm(&confirmation, &mut plain, |certificate| certificate == CERTIFICATE).unwrap();
+    assert_eq!(&plain[TRANSCRIPT_LEN..], CERTIFICATE);
+    (client.finish(&reply).unwrap(), server)
+}
+
+#[test]
+fn test_handshake_and_messages_in_stack_buffers() {
+    let mut rng = ChaCha20Rng::seed_from_u64(7);
+    let (mut client, mut server) = handshake(&mut rng);
+
+    let mut record = [0u8; 256 + OVERHEAD];
+    let mut plain = [0u8; 256];
+    for (i, message) in [&b"hello"[..], b"", &[0x5a; 256]].into_iter().enumerate() {
+        let written = client.seal_into(message, &mut record).unwrap();
+        let read = server.open_into(&record[..written], &mut plain).unwrap();
+        assert_eq!(&plain[..read], message);
+
+        let answer = server.seal_record::<{ 256 + OVERHEAD }>(&plain[..read]).unwrap();
+        assert_eq!(&client.open_record::<256>(&answer).unwrap()[..], message);
+        assert_eq!(client.sent(), i as u64 + 2);
+    }
+
+    let mut tampered = client.seal_record::<64>(b"again").unwrap();
+    let original = tampered.clone();
+    tampered[OVERHEAD] ^= 1;
+    assert_eq!(server.open_record::<16>(&tampered), Err(SessionError::MacMismatch));
+    assert_eq!(&server.open_record::<16>(&original).unwrap()[..], b"again");
+}
+
+#[test]
+fn test_handshake_refusals_in_stack_buffers() {
+    let mut rng = ChaCha20Rng::seed_from_u64(8);
+    let client = ClientHandshake::new(&mut rng);
+    let server = ServerHandshake::respond(&mut rng, client.hello()).unwrap();
+    let response = *server.response();
+
+    let mut small = [0u8; confirmation_len(CERTIFICATE.len()) - 1];
+    let needed = HandshakeError::BufferTooSmall(confirmation_len(CERTIFICATE.len()));
+    assert_eq!(ClientHandshake::new(&mut rng).confirm(&response, CERTIFICATE, &mut small).err(), Some(needed));
+
+    let mut confirmation = [0u8; confirmation_len(CERTIFICATE.len())];
+    let (_, written) = client.confirm(&response, CERTIFICATE, &mut confirmation).unwrap();
+    let mut plain = [0u8; 64];
+    let refused = server.confirm(&confirmation[..written], &mut plain, |_| false);
+    assert_eq!(refused.err(), Some(HandshakeError::BadCertificate));
+
+    // a client answered with another server's response keys differently
+    let client = ClientHandshake::new(&mut rng);
+    let server = ServerHandshake::respond(&mut rng, client.hello()).unwrap();
+    let (_, written) = client.confirm(&response, CERTIFICATE, &mut confirmation).unwrap();
+    let mismatch = server.confirm(&confirmation[..written], &mut plain, |_| true);
+    assert_eq!(mismatch.err(), Some(HandshakeError::ConfirmationMismatch));
+}
@@ -0,0 +1,50 @@
+// a native session for test/ to talk to over stdin and stdout, in hex
+// lines. it writes its public key, then a recipient key pair for sealed
+// messages (public, then secret), and reads the JS session's public key.
+// after that each `record <hex>` is opened and sent back sealed again as
+// `record <hex>`, and each `sealed <hex>` likewise as `sealed <hex>`
+// sealed to the recipient key. anything refused is `error <code>`
+
+use std::io::{self, BufRead, Write};
+
+use rand_core::OsRng;
+use xc220b3::bindings::BindingError;
+use xc220b3::{ecies, EncodedPoint, SecretKey, Session, SessionError};
+
+fn reply(session: &mut Session, recipient: &SecretKey, line: &str) -> Result<String, SessionError> {
+    let (kind, bytes) = line.split_once(' ').ok_or(SessionError::MalformedEnvelope)?;
+    let bytes = hex::decode(bytes).map_err(|_| SessionError::MalformedEnvelope)?;
+    match kind {
+        "record" => {
+            let plain = session.decrypt(bytes)?;
+            Ok(format!("record {}", hex::encode(session.encrypt(plain)?)))
+        }
+        "sealed" => {
+            let plain = ecies::open(recipient, &bytes)?;
+            Ok(format!("sealed {}", hex::encode(ecies::seal(&recipient.public_key(), &plain, &mut OsRng))))
+        }
+        _ => Err(SessionError::MalformedEnvelope),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut session = Session::new(&mut OsRng);
+    let recipient = SecretKey::random(&mut OsRng);
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", hex::encode(session.pk().unwrap().as_bytes()))?;
+    writeln!(out, "{}", hex::encode(EncodedPoint::from(recipient.public_key()).as_bytes()))?;
+    writeln!(out, "{}", hex::encode(recipient.to_be_bytes()))?;
+    out.flush()?;
+
+    let mut lines = io::stdin().lock().lines();
+    let peer = hex::decode(lines.next().unwrap()?).unwrap();
+    session.set_sym_key(&EncodedPoint::from_bytes(peer).unwrap()).unwrap();
+    for line in lines {
+        match reply(&mut session, &recipient, &line?) {
+            Ok(reply) => writeln!(out, "{}", reply)?,
+            Err(e) => writeln!(out, "error {}", BindingError::from(e).code())?,
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,38 @@
+// what the addon exports, as napi-derive declares it. the declarations are
+// what building with TYPE_DEF_TMP_PATH set writes out, in the order napi-rs
+// writes them
+
+/**
+ * Seals `plain` to the recipient's SEC1 encoded public key, with no
+ * session to set up.
+ */
+export declare function seal(recipientPublicKey: Buffer, plain: Buffer): Buffer
+/**
+ * Opens what `seal` sealed, with the recipient's 32-byte big-endian
+ * secret key.
+ */
+export declare function open(recipientSecretKey: Buffer, sealed: Buffer): Buffer
+/** `seal` on the thread pool. */
+export declare function sealAsync(recipientPublicKey: Buffer, plain: Buffer): Promise<Buffer>
+/** `open` on the thread pool. */
+export declare function openAsync(recipientSecretKey: Buffer, sealed: Buffer): Promise<Buffer>
+/** A session, as `Session` in JS. */
+export declare class Session {
+  /** A session with a fresh key pair from the OS. */
+  constructor()
+  /** The SEC1 encoded public key to send the peer. */
+  publicKey(): Buffer
+  /** Keys the session with the peer's SEC1 encoded public key. */
+  setSymKey(peer: Buffer): void
+  /** Seals `plain` into a record for the peer. */
+  encrypt(plain: Buffer): Buffer
+  /**
+   * Opens a record from the peer, throwing `XC220B3_MAC_MISMATCH` if it
+   * was tampered with.
+   */
+  decrypt(record: Buffer): Buffer
+  /** `encrypt` on the thread pool. */
+  encryptAsync(plain: Buffer): Promise<Buffer>
+  /** `decrypt` on the thread pool. */
+  decryptAsync(record: Buffer): Promise<Buffer>
+}
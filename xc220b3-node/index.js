@@ -0,0 +1,2 @@
+// the addon `npm run build` copies here, see src/lib.rs for what it exports
+module.exports = require('./xc220b3.node')
@@ -0,0 +1,26 @@
+// builds the addon and copies it next to index.js as xc220b3.node, with
+// --release for an optimised one and --peer to build the native peer
+// test/ talks to as well
+const { execFileSync } = require('node:child_process')
+const { copyFileSync } = require('node:fs')
+const { join } = require('node:path')
+
+const root = join(__dirname, '..')
+const release = process.argv.includes('--release')
+const cargo = (...args) =>
+  execFileSync('cargo', ['build', '-p', 'xc220b3-node', ...(release ? ['--release'] : []), ...args], {
+    cwd: root,
+    stdio: 'inherit',
+  })
+
+cargo('--lib')
+if (process.argv.includes('--peer')) {
+  cargo('--example', 'peer')
+}
+
+const library = {
+  darwin: 'libxc220b3_node.dylib',
+  win32: 'xc220b3_node.dll',
+}[process.platform] ?? 'libxc220b3_node.so'
+const target = process.env.CARGO_TARGET_DIR ?? join(root, '..', 'target')
+copyFileSync(join(target, release ? 'release' : 'debug', library), join(root, 'xc220b3.node'))
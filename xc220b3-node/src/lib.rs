@@ -0,0 +1,133 @@
+//! Node.js bindings of the `bindings` module of xc220b3, so a Node server
+//! speaks the handshake and record format through the native session
+//! rather than a helper process per message.
+//!
+//! Buffers come in lent rather than copied: the session reads them where
+//! they are in JS memory, and what it returns becomes an external buffer
+//! over the native allocation. The `*Async` variants run on libuv's thread
+//! pool and give a promise, for payloads big enough to hold up the event
+//! loop, and a buffer lent to one mustn't change until it settles.
+//!
+//! Errors are thrown as `Error`s whose `code` is [`BindingError::code`],
+//! `XC220B3_MAC_MISMATCH` for a record that was tampered with.
+
+use std::sync::Arc;
+
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::{Env, JsError, Task};
+use napi_derive::napi;
+use xc220b3::bindings::{self, BindingError, BindingSession};
+
+// a JS error with the binding error's code as its `code`, which napi
+// throws, or rejects a promise with, as it is
+fn thrown(env: Env, e: BindingError) -> napi::Error {
+    let coded = napi::Error::new(e.code().to_string(), format!("xc220b3: {}", e.code()));
+    napi::Error::from(JsError::from(coded).into_unknown(env))
+}
+
+/// A session, as `Session` in JS.
+#[napi(js_name = "Session")]
+pub struct JsSession {
+    session: Arc<BindingSession>,
+}
+
+#[napi]
+impl JsSession {
+    /// A session with a fresh key pair from the OS.
+    #[napi(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        JsSession { session: BindingSession::new() }
+    }
+
+    /// The SEC1 encoded public key to send the peer.
+    #[napi]
+    pub fn public_key(&self, env: Env) -> napi::Result<Buffer> {
+        self.session.public_key().map(Buffer::from).map_err(|e| thrown(env, e))
+    }
+
+    /// Keys the session with the peer's SEC1 encoded public key.
+    #[napi]
+    pub fn set_sym_key(&self, env: Env, peer: Buffer) -> napi::Result<()> {
+        self.session.set_peer_key(peer.to_vec()).map_err(|e| thrown(env, e))
+    }
+
+    /// Seals `plain` into a record for the peer.
+    #[napi]
+    pub fn encrypt(&self, env: Env, plain: Buffer) -> napi::Result<Buffer> {
+        self.session.encrypt_slice(&plain).map(Buffer::from).map_err(|e| thrown(env, e))
+    }
+
+    /// Opens a record from the peer, throwing `XC220B3_MAC_MISMATCH` if it
+    /// was tampered with.
+    #[napi]
+    pub fn decrypt(&self, env: Env, record: Buffer) -> napi::Result<Buffer> {
+        self.session.decrypt_slice(&record).map(Buffer::from).map_err(|e| thrown(env, e))
+    }
+
+    /// `encrypt` on the thread pool.
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn encrypt_async(&self, plain: Buffer) -> AsyncTask<Call> {
+        AsyncTask::new(Call::Encrypt(self.session.clone(), plain))
+    }
+
+    /// `decrypt` on the thread pool.
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn decrypt_async(&self, record: Buffer) -> AsyncTask<Call> {
+        AsyncTask::new(Call::Decrypt(self.session.clone(), record))
+    }
+}
+
+/// Seals `plain` to the recipient's SEC1 encoded public key, with no
+/// session to set up.
+#[napi]
+pub fn seal(env: Env, recipient_public_key: Buffer, plain: Buffer) -> napi::Result<Buffer> {
+    bindings::seal_slice(&recipient_public_key, &plain).map(Buffer::from).map_err(|e| thrown(env, e))
+}
+
+/// Opens what `seal` sealed, with the recipient's 32-byte big-endian
+/// secret key.
+#[napi]
+pub fn open(env: Env, recipient_secret_key: Buffer, sealed: Buffer) -> napi::Result<Buffer> {
+    bindings::open_slice(&recipient_secret_key, &sealed).map(Buffer::from).map_err(|e| thrown(env, e))
+}
+
+/// `seal` on the thread pool.
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn seal_async(recipient_public_key: Buffer, plain: Buffer) -> AsyncTask<Call> {
+    AsyncTask::new(Call::Seal(recipient_public_key, plain))
+}
+
+/// `open` on the thread pool.
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn open_async(recipient_secret_key: Buffer, sealed: Buffer) -> AsyncTask<Call> {
+    AsyncTask::new(Call::Open(recipient_secret_key, sealed))
+}
+
+/// A call run on the thread pool, with the buffers lent to it.
+pub enum Call {
+    Encrypt(Arc<BindingSession>, Buffer),
+    Decrypt(Arc<BindingSession>, Buffer),
+    Seal(Buffer, Buffer),
+    Open(Buffer, Buffer),
+}
+
+impl Task for Call {
+    // the error is made a JS one back on the main thread, where there's an
+    // env to make it in
+    type Output = Result<Vec<u8>, BindingError>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        Ok(match self {
+            Call::Encrypt(session, plain) => session.encrypt_slice(plain),
+            Call::Decrypt(session, record) => session.decrypt_slice(record),
+            Call::Seal(recipient, plain) => bindings::seal_slice(recipient, plain),
+            Call::Open(recipient, sealed) => bindings::open_slice(recipient, sealed),
+        })
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> napi::Result<Buffer> {
+        output.map(Buffer::from).map_err(|e| thrown(env, e))
+    }
+}
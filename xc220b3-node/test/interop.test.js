@@ -0,0 +1,102 @@
+// a JS session against a native one (examples/peer.rs), over the record
+// format and the sealed one. `npm test` builds both first
+const { describe, it, before, after } = require('node:test')
+const assert = require('node:assert/strict')
+const { spawn } = require('node:child_process')
+const { join } = require('node:path')
+const { createInterface } = require('node:readline')
+
+const { Session, seal, open, sealAsync, openAsync } = require('..')
+
+const target = process.env.CARGO_TARGET_DIR ?? join(__dirname, '..', '..', 'target')
+
+// the peer answers every line with one, in order
+function startPeer() {
+  const child = spawn(join(target, 'debug', 'examples', process.platform === 'win32' ? 'peer.exe' : 'peer'))
+  const waiting = []
+  createInterface({ input: child.stdout }).on('line', (line) => waiting.shift()(line))
+  const next = () => new Promise((resolve) => waiting.push(resolve))
+  const ask = (kind, bytes) => {
+    const answer = next()
+    child.stdin.write(`${kind} ${bytes.toString('hex')}\n`)
+    return answer.then((line) => {
+      const [answered, rest] = line.split(' ')
+      if (answered === 'error') throw new Error(rest)
+      assert.equal(answered, kind)
+      return Buffer.from(rest, 'hex')
+    })
+  }
+  return { child, next, ask }
+}
+
+describe('a JS session and a native one', () => {
+  let peer, session, recipient
+
+  before(async () => {
+    peer = startPeer()
+    const [publicKey, recipientPublicKey, recipientSecretKey] = await Promise.all([peer.next(), peer.next(), peer.next()])
+    recipient = { publicKey: Buffer.from(recipientPublicKey, 'hex'), secretKey: Buffer.from(recipientSecretKey, 'hex') }
+    session = new Session()
+    peer.child.stdin.write(`${session.publicKey().toString('hex')}\n`)
+    session.setSymKey(Buffer.from(publicKey, 'hex'))
+  })
+
+  after(() => peer.child.stdin.end())
+
+  it('exchange records', async () => {
+    for (const message of ['hello', '', 'x'.repeat(70000)]) {
+      const answer = await peer.ask('record', session.encrypt(Buffer.from(message)))
+      assert.equal(session.decrypt(answer).toString(), message)
+    }
+  })
+
+  it('refuse a tampered record with a coded error', async () => {
+    const answer = await peer.ask('record', session.encrypt(Buffer.from('again')))
+    const tampered = Buffer.from(answer)
+    tampered[1] ^= 1
+    assert.throws(() => session.decrypt(tampered), { code: 'XC220B3_MAC_MISMATCH' })
+    assert.equal(session.decrypt(answer).toString(), 'again')
+  })
+
+  it('exchange records off the event loop', async () => {
+    const message = Buffer.alloc(8 << 20, 7)
+    let ticked = false
+    setImmediate(() => (ticked = true))
+    const record = await session.encryptAsync(message)
+    assert.ok(ticked, 'encryptAsync held up the event loop')
+    const answer = await peer.ask('record', record)
+    const tampered = Buffer.from(answer)
+    tampered[1] ^= 1
+    await assert.rejects(session.decryptAsync(tampered), { code: 'XC220B3_MAC_MISMATCH' })
+    assert.ok((await session.decryptAsync(answer)).equals(message))
+  })
+
+  it('seal and open one-shot messages', async () => {
+    const answer = await peer.ask('sealed', seal(recipient.publicKey, Buffer.from('sealed')))
+    assert.equal(open(recipient.secretKey, answer).toString(), 'sealed')
+    const again = await peer.ask('sealed', await sealAsync(recipient.publicKey, Buffer.from('again')))
+    assert.equal((await openAsync(recipient.secretKey, again)).toString(), 'again')
+    await assert.rejects(openAsync(Buffer.alloc(32), again), { code: 'XC220B3_INVALID_SECRET_KEY' })
+  })
+})
+
+describe('a JS session', () => {
+  it('is keyed once', () => {
+    const session = new Session()
+    assert.throws(() => session.encrypt(Buffer.from('early')), { code: 'XC220B3_NOT_READY' })
+    assert.throws(() => session.setSymKey(Buffer.alloc(5, 2)), { code: 'XC220B3_INVALID_PUBLIC_KEY' })
+    session.setSymKey(new Session().publicKey())
+    assert.throws(() => session.publicKey(), { code: 'XC220B3_KEY_SPENT' })
+  })
+})
+
+describe('index.d.ts', () => {
+  it('declares what the addon exports', () => {
+    const addon = require('..')
+    const declarations = require('node:fs').readFileSync(join(__dirname, '..', 'index.d.ts'), 'utf8')
+    const declared = (pattern) => [...declarations.matchAll(pattern)].map((match) => match[1]).sort()
+    assert.deepEqual(declared(/^export declare (?:function|class) (\w+)/gm), Object.keys(addon).sort())
+    const methods = Object.getOwnPropertyNames(Session.prototype).filter((name) => name !== 'constructor')
+    assert.deepEqual(declared(/^  (?!constructor)(\w+)\(/gm), methods.sort())
+  })
+})
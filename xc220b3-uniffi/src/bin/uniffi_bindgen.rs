@@ -0,0 +1,6 @@
+// uniffi-bindgen for the Swift and Kotlin bindings, see the crate docs for
+// how it's run
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
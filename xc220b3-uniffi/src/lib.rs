@@ -0,0 +1,17 @@
+//! The cdylib the Swift and Kotlin bindings in `bindings/` load. The
+//! exports themselves are the `bindings` module of xc220b3 with its `uniffi`
+//! feature, this only links them into a library of their own.
+//!
+//! The bindings are generated from it with
+//!
+//! ```text
+//! cargo build -p xc220b3-uniffi
+//! cargo run -p xc220b3-uniffi --bin uniffi-bindgen -- generate --no-format \
+//!     --library target/debug/libxc220b3_uniffi.so --language swift \
+//!     --out-dir xc220b3-uniffi/bindings/swift
+//! ```
+//!
+//! and the same with `--language kotlin`, `tests/exported.rs` fails when
+//! the committed ones are stale.
+
+xc220b3::uniffi_reexport_scaffolding!();
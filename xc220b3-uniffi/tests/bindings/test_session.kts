@@ -0,0 +1,31 @@
+import uniffi.xc220b3.*
+
+val alice = BindingSession()
+val bob = BindingSession()
+val alicePk = alice.publicKey()
+val bobPk = bob.publicKey()
+alice.setPeerKey(bobPk)
+bob.setPeerKey(alicePk)
+
+assert(bob.decrypt(alice.encrypt("hello".toByteArray())).contentEquals("hello".toByteArray()))
+assert(alice.decrypt(bob.encrypt("back".toByteArray())).contentEquals("back".toByteArray()))
+
+val tampered = alice.encrypt("again".toByteArray())
+tampered[1] = (tampered[1].toInt() xor 1).toByte()
+try {
+    bob.decrypt(tampered)
+    throw AssertionError("a tampered record opened")
+} catch (e: BindingException.MacMismatch) {
+}
+
+try {
+    alice.publicKey()
+    throw AssertionError("a spent key pair was exported")
+} catch (e: BindingException.KeySpent) {
+}
+
+try {
+    open(ByteArray(32), seal(bobPk, "sealed".toByteArray()))
+    throw AssertionError("a zero secret key was taken")
+} catch (e: BindingException.InvalidSecretKey) {
+}
@@ -0,0 +1,30 @@
+from xc220b3 import *
+
+alice = BindingSession()
+bob = BindingSession()
+alice_pk, bob_pk = alice.public_key(), bob.public_key()
+alice.set_peer_key(bob_pk)
+bob.set_peer_key(alice_pk)
+
+assert bob.decrypt(alice.encrypt(b"hello")) == b"hello"
+assert alice.decrypt(bob.encrypt(b"back")) == b"back"
+
+tampered = bytearray(alice.encrypt(b"again"))
+tampered[1] ^= 1
+try:
+    bob.decrypt(bytes(tampered))
+    raise AssertionError("a tampered record opened")
+except BindingError.MacMismatch:
+    pass
+
+try:
+    alice.public_key()
+    raise AssertionError("a spent key pair was exported")
+except BindingError.KeySpent:
+    pass
+
+try:
+    open(bytes(32), seal(bob_pk, b"sealed"))
+    raise AssertionError("a zero secret key was taken")
+except BindingError.InvalidSecretKey:
+    pass
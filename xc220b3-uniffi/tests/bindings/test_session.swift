@@ -0,0 +1,32 @@
+import xc220b3
+import Foundation
+
+let alice = BindingSession()
+let bob = BindingSession()
+let alicePk = try! alice.publicKey()
+let bobPk = try! bob.publicKey()
+try! alice.setPeerKey(peer: bobPk)
+try! bob.setPeerKey(peer: alicePk)
+
+assert(try! bob.decrypt(record: alice.encrypt(plain: Data("hello".utf8))) == Data("hello".utf8))
+assert(try! alice.decrypt(record: bob.encrypt(plain: Data("back".utf8))) == Data("back".utf8))
+
+var tampered = try! alice.encrypt(plain: Data("again".utf8))
+tampered[1] ^= 1
+do {
+    _ = try bob.decrypt(record: tampered)
+    fatalError("a tampered record opened")
+} catch BindingError.MacMismatch {
+}
+
+do {
+    _ = try alice.publicKey()
+    fatalError("a spent key pair was exported")
+} catch BindingError.KeySpent {
+}
+
+do {
+    _ = try open(recipientSecretKey: Data(count: 32), sealed: seal(recipientPublicKey: bobPk, plain: Data("sealed".utf8)))
+    fatalError("a zero secret key was taken")
+} catch BindingError.InvalidSecretKey {
+}
@@ -0,0 +1,91 @@
+// what the cdylib exports, read back from its UniFFI metadata: nothing but
+// the bindings module, each export one call of the native API, and the
+// committed Swift and Kotlin bindings generated from exactly that
+
+use camino::{Utf8Path, Utf8PathBuf};
+use uniffi_bindgen::bindings::{KotlinBindingGenerator, SwiftBindingGenerator};
+use uniffi_bindgen::{library_mode, ComponentInterface, EmptyCrateConfigSupplier};
+
+use rand_core::OsRng;
+use xc220b3::ecies;
+use xc220b3::{EncodedPoint, PublicKey, SecretKey, Session, SessionError};
+
+// every export and the native function it calls
+const EXPORTS: &[(&str, &str)] = &[
+    ("BindingSession::new", "Session::new"),
+    ("BindingSession::public_key", "Session::pk"),
+    ("BindingSession::set_peer_key", "Session::set_sym_key"),
+    ("BindingSession::encrypt", "Session::encrypt"),
+    ("BindingSession::decrypt", "Session::decrypt"),
+    ("seal", "ecies::seal"),
+    ("open", "ecies::open"),
+];
+
+// the native side of EXPORTS, which stops compiling when one of them goes
+// away or changes shape under its binding
+#[allow(dead_code)]
+fn natives() {
+    let _: fn(&mut OsRng) -> Session = Session::new;
+    let _: fn(&Session) -> Result<EncodedPoint, SessionError> = Session::pk;
+    let _: fn(&mut Session, &EncodedPoint) -> Result<(), SessionError> = Session::set_sym_key;
+    let _: fn(&mut Session, Vec<u8>) -> Result<Vec<u8>, SessionError> = Session::encrypt;
+    let _: fn(&mut Session, Vec<u8>) -> Result<Vec<u8>, SessionError> = Session::decrypt;
+    let _: fn(&PublicKey, &[u8], &mut OsRng) -> Vec<u8> = ecies::seal;
+    let _: fn(&SecretKey, &[u8]) -> Result<Vec<u8>, SessionError> = ecies::open;
+}
+
+fn cdylib() -> Utf8PathBuf {
+    // the integration test is built next to the cdylib it links against
+    let exe = std::env::current_exe().unwrap();
+    let name = format!("{}xc220b3_uniffi{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX);
+    Utf8PathBuf::try_from(exe.parent().unwrap().join(name)).unwrap()
+}
+
+fn interface() -> ComponentInterface {
+    let mut components = library_mode::find_components(&cdylib(), &EmptyCrateConfigSupplier).unwrap();
+    assert_eq!(components.len(), 1);
+    components.remove(0).ci
+}
+
+#[test]
+fn test_exports_are_the_native_api() {
+    let ci = interface();
+    assert_eq!(ci.namespace(), "xc220b3");
+    let mut exported: Vec<String> = ci.function_definitions().iter().map(|f| f.name().to_string()).collect();
+    for object in ci.object_definitions() {
+        let calls = object.constructors().into_iter().map(|c| c.name()).chain(object.methods().into_iter().map(|m| m.name()));
+        exported.extend(calls.map(|call| format!("{}::{}", object.name(), call)));
+    }
+    exported.sort();
+    let mut expected: Vec<String> = EXPORTS.iter().map(|(export, _)| export.to_string()).collect();
+    expected.sort();
+    assert_eq!(exported, expected);
+    assert_eq!(ci.enum_definitions().map(|e| e.name()).collect::<Vec<_>>(), ["BindingError"]);
+    assert!(ci.is_name_used_as_error("BindingError"));
+}
+
+fn generate(out: &Utf8Path, language: &str) {
+    let (library, config) = (cdylib(), &EmptyCrateConfigSupplier);
+    match language {
+        "kotlin" => drop(library_mode::generate_bindings(&library, None, &KotlinBindingGenerator, config, None, out, false).unwrap()),
+        _ => drop(library_mode::generate_bindings(&library, None, &SwiftBindingGenerator, config, None, out, false).unwrap()),
+    }
+}
+
+#[test]
+fn test_committed_bindings_are_current() {
+    let root = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("bindings");
+    for (language, files) in [
+        ("kotlin", &["uniffi/xc220b3/xc220b3.kt"][..]),
+        ("swift", &["xc220b3.swift", "xc220b3FFI.h", "xc220b3FFI.modulemap"][..]),
+    ] {
+        let out = Utf8PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(language);
+        let _ = std::fs::remove_dir_all(&out);
+        generate(&out, language);
+        for file in files {
+            let fresh = std::fs::read_to_string(out.join(file)).unwrap();
+            let committed = std::fs::read_to_string(root.join(language).join(file)).unwrap();
+            assert!(fresh == committed, "bindings/{language}/{file} is stale, regenerate it as the crate docs say");
+        }
+    }
+}
@@ -0,0 +1,10 @@
+// the generated bindings against the cdylib, each script keying a session
+// with another and checking MacMismatch is its own exception. the
+// foreign-tests feature, with UNIFFI_TESTS_DISABLE_EXTENSIONS=kts,swift set
+// when it's built to leave out the languages there's no compiler for
+
+uniffi::build_foreign_language_testcases!(
+    "tests/bindings/test_session.kts",
+    "tests/bindings/test_session.swift",
+    "tests/bindings/test_session.py",
+);
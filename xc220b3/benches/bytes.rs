@@ -0,0 +1,27 @@
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rand::{thread_rng, RngCore};
+use xc220b3::Session;
+
+const RECORD_LEN: usize = 64 * 1024;
+
+// a network stack that hands over and takes back Bytes, going through the
+// Vec API the way callers had to, or through the Bytes one
+fn records(c: &mut Criterion) {
+    let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+    let mut plain = vec![0u8; RECORD_LEN];
+    thread_rng().fill_bytes(&mut plain);
+    let plain = Bytes::from(plain);
+    let record = sesh1.encrypt_bytes(plain.clone()).unwrap();
+
+    let mut group = c.benchmark_group("records_64k");
+    group.throughput(Throughput::Bytes(RECORD_LEN as u64));
+    group.bench_function("encrypt_vec", |b| b.iter(|| Bytes::from(sesh1.encrypt(plain.to_vec()).unwrap())));
+    group.bench_function("encrypt_bytes", |b| b.iter(|| sesh1.encrypt_bytes(plain.clone()).unwrap()));
+    group.bench_function("decrypt_vec", |b| b.iter(|| Bytes::from(sesh2.decrypt(record.to_vec()).unwrap())));
+    group.bench_function("decrypt_bytes", |b| b.iter(|| sesh2.decrypt_bytes(record.clone()).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, records);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rand::thread_rng;
+use xc220b3::handshake::{HandshakeConfig, HandshakePool};
+use xc220b3::{verify_certificate_chain, Certificate, SecretKey, Session};
+
+const BATCH: usize = 64;
+
+// the handshake's parts, one at a time
+fn components(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handshake");
+    group.bench_function("keygen", |b| b.iter(|| Session::new(&mut thread_rng())));
+
+    let peer = Session::new(&mut thread_rng()).pk().unwrap();
+    group.bench_function("ecdh", |b| {
+        b.iter_batched(|| Session::new(&mut thread_rng()), |mut session| session.set_sym_key(&peer).unwrap(), BatchSize::SmallInput)
+    });
+
+    let root = SecretKey::random(&mut thread_rng());
+    let subject = SecretKey::random(&mut thread_rng()).public_key();
+    let chain = [Certificate::issue(&root, &subject)];
+    group.bench_function("verify_certificate", |b| b.iter(|| verify_certificate_chain(&chain, &root.public_key()).unwrap()));
+    group.finish();
+}
+
+// answering a client's hello, with the key pair generated then or taken
+// from a pool filled beforehand
+fn accept(c: &mut Criterion) {
+    let hello = Session::new(&mut thread_rng()).initiate().unwrap();
+    let hellos = vec![hello.clone(); BATCH];
+    let prepared = |count| {
+        let mut pool = HandshakePool::new(HandshakeConfig::default());
+        pool.prepare(count, &mut thread_rng());
+        pool
+    };
+
+    let mut group = c.benchmark_group("accept");
+    let mut empty = prepared(0);
+    group.bench_function("fresh", |b| b.iter(|| empty.respond(&hello).unwrap()));
+    group.bench_function("prepared", |b| {
+        b.iter_batched(|| prepared(1), |mut pool| pool.respond(&hello).unwrap(), BatchSize::SmallInput)
+    });
+    group.bench_function("batch_64", |b| {
+        b.iter_batched(|| prepared(BATCH), |mut pool| pool.respond_batch(&hellos), BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+criterion_group!(benches, components, accept);
+criterion_main!(benches);
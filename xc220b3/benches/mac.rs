@@ -0,0 +1,35 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use xc220b3::{set_parallel_mac_threshold, RecordCipher, XChaCha20Blake3};
+
+const LENS: [usize; 7] = [16 << 10, 64 << 10, 128 << 10, 256 << 10, 1 << 20, 4 << 20, 64 << 20];
+
+// the same seal with every MAC on the calling thread and with every MAC
+// across the pool, the crossover being the length where the second pulls
+// ahead. sealing hashes the plaintext once and the keystream is the same
+// either way, so the difference is all the MAC's
+fn mac_threads(c: &mut Criterion) {
+    let key = [7u8; 32];
+    let mut group = c.benchmark_group("seal");
+    for len in LENS {
+        let plain = vec![0x5a; len];
+        let mut out = Vec::with_capacity(len + XChaCha20Blake3.overhead());
+        group.throughput(Throughput::Bytes(len as u64));
+        // a snapshot record takes long enough that a few samples do
+        if len >= 64 << 20 {
+            group.sample_size(10);
+        }
+        for (name, threshold) in [("one_thread", usize::MAX), ("rayon", 0)] {
+            group.bench_with_input(BenchmarkId::new(name, len), &plain, |b, plain| {
+                set_parallel_mac_threshold(threshold);
+                b.iter(|| {
+                    out.clear();
+                    XChaCha20Blake3.seal(&key, &[plain], b"", &mut out);
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mac_threads);
+criterion_main!(benches);
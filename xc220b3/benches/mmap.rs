@@ -0,0 +1,52 @@
+use std::{fs::{self, File}, io::{BufReader, BufWriter, Read, Write}, path::Path};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{thread_rng, RngCore};
+use xc220b3::{Session, STREAM_CHUNK_LEN};
+
+// the std::io path the mmap one replaces: read into a buffer, feed the
+// stream, write out
+fn encrypt_file_buffered(session: &mut Session, src: &Path, dst: &Path) {
+    let mut src = BufReader::new(File::open(src).unwrap());
+    let mut dst = BufWriter::new(File::create(dst).unwrap());
+    let mut buf = vec![0u8; 64 * STREAM_CHUNK_LEN];
+
+    let mut encryptor = session.stream_encryptor();
+    loop {
+        let n = src.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&encryptor.update(&buf[..n]).unwrap()).unwrap();
+    }
+    dst.write_all(&encryptor.finish().unwrap()).unwrap();
+}
+
+fn file_encryption(c: &mut Criterion) {
+    let dir = std::env::temp_dir();
+    let (src, dst) = (dir.join("xc220b3-bench-src"), dir.join("xc220b3-bench-dst"));
+    let (mut session, _) = Session::pair(&mut thread_rng());
+
+    let mut group = c.benchmark_group("encrypt_file");
+    group.sample_size(10);
+    for mib in [16usize, 256] {
+        let mut data = vec![0u8; mib * 1024 * 1024];
+        thread_rng().fill_bytes(&mut data);
+        fs::write(&src, &data).unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("buffered", mib), &mib, |b, _| {
+            b.iter(|| encrypt_file_buffered(&mut session, &src, &dst))
+        });
+        group.bench_with_input(BenchmarkId::new("mmap", mib), &mib, |b, _| {
+            b.iter(|| session.encrypt_file_mmap(&src, &dst).unwrap())
+        });
+    }
+    group.finish();
+
+    let _ = fs::remove_file(src);
+    let _ = fs::remove_file(dst);
+}
+
+criterion_group!(benches, file_encryption);
+criterion_main!(benches);
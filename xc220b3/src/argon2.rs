@@ -0,0 +1,145 @@
+// Session::from_password over the argon2 crate's Argon2id (RFC 9106):
+//
+//   shared = Argon2id(password, salt)
+//
+// and the two direction keys from `shared` as a key exchange's are, with
+// PASSWORD_CONTEXT as the transcript, so each end needs its role. the RFC's
+// test vector is checked below, which pins the variant and version
+
+use ::argon2::{Algorithm, Argon2, Params, Version};
+use zeroize::Zeroizing;
+
+use crate::builder::ConfigError;
+use crate::session::{Role, Session};
+
+const PASSWORD_CONTEXT: &[u8] = b"xc220b3 2022-10 password session key";
+
+const ALGORITHM: Algorithm = Algorithm::Argon2id;
+const VERSION: Version = Version::V0x13;
+
+/// Shortest salt [`Session::from_password`] takes, RFC 9106's minimum.
+pub const MIN_SALT_LEN: usize = 8;
+
+/// Argon2id's costs for [`Session::from_password`]. The default is RFC
+/// 9106's second recommendation, 64 MiB over 3 passes and 4 lanes, for
+/// when the first's 2 GiB is too much.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PasswordConfig {
+    /// Memory to fill, in KiB. At least 8 per lane.
+    pub memory_kib: u32,
+    /// Passes over the memory, at least 1.
+    pub iterations: u32,
+    /// Lanes (Argon2's parallelism), 1 to 2^24 - 1. Both ends must agree on
+    /// it, it changes the key.
+    pub lanes: u32,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        PasswordConfig { memory_kib: 64 * 1024, iterations: 3, lanes: 4 }
+    }
+}
+
+impl PasswordConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.params().map(|_| ())
+    }
+
+    fn params(&self) -> Result<Params, ConfigError> {
+        Params::new(self.memory_kib, self.iterations, self.lanes, Some(32)).map_err(|_| ConfigError::InvalidPasswordConfig(*self))
+    }
+}
+
+impl Session {
+    /// A ready session keyed from `password` and `salt` by Argon2id with
+    /// `config`'s costs, rather than by a key exchange (`argon2` feature).
+    /// Both ends must use the same password, salt and config, and one of
+    /// each `role`: each direction has its own key, and the initiator's send
+    /// key is the responder's receive key, so neither end accepts its own
+    /// records reflected back. The salt should be random and unique to the
+    /// pair, can be sent in the clear, and is at least [`MIN_SALT_LEN`]
+    /// bytes, or this is [`ConfigError::InvalidPasswordConfig`].
+    pub fn from_password(password: &[u8], salt: &[u8], role: Role, config: &PasswordConfig) -> Result<Session, ConfigError> {
+        let params = config.params()?;
+        if salt.len() < MIN_SALT_LEN {
+            return Err(ConfigError::InvalidPasswordConfig(*config));
+        }
+        let mut shared = Zeroizing::new([0u8; 32]);
+        Argon2::new(ALGORITHM, VERSION, params)
+            .hash_password_into(password, salt, &mut shared[..])
+            .map_err(|_| ConfigError::InvalidPasswordConfig(*config))?;
+        let mut session = Session::from_secret(None);
+        session.install_keys(role, &shared[..], PASSWORD_CONTEXT);
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::argon2::{Argon2, AssociatedData, ParamsBuilder};
+
+    use crate::argon2::{PasswordConfig, ALGORITHM, VERSION};
+    use crate::builder::ConfigError;
+    use crate::session::{Role, Session, SessionError};
+
+    const CHEAP: PasswordConfig = PasswordConfig { memory_kib: 64, iterations: 2, lanes: 2 };
+    const SALT: &[u8] = b"salt for the pair";
+
+    #[test]
+    fn test_rfc_9106_vector() {
+        let params = ParamsBuilder::new()
+            .m_cost(32)
+            .t_cost(3)
+            .p_cost(4)
+            .data(AssociatedData::new(&[4; 12]).unwrap())
+            .output_len(32)
+            .build()
+            .unwrap();
+        let mut tag = [0u8; 32];
+        Argon2::new_with_secret(&[3; 8], ALGORITHM, VERSION, params).unwrap().hash_password_into(&[1; 32], &[2; 16], &mut tag).unwrap();
+        let expected = [
+            0x0d, 0x64, 0x0d, 0xf5, 0x8d, 0x78, 0x76, 0x6c, 0x08, 0xc0, 0x37, 0xa3, 0x4a, 0x8b, 0x53, 0xc9, 0xd0, 0x1e, 0xf0,
+            0x45, 0x2d, 0x75, 0xb6, 0x5e, 0xb5, 0x25, 0x20, 0xe9, 0x6b, 0x01, 0xe6, 0x59,
+        ];
+        assert_eq!(tag, expected);
+    }
+
+    #[test]
+    fn test_password_sessions() {
+        let mut sesh1 = Session::from_password(b"hunter2", SALT, Role::Initiator, &CHEAP).unwrap();
+        let mut sesh2 = Session::from_password(b"hunter2", SALT, Role::Responder, &CHEAP).unwrap();
+        assert_eq!((sesh1.send_key, sesh1.recv_key), (sesh2.recv_key, sesh2.send_key));
+        assert_ne!(sesh1.send_key, sesh1.recv_key);
+        assert_eq!(sesh2.decrypt(sesh1.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+        assert_eq!(sesh1.decrypt(sesh2.encrypt(b"hi back".to_vec()).unwrap()).unwrap(), b"hi back");
+
+        // the salt and the costs are part of the key
+        let other_salt = Session::from_password(b"hunter2", b"another salt", Role::Responder, &CHEAP).unwrap();
+        let other_costs = Session::from_password(b"hunter2", SALT, Role::Responder, &PasswordConfig { iterations: 3, ..CHEAP }).unwrap();
+        for mut other in [other_salt, other_costs] {
+            assert!(other.decrypt(sesh1.encrypt(b"hello".to_vec()).unwrap()).is_err());
+        }
+
+        let bad = PasswordConfig { memory_kib: 15, ..CHEAP };
+        assert!(matches!(Session::from_password(b"hunter2", SALT, Role::Initiator, &bad), Err(ConfigError::InvalidPasswordConfig(config)) if config == bad));
+    }
+
+    #[test]
+    fn test_reflected_records_are_refused() {
+        let mut sesh1 = Session::from_password(b"hunter2", SALT, Role::Initiator, &CHEAP).unwrap();
+        let mut same_role = Session::from_password(b"hunter2", SALT, Role::Initiator, &CHEAP).unwrap();
+
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(sesh1.decrypt(record.clone()), Err(SessionError::MacMismatch)));
+        assert!(matches!(same_role.decrypt(record), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_short_salts_are_refused() {
+        for salt in [&b""[..], b"salt", b"7 bytes"] {
+            let refused = Session::from_password(b"hunter2", salt, Role::Initiator, &CHEAP);
+            assert!(matches!(refused, Err(ConfigError::InvalidPasswordConfig(config)) if config == CHEAP));
+        }
+        assert!(Session::from_password(b"hunter2", b"8 bytes!", Role::Initiator, &CHEAP).is_ok());
+    }
+}
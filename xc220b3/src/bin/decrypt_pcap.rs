@@ -0,0 +1,82 @@
+// decrypts captured records with the keys a dangerous-debug build logged:
+//
+//   xc220b3-decrypt-pcap <keylog> <capture>
+//
+// the capture is one direction of a connection's byte stream, as pulled out
+// of a pcap by a tool like tshark's `follow,tcp,raw`, made of frames as
+// framing::write_frame writes them. every keylog line is tried both ways on
+// the first frame, and the first that opens it decrypts the rest. records
+// are assumed to use the default record configuration
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::process::ExitCode;
+
+use xc220b3::framing::read_frame;
+use xc220b3::{DebugKeys, Session};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <keylog> <capture>", args[0]);
+        return ExitCode::FAILURE;
+    }
+    match run(&args[1], &args[2]) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(keylog: &str, capture: &str) -> Result<(), String> {
+    let keylog = fs::read_to_string(keylog).map_err(|e| format!("reading keylog: {}", e))?;
+    let frames = read_frames(capture).map_err(|e| format!("reading capture: {}", e))?;
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+
+    let candidates = keylog.lines().filter_map(DebugKeys::from_keylog_line).flat_map(|keys| {
+        let swapped = DebugKeys { send_key: keys.recv_key, recv_key: keys.send_key, ..keys };
+        [Session::from_debug_keys(&keys), Session::from_debug_keys(&swapped)]
+    });
+    let mut session = candidates
+        .into_iter()
+        .find(|session| session.clone().decrypt(first.clone()).is_ok())
+        .ok_or("no keylog line opens the first record")?;
+
+    for (i, frame) in frames.into_iter().enumerate() {
+        match session.receive(frame) {
+            Ok(received) => println!("{}: {:?}", i, Printable(received)),
+            Err(e) => println!("{}: refused, {:?}", i, e),
+        }
+    }
+    Ok(())
+}
+
+fn read_frames(path: &str) -> io::Result<Vec<Vec<u8>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut frames = Vec::new();
+    loop {
+        match read_frame(&mut reader) {
+            Ok(frame) => frames.push(frame),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(frames),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// plaintext as text where it is, escaped where it isn't
+struct Printable(xc220b3::Received);
+
+impl std::fmt::Debug for Printable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            xc220b3::Received::Data(plain) => write!(f, "\"{}\"", plain.escape_ascii()),
+            xc220b3::Received::Heartbeat => write!(f, "heartbeat"),
+            xc220b3::Received::Rekeyed => write!(f, "rekeyed"),
+            xc220b3::Received::Closed(reason) => write!(f, "closed, {:?}", reason),
+        }
+    }
+}
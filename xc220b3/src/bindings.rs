@@ -0,0 +1,326 @@
+//! The surface foreign-language bindings (Swift and Kotlin via UniFFI,
+//! Node via napi-rs) are generated from, so other runtimes speak the
+//! handshake and record format of this crate rather than a reimplementation
+//! of it.
+//!
+//! Everything here is shaped for a binding generator: objects are shared
+//! behind an `Arc` and take `&self`, arguments and results are owned bytes,
+//! and errors are one [`BindingError`] with
+//! [`MacMismatch`](BindingError::MacMismatch) a case of its own, so it's a
+//! distinct exception on both platforms. It only calls the native API,
+//! nothing is implemented twice.
+//!
+//! With the `uniffi` feature these are exported through UniFFI's proc
+//! macros. The `xc220b3-uniffi` crate builds them into the cdylib the Swift
+//! and Kotlin bindings are generated from, and is where those bindings and
+//! their tests live. The `xc220b3-node` crate exports them to Node with
+//! napi-rs, using the `_slice` variants to read its buffers in place.
+
+use std::sync::{Arc, Mutex};
+
+use k256::{EncodedPoint, PublicKey, SecretKey};
+use rand_core::OsRng;
+use zeroize::Zeroizing;
+
+use crate::ecies;
+use crate::session::{Session, SessionError};
+
+/// What a binding throws. Native errors a binding has nothing to do with
+/// but report are [`Other`](BindingError::Other) with a stable code, never
+/// how the native error prints.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error))]
+pub enum BindingError {
+    /// [`SessionError::MacMismatch`], the record was tampered with or isn't
+    /// for this session.
+    MacMismatch,
+    /// The peer's public key isn't a valid SEC1 point.
+    InvalidPublicKey,
+    /// [`SessionError::WeakSharedSecret`].
+    WeakSharedSecret,
+    /// [`SessionError::NotReady`].
+    NotReady,
+    /// [`SessionError::AlreadyReady`].
+    AlreadyReady,
+    /// [`SessionError::EmptySecret`], the session's key pair was used up
+    /// keying it.
+    KeySpent,
+    /// A secret key that isn't a valid secp256k1 scalar.
+    InvalidSecretKey,
+    /// Not a record of this format: the wrong length, an unknown version,
+    /// or trailing bytes.
+    InvalidRecord,
+    /// Anything else, by its code, see [`BindingError::code`].
+    Other { code: String },
+}
+
+impl BindingError {
+    /// A stable code for the error, for runtimes whose errors carry one
+    /// (Node's `error.code`) rather than a type.
+    pub fn code(&self) -> &str {
+        match self {
+            BindingError::MacMismatch => "XC220B3_MAC_MISMATCH",
+            BindingError::InvalidPublicKey => "XC220B3_INVALID_PUBLIC_KEY",
+            BindingError::WeakSharedSecret => "XC220B3_WEAK_SHARED_SECRET",
+            BindingError::NotReady => "XC220B3_NOT_READY",
+            BindingError::AlreadyReady => "XC220B3_ALREADY_READY",
+            BindingError::KeySpent => "XC220B3_KEY_SPENT",
+            BindingError::InvalidSecretKey => "XC220B3_INVALID_SECRET_KEY",
+            BindingError::InvalidRecord => "XC220B3_INVALID_RECORD",
+            BindingError::Other { code } => code,
+        }
+    }
+}
+
+impl core::fmt::Display for BindingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl std::error::Error for BindingError {}
+
+impl From<SessionError> for BindingError {
+    fn from(e: SessionError) -> Self {
+        match e {
+            SessionError::MacMismatch => BindingError::MacMismatch,
+            SessionError::InvalidPubKey => BindingError::InvalidPublicKey,
+            SessionError::WeakSharedSecret => BindingError::WeakSharedSecret,
+            SessionError::NotReady => BindingError::NotReady,
+            SessionError::AlreadyReady => BindingError::AlreadyReady,
+            SessionError::EmptySecret => BindingError::KeySpent,
+            SessionError::InvalidSecret | SessionError::InvalidKeyLength(_) => BindingError::InvalidSecretKey,
+            SessionError::InvalidLength(_)
+            | SessionError::UnsupportedVersion(_)
+            | SessionError::TrailingData(_)
+            | SessionError::NonceMismatch => BindingError::InvalidRecord,
+            e => BindingError::Other { code: other_code(&e).to_string() },
+        }
+    }
+}
+
+// every native error has its code here, so a new one has to be given one
+// before it can reach a binding
+fn other_code(e: &SessionError) -> &'static str {
+    match e {
+        SessionError::MacMismatch => "XC220B3_MAC_MISMATCH",
+        SessionError::NonceMismatch => "XC220B3_NONCE_MISMATCH",
+        SessionError::InvalidLength(_) => "XC220B3_INVALID_LENGTH",
+        SessionError::BufferTooSmall(_) => "XC220B3_BUFFER_TOO_SMALL",
+        SessionError::UnsupportedVersion(_) => "XC220B3_UNSUPPORTED_VERSION",
+        SessionError::InvalidPubKey => "XC220B3_INVALID_PUBLIC_KEY",
+        SessionError::WeakSharedSecret => "XC220B3_WEAK_SHARED_SECRET",
+        SessionError::InvalidCertificate(_) => "XC220B3_INVALID_CERTIFICATE",
+        SessionError::TooManySkipped(_) => "XC220B3_TOO_MANY_SKIPPED",
+        SessionError::InvalidSignature => "XC220B3_INVALID_SIGNATURE",
+        SessionError::UnexpectedSender => "XC220B3_UNEXPECTED_SENDER",
+        SessionError::UntrustedIdentity => "XC220B3_UNTRUSTED_IDENTITY",
+        SessionError::ProviderFailure(_) => "XC220B3_PROVIDER_FAILURE",
+        SessionError::InvalidSecret => "XC220B3_INVALID_SECRET_KEY",
+        SessionError::EmptySecret => "XC220B3_KEY_SPENT",
+        SessionError::NotReady => "XC220B3_NOT_READY",
+        SessionError::AlreadyReady => "XC220B3_ALREADY_READY",
+        SessionError::StaleEpoch => "XC220B3_STALE_EPOCH",
+        SessionError::OutOfOrder { .. } => "XC220B3_OUT_OF_ORDER",
+        SessionError::DuplicateMessage => "XC220B3_DUPLICATE_MESSAGE",
+        SessionError::Heartbeat => "XC220B3_HEARTBEAT",
+        SessionError::PeerClosed(_) => "XC220B3_PEER_CLOSED",
+        SessionError::Rekeyed => "XC220B3_REKEYED",
+        SessionError::Closed => "XC220B3_CLOSED",
+        SessionError::InvalidKeyLength(_) => "XC220B3_INVALID_KEY_LENGTH",
+        SessionError::TruncatedStream => "XC220B3_TRUNCATED_STREAM",
+        SessionError::TrailingData(_) => "XC220B3_TRAILING_DATA",
+        SessionError::AadAfterData => "XC220B3_AAD_AFTER_DATA",
+        SessionError::FrameDesync => "XC220B3_FRAME_DESYNC",
+        SessionError::CounterExhausted => "XC220B3_COUNTER_EXHAUSTED",
+        SessionError::MessageTooLarge(_) => "XC220B3_MESSAGE_TOO_LARGE",
+        SessionError::TooManyFailures => "XC220B3_TOO_MANY_FAILURES",
+        SessionError::Serialization(_) => "XC220B3_SERIALIZATION",
+        SessionError::MetadataTooLarge(_) => "XC220B3_METADATA_TOO_LARGE",
+        SessionError::MalformedEnvelope => "XC220B3_MALFORMED_ENVELOPE",
+        SessionError::Refused => "XC220B3_REFUSED",
+        SessionError::Io(_) => "XC220B3_IO",
+    }
+}
+
+/// A [`Session`] as a binding object.
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct BindingSession {
+    session: Mutex<Session>,
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl BindingSession {
+    /// [`Session::new`], with a fresh key pair from the OS.
+    #[cfg_attr(feature = "uniffi", uniffi::constructor)]
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> Arc<BindingSession> {
+        Arc::new(BindingSession { session: Mutex::new(Session::new(&mut OsRng)) })
+    }
+
+    /// [`Session::pk`], SEC1 encoded.
+    pub fn public_key(&self) -> Result<Vec<u8>, BindingError> {
+        Ok(self.session().pk()?.as_bytes().to_vec())
+    }
+
+    /// [`Session::set_sym_key`] with the peer's SEC1 encoded public key.
+    pub fn set_peer_key(&self, peer: Vec<u8>) -> Result<(), BindingError> {
+        let peer = EncodedPoint::from_bytes(peer).map_err(|_| BindingError::InvalidPublicKey)?;
+        Ok(self.session().set_sym_key(&peer)?)
+    }
+
+    /// [`Session::encrypt`].
+    pub fn encrypt(&self, plain: Vec<u8>) -> Result<Vec<u8>, BindingError> {
+        Ok(self.session().encrypt(plain)?)
+    }
+
+    /// [`Session::decrypt`].
+    pub fn decrypt(&self, record: Vec<u8>) -> Result<Vec<u8>, BindingError> {
+        Ok(self.session().decrypt(record)?)
+    }
+}
+
+impl BindingSession {
+    /// [`BindingSession::encrypt`] of borrowed bytes, for a binding that can
+    /// lend its runtime's buffer rather than copy it out first. The record
+    /// is the same.
+    pub fn encrypt_slice(&self, plain: &[u8]) -> Result<Vec<u8>, BindingError> {
+        Ok(self.session().encrypt_vectored(&[plain])?)
+    }
+
+    /// [`BindingSession::decrypt`] of borrowed bytes, see
+    /// [`BindingSession::encrypt_slice`].
+    pub fn decrypt_slice(&self, record: &[u8]) -> Result<Vec<u8>, BindingError> {
+        let mut session = self.session();
+        let mut plain = vec![0; record.len().saturating_sub(session.ciphertext_len(0))];
+        let plain_len = session.decrypt_into(record, &mut plain)?;
+        plain.truncate(plain_len);
+        Ok(plain)
+    }
+
+    // a panic elsewhere doesn't leave the session half way through anything
+    // a binding can see, every call is one native call
+    fn session(&self) -> std::sync::MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// [`ecies::seal`] to the recipient's SEC1 encoded public key.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn seal(recipient_public_key: Vec<u8>, plain: Vec<u8>) -> Result<Vec<u8>, BindingError> {
+    seal_slice(&recipient_public_key, &plain)
+}
+
+/// [`seal`] of borrowed bytes, see [`BindingSession::encrypt_slice`].
+pub fn seal_slice(recipient_public_key: &[u8], plain: &[u8]) -> Result<Vec<u8>, BindingError> {
+    let recipient = PublicKey::from_sec1_bytes(recipient_public_key).map_err(|_| BindingError::InvalidPublicKey)?;
+    Ok(ecies::seal(&recipient, plain, &mut OsRng))
+}
+
+/// [`ecies::open`] with the recipient's 32-byte big-endian secret key.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn open(recipient_secret_key: Vec<u8>, sealed: Vec<u8>) -> Result<Vec<u8>, BindingError> {
+    open_slice(&Zeroizing::new(recipient_secret_key), &sealed)
+}
+
+/// [`open`] of borrowed bytes, see [`BindingSession::encrypt_slice`]. The
+/// lent secret key is the caller's to wipe.
+pub fn open_slice(recipient_secret_key: &[u8], sealed: &[u8]) -> Result<Vec<u8>, BindingError> {
+    // k256 zeroes its secret keys on drop
+    let recipient = SecretKey::from_be_bytes(recipient_secret_key).map_err(|_| BindingError::InvalidSecretKey)?;
+    Ok(ecies::open(&recipient, sealed)?)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+    use crate::bindings::{open, seal, BindingError, BindingSession};
+    use crate::close::CloseReason;
+    use crate::ecies;
+    use crate::session::{Session, SessionError};
+
+    // a binding and a native session are interchangeable peers
+    #[test]
+    fn test_binding_speaks_the_native_format() {
+        let binding = BindingSession::new();
+        let mut native = Session::new(&mut thread_rng());
+        let binding_pk = k256::EncodedPoint::from_bytes(binding.public_key().unwrap()).unwrap();
+        binding.set_peer_key(native.pk().unwrap().as_bytes().to_vec()).unwrap();
+        native.set_sym_key(&binding_pk).unwrap();
+        // the key pair is spent once keyed, as natively
+        assert_eq!(binding.public_key(), Err(BindingError::KeySpent));
+
+        let record = binding.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(record.len(), native.ciphertext_len(5));
+        assert_eq!(native.decrypt(record).unwrap(), b"hello");
+        let record = native.encrypt(b"back".to_vec()).unwrap();
+        assert_eq!(binding.decrypt(record).unwrap(), b"back");
+
+        let mut tampered = native.encrypt(b"again".to_vec()).unwrap();
+        tampered[1] ^= 1;
+        let refused = binding.decrypt(tampered).unwrap_err();
+        assert_eq!(refused, BindingError::MacMismatch);
+        assert_eq!(refused.code(), "XC220B3_MAC_MISMATCH");
+        assert_eq!(binding.decrypt(vec![9; 30]), Err(BindingError::InvalidRecord));
+    }
+
+    // borrowed and owned bytes give the same records, and share a counter
+    #[test]
+    fn test_binding_slices() {
+        let (alice, bob) = (BindingSession::new(), BindingSession::new());
+        let alice_pk = alice.public_key().unwrap();
+        alice.set_peer_key(bob.public_key().unwrap()).unwrap();
+        bob.set_peer_key(alice_pk).unwrap();
+
+        let record = alice.encrypt_slice(b"hello").unwrap();
+        assert_eq!(bob.decrypt(record).unwrap(), b"hello");
+        let record = alice.encrypt(b"again".to_vec()).unwrap();
+        assert_eq!(bob.decrypt_slice(&record).unwrap(), b"again");
+        assert_eq!(bob.decrypt_slice(&record), Err(BindingError::Other { code: "XC220B3_OUT_OF_ORDER".to_string() }));
+        let mut tampered = alice.encrypt_slice(&[]).unwrap();
+        tampered[1] ^= 1;
+        assert_eq!(bob.decrypt_slice(&tampered), Err(BindingError::MacMismatch));
+        assert_eq!(bob.decrypt_slice(&[9; 30]), Err(BindingError::InvalidRecord));
+    }
+
+    #[test]
+    fn test_binding_seals_the_native_format() {
+        let recipient = k256::SecretKey::random(&mut thread_rng());
+        let public_key = recipient.public_key().to_encoded_point(true).as_bytes().to_vec();
+        let secret_key = recipient.to_be_bytes().to_vec();
+
+        let sealed = seal(public_key.clone(), b"hello".to_vec()).unwrap();
+        assert_eq!(ecies::open(&recipient, &sealed).unwrap(), b"hello");
+        let sealed = ecies::seal(&recipient.public_key(), b"back", &mut thread_rng());
+        assert_eq!(open(secret_key, sealed.clone()).unwrap(), b"back");
+
+        assert_eq!(seal(vec![2; 5], b"hello".to_vec()), Err(BindingError::InvalidPublicKey));
+        assert_eq!(open(vec![0; 32], sealed.clone()), Err(BindingError::InvalidSecretKey));
+        let other = k256::SecretKey::random(&mut thread_rng()).to_be_bytes().to_vec();
+        assert_eq!(open(other, sealed), Err(BindingError::MacMismatch));
+    }
+
+    // the codes are what a binding matches on, so they're pinned rather than
+    // derived from anything that prints
+    #[test]
+    fn test_binding_error_codes() {
+        assert_eq!(BindingError::from(SessionError::EmptySecret).code(), "XC220B3_KEY_SPENT");
+        assert_eq!(BindingError::from(SessionError::UnsupportedVersion(7)), BindingError::InvalidRecord);
+        let other = BindingError::from(SessionError::Serialization("at byte 3".to_string()));
+        assert_eq!(other, BindingError::Other { code: "XC220B3_SERIALIZATION".to_string() });
+        assert_eq!(other.to_string(), "XC220B3_SERIALIZATION");
+        assert_eq!(BindingError::from(SessionError::PeerClosed(CloseReason::GoingAway)).code(), "XC220B3_PEER_CLOSED");
+    }
+
+    #[test]
+    fn test_binding_errors() {
+        let binding = BindingSession::new();
+        assert_eq!(binding.encrypt(b"hello".to_vec()), Err(BindingError::NotReady));
+        assert_eq!(binding.set_peer_key(vec![2; 5]), Err(BindingError::InvalidPublicKey));
+        binding.set_peer_key(BindingSession::new().public_key().unwrap()).unwrap();
+        assert_eq!(binding.set_peer_key(BindingSession::new().public_key().unwrap()), Err(BindingError::AlreadyReady));
+    }
+}
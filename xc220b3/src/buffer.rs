@@ -60,14 +60,14 @@ pub trait WriteBuffer {
     fn reset(&mut self);
 
     // FIXME - Shouldn't need mut self
-    fn peek_read_buffer(&mut self) -> RefReadBuffer;
+    fn peek_read_buffer(&mut self) -> RefReadBuffer<'_>;
 
     fn take_next(&mut self, count: usize) -> &mut [u8];
     fn take_remaining(&mut self) -> &mut [u8] {
         let rem = self.remaining();
         self.take_next(rem)
     }
-    fn take_read_buffer(&mut self) -> RefReadBuffer;
+    fn take_read_buffer(&mut self) -> RefReadBuffer<'_>;
 }
 
 pub struct RefReadBuffer<'a> {
@@ -76,9 +76,9 @@ pub struct RefReadBuffer<'a> {
 }
 
 impl <'a> RefReadBuffer<'a> {
-    pub fn new(buff: &[u8]) -> RefReadBuffer {
+    pub fn new(buff: &[u8]) -> RefReadBuffer<'_> {
         RefReadBuffer {
-            buff: buff,
+            buff,
             pos: 0
         }
     }
@@ -120,8 +120,8 @@ impl <'a> WriteBuffer for RefWriteBuffer<'a> {
     fn rewind(&mut self, distance: usize) { self.pos -= distance; }
     fn reset(&mut self) { self.pos = 0; }
 
-    fn peek_read_buffer(&mut self) -> RefReadBuffer {
-        RefReadBuffer::new(&mut self.buff[..self.pos])
+    fn peek_read_buffer(&mut self) -> RefReadBuffer<'_> {
+        RefReadBuffer::new(&self.buff[..self.pos])
     }
 
     fn take_next(&mut self, count: usize) -> &mut [u8] {
@@ -129,8 +129,8 @@ impl <'a> WriteBuffer for RefWriteBuffer<'a> {
         self.pos += count;
         r
     }
-    fn take_read_buffer(&mut self) -> RefReadBuffer {
-        let r = RefReadBuffer::new(&mut self.buff[..self.pos]);
+    fn take_read_buffer(&mut self) -> RefReadBuffer<'_> {
+        let r = RefReadBuffer::new(&self.buff[..self.pos]);
         self.pos = 0;
         r
     }
@@ -0,0 +1,620 @@
+use std::sync::Arc;
+
+use k256::{PublicKey, SecretKey};
+use rand_core::{CryptoRng, RngCore};
+use xc220b3_core::protocol::{
+    self, ConfigFields, FLAG_HIDE_LENGTHS, FLAG_OBFUSCATE, FLAG_ORDERED, SUITE_XCHACHA20_BLAKE3,
+};
+
+use crate::cipher::{RecordCipher, XCHACHA20_BLAKE3};
+use crate::failures::{Clock, FailurePolicy};
+use crate::kdf::KdfAlgorithm;
+use crate::kex::{KeyExchange, Secp256k1};
+use crate::provider::SecretProvider;
+use crate::random::{self, RandomSource, SourceRng};
+use crate::record::{self, HEADER_LEN, SALT_LEN, SEQ_LEN};
+use crate::session::{LogPolicy, Session, REKEY_AFTER_MESSAGES};
+use crate::stream::{MAX_STREAM_CHUNK_LEN, MIN_STREAM_CHUNK_LEN, STREAM_CHUNK_LEN};
+
+/// A session that hasn't been keyed yet: exchange public keys, then call
+/// [`Session::set_sym_key`].
+pub type PendingSession = Session;
+
+/// The record cipher a session uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherSuite {
+    /// XChaCha20 with a BLAKE3 MAC as its nonce.
+    XChaCha20Blake3,
+}
+
+impl CipherSuite {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            CipherSuite::XChaCha20Blake3 => SUITE_XCHACHA20_BLAKE3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<CipherSuite> {
+        match id {
+            SUITE_XCHACHA20_BLAKE3 => Some(CipherSuite::XChaCha20Blake3),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn cipher(self) -> &'static dyn RecordCipher {
+        match self {
+            CipherSuite::XChaCha20Blake3 => &XCHACHA20_BLAKE3,
+        }
+    }
+}
+
+/// The parts of a session's configuration both peers must agree on. Local
+/// choices like the log policy aren't included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionConfig {
+    pub suite: CipherSuite,
+    pub kdf: KdfAlgorithm,
+    pub tag_len: usize,
+    /// Longest plaintext a single record may carry, [`DEFAULT_MAX_MESSAGE_LEN`]
+    /// unless set. Longer ones are refused before anything is allocated. It
+    /// can't be more than the cipher allows one record, 256 GiB less 64 bytes
+    /// for XChaCha20-BLAKE3, so anything longer has to go as a chunked stream.
+    pub max_message_len: usize,
+    /// How many records may be sent before the session refuses to send more,
+    /// at most [`REKEY_AFTER_MESSAGES`].
+    pub rekey_after: u64,
+    /// Records with no cleartext header, indistinguishable from random
+    /// bytes, see [`SessionBuilder::obfuscate`].
+    pub obfuscate: bool,
+    /// Records carry the sender's counter and are only accepted in the order
+    /// they were sent, the default, see [`SessionBuilder::ordered`].
+    pub ordered: bool,
+    /// The length prefix of each frame is masked, see
+    /// [`SessionBuilder::hide_lengths`]. Only with `obfuscate`.
+    pub hide_lengths: bool,
+    /// Plaintext bytes per chunk of a [`StreamEncryptor`](crate::StreamEncryptor),
+    /// [`STREAM_CHUNK_LEN`] unless set. Smaller chunks buffer less, larger
+    /// ones spend less per byte on their records. A chunk is a record like
+    /// any other, so one longer than `max_message_len` can't be sent.
+    pub stream_chunk_len: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The suite can't produce tags of this length.
+    UnsupportedTagLen(CipherSuite, usize),
+    ZeroMaxMessageLen,
+    /// Above what the suite's cipher can seal in one record, see
+    /// [`RecordCipher::max_plaintext_len`](crate::RecordCipher::max_plaintext_len).
+    MaxMessageLenTooLarge(usize),
+    /// Zero, or above [`REKEY_AFTER_MESSAGES`].
+    InvalidRekeyAfter(u64),
+    /// Below [`MIN_STREAM_CHUNK_LEN`] or above [`MAX_STREAM_CHUNK_LEN`].
+    InvalidStreamChunkLen(usize),
+    /// [`SessionConfig::from_bytes`] was given something it didn't write.
+    Malformed,
+    /// Outside the limits documented on each of its fields, or given with a
+    /// salt under [`MIN_SALT_LEN`](crate::MIN_SALT_LEN) bytes.
+    #[cfg(feature = "argon2")]
+    InvalidPasswordConfig(crate::PasswordConfig),
+    /// [`SessionConfig::hide_lengths`] without [`SessionConfig::obfuscate`],
+    /// which would leave a record header at a fixed place in every frame.
+    HiddenLengthsWithoutObfuscation,
+    /// No [`SessionBuilder::random_source`] was set, in a build without the
+    /// `std` feature's OS RNG to fall back on.
+    NoRandomSource,
+}
+
+/// The default [`SessionConfig::max_message_len`], generous for any single
+/// message but small enough that one hostile record can't exhaust memory.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = protocol::DEFAULT_MAX_MESSAGE_LEN;
+
+pub(crate) const CONFIG_ENCODED_LEN: usize = protocol::CONFIG_LEN;
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            suite: CipherSuite::XChaCha20Blake3,
+            kdf: KdfAlgorithm::default(),
+            tag_len: XCHACHA20_BLAKE3.tag_len(),
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            rekey_after: REKEY_AFTER_MESSAGES,
+            obfuscate: false,
+            ordered: true,
+            hide_lengths: false,
+            stream_chunk_len: STREAM_CHUNK_LEN,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.tag_len != self.suite.cipher().tag_len() {
+            return Err(ConfigError::UnsupportedTagLen(self.suite, self.tag_len));
+        }
+        if self.max_message_len == 0 {
+            return Err(ConfigError::ZeroMaxMessageLen);
+        }
+        if self.max_message_len as u64 > self.suite.cipher().max_plaintext_len() {
+            return Err(ConfigError::MaxMessageLenTooLarge(self.max_message_len));
+        }
+        if self.rekey_after == 0 || self.rekey_after > REKEY_AFTER_MESSAGES {
+            return Err(ConfigError::InvalidRekeyAfter(self.rekey_after));
+        }
+        if !(MIN_STREAM_CHUNK_LEN..=MAX_STREAM_CHUNK_LEN).contains(&self.stream_chunk_len) {
+            return Err(ConfigError::InvalidStreamChunkLen(self.stream_chunk_len));
+        }
+        if self.hide_lengths && !self.obfuscate {
+            return Err(ConfigError::HiddenLengthsWithoutObfuscation);
+        }
+        Ok(())
+    }
+
+    /// Length of the largest record this config allows, for bounding reads
+    /// with [`read_frame_max`](crate::framing::read_frame_max).
+    pub fn max_record_len(&self) -> usize {
+        self.max_message_len.saturating_add(self.header_len() + self.suite.cipher().overhead())
+    }
+
+    // what precedes the cipher's output in a record
+    pub(crate) fn header_len(&self) -> usize {
+        match (self.obfuscate, self.ordered) {
+            (true, _) => SALT_LEN,
+            (false, true) => HEADER_LEN + SEQ_LEN,
+            (false, false) => HEADER_LEN,
+        }
+    }
+
+    /// Identifies the record format version, cipher suite, curve and KDF,
+    /// what [`SessionBuilder::bind_suite`] mixes into the session keys.
+    ///
+    /// `format version (1) || suite (1) || curve (1) || kdf (1)`
+    pub fn suite_id(&self) -> [u8; 4] {
+        [record::VERSION, self.suite.id(), Secp256k1::ID, self.kdf.id()]
+    }
+
+    /// A canonical encoding, for putting in a handshake transcript (so both
+    /// sides provably agree) or sending to a peer.
+    ///
+    /// `version (1) || suite (1) || kdf (1) || tag_len (1) || le64(max_message_len) || le64(rekey_after) || flags (1) || le32(stream_chunk_len)`
+    ///
+    /// where bit 0 of the flags is [`SessionConfig::obfuscate`], bit 1
+    /// [`SessionConfig::ordered`] and bit 2 [`SessionConfig::hide_lengths`].
+    pub fn to_bytes(&self) -> [u8; CONFIG_ENCODED_LEN] {
+        let fields = ConfigFields {
+            suite: self.suite.id(),
+            kdf: self.kdf.id(),
+            tag_len: self.tag_len as u8,
+            max_message_len: self.max_message_len as u64,
+            rekey_after: self.rekey_after,
+            flags: if self.obfuscate { FLAG_OBFUSCATE } else { 0 }
+                | if self.ordered { FLAG_ORDERED } else { 0 }
+                | if self.hide_lengths { FLAG_HIDE_LENGTHS } else { 0 },
+            stream_chunk_len: self.stream_chunk_len as u32,
+        };
+        fields.to_bytes()
+    }
+
+    /// Reverses [`SessionConfig::to_bytes`], and validates the result.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SessionConfig, ConfigError> {
+        let fields = ConfigFields::from_bytes(bytes).ok_or(ConfigError::Malformed)?;
+        let config = SessionConfig {
+            suite: CipherSuite::from_id(fields.suite).ok_or(ConfigError::Malformed)?,
+            kdf: KdfAlgorithm::from_id(fields.kdf).ok_or(ConfigError::Malformed)?,
+            tag_len: fields.tag_len as usize,
+            max_message_len: usize::try_from(fields.max_message_len).unwrap_or(usize::MAX),
+            rekey_after: fields.rekey_after,
+            obfuscate: fields.flags & FLAG_OBFUSCATE != 0,
+            ordered: fields.flags & FLAG_ORDERED != 0,
+            hide_lengths: fields.flags & FLAG_HIDE_LENGTHS != 0,
+            stream_chunk_len: fields.stream_chunk_len as usize,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Configures a session. `SessionBuilder::default().build(rng)` is the same
+/// as [`Session::new`].
+#[derive(Clone, Default)]
+pub struct SessionBuilder {
+    config: SessionConfig,
+    log_policy: LogPolicy,
+    opaque_errors: bool,
+    failure_policy: Option<FailurePolicy>,
+    clock: Option<Arc<dyn Clock>>,
+    random: Option<Arc<dyn RandomSource>>,
+    bind_suite: bool,
+    identity: Option<Arc<dyn SecretProvider>>,
+    trusted_identities: Vec<PublicKey>,
+}
+
+impl SessionBuilder {
+    pub fn new() -> SessionBuilder {
+        SessionBuilder::default()
+    }
+
+    /// Takes every agreed-on option from `config`, e.g. one decoded from a
+    /// peer with [`SessionConfig::from_bytes`].
+    pub fn config(mut self, config: SessionConfig) -> SessionBuilder {
+        self.config = config;
+        self
+    }
+
+    pub fn suite(mut self, suite: CipherSuite) -> SessionBuilder {
+        self.config.suite = suite;
+        self
+    }
+
+    pub fn kdf(mut self, kdf: KdfAlgorithm) -> SessionBuilder {
+        self.config.kdf = kdf;
+        self
+    }
+
+    pub fn tag_len(mut self, tag_len: usize) -> SessionBuilder {
+        self.config.tag_len = tag_len;
+        self
+    }
+
+    pub fn max_message_len(mut self, max_message_len: usize) -> SessionBuilder {
+        self.config.max_message_len = max_message_len;
+        self
+    }
+
+    /// Sets [`SessionConfig::stream_chunk_len`]. Both ends of a stream must
+    /// use the same length, a stream with another one fails on its first
+    /// chunk.
+    pub fn stream_chunk_len(mut self, len: usize) -> SessionBuilder {
+        self.config.stream_chunk_len = len;
+        self
+    }
+
+    pub fn rekey_after(mut self, messages: u64) -> SessionBuilder {
+        self.config.rekey_after = messages;
+        self
+    }
+
+    /// Sends records with no version byte or any other fixed marker, so that
+    /// to anyone without the key they look like random bytes. Each record
+    /// carries 16 random bytes in place of the header, so the same plaintext
+    /// never gives the same record twice.
+    ///
+    /// This gives up everything the cleartext header is for: a record of
+    /// the wrong version, for the wrong session, or tampered with are all
+    /// just [`SessionError::MacMismatch`](crate::SessionError::MacMismatch),
+    /// and a receiver multiplexing sessions has to try each one. Framing
+    /// lengths and the handshake are still visible, that's up to the
+    /// transport.
+    pub fn obfuscate(mut self, obfuscate: bool) -> SessionBuilder {
+        self.config.obfuscate = obfuscate;
+        self
+    }
+
+    /// Puts the sender's record counter in each record's authenticated
+    /// header, and accepts records only in exactly the order they were
+    /// sent, for TCP-like transports that never reorder or drop. Anything
+    /// else, a swapped pair, a replay or a gap, is
+    /// [`SessionError::OutOfOrder`](crate::SessionError::OutOfOrder) with
+    /// the number expected and the number received, and the session waits
+    /// for the expected one. Costs 8 bytes a record.
+    ///
+    /// On unless turned off. `ordered(false)` is for transports that drop or
+    /// reorder datagrams: records then open in any order, with replays
+    /// caught by the tag cache (see [`Session::set_dedup_capacity`](crate::Session::set_dedup_capacity))
+    /// and older keys kept for a drain window across a rekey.
+    ///
+    /// Every record sealed counts, so one that's sealed and never sent
+    /// stalls the peer. An obfuscated session doesn't send the counter and
+    /// can't say what it got: a record out of order is just
+    /// [`SessionError::MacMismatch`](crate::SessionError::MacMismatch).
+    pub fn ordered(mut self, ordered: bool) -> SessionBuilder {
+        self.config.ordered = ordered;
+        self
+    }
+
+    /// Masks the length prefix of every frame [`Session::send_frame`] and
+    /// [`SecureChannel`](crate::SecureChannel) write with a keystream only
+    /// the peer can reproduce, so a passive observer sees neither message
+    /// sizes nor where one frame ends and the next starts. Both ends have to
+    /// set it, the handshake checks they agree.
+    ///
+    /// A masked prefix can't be checked on its own: a frame whose prefix or
+    /// body was damaged can't be told from one that was read at the wrong
+    /// length, and there's no finding the next frame after either. So a
+    /// frame that doesn't authenticate ends the receiving side, every read
+    /// after it [`SessionError::FrameDesync`](crate::SessionError::FrameDesync).
+    /// The functions in [`framing`](crate::framing) have no session and keep
+    /// writing plain prefixes.
+    ///
+    /// Turns [obfuscation](SessionBuilder::obfuscate) on too: a record's
+    /// header, its version byte and an [ordered](SessionBuilder::ordered)
+    /// record's counter, would otherwise sit at the same offset in every
+    /// frame and mark where it starts. Turning obfuscation back off after
+    /// this is [`ConfigError::HiddenLengthsWithoutObfuscation`].
+    pub fn hide_lengths(mut self, hide: bool) -> SessionBuilder {
+        self.config.hide_lengths = hide;
+        self.config.obfuscate |= hide;
+        self
+    }
+
+    /// Mixes [`SessionConfig::suite_id`] into the keys [`Session::set_sym_key`]
+    /// derives, so peers that disagree about the format, cipher, curve or
+    /// KDF (say, after a downgrade) fail the MAC instead of talking. Off by
+    /// default, since it changes the keys: both ends have to set it.
+    pub fn bind_suite(mut self, bind: bool) -> SessionBuilder {
+        self.bind_suite = bind;
+        self
+    }
+
+    pub fn log_policy(mut self, policy: LogPolicy) -> SessionBuilder {
+        self.log_policy = policy;
+        self
+    }
+
+    /// See [`Session::set_opaque_errors`]. Only this side's, the peer needn't
+    /// set it too.
+    pub fn opaque_errors(mut self, opaque: bool) -> SessionBuilder {
+        self.opaque_errors = opaque;
+        self
+    }
+
+    /// See [`Session::set_identity`]. A handshake signs this side's
+    /// ephemeral key with it.
+    pub fn identity(self, identity: SecretKey) -> SessionBuilder {
+        self.identity_provider(Arc::new(identity))
+    }
+
+    /// [`SessionBuilder::identity`] with a key kept by a [`SecretProvider`].
+    pub fn identity_provider(mut self, identity: Arc<dyn SecretProvider>) -> SessionBuilder {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// See [`Session::trust_identity`]. A handshake refuses a peer whose
+    /// ephemeral key isn't signed by one of these, once there are any.
+    pub fn trust_identity(mut self, identity: PublicKey) -> SessionBuilder {
+        self.trusted_identities.push(identity);
+        self
+    }
+
+    pub fn failure_policy(mut self, policy: FailurePolicy) -> SessionBuilder {
+        self.failure_policy = Some(policy);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> SessionBuilder {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Where built sessions draw their own random bytes from, see
+    /// [`RandomSource`]. The OS RNG unless set, and required without the
+    /// `std` feature.
+    pub fn random_source(mut self, source: Arc<dyn RandomSource>) -> SessionBuilder {
+        self.random = Some(source);
+        self
+    }
+
+    /// Validates the configuration and creates a session with a random
+    /// ephemeral secret from `rng`.
+    pub fn build(self, rng: &mut (impl CryptoRng + RngCore)) -> Result<PendingSession, ConfigError> {
+        self.validate()?;
+        Ok(self.apply(Session::new(rng)))
+    }
+
+    /// [`SessionBuilder::build`] around a key pair generated earlier, say
+    /// taken from an [`EphemeralPool`](crate::EphemeralPool).
+    pub(crate) fn build_with_pair(
+        self,
+        pair: (<Secp256k1 as KeyExchange>::Private, <Secp256k1 as KeyExchange>::Public),
+    ) -> Result<PendingSession, ConfigError> {
+        self.validate()?;
+        Ok(self.apply(Session::from_secret(Some(pair))))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if cfg!(not(feature = "std")) && self.random.is_none() {
+            return Err(ConfigError::NoRandomSource);
+        }
+        self.config.validate()
+    }
+
+    // the rest of the configuration, onto a session that has its key pair
+    fn apply(self, mut session: PendingSession) -> PendingSession {
+        session.config = self.config;
+        session.cipher = self.config.suite.cipher();
+        session.kdf = self.config.kdf.kdf();
+        if self.bind_suite {
+            session.suite_binding = Some(self.config.suite_id());
+        }
+        session.set_log_policy(self.log_policy);
+        session.set_opaque_errors(self.opaque_errors);
+        session.set_identity_provider(self.identity);
+        for identity in self.trusted_identities {
+            session.trust_identity(identity);
+        }
+        if let Some(clock) = self.clock {
+            session.set_clock(clock);
+        }
+        if let Some(source) = self.random {
+            session.set_random_source(source);
+        }
+        if let Some(policy) = self.failure_policy {
+            session.set_failure_policy(policy);
+        }
+        session
+    }
+
+    /// [`SessionBuilder::build`], drawing the ephemeral secret from the
+    /// [`random_source`](Self::random_source) too, so a seeded source makes
+    /// the whole session reproducible. What a handshake builds with.
+    pub fn build_from_source(self) -> Result<PendingSession, ConfigError> {
+        let source = self.random.clone().unwrap_or_else(random::default_source);
+        self.build(&mut SourceRng(&*source))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use crate::builder::{CipherSuite, ConfigError, SessionBuilder, SessionConfig};
+    use crate::session::{LogPolicy, Session, SessionError, REKEY_AFTER_MESSAGES};
+    use crate::stream::{MAX_STREAM_CHUNK_LEN, MIN_STREAM_CHUNK_LEN};
+
+    fn connect(mut first: Session, mut second: Session) -> (Session, Session) {
+        let (first_pk, second_pk) = (first.pk().unwrap(), second.pk().unwrap());
+        first.set_sym_key(&second_pk).unwrap();
+        second.set_sym_key(&first_pk).unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn test_defaults_match_new() {
+        let built = SessionBuilder::default().build(&mut thread_rng()).unwrap();
+        let new = Session::new(&mut thread_rng());
+        assert_eq!(built.config(), new.config());
+        assert_eq!(built.log_policy(), new.log_policy());
+        assert_eq!(built.config().rekey_after, REKEY_AFTER_MESSAGES);
+
+        // and they talk to each other
+        let (mut built, mut new) = connect(built, new);
+        let record = built.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(new.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    #[cfg(not(feature = "std"))]
+    fn test_random_source_required_without_std() {
+        use std::sync::Arc;
+
+        use rand::{rngs::StdRng, SeedableRng};
+
+        use crate::random::SharedRng;
+
+        assert_eq!(SessionBuilder::new().build(&mut thread_rng()).err(), Some(ConfigError::NoRandomSource));
+        assert_eq!(SessionBuilder::new().build_from_source().err(), Some(ConfigError::NoRandomSource));
+        let source = Arc::new(SharedRng::new(StdRng::seed_from_u64(1)));
+        let builder = SessionBuilder::new().obfuscate(true).random_source(source);
+        let (mut first, mut second) = connect(builder.clone().build_from_source().unwrap(), builder.build_from_source().unwrap());
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(second.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_invalid_configs_are_rejected() {
+        let build = |builder: SessionBuilder| builder.build(&mut thread_rng()).err();
+
+        assert_eq!(build(SessionBuilder::new().max_message_len(0)), Some(ConfigError::ZeroMaxMessageLen));
+        #[cfg(target_pointer_width = "64")]
+        {
+            let limit = 64 * u32::MAX as usize;
+            assert!(build(SessionBuilder::new().max_message_len(limit)).is_none());
+            assert_eq!(
+                build(SessionBuilder::new().max_message_len(limit + 1)),
+                Some(ConfigError::MaxMessageLenTooLarge(limit + 1))
+            );
+        }
+        assert_eq!(
+            build(SessionBuilder::new().tag_len(16)),
+            Some(ConfigError::UnsupportedTagLen(CipherSuite::XChaCha20Blake3, 16))
+        );
+        assert_eq!(build(SessionBuilder::new().rekey_after(0)), Some(ConfigError::InvalidRekeyAfter(0)));
+        assert_eq!(
+            build(SessionBuilder::new().rekey_after(REKEY_AFTER_MESSAGES + 1)),
+            Some(ConfigError::InvalidRekeyAfter(REKEY_AFTER_MESSAGES + 1))
+        );
+
+        let mut bytes = SessionConfig::default().to_bytes();
+        bytes[1] = 0xff;
+        assert_eq!(SessionConfig::from_bytes(&bytes), Err(ConfigError::Malformed));
+        let mut bytes = SessionConfig::default().to_bytes();
+        bytes[2] = 0xff;
+        assert_eq!(SessionConfig::from_bytes(&bytes), Err(ConfigError::Malformed));
+        assert_eq!(SessionConfig::from_bytes(&bytes[..4]), Err(ConfigError::Malformed));
+        let mut bytes = SessionConfig::default().to_bytes();
+        bytes[20] = 0x80;
+        assert_eq!(SessionConfig::from_bytes(&bytes), Err(ConfigError::Malformed));
+
+        for len in [MIN_STREAM_CHUNK_LEN - 1, MAX_STREAM_CHUNK_LEN + 1] {
+            assert_eq!(build(SessionBuilder::new().stream_chunk_len(len)), Some(ConfigError::InvalidStreamChunkLen(len)));
+        }
+        let config = SessionConfig { stream_chunk_len: MIN_STREAM_CHUNK_LEN, ..SessionConfig::default() };
+        assert_eq!(SessionConfig::from_bytes(&config.to_bytes()), Ok(config));
+
+        // hidden lengths bring obfuscation with them, and can't go without
+        assert!(SessionBuilder::new().hide_lengths(true).build(&mut thread_rng()).unwrap().config().obfuscate);
+        let unobfuscated = SessionBuilder::new().hide_lengths(true).obfuscate(false);
+        assert_eq!(build(unobfuscated), Some(ConfigError::HiddenLengthsWithoutObfuscation));
+        let config = SessionConfig { hide_lengths: true, ..SessionConfig::default() };
+        assert_eq!(SessionConfig::from_bytes(&config.to_bytes()), Err(ConfigError::HiddenLengthsWithoutObfuscation));
+    }
+
+    #[test]
+    fn test_non_default_config_roundtrips() {
+        let builder = SessionBuilder::new()
+            .max_message_len(1024)
+            .rekey_after(2)
+            .obfuscate(true)
+            .ordered(true)
+            .hide_lengths(true)
+            .log_policy(LogPolicy::Off);
+        let first = builder.clone().build(&mut thread_rng()).unwrap();
+
+        // the peer learns the config from its encoding
+        let config = SessionConfig::from_bytes(&first.config().to_bytes()).unwrap();
+        assert_eq!(&config, first.config());
+        let second = SessionBuilder::new().config(config).build(&mut thread_rng()).unwrap();
+
+        let (mut first, mut second) = connect(first, second);
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(second.decrypt(record).unwrap(), b"hello");
+        assert!(matches!(first.encrypt(vec![0; 1025]), Err(SessionError::MessageTooLarge(1025))));
+        first.encrypt(Vec::new()).unwrap();
+        assert!(matches!(first.encrypt(Vec::new()), Err(SessionError::CounterExhausted)));
+    }
+
+    #[test]
+    fn test_mismatched_suite_ids_cannot_talk() {
+        let bound = SessionBuilder::new().bind_suite(true);
+        let build = |builder: &SessionBuilder| builder.clone().build(&mut thread_rng()).unwrap();
+
+        let (mut first, mut second) = connect(build(&bound), build(&bound));
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(second.decrypt(record).unwrap(), b"hello");
+
+        // a peer that thinks it's on another format version, with everything
+        // else the same
+        let mut other = build(&bound);
+        let mut suite_id = SessionConfig::default().suite_id();
+        suite_id[0] ^= 0xff;
+        other.suite_binding = Some(suite_id);
+        let (mut first, mut second) = connect(build(&bound), other);
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(second.decrypt(record), Err(SessionError::MacMismatch)));
+
+        // and binding is part of the agreement too
+        let (mut first, mut second) = connect(build(&bound), build(&SessionBuilder::new()));
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(second.decrypt(record), Err(SessionError::MacMismatch)));
+    }
+
+    #[cfg(feature = "hkdf-sha256")]
+    #[test]
+    fn test_kdf_mismatch_fails_cleanly() {
+        use crate::kdf::KdfAlgorithm;
+
+        let hkdf = SessionBuilder::new().kdf(KdfAlgorithm::HkdfSha256);
+        let blake3 = SessionBuilder::new();
+
+        // matching KDFs talk
+        let (mut first, mut second) = connect(hkdf.clone().build(&mut thread_rng()).unwrap(), hkdf.clone().build(&mut thread_rng()).unwrap());
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(second.decrypt(record).unwrap(), b"hello");
+
+        // mismatched ones derive different keys, which the MAC catches
+        let (mut first, mut second) = connect(hkdf.build(&mut thread_rng()).unwrap(), blake3.build(&mut thread_rng()).unwrap());
+        let record = first.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(second.decrypt(record), Err(SessionError::MacMismatch)));
+    }
+}
@@ -0,0 +1,177 @@
+// a certificate is a public key and an issuer's signature over it:
+//
+//   subject (33, compressed SEC1) || r (32) || s (32)
+//
+// the signature is ECDSA over secp256k1, of the 32 byte BLAKE3 hash of the
+// subject under CERTIFICATE_CONTEXT taken as the prehash. any ECDSA
+// implementation that signs a prehash can issue them. this one is k256's,
+// with the nonce derived from the key and hash as RFC 6979 has it, so no
+// randomness goes into a signature and a bad RNG can't leak the issuer's
+// key. issuing the same certificate twice gives the same bytes. a chain
+// runs from the leaf, each certificate issued by the key in the next, to one
+// issued by the root
+
+use k256::{
+    ecdsa::{
+        signature::hazmat::{PrehashSigner, PrehashVerifier},
+        Signature, SigningKey, VerifyingKey,
+    },
+    elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint},
+    EncodedPoint, PublicKey, SecretKey,
+};
+
+use crate::session::SessionError;
+
+const CERTIFICATE_CONTEXT: &str = "xc220b3 2022-10 certificate";
+
+/// Length of an encoded [`Certificate`].
+pub const CERTIFICATE_LEN: usize = 33 + 64;
+
+/// A public key, signed by the key that issued it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    pub subject: PublicKey,
+    signature: [u8; 64],
+}
+
+impl Certificate {
+    /// Signs `subject` with `issuer`, deterministically.
+    pub fn issue(issuer: &SecretKey, subject: &PublicKey) -> Certificate {
+        let hash = blake3::derive_key(CERTIFICATE_CONTEXT, subject.to_encoded_point(true).as_bytes());
+        let signature = SigningKey::from(issuer).sign_prehash(&hash).expect("a 32 byte prehash");
+        Certificate { subject: *subject, signature: signature_bytes(signature) }
+    }
+
+    /// Whether `issuer` signed this certificate.
+    pub fn is_issued_by(&self, issuer: &PublicKey) -> bool {
+        let hash = blake3::derive_key(CERTIFICATE_CONTEXT, self.subject.to_encoded_point(true).as_bytes());
+        let Ok(signature) = Signature::try_from(&self.signature[..]) else {
+            return false;
+        };
+        VerifyingKey::from(issuer).verify_prehash(&hash, &signature).is_ok()
+    }
+
+    pub fn to_bytes(&self) -> [u8; CERTIFICATE_LEN] {
+        let mut out = [0u8; CERTIFICATE_LEN];
+        out[..33].copy_from_slice(self.subject.to_encoded_point(true).as_bytes());
+        out[33..].copy_from_slice(&self.signature);
+        out
+    }
+
+    /// Parses a certificate, without checking its signature.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Certificate> {
+        if bytes.len() != CERTIFICATE_LEN {
+            return None;
+        }
+        let point = EncodedPoint::from_bytes(&bytes[..33]).ok()?;
+        let subject = Option::from(PublicKey::from_encoded_point(&point))?;
+        Some(Certificate { subject, signature: bytes[33..].try_into().unwrap() })
+    }
+}
+
+/// Checks that `chain[0]` was issued by the key in `chain[1]`, and so on up
+/// to the last, which `root` must have issued, giving the leaf's key to key
+/// a session with (see [`Session::set_sym_key`](crate::Session::set_sym_key)).
+/// The first link that doesn't verify is
+/// [`SessionError::InvalidCertificate`] with its index, an empty chain is
+/// `InvalidCertificate(0)`.
+pub fn verify_certificate_chain(chain: &[Certificate], root: &PublicKey) -> Result<PublicKey, SessionError> {
+    let leaf = chain.first().ok_or(SessionError::InvalidCertificate(0))?;
+    for (i, certificate) in chain.iter().enumerate() {
+        let issuer = chain.get(i + 1).map_or(root, |next| &next.subject);
+        if !certificate.is_issued_by(issuer) {
+            return Err(SessionError::InvalidCertificate(i));
+        }
+    }
+    Ok(leaf.subject)
+}
+
+// r || s with s low, as most verifiers insist on
+pub(crate) fn signature_bytes(signature: Signature) -> [u8; 64] {
+    let signature = signature.normalize_s().unwrap_or(signature);
+    signature.as_ref().try_into().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use k256::{ecdsa::Signature, elliptic_curve::sec1::ToEncodedPoint, elliptic_curve::IsHigh, SecretKey};
+
+    use crate::certificate::{verify_certificate_chain, Certificate};
+    use crate::session::{Session, SessionError};
+
+    #[test]
+    fn test_valid_chain_gives_the_leaf() {
+        let rng = &mut thread_rng();
+        let (root, intermediate) = (SecretKey::random(&mut *rng), SecretKey::random(&mut *rng));
+        let mut server = Session::new(rng);
+        let leaf = k256::PublicKey::from_sec1_bytes(server.pk().unwrap().as_bytes()).unwrap();
+        let chain = [
+            Certificate::issue(&intermediate, &leaf),
+            Certificate::issue(&root, &intermediate.public_key()),
+        ];
+        assert_eq!(verify_certificate_chain(&chain, &root.public_key()).unwrap(), leaf);
+        assert_eq!(verify_certificate_chain(&chain[1..], &root.public_key()).unwrap(), intermediate.public_key());
+
+        // certificates travel as bytes
+        let parsed: Vec<Certificate> = chain.iter().map(|cert| Certificate::from_bytes(&cert.to_bytes()).unwrap()).collect();
+        let verified = verify_certificate_chain(&parsed, &root.public_key()).unwrap();
+
+        let mut client = Session::new(rng);
+        let client_pk = client.pk().unwrap();
+        client.set_sym_key(&verified.to_encoded_point(true)).unwrap();
+        server.set_sym_key(&client_pk).unwrap();
+        let record = client.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(server.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_broken_link_is_found() {
+        let rng = &mut thread_rng();
+        let keys: Vec<SecretKey> = (0..4).map(|_| SecretKey::random(&mut *rng)).collect();
+        let (root, intermediate, leaf, stranger) = (&keys[0], &keys[1], &keys[2], &keys[3]);
+        let good = [
+            Certificate::issue(intermediate, &leaf.public_key()),
+            Certificate::issue(root, &intermediate.public_key()),
+        ];
+
+        let leaf_by_stranger = [Certificate::issue(stranger, &leaf.public_key()), good[1].clone()];
+        assert!(matches!(verify_certificate_chain(&leaf_by_stranger, &root.public_key()), Err(SessionError::InvalidCertificate(0))));
+        let wrong_root = verify_certificate_chain(&good, &stranger.public_key());
+        assert!(matches!(wrong_root, Err(SessionError::InvalidCertificate(1))));
+        assert!(matches!(verify_certificate_chain(&good[..1], &root.public_key()), Err(SessionError::InvalidCertificate(0))));
+        assert!(matches!(verify_certificate_chain(&[], &root.public_key()), Err(SessionError::InvalidCertificate(0))));
+
+        // the signature covers the subject, and every bit of itself
+        let mut swapped = good[0].clone();
+        swapped.subject = stranger.public_key();
+        assert!(!swapped.is_issued_by(&intermediate.public_key()));
+        let bytes = good[0].to_bytes();
+        for i in 33..bytes.len() {
+            let mut tampered = bytes;
+            tampered[i] ^= 1;
+            if let Some(cert) = Certificate::from_bytes(&tampered) {
+                assert!(!cert.is_issued_by(&intermediate.public_key()), "{}", i);
+            }
+        }
+        assert!(Certificate::from_bytes(&bytes[1..]).is_none());
+    }
+
+    #[test]
+    fn test_issuing_is_deterministic_and_low_s() {
+        let rng = &mut thread_rng();
+        let (issuer, subject) = (SecretKey::random(&mut *rng), SecretKey::random(&mut *rng).public_key());
+        let certificate = Certificate::issue(&issuer, &subject);
+        assert_eq!(certificate.to_bytes(), Certificate::issue(&issuer, &subject).to_bytes());
+
+        let signature = Signature::try_from(&certificate.to_bytes()[33..]).unwrap();
+        assert!(!bool::from(signature.s().is_high()));
+        // the same signature with s high is refused
+        let high = Signature::from_scalars(signature.r().to_bytes(), (-*signature.s()).to_bytes()).unwrap();
+        let mut bytes = certificate.to_bytes();
+        bytes[33..].copy_from_slice(high.as_ref());
+        assert!(!Certificate::from_bytes(&bytes).unwrap().is_issued_by(&issuer.public_key()));
+        assert!(certificate.is_issued_by(&issuer.public_key()));
+    }
+}
@@ -0,0 +1,398 @@
+// a transport and the session over it: the handshake in `handshake`, then
+// one record per frame (see `framing`). each record's plaintext is a kind
+// byte and its body:
+//
+//   0 || data          a message for recv
+//   1 || public key    a rekey, SEC1
+//
+// a side rekeys by sending a fresh public key, and the peer answers with its
+// own, sealed under the old keys, before switching. each completes with the
+// key the other sent, so two rekeys that cross still meet. a side starts one
+// itself once half its SessionConfig::rekey_after is used up, and records
+// the peer sealed under the old keys meanwhile are opened through the drain
+// window
+
+use std::io::{self, Read, Write};
+
+use k256::{ecdh::EphemeralSecret, EncodedPoint};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::builder::ConfigError;
+use crate::close::CloseReason;
+use crate::handshake::{self, CertificateVerifier, HandshakeConfig, HandshakeError};
+use crate::kex::{KeyExchange, Secp256k1};
+use crate::random::SourceRng;
+use crate::renegotiate::DEFAULT_DRAIN_WINDOW;
+use crate::session::{Received, Session, SessionError};
+
+const DATA: u8 = 0;
+const REKEY: u8 = 1;
+
+/// What went wrong on a [`SecureChannel`].
+#[derive(Debug)]
+pub enum ChannelError {
+    /// The transport failed or hung up, or the handshake timed out.
+    Transport(io::Error),
+    /// The peer couldn't prove it holds the session keys: a record, or the
+    /// handshake's confirmation, didn't authenticate, the server's verifier
+    /// refused the client's certificate, or the peer's key wasn't signed by
+    /// a trusted identity.
+    Authentication,
+    /// The peer sent something the protocol doesn't allow: a malformed
+    /// message, a config or versions this side doesn't share, or a record
+    /// replayed or out of sequence.
+    Protocol,
+    /// The peer closed the channel, nothing more will come from it.
+    Closed(CloseReason),
+    /// The local [`HandshakeConfig::builder`] is invalid.
+    Config(ConfigError),
+    /// This side refused to send: the message is too long, or the channel
+    /// is closed.
+    Session(SessionError),
+}
+
+impl From<HandshakeError> for ChannelError {
+    fn from(e: HandshakeError) -> Self {
+        match e {
+            HandshakeError::Timeout(_) => ChannelError::Transport(io::ErrorKind::TimedOut.into()),
+            HandshakeError::Io(_, e) => ChannelError::Transport(e),
+            HandshakeError::BadCertificate | HandshakeError::UntrustedIdentity | HandshakeError::ConfirmationMismatch => {
+                ChannelError::Authentication
+            }
+            HandshakeError::Malformed(_)
+            | HandshakeError::ConfigMismatch
+            | HandshakeError::NoCommonVersion
+            | HandshakeError::UnknownServerName => {
+                ChannelError::Protocol
+            }
+            HandshakeError::Config(e) => ChannelError::Config(e),
+        }
+    }
+}
+
+// what a record that didn't open says about the peer
+fn receive_error(e: SessionError) -> ChannelError {
+    match e {
+        SessionError::MacMismatch
+        | SessionError::NonceMismatch
+        | SessionError::StaleEpoch
+        | SessionError::TooManyFailures
+        | SessionError::Refused
+        | SessionError::FrameDesync => ChannelError::Authentication,
+        SessionError::PeerClosed(reason) => ChannelError::Closed(reason),
+        SessionError::Io(e) => ChannelError::Transport(e),
+        SessionError::Closed => ChannelError::Session(e),
+        _ => ChannelError::Protocol,
+    }
+}
+
+// a frame too long for the session, which the transport was the one to
+// carry, unless the length was hidden from it
+fn frame_error(e: SessionError) -> ChannelError {
+    match e {
+        SessionError::MessageTooLarge(_) => ChannelError::Transport(io::ErrorKind::InvalidData.into()),
+        e => receive_error(e),
+    }
+}
+
+/// A transport with a session over it, from the handshake on: send and
+/// receive plaintext, and the channel frames it, rekeys and closes.
+pub struct SecureChannel<T> {
+    io: T,
+    session: Session,
+    rekey: Option<(EphemeralSecret, EncodedPoint)>,
+    // sealed, not yet written
+    outgoing: Vec<Vec<u8>>,
+}
+
+impl<T> SecureChannel<T> {
+    fn new(io: T, session: Session) -> SecureChannel<T> {
+        SecureChannel { io, session, rekey: None, outgoing: Vec::new() }
+    }
+
+    /// The session underneath, for its [`session_id`](Session::session_id),
+    /// [`peer_identity`](Session::peer_identity) and the like.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    fn seal(&mut self, data: &[u8]) -> Result<(), ChannelError> {
+        let config = self.session.config();
+        if self.rekey.is_none() && self.session.messages_remaining() <= config.rekey_after / 2 {
+            self.start_rekey()?;
+        }
+        let record = self.session.encrypt([&[DATA][..], data].concat()).map_err(ChannelError::Session)?;
+        self.outgoing.push(record);
+        Ok(())
+    }
+
+    fn start_rekey(&mut self) -> Result<(), ChannelError> {
+        let (secret, public) = Secp256k1::generate(&mut SourceRng(&*self.session.random));
+        let record = self.session.encrypt([&[REKEY][..], public.as_bytes()].concat()).map_err(ChannelError::Session)?;
+        self.outgoing.push(record);
+        self.rekey = Some((secret, public));
+        Ok(())
+    }
+
+    // the data a record carries, if it isn't one the channel takes itself
+    fn open(&mut self, record: Vec<u8>) -> Result<Option<Vec<u8>>, ChannelError> {
+        let received = self.session.open_frame(|session| session.receive(record));
+        let mut plain = match received.map_err(receive_error)? {
+            Received::Data(plain) => plain,
+            Received::Heartbeat | Received::Rekeyed => return Ok(None),
+            Received::Closed(reason) => return Err(ChannelError::Closed(reason)),
+        };
+        match plain.first() {
+            Some(&DATA) => Ok(Some(plain.split_off(1))),
+            Some(&REKEY) => {
+                let peer = EncodedPoint::from_bytes(&plain[1..]).map_err(|_| ChannelError::Protocol)?;
+                // our answer goes under the old keys
+                if self.rekey.is_none() {
+                    self.start_rekey()?;
+                }
+                let (secret, public) = self.rekey.take().unwrap();
+                self.session
+                    .complete_renegotiation(&secret, &public, &peer, DEFAULT_DRAIN_WINDOW, u64::MAX)
+                    .map_err(|_| ChannelError::Protocol)?;
+                Ok(None)
+            }
+            _ => Err(ChannelError::Protocol),
+        }
+    }
+}
+
+impl<T: Read + Write> SecureChannel<T> {
+    /// Runs the client side of the handshake over `io`, see
+    /// [`handshake::client_sync`].
+    pub fn connect(io: T, config: &HandshakeConfig) -> Result<SecureChannel<T>, ChannelError> {
+        let (session, io) = handshake::client_sync(io, config)?;
+        Ok(SecureChannel::new(io, session))
+    }
+
+    /// Runs the server side of the handshake over `io`, taking clients
+    /// whose certificate `verifier` accepts. `&|_: &[u8]| true` takes any.
+    pub fn accept(io: T, config: &HandshakeConfig, verifier: &impl CertificateVerifier) -> Result<SecureChannel<T>, ChannelError> {
+        let (session, io) = handshake::server_sync(io, config, verifier)?;
+        Ok(SecureChannel::new(io, session))
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> Result<(), ChannelError> {
+        self.seal(data)?;
+        self.flush()
+    }
+
+    /// The next message from the peer, once it has authenticated. A close
+    /// from the peer is [`ChannelError::Closed`], a transport that ends
+    /// without one is [`ChannelError::Transport`].
+    pub fn recv(&mut self) -> Result<Vec<u8>, ChannelError> {
+        loop {
+            let record = self.session.read_frame_from(&mut self.io).map_err(frame_error)?;
+            let data = self.open(record)?;
+            self.flush()?;
+            if let Some(data) = data {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// Sends a close record, so the peer's `recv` gives
+    /// [`ChannelError::Closed`], and hands back the transport.
+    pub fn close(mut self, reason: CloseReason) -> Result<T, ChannelError> {
+        let record = self.session.close(reason).map_err(ChannelError::Session)?;
+        self.outgoing.push(record);
+        self.flush()?;
+        Ok(self.io)
+    }
+
+    fn flush(&mut self) -> Result<(), ChannelError> {
+        for record in self.outgoing.drain(..) {
+            self.io.write_all(&self.session.frame(&record)).map_err(ChannelError::Transport)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncRead + AsyncWrite + Unpin> SecureChannel<T> {
+    /// [`SecureChannel::connect`] for async transports, with
+    /// [`handshake::client`].
+    pub async fn connect_async(io: T, config: &HandshakeConfig) -> Result<SecureChannel<T>, ChannelError> {
+        let (session, io) = handshake::client(io, config).await?;
+        Ok(SecureChannel::new(io, session))
+    }
+
+    /// [`SecureChannel::accept`] for async transports, with
+    /// [`handshake::server`].
+    pub async fn accept_async(
+        io: T,
+        config: &HandshakeConfig,
+        verifier: &impl CertificateVerifier,
+    ) -> Result<SecureChannel<T>, ChannelError> {
+        let (session, io) = handshake::server(io, config, verifier).await?;
+        Ok(SecureChannel::new(io, session))
+    }
+
+    pub async fn send_async(&mut self, data: &[u8]) -> Result<(), ChannelError> {
+        self.seal(data)?;
+        self.flush_async().await
+    }
+
+    /// [`SecureChannel::recv`] for async transports.
+    pub async fn recv_async(&mut self) -> Result<Vec<u8>, ChannelError> {
+        loop {
+            let record = self.session.read_frame_from_async(&mut self.io).await.map_err(frame_error)?;
+            let data = self.open(record)?;
+            self.flush_async().await?;
+            if let Some(data) = data {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// [`SecureChannel::close`] for async transports.
+    pub async fn close_async(mut self, reason: CloseReason) -> Result<T, ChannelError> {
+        let record = self.session.close(reason).map_err(ChannelError::Session)?;
+        self.outgoing.push(record);
+        self.flush_async().await?;
+        self.io.shutdown().await.map_err(ChannelError::Transport)?;
+        Ok(self.io)
+    }
+
+    async fn flush_async(&mut self) -> Result<(), ChannelError> {
+        for record in core::mem::take(&mut self.outgoing) {
+            let frame = self.session.frame(&record);
+            self.io.write_all(&frame).await.map_err(ChannelError::Transport)?;
+        }
+        self.io.flush().await.map_err(ChannelError::Transport)
+    }
+}
+
+// socketpairs are unix only
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use crate::builder::SessionBuilder;
+    use crate::channel::{ChannelError, SecureChannel};
+    use crate::close::CloseReason;
+    use crate::framing::write_frame;
+    use crate::handshake::{self, HandshakeConfig};
+
+    fn config(certificate: &[u8]) -> HandshakeConfig {
+        HandshakeConfig {
+            builder: SessionBuilder::new().rekey_after(8),
+            certificate: certificate.to_vec(),
+            ..HandshakeConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_channel_over_a_socketpair() {
+        let (client_io, server_io) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut channel = SecureChannel::accept(server_io, &config(b""), &|cert: &[u8]| cert == b"ok").unwrap();
+            assert_eq!(channel.session().peer_identity(), Some(&b"ok"[..]));
+            // echoes until the client closes
+            loop {
+                match channel.recv() {
+                    Ok(message) => channel.send(&[b"echo ", &message[..]].concat()).unwrap(),
+                    Err(ChannelError::Closed(reason)) => return (reason, channel.session().epoch),
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        });
+
+        let mut channel = SecureChannel::connect(client_io, &config(b"ok")).unwrap();
+        for i in 0..20u8 {
+            channel.send(&[i; 3]).unwrap();
+            assert_eq!(channel.recv().unwrap(), [&b"echo "[..], &[i; 3]].concat());
+        }
+        // rekey_after is 8, so that took several rekeys on each side
+        let epoch = channel.session().epoch;
+        assert!(epoch >= 3);
+        channel.close(CloseReason::Normal).unwrap();
+
+        let (reason, server_epoch) = server.join().unwrap();
+        assert_eq!(reason, CloseReason::Normal);
+        assert_eq!(server_epoch, epoch);
+    }
+
+    #[test]
+    fn test_channel_with_hidden_lengths() {
+        let config = || HandshakeConfig { builder: SessionBuilder::new().rekey_after(8).hide_lengths(true), ..config(b"") };
+        let (client_io, server_io) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut channel = SecureChannel::accept(server_io, &config(), &|_: &[u8]| true).unwrap();
+            while let Ok(message) = channel.recv() {
+                channel.send(&message).unwrap();
+            }
+        });
+
+        // through several rekeys, the masks don't depend on the record keys
+        let mut channel = SecureChannel::connect(client_io, &config()).unwrap();
+        assert!(channel.session().config().hide_lengths);
+        for i in 0..20u8 {
+            channel.send(&[i; 3]).unwrap();
+            assert_eq!(channel.recv().unwrap(), [i; 3]);
+        }
+        assert!(channel.session().epoch >= 3);
+        channel.close(CloseReason::Normal).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_channel_errors_are_told_apart() {
+        let (client_io, server_io) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut channel = SecureChannel::accept(server_io, &config(b""), &|_: &[u8]| true).unwrap();
+            let tampered = channel.recv().unwrap_err();
+            let hung_up = channel.recv().unwrap_err();
+            (tampered, hung_up)
+        });
+
+        let (mut session, mut client_io) = handshake::client_sync(client_io, &config(b"")).unwrap();
+        let mut record = session.encrypt(vec![0, 1, 2]).unwrap();
+        record[2] ^= 1;
+        write_frame(&mut client_io, &record).unwrap();
+        drop(client_io);
+
+        let (tampered, hung_up) = server.join().unwrap();
+        assert!(matches!(tampered, ChannelError::Authentication));
+        assert!(matches!(hung_up, ChannelError::Transport(_)));
+
+        // and a refused certificate is an authentication failure too
+        let (client_io, server_io) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || SecureChannel::accept(server_io, &config(b""), &|_: &[u8]| false).err());
+        assert!(SecureChannel::connect(client_io, &config(b"")).is_err());
+        assert!(matches!(server.join().unwrap(), Some(ChannelError::Authentication)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_channel() {
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let server = async move {
+            let mut channel = SecureChannel::accept_async(server_io, &config(b""), &|_: &[u8]| true).await.unwrap();
+            loop {
+                match channel.recv_async().await {
+                    Ok(message) => channel.send_async(&message).await.unwrap(),
+                    Err(ChannelError::Closed(reason)) => return reason,
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        };
+        let client = async move {
+            let mut channel = SecureChannel::connect_async(client_io, &config(b"")).await.unwrap();
+            for i in 0..20u8 {
+                channel.send_async(&[i]).await.unwrap();
+                assert_eq!(channel.recv_async().await.unwrap(), [i]);
+            }
+            assert!(channel.session().epoch >= 3);
+            channel.close_async(CloseReason::GoingAway).await.unwrap();
+        };
+        let (reason, ()) = tokio::join!(server, client);
+        assert_eq!(reason, CloseReason::GoingAway);
+    }
+}
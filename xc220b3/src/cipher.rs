@@ -0,0 +1,445 @@
+use core::hint::black_box;
+#[cfg(feature = "rayon")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use blake3::Hasher;
+
+use crate::{
+    mac::MAC,
+    session::SessionError,
+    symmetriccipher::SynchronousStreamCipher,
+    xc220::{MAX_KEYSTREAM_LEN, XC220},
+};
+
+pub(crate) mod private {
+    pub trait Sealed {}
+}
+
+/// The authenticated encryption a session's records are made with.
+///
+/// Sealed: every backend lives in this crate. There is deliberately no way to
+/// decrypt without verifying, [`RecordCipher::open`] only hands back
+/// plaintext once the tag has checked out.
+///
+/// A cipher's output is `ciphertext || nonce || tag`. The nonce is only
+/// there for ciphers that carry it apart from the tag, and then `open` must
+/// check it's the nonce the record commits to (returning
+/// [`SessionError::NonceMismatch`] if not), not just that the tag verifies.
+pub trait RecordCipher: private::Sealed + Send + Sync {
+    fn key_len(&self) -> usize;
+    /// Length of a nonce carried apart from the tag, 0 if there isn't one.
+    fn nonce_len(&self) -> usize;
+    fn tag_len(&self) -> usize;
+
+    /// Longest plaintext one record can carry, all of it under one nonce.
+    /// [`RecordCipher::seal`] panics past it, sessions refuse such a
+    /// [`SessionConfig::max_message_len`](crate::SessionConfig::max_message_len)
+    /// up front.
+    fn max_plaintext_len(&self) -> u64 {
+        u64::MAX
+    }
+
+    /// Bytes added on top of the ciphertext.
+    fn overhead(&self) -> usize {
+        self.nonce_len() + self.tag_len()
+    }
+
+    /// Appends `ciphertext || nonce || tag` for the concatenation of `parts`
+    /// to `out`, with `aad` bound under the tag.
+    fn seal(&self, key: &[u8], parts: &[&[u8]], aad: &[u8], out: &mut Vec<u8>);
+
+    /// Checks and decrypts `ciphertext || nonce || tag`, `sealed` is at
+    /// least [`RecordCipher::overhead`] long.
+    fn open(&self, key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, SessionError>;
+
+    /// [`RecordCipher::seal`] of `plain` into `out`, which is exactly
+    /// [`RecordCipher::overhead`] longer than it. Backends that can override
+    /// this to not allocate.
+    fn seal_into(&self, key: &[u8], plain: &[u8], aad: &[u8], out: &mut [u8]) {
+        let mut sealed = Vec::with_capacity(out.len());
+        self.seal(key, &[plain], aad, &mut sealed);
+        out.copy_from_slice(&sealed);
+    }
+
+    /// [`RecordCipher::open`] into `out`, which is exactly the plaintext's
+    /// length. `out` is zeroed if the record doesn't open, so nothing
+    /// unauthenticated is left in it. Backends that can override this to not
+    /// allocate.
+    fn open_into(&self, key: &[u8], sealed: &[u8], aad: &[u8], out: &mut [u8]) -> Result<(), SessionError> {
+        match self.open(key, sealed, aad) {
+            Ok(plain) => {
+                out.copy_from_slice(&plain);
+                Ok(())
+            }
+            Err(e) => {
+                out.fill(0);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// XChaCha20, with the 24 byte BLAKE3 MAC of the plaintext as its nonce.
+pub struct XChaCha20Blake3;
+
+pub(crate) static XCHACHA20_BLAKE3: XChaCha20Blake3 = XChaCha20Blake3;
+
+/// The default [`set_parallel_mac_threshold`], twice the 128 KiB BLAKE3
+/// gives as the length under which hashing across threads is slower on
+/// x86-64. `cargo bench --features rayon --bench mac` finds the crossover on
+/// a given machine. With one core the pool never pays: sealing measured 14%
+/// slower through it at 16 KiB, and within noise of one thread from 256 KiB
+/// up to 64 MiB.
+#[cfg(feature = "rayon")]
+pub const DEFAULT_PARALLEL_MAC_THRESHOLD: usize = 256 * 1024;
+
+#[cfg(feature = "rayon")]
+static PARALLEL_MAC_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_PARALLEL_MAC_THRESHOLD);
+
+/// Sets how long a piece of a record's MAC input (a plaintext part, or
+/// the aad) has to be for BLAKE3 to hash it across rayon's thread pool, for
+/// every session in the process. The tag is the same either way, only the
+/// time changes. `usize::MAX` keeps every MAC on the calling thread. Only
+/// with the `rayon` feature.
+#[cfg(feature = "rayon")]
+pub fn set_parallel_mac_threshold(len: usize) {
+    PARALLEL_MAC_THRESHOLD.store(len, Ordering::Relaxed);
+}
+
+impl XChaCha20Blake3 {
+    fn mac(key: &[u8], parts: &[&[u8]], aad: &[u8]) -> MAC {
+        cfg_if!(
+            if #[cfg(feature = "rayon")] {
+                let threshold = PARALLEL_MAC_THRESHOLD.load(Ordering::Relaxed);
+            } else {
+                let threshold = usize::MAX;
+            }
+        );
+        XChaCha20Blake3::mac_threaded_from(key, parts, aad, threshold)
+    }
+
+    // pieces from `threshold` bytes are hashed across threads, BLAKE3's tree
+    // comes to the same hash however it's split
+    fn mac_threaded_from(key: &[u8], parts: &[&[u8]], aad: &[u8], threshold: usize) -> MAC {
+        let mut b3 = Hasher::new();
+        let mut update = |bytes: &[u8]| match bytes.len() >= threshold {
+            #[cfg(feature = "rayon")]
+            true => {
+                b3.update_rayon(bytes);
+            }
+            _ => {
+                b3.update(bytes);
+            }
+        };
+        xc220b3_core::protocol::mac_input(key, parts, aad, &mut update);
+
+        MAC::from_output_reader(&mut b3.finalize_xof())
+    }
+}
+
+impl private::Sealed for XChaCha20Blake3 {}
+
+impl RecordCipher for XChaCha20Blake3 {
+    fn key_len(&self) -> usize {
+        32
+    }
+
+    // the tag is the nonce, and recomputing it is the nonce check
+    fn nonce_len(&self) -> usize {
+        0
+    }
+
+    fn tag_len(&self) -> usize {
+        24
+    }
+
+    // 256 GiB less a block, past which the keystream would repeat
+    fn max_plaintext_len(&self) -> u64 {
+        MAX_KEYSTREAM_LEN
+    }
+
+    fn seal(&self, key: &[u8], parts: &[&[u8]], aad: &[u8], out: &mut Vec<u8>) {
+        let mac = XChaCha20Blake3::mac(key, parts, aad);
+        let mut xcc20 = XC220::new(key, mac.as_bytes());
+
+        // the keystream carries on across parts
+        let mut at = out.len();
+        out.resize(at + parts.iter().map(|part| part.len()).sum::<usize>(), 0);
+        for part in parts {
+            xcc20.process(part, &mut out[at..at + part.len()]);
+            at += part.len();
+        }
+        out.extend_from_slice(mac.as_bytes());
+    }
+
+    fn open(&self, key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let mut output = vec![0; sealed.len() - self.overhead()];
+        self.open_into(key, sealed, aad, &mut output)?;
+        Ok(output)
+    }
+
+    fn seal_into(&self, key: &[u8], plain: &[u8], aad: &[u8], out: &mut [u8]) {
+        let mac = XChaCha20Blake3::mac(key, &[plain], aad);
+        let (ciphertext, tag) = out.split_at_mut(plain.len());
+        XC220::new(key, mac.as_bytes()).process(plain, ciphertext);
+        tag.copy_from_slice(mac.as_bytes());
+    }
+
+    fn open_into(&self, key: &[u8], sealed: &[u8], aad: &[u8], out: &mut [u8]) -> Result<(), SessionError> {
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - self.overhead());
+        XC220::try_new(key, tag)?.process(ciphertext, out);
+
+        // the comparison is constant-time, and so is what follows it: the
+        // plaintext is masked either way, to itself or to zeroes, rather
+        // than only wiped when it's bad
+        let good = XChaCha20Blake3::mac(key, &[out], aad) == *tag;
+        let mask = black_box(good as u8).wrapping_neg();
+        out.iter_mut().for_each(|byte| *byte &= mask);
+        match good {
+            true => Ok(()),
+            false => Err(SessionError::MacMismatch),
+        }
+    }
+}
+
+// no encryption at all, and a checksum nobody should trust as a tag, for
+// testing everything around the cipher. it carries a separate (synthetic)
+// nonce that the tag doesn't cover, so only the nonce check catches a
+// swapped one
+#[cfg(test)]
+pub(crate) struct NullCipher;
+
+#[cfg(test)]
+pub(crate) static NULL_CIPHER: NullCipher = NullCipher;
+
+#[cfg(test)]
+impl NullCipher {
+    fn checksum(key: &[u8], parts: &[&[u8]], aad: &[u8]) -> [u8; 24] {
+        // FNV-1a, spread over the tag
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let aad_len = (aad.len() as u64).to_le_bytes();
+        let bytes = parts.iter().copied().chain([key, aad, &aad_len[..]]);
+        for byte in bytes.flatten() {
+            hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+        let mut tag = [0u8; 24];
+        for (i, chunk) in tag.chunks_mut(8).enumerate() {
+            chunk.copy_from_slice(&hash.rotate_left(i as u32 * 8).to_le_bytes());
+        }
+        tag
+    }
+
+    fn nonce(key: &[u8], parts: &[&[u8]]) -> [u8; 8] {
+        let mut nonce = [0u8; 8];
+        nonce.copy_from_slice(&NullCipher::checksum(key, parts, b"nonce")[..8]);
+        nonce
+    }
+}
+
+#[cfg(test)]
+impl private::Sealed for NullCipher {}
+
+#[cfg(test)]
+impl RecordCipher for NullCipher {
+    fn key_len(&self) -> usize {
+        32
+    }
+
+    fn nonce_len(&self) -> usize {
+        8
+    }
+
+    fn tag_len(&self) -> usize {
+        24
+    }
+
+    fn seal(&self, key: &[u8], parts: &[&[u8]], aad: &[u8], out: &mut Vec<u8>) {
+        for part in parts {
+            out.extend_from_slice(part);
+        }
+        out.extend_from_slice(&NullCipher::nonce(key, parts));
+        out.extend_from_slice(&NullCipher::checksum(key, parts, aad));
+    }
+
+    fn open(&self, key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let (plain, rest) = sealed.split_at(sealed.len() - self.overhead());
+        let (nonce, tag) = rest.split_at(self.nonce_len());
+        if tag != NullCipher::checksum(key, &[plain], aad) {
+            return Err(SessionError::MacMismatch);
+        }
+        if nonce != NullCipher::nonce(key, &[plain]) {
+            return Err(SessionError::NonceMismatch);
+        }
+        Ok(plain.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{thread_rng, RngCore};
+
+    use crate::cipher::{RecordCipher, NULL_CIPHER, XCHACHA20_BLAKE3};
+    use crate::session::{ciphertext_len, Session, SessionError};
+
+    // every backend has to pass these
+    fn conformance(cipher: &dyn RecordCipher) {
+        let mut key = vec![0; cipher.key_len()];
+        thread_rng().fill_bytes(&mut key);
+        let seal = |parts: &[&[u8]], aad: &[u8]| {
+            let mut out = Vec::new();
+            cipher.seal(&key, parts, aad, &mut out);
+            out
+        };
+
+        for len in [0, 1, 63, 64, 65, 1000] {
+            let plain = vec![0x5a; len];
+            let sealed = seal(&[&plain], b"aad");
+            assert_eq!(sealed.len(), len + cipher.overhead());
+            assert_eq!(cipher.open(&key, &sealed, b"aad").unwrap(), plain);
+
+            // every byte matters, nonce and tag included
+            let nonce = len..len + cipher.nonce_len();
+            for i in 0..sealed.len() {
+                let mut tampered = sealed.clone();
+                tampered[i] ^= 0x01;
+                match cipher.open(&key, &tampered, b"aad") {
+                    Err(SessionError::NonceMismatch) => assert!(nonce.contains(&i)),
+                    Err(SessionError::MacMismatch) => assert!(!nonce.contains(&i)),
+                    other => panic!("tampering byte {} gave {:?}", i, other),
+                }
+            }
+            assert!(matches!(cipher.open(&key, &sealed, b"other"), Err(SessionError::MacMismatch)));
+            assert!(matches!(cipher.open(&key, &sealed, b""), Err(SessionError::MacMismatch)));
+
+            let mut other_key = key.clone();
+            other_key[0] ^= 1;
+            assert!(matches!(cipher.open(&other_key, &sealed, b"aad"), Err(SessionError::MacMismatch)));
+        }
+
+        // parts are the same as their concatenation
+        assert_eq!(seal(&[b"ab", b"", b"cdef"], &[]), seal(&[b"abcdef"], &[]));
+
+        // appends to what's already there
+        let mut out = vec![7];
+        cipher.seal(&key, &[b"hi"], &[], &mut out);
+        assert_eq!(out[0], 7);
+        assert_eq!(cipher.open(&key, &out[1..], &[]).unwrap(), b"hi");
+
+        // the slice versions make the same records
+        let mut sealed = vec![0; 5 + cipher.overhead()];
+        cipher.seal_into(&key, b"hello", b"aad", &mut sealed);
+        assert_eq!(sealed, seal(&[b"hello"], b"aad"));
+        let mut plain = [0u8; 5];
+        cipher.open_into(&key, &sealed, b"aad", &mut plain).unwrap();
+        assert_eq!(&plain, b"hello");
+        sealed[0] ^= 1;
+        assert!(matches!(cipher.open_into(&key, &sealed, b"aad", &mut plain), Err(SessionError::MacMismatch)));
+        assert_eq!(plain, [0; 5]);
+    }
+
+    #[test]
+    fn test_xchacha20_blake3_conformance() {
+        conformance(&XCHACHA20_BLAKE3);
+    }
+
+    #[test]
+    fn test_xchacha20_blake3_is_keyed_xchacha20() {
+        // the ciphertext is the plaintext under plain XChaCha20 from block 0,
+        // nonced with the tag, so the keystream is the one
+        // xc220::test_xchacha20_draft_vectors checks
+        use crate::symmetriccipher::SynchronousStreamCipher;
+        use crate::xc220::XC220;
+
+        let key: Vec<u8> = (0x80..=0x9f).collect();
+        let plain = [0x5a; 200];
+        let mut sealed = Vec::new();
+        XCHACHA20_BLAKE3.seal(&key, &[&plain], b"aad", &mut sealed);
+        let (ciphertext, tag) = sealed.split_at(plain.len());
+        let mut keystream = [0u8; 200];
+        XC220::new(&key, tag).process(&[0; 200], &mut keystream);
+        let xored: Vec<u8> = plain.iter().zip(keystream).map(|(p, k)| p ^ k).collect();
+        assert_eq!(ciphertext, &xored[..]);
+    }
+
+    #[test]
+    fn test_null_cipher_conformance() {
+        conformance(&NULL_CIPHER);
+    }
+
+    #[test]
+    fn test_wrong_key_length_is_an_error() {
+        let mut sealed = Vec::new();
+        XCHACHA20_BLAKE3.seal(&[7; 32], &[b"hello"], &[], &mut sealed);
+        assert!(matches!(XCHACHA20_BLAKE3.open(&[7; 16], &sealed, &[]), Err(SessionError::InvalidKeyLength(16))));
+    }
+
+    #[test]
+    fn test_ciphertext_len_matches_output() {
+        let ciphers: [&'static dyn RecordCipher; 2] = [&XCHACHA20_BLAKE3, &NULL_CIPHER];
+        for cipher in ciphers {
+            let (mut sesh, _) = Session::pair(&mut thread_rng());
+            sesh.cipher = cipher;
+            for len in [0, 1, 24, 63, 64, 65, 1000, 70000] {
+                let expected = sesh.ciphertext_len(len);
+                let plain = vec![0; len];
+                assert_eq!(sesh.encrypt(plain.clone()).unwrap().len(), expected);
+                let (head, tail) = plain.split_at(len / 2);
+                assert_eq!(sesh.encrypt_vectored(&[head, tail]).unwrap().len(), expected);
+            }
+        }
+
+        let sesh = Session::new(&mut thread_rng());
+        for len in [0, 5, 1000] {
+            assert_eq!(ciphertext_len(len), sesh.ciphertext_len(len));
+        }
+        assert_eq!(ciphertext_len(usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn test_session_logic_over_null_cipher() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        sesh1.cipher = &NULL_CIPHER;
+        sesh2.cipher = &NULL_CIPHER;
+
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        // plaintext in the clear, just framed, after the header and counter
+        assert_eq!(&record[9..14], b"hello");
+        assert_eq!(sesh2.decrypt(record.clone()).unwrap(), b"hello");
+
+        let mut tampered = record.clone();
+        tampered[9] ^= 1;
+        assert!(matches!(sesh2.decrypt(tampered), Err(SessionError::MacMismatch)));
+        assert_eq!(sesh2.stats().auth_failures, 1);
+        assert_eq!(sesh2.stats().received, 1);
+
+        // only the nonce swapped, the tag still verifies
+        let mut swapped = record;
+        swapped[14] ^= 1;
+        assert!(matches!(sesh2.decrypt(swapped), Err(SessionError::NonceMismatch)));
+        assert_eq!(sesh2.stats().auth_failures, 2);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_threaded_mac_matches() {
+        use crate::cipher::XChaCha20Blake3;
+
+        // past every size BLAKE3 splits its tree at, and a ragged end
+        let mut plain = vec![0u8; (4 << 20) + 1234];
+        thread_rng().fill_bytes(&mut plain);
+        let (head, tail) = plain.split_at(3 << 20);
+        for aad in [&b""[..], &plain[..300 << 10]] {
+            let one_thread = *XChaCha20Blake3::mac_threaded_from(&[9; 32], &[&plain], aad, usize::MAX).as_bytes();
+            for threshold in [0, 1 << 20, super::DEFAULT_PARALLEL_MAC_THRESHOLD] {
+                assert_eq!(*XChaCha20Blake3::mac_threaded_from(&[9; 32], &[&plain], aad, threshold).as_bytes(), one_thread);
+                assert_eq!(*XChaCha20Blake3::mac_threaded_from(&[9; 32], &[head, tail], aad, threshold).as_bytes(), one_thread);
+            }
+        }
+
+        // and a record sealed through the pool opens on one thread
+        let mut sealed = Vec::new();
+        XCHACHA20_BLAKE3.seal(&[9; 32], &[&plain], b"aad", &mut sealed);
+        let mac = XChaCha20Blake3::mac_threaded_from(&[9; 32], &[&plain], b"aad", 0);
+        assert_eq!(&sealed[plain.len()..], mac.as_bytes());
+    }
+}
@@ -0,0 +1,145 @@
+use zeroize::Zeroize;
+
+use crate::kex::KeyExchange;
+use crate::record;
+use crate::session::{Session, SessionError};
+
+/// Why a session was [closed](Session::close), sent to the peer in the
+/// close record as a one byte code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Done talking, code 0.
+    Normal,
+    /// The process is shutting down or restarting, code 1.
+    GoingAway,
+    /// Something went wrong above the session, code 2.
+    Error,
+    /// Any other code, for applications to give their own meanings.
+    Other(u8),
+}
+
+impl CloseReason {
+    pub fn code(self) -> u8 {
+        match self {
+            CloseReason::Normal => 0,
+            CloseReason::GoingAway => 1,
+            CloseReason::Error => 2,
+            CloseReason::Other(code) => code,
+        }
+    }
+
+    /// The reason for `code`, the named ones before [`CloseReason::Other`].
+    pub fn from_code(code: u8) -> CloseReason {
+        match code {
+            0 => CloseReason::Normal,
+            1 => CloseReason::GoingAway,
+            2 => CloseReason::Error,
+            code => CloseReason::Other(code),
+        }
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Seals a close record carrying `reason`, so the peer can tell a clean
+    /// shutdown from a cut connection, and zeroes the send key. Anything
+    /// sent after is [`SessionError::Closed`]. Records from the peer are
+    /// still opened until its own close record arrives, then its
+    /// [`Session::receive`] gives [`Received::Closed`](crate::Received::Closed),
+    /// it zeroes its receive keys, and refuses anything after.
+    ///
+    /// Version 1 sessions can't send one, the flag would be outside the MAC.
+    pub fn close(&mut self, reason: CloseReason) -> Result<Vec<u8>, SessionError> {
+        if self.negotiated_version() == record::V1 {
+            return Err(SessionError::UnsupportedVersion(record::V1));
+        }
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("close", 1);
+                let _enter = span.enter();
+            }
+        );
+
+        let record = self.seal_record(&[&[reason.code()]], &[], record::CLOSE_BIT)?;
+        self.send_key.zeroize();
+        self.send_closed = true;
+        Ok(record)
+    }
+
+    /// Whether this side has sent a close record, and whether the peer's
+    /// has been received.
+    pub fn is_closed(&self) -> (bool, bool) {
+        (self.send_closed, self.recv_closed)
+    }
+
+    pub(crate) fn closed_by_peer(&mut self) {
+        self.recv_key.zeroize();
+        self.previous = None;
+        self.recv_closed = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::builder::SessionBuilder;
+    use crate::session::{Received, Session, SessionError};
+
+    use super::CloseReason;
+
+    fn pair(obfuscate: bool) -> (Session, Session) {
+        Session::pair_with(SessionBuilder::new().obfuscate(obfuscate))
+    }
+
+    #[test]
+    fn test_close_roundtrip() {
+        for obfuscate in [false, true] {
+            let (mut sesh1, mut sesh2) = pair(obfuscate);
+            let last = sesh1.encrypt(b"last".to_vec()).unwrap();
+            let one_byte = sesh1.encrypt(vec![7]).unwrap();
+            let close = sesh1.close(CloseReason::Other(42)).unwrap();
+            assert_eq!(sesh1.send_key, [0; 32]);
+            assert!(matches!(sesh1.encrypt(b"more".to_vec()), Err(SessionError::Closed)));
+            assert!(matches!(sesh1.close(CloseReason::Normal), Err(SessionError::Closed)));
+            assert_eq!(sesh1.is_closed(), (true, false));
+
+            // the closing side still hears the peer
+            let reply = sesh2.encrypt(b"reply".to_vec()).unwrap();
+            assert_eq!(sesh1.decrypt(reply).unwrap(), b"reply");
+
+            assert_eq!(sesh2.decrypt(last).unwrap(), b"last");
+            assert_eq!(sesh2.receive(one_byte).unwrap(), Received::Data(vec![7]));
+            assert_eq!(sesh2.receive(close.clone()).unwrap(), Received::Closed(CloseReason::Other(42)));
+            assert_eq!(sesh2.recv_key, [0; 32]);
+            assert_eq!(sesh2.is_closed(), (false, true));
+            assert!(matches!(sesh2.decrypt(close), Err(SessionError::Closed)));
+        }
+    }
+
+    #[test]
+    fn test_decrypt_reports_the_close() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let close = sesh1.close(CloseReason::GoingAway).unwrap();
+        assert!(matches!(sesh2.decrypt(close), Err(SessionError::PeerClosed(CloseReason::GoingAway))));
+        assert_eq!(sesh2.stats().received, 1);
+        for code in 0..=u8::MAX {
+            assert_eq!(CloseReason::from_code(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn test_forged_close_is_ignored() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        let (mut other, _) = pair(false);
+
+        // a close under the wrong key, and a message with the close bit set
+        let forged = other.close(CloseReason::Normal).unwrap();
+        assert!(matches!(sesh2.decrypt(forged), Err(SessionError::MacMismatch)));
+        let mut flagged = sesh1.encrypt(vec![0]).unwrap();
+        flagged[0] |= crate::record::CLOSE_BIT;
+        assert!(matches!(sesh2.decrypt(flagged), Err(SessionError::MacMismatch)));
+
+        assert_eq!(sesh2.is_closed(), (false, false));
+        let record = sesh1.encrypt(b"still open".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"still open");
+    }
+}
@@ -9,7 +9,7 @@
 // except according to those terms.
 
 use std::{io};
-use core::{ptr, mem::MaybeUninit};
+use core::ptr;
 
 use crate::buffer::{ReadBuffer, WriteBuffer, BufferResult::{self, BufferUnderflow, BufferOverflow}};
 use crate::symmetriccipher::{SynchronousStreamCipher};
@@ -62,12 +62,9 @@ pub fn write_u32_le(dst: &mut[u8], mut input: u32) {
 /// Read the value of a vector of bytes as a u32 value in little-endian format.
 pub fn read_u32_le(input: &[u8]) -> u32 {
     assert!(input.len() == 4);
-    unsafe {
-        #[allow(invalid_value)]
-        let mut tmp: u32 = MaybeUninit::uninit().assume_init();
-        ptr::copy_nonoverlapping(input.get_unchecked(0), &mut tmp as *mut _ as *mut u8, 4);
-        u32::from_le(tmp)
-    }
+    let mut tmp = [0u8; 4];
+    tmp.copy_from_slice(input);
+    u32::from_le_bytes(tmp)
 }
 
 /// XOR plaintext and keystream, storing the result in dst.
@@ -156,13 +153,13 @@ pub trait FixedBuffer {
 
     /// Get a slice of the buffer of the specified size. There must be at least that many bytes
     /// remaining in the buffer.
-    fn next<'s>(&'s mut self, len: usize) -> &'s mut [u8];
+    fn next(&mut self, len: usize) -> &mut [u8];
 
     /// Get the current buffer. The buffer must already be full. This clears the buffer as well.
-    fn full_buffer<'s>(&'s mut self) -> &'s [u8];
+    fn full_buffer(&mut self) -> &[u8];
 
      /// Get the current buffer.
-    fn current_buffer<'s>(&'s mut self) -> &'s [u8];
+    fn current_buffer(&mut self) -> &[u8];
 
     /// Get the current position of the buffer.
     fn position(&self) -> usize;
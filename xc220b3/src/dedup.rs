@@ -0,0 +1,250 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::kex::KeyExchange;
+use crate::session::Session;
+
+// the tags of accepted records, least recently seen first. a record's tag is
+// its MAC, so two records with the same tag are the same record.
+//
+// seeing a tag again moves it to the back by pushing it anew under a fresh
+// stamp, leaving the old entry in `order` stale: it's skipped wherever it
+// turns up, and `order` is compacted once stale entries make up half of it,
+// so it never holds more than twice `capacity`
+#[derive(Clone, Default)]
+pub(crate) struct TagCache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    order: VecDeque<(Vec<u8>, u64)>,
+    // tag to the stamp of its live entry in `order`, and when it was last seen
+    seen: HashMap<Vec<u8>, (u64, Instant)>,
+    stamp: u64,
+}
+
+impl TagCache {
+    /// Whether `tag` is remembered, which counts as seeing it again. Always
+    /// false when the cache is off.
+    pub(crate) fn contains(&mut self, tag: &[u8], now: Instant) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        self.expire(now);
+        match self.seen.contains_key(tag) {
+            true => {
+                self.touch(tag.to_vec(), now);
+                true
+            }
+            false => false,
+        }
+    }
+
+    /// Remembers the tag of a record that verified, or returns false if it's
+    /// remembered already. Always true when the cache is off.
+    pub(crate) fn accept(&mut self, tag: &[u8], now: Instant) -> bool {
+        if self.contains(tag, now) {
+            return false;
+        }
+        if self.capacity == 0 {
+            return true;
+        }
+        if self.seen.len() == self.capacity {
+            while let Some((oldest, stamp)) = self.order.pop_front() {
+                if self.seen.get(&oldest).map(|&(live, _)| live) == Some(stamp) {
+                    self.seen.remove(&oldest);
+                    break;
+                }
+            }
+        }
+        self.touch(tag.to_vec(), now);
+        true
+    }
+
+    fn touch(&mut self, tag: Vec<u8>, now: Instant) {
+        self.stamp += 1;
+        self.seen.insert(tag.clone(), (self.stamp, now));
+        self.order.push_back((tag, self.stamp));
+        if self.order.len() > 2 * self.capacity {
+            let seen = &self.seen;
+            self.order.retain(|(tag, stamp)| seen.get(tag).map(|&(live, _)| live) == Some(*stamp));
+        }
+    }
+
+    // the least recently seen are at the front, so expiry stops at the first
+    // live tag that's still fresh
+    fn expire(&mut self, now: Instant) {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        while let Some((tag, stamp)) = self.order.front() {
+            match self.seen.get(tag) {
+                Some(&(live, at)) if live == *stamp => {
+                    if now.saturating_duration_since(at) < ttl {
+                        break;
+                    }
+                    self.seen.remove(tag);
+                }
+                _ => {}
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Refuses a record whose tag is among the `capacity` most recently seen
+    /// with [`SessionError::DuplicateMessage`](crate::SessionError::DuplicateMessage),
+    /// for transports that may deliver the same record more than once. A
+    /// duplicate is refused before it's decrypted, and counts as seeing its
+    /// tag again. Only the tags of records that verified are kept, and the
+    /// least recently seen is forgotten to make room, so a duplicate that
+    /// comes after `capacity` other records is let through again. Zero, the
+    /// default, turns it off, and changing the capacity forgets every tag.
+    ///
+    /// The cache holds at most `capacity` tags, 24 bytes each with the
+    /// default cipher, and twice that in bookkeeping, however the records
+    /// come.
+    ///
+    /// Records don't carry a counter, so unless the session is
+    /// [obfuscated](crate::SessionBuilder::obfuscate) the same plaintext
+    /// always seals to the same record, and a message that's really sent
+    /// twice is refused the second time as well. Only use this where every
+    /// message is unique anyway, carrying its own id say. Heartbeats are
+    /// never refused.
+    pub fn set_dedup_capacity(&mut self, capacity: usize) {
+        self.seen = TagCache { capacity, ttl: self.seen.ttl, ..TagCache::default() };
+    }
+
+    /// Forgets a tag in the [duplicate cache](Session::set_dedup_capacity)
+    /// `ttl` after it was last seen, measured with the
+    /// [clock](Session::set_clock), so a redelivery later than that is let
+    /// through. `None`, the default, keeps tags until they're pushed out.
+    pub fn set_dedup_ttl(&mut self, ttl: Option<Duration>) {
+        self.seen.ttl = ttl;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+
+    use crate::dedup::TagCache;
+    use crate::failures::Clock;
+    use crate::session::{Received, Session, SessionError};
+
+    struct TestClock(Mutex<Instant>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_replayed_record_is_refused() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+
+        // off by default
+        assert_eq!(sesh2.clone().decrypt(record.clone()).unwrap(), b"hello");
+        assert_eq!(sesh2.clone().decrypt(record.clone()).unwrap(), b"hello");
+
+        sesh2.set_dedup_capacity(16);
+        assert_eq!(sesh2.decrypt(record.clone()).unwrap(), b"hello");
+        assert!(matches!(sesh2.decrypt(record.clone()), Err(SessionError::DuplicateMessage)));
+        assert_eq!((sesh2.stats().received, sesh2.stats().auth_failures), (1, 0));
+
+        // refused by its tag alone, before anything is decrypted
+        let mut altered = record.clone();
+        altered[1] ^= 1;
+        assert!(matches!(sesh2.decrypt(altered), Err(SessionError::DuplicateMessage)));
+        assert_eq!(sesh2.stats().auth_failures, 0);
+
+        // a tampered tag is still just tampering
+        let mut tampered = record;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(sesh2.decrypt(tampered), Err(SessionError::MacMismatch)));
+
+        let heartbeat = sesh1.heartbeat().unwrap();
+        assert_eq!(sesh2.receive(heartbeat.clone()).unwrap(), Received::Heartbeat);
+        assert_eq!(sesh2.receive(heartbeat).unwrap(), Received::Heartbeat);
+    }
+
+    #[test]
+    fn test_oldest_tags_are_evicted() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        sesh2.set_dedup_capacity(2);
+        let records: Vec<Vec<u8>> = (0..3u8).map(|i| sesh1.encrypt(vec![i]).unwrap()).collect();
+        for record in &records {
+            sesh2.decrypt(record.clone()).unwrap();
+        }
+
+        assert!(matches!(sesh2.decrypt(records[2].clone()), Err(SessionError::DuplicateMessage)));
+        assert!(matches!(sesh2.decrypt(records[1].clone()), Err(SessionError::DuplicateMessage)));
+        // forgotten, so it's let through, pushing out records[2], which was
+        // seen less recently than records[1]
+        assert_eq!(sesh2.decrypt(records[0].clone()).unwrap(), [0]);
+        assert!(matches!(sesh2.decrypt(records[1].clone()), Err(SessionError::DuplicateMessage)));
+        assert_eq!(sesh2.decrypt(records[2].clone()).unwrap(), [2]);
+    }
+
+    #[test]
+    fn test_unverified_records_are_never_remembered() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        sesh2.set_dedup_capacity(2);
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+
+        // garbage carrying the real record's tag, sent ahead of it
+        let mut forged = vec![0x5a; record.len()];
+        forged[0] = record[0];
+        let tag_start = record.len() - 24;
+        forged[tag_start..].copy_from_slice(&record[tag_start..]);
+        for _ in 0..3 {
+            assert!(matches!(sesh2.decrypt(forged.clone()), Err(SessionError::MacMismatch)));
+        }
+        for len in [30, 64, 200] {
+            let mut garbage = vec![0xa5; len];
+            garbage[0] = record[0];
+            assert!(sesh2.decrypt(garbage).is_err());
+        }
+        assert!(sesh2.seen.seen.is_empty() && sesh2.seen.order.is_empty());
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_tags_expire() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        let clock = Arc::new(TestClock(Mutex::new(Instant::now())));
+        sesh2.set_clock(clock.clone());
+        sesh2.set_dedup_capacity(16);
+        sesh2.set_dedup_ttl(Some(Duration::from_secs(60)));
+        let (first, second) = (sesh1.encrypt(vec![1]).unwrap(), sesh1.encrypt(vec![2]).unwrap());
+        sesh2.decrypt(first.clone()).unwrap();
+        sesh2.decrypt(second.clone()).unwrap();
+
+        // seeing one again keeps it for another minute
+        *clock.0.lock().unwrap() += Duration::from_secs(40);
+        assert!(matches!(sesh2.decrypt(second.clone()), Err(SessionError::DuplicateMessage)));
+        *clock.0.lock().unwrap() += Duration::from_secs(40);
+        assert!(matches!(sesh2.decrypt(second), Err(SessionError::DuplicateMessage)));
+        assert_eq!(sesh2.decrypt(first).unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_cache_stays_bounded() {
+        let mut cache = TagCache { capacity: 4, ..TagCache::default() };
+        let now = Instant::now();
+        for i in 0..1000u32 {
+            assert!(cache.accept(&i.to_le_bytes(), now));
+            // redelivered over and over, which only moves them to the back
+            for _ in 0..3 {
+                assert!(cache.contains(&i.to_le_bytes(), now));
+            }
+            assert!(cache.seen.len() <= 4 && cache.order.len() <= 8);
+        }
+        assert!((996..1000u32).all(|i| cache.contains(&i.to_le_bytes(), now)));
+        assert!(!cache.contains(&995u32.to_le_bytes(), now));
+    }
+}
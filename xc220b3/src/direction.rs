@@ -0,0 +1,170 @@
+// a keyed session cut down to one direction. each direction has its own key
+// (see Session::set_sym_key), so the half that only sends zeroes the receive
+// key before it's handed out and the half that only receives the send key:
+// neither holds what it would need for the other direction, whatever is done
+// with it
+
+use zeroize::Zeroize;
+
+use crate::close::CloseReason;
+use crate::kex::{KeyExchange, Secp256k1};
+use crate::session::{Received, Session, SessionError};
+
+/// A session that only seals records, from [`Session::into_sender`].
+///
+/// There's nothing to open records with:
+///
+/// ```compile_fail,E0599
+/// # let (sesh1, _) = xc220b3::Session::pair(&mut rand::thread_rng());
+/// let mut sender = sesh1.into_sender().unwrap();
+/// sender.decrypt(Vec::new());
+/// ```
+pub struct SessionSender<K: KeyExchange = Secp256k1> {
+    session: Session<K>,
+}
+
+/// A session that only opens records, from [`Session::into_receiver`].
+///
+/// There's nothing to seal records with:
+///
+/// ```compile_fail,E0599
+/// # let (sesh1, _) = xc220b3::Session::pair(&mut rand::thread_rng());
+/// let mut receiver = sesh1.into_receiver().unwrap();
+/// receiver.encrypt(Vec::new());
+/// ```
+pub struct SessionReceiver<K: KeyExchange = Secp256k1> {
+    session: Session<K>,
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Gives up receiving for a handle that can only send, for a flow that
+    /// only goes one way or to move the sending half to a task of its own.
+    /// The receive keys are zeroed. [`SessionError::NotReady`] before the
+    /// session is keyed.
+    pub fn into_sender(mut self) -> Result<SessionSender<K>, SessionError> {
+        self.require_ready()?;
+        self.recv_key.zeroize();
+        self.lengths.recv.zeroize();
+        self.previous = None;
+        self.recv_closed = true;
+        Ok(SessionSender { session: self })
+    }
+
+    /// Gives up sending for a handle that can only receive, see
+    /// [`Session::into_sender`]. The send key is zeroed.
+    pub fn into_receiver(mut self) -> Result<SessionReceiver<K>, SessionError> {
+        self.require_ready()?;
+        self.send_key.zeroize();
+        self.lengths.send.zeroize();
+        self.send_closed = true;
+        Ok(SessionReceiver { session: self })
+    }
+}
+
+impl<K: KeyExchange> SessionSender<K> {
+    /// [`Session::encrypt`].
+    pub fn encrypt(&mut self, plain: Vec<u8>) -> Result<Vec<u8>, SessionError> {
+        self.session.encrypt(plain)
+    }
+
+    /// [`Session::encrypt_into`].
+    pub fn encrypt_into(&mut self, plain: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        self.session.encrypt_into(plain, out)
+    }
+
+    /// [`Session::heartbeat`].
+    pub fn heartbeat(&mut self) -> Result<Vec<u8>, SessionError> {
+        self.session.heartbeat()
+    }
+
+    /// [`Session::send_rekey`].
+    pub fn send_rekey(&mut self) -> Result<Vec<u8>, SessionError> {
+        self.session.send_rekey()
+    }
+
+    /// [`Session::close`].
+    pub fn close(&mut self, reason: CloseReason) -> Result<Vec<u8>, SessionError> {
+        self.session.close(reason)
+    }
+
+    /// [`Session::messages_remaining`].
+    pub fn messages_remaining(&self) -> u64 {
+        self.session.messages_remaining()
+    }
+
+    /// [`Session::session_id`].
+    pub fn session_id(&self) -> [u8; 16] {
+        self.session.session_id()
+    }
+}
+
+impl<K: KeyExchange> SessionReceiver<K> {
+    /// [`Session::decrypt`].
+    pub fn decrypt(&mut self, ciphertext: Vec<u8>) -> Result<Vec<u8>, SessionError> {
+        self.session.decrypt(ciphertext)
+    }
+
+    /// [`Session::decrypt_into`].
+    pub fn decrypt_into(&mut self, record: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        self.session.decrypt_into(record, out)
+    }
+
+    /// [`Session::receive`].
+    pub fn receive(&mut self, ciphertext: Vec<u8>) -> Result<Received, SessionError> {
+        self.session.receive(ciphertext)
+    }
+
+    /// [`Session::session_id`].
+    pub fn session_id(&self) -> [u8; 16] {
+        self.session.session_id()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use rand::thread_rng;
+
+    use crate::close::CloseReason;
+    use crate::session::{Received, Session, SessionError};
+
+    #[test]
+    fn test_halves_on_their_own_threads() {
+        let (sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let mut sender = sesh1.into_sender().unwrap();
+        let mut receiver = sesh2.into_receiver().unwrap();
+        assert_eq!(sender.session.recv_key, [0; 32]);
+        assert_eq!(receiver.session.send_key, [0; 32]);
+        assert_eq!(sender.session_id(), receiver.session_id());
+
+        let (records, incoming) = mpsc::channel();
+        let sending = thread::spawn(move || {
+            for i in 0..10u8 {
+                records.send(sender.encrypt(vec![i]).unwrap()).unwrap();
+            }
+            records.send(sender.send_rekey().unwrap()).unwrap();
+            records.send(sender.close(CloseReason::Normal).unwrap()).unwrap();
+        });
+        let receiving = thread::spawn(move || incoming.into_iter().map(|record| receiver.receive(record).unwrap()).collect::<Vec<_>>());
+        sending.join().unwrap();
+        let received = receiving.join().unwrap();
+        assert_eq!(received[..10], (0..10u8).map(|i| Received::Data(vec![i])).collect::<Vec<_>>()[..]);
+        assert_eq!(received[10..], [Received::Rekeyed, Received::Closed(CloseReason::Normal)]);
+    }
+
+    #[test]
+    fn test_halves_need_a_keyed_session() {
+        assert!(matches!(Session::new(&mut thread_rng()).into_sender(), Err(SessionError::NotReady)));
+        assert!(matches!(Session::new(&mut thread_rng()).into_receiver(), Err(SessionError::NotReady)));
+
+        // what's left of the other direction refuses, keyless
+        let (sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let record = sesh2.encrypt(b"to the sender".to_vec()).unwrap();
+        let mut sender = sesh1.into_sender().unwrap();
+        assert!(matches!(sender.session.decrypt(record), Err(SessionError::Closed)));
+        let mut receiver = sesh2.into_receiver().unwrap();
+        assert!(matches!(receiver.session.encrypt(b"back".to_vec()), Err(SessionError::Closed)));
+    }
+}
@@ -0,0 +1,283 @@
+// one-shot public key encryption, ECIES style, for payloads sent to someone
+// who reads them later and never answers. a sealed payload is
+//
+//   ephemeral public key (33, compressed) || record
+//
+// where the record is the first record of a session between a fresh
+// ephemeral key and the recipient's static one, and it shares everything
+// with sessions but how the two keys met: the shared secret is ECDH between
+// them, the transcript is the BLAKE3 hash under ECIES_CONTEXT of both keys
+// (compressed, ephemeral first), and the session KDF turns them into the
+// sender's initiator to responder key, as for any initiator. the record is
+// an ordinary version 2 one, header || ciphertext || tag, its tag being its
+// nonce as for every record, so there's no nonce apart from it. its keys
+// were bound to both public keys from the start, so it has no use for
+// version 3, and payloads sealed before version 3 still open
+//
+// nothing the recipient contributed goes in, so a sealed payload opens as
+// often as it's sent: it's confidential and authentic for anyone who holds
+// the recipient's secret, not fresh
+//
+// a signcrypted payload is sealed the same way, under SIGNCRYPT_CONTEXT
+// instead, around
+//
+//   sender public key (33, compressed) || signature (64) || plaintext
+//
+// the signature is ECDSA over secp256k1 as for certificates, of the BLAKE3
+// hash under SIGNATURE_CONTEXT of the ephemeral key, the recipient's key
+// and the plaintext, so a recipient can't pass it on sealed to someone else
+// as if the sender had sent it to them. it's inside the record, so only the
+// recipient learns who signed
+
+use k256::{
+    ecdsa::{
+        signature::hazmat::{PrehashSigner, PrehashVerifier},
+        Signature, SigningKey, VerifyingKey,
+    },
+    elliptic_curve::sec1::ToEncodedPoint,
+    EncodedPoint, PublicKey, SecretKey,
+};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::certificate::{signature_bytes, verify_certificate_chain, Certificate};
+use crate::record;
+use crate::session::{Role, Session, SessionError};
+
+const ECIES_CONTEXT: &str = "xc220b3 2022-10 ecies";
+const SIGNCRYPT_CONTEXT: &str = "xc220b3 2022-10 signcrypt";
+const SIGNATURE_CONTEXT: &str = "xc220b3 2022-10 signcrypt signature";
+
+/// Bytes [`seal`] adds to a plaintext.
+pub const SEALED_OVERHEAD: usize = EPHEMERAL_LEN + record::OVERHEAD;
+/// Bytes [`signcrypt`] adds to a plaintext.
+pub const SIGNCRYPTED_OVERHEAD: usize = SEALED_OVERHEAD + SIGNED_LEN;
+
+const EPHEMERAL_LEN: usize = 33;
+// the sender's key and signature in front of a signcrypted plaintext
+const SIGNED_LEN: usize = 33 + 64;
+
+/// Encrypts `plain` to whoever holds the secret key of `recipient`, with a
+/// fresh ephemeral key. [`open`] reverses it.
+pub fn seal(recipient: &PublicKey, plain: &[u8], rng: &mut (impl CryptoRng + RngCore)) -> Vec<u8> {
+    seal_with_ephemeral(recipient, plain, &SecretKey::random(rng))
+}
+
+/// [`seal`] to the key `certificate` names, once it's checked that `root`
+/// issued it, for encrypting to a verified identity with no session to it.
+/// A certificate `root` didn't issue is
+/// [`SessionError::InvalidCertificate`] and nothing is sealed. The recipient
+/// opens it with [`open`] and the certified key's secret.
+pub fn seal_to_certificate(
+    certificate: &Certificate,
+    root: &PublicKey,
+    plain: &[u8],
+    rng: &mut (impl CryptoRng + RngCore),
+) -> Result<Vec<u8>, SessionError> {
+    let recipient = verify_certificate_chain(core::slice::from_ref(certificate), root)?;
+    Ok(seal(&recipient, plain, rng))
+}
+
+// the ephemeral key given, for known-answer vectors
+pub(crate) fn seal_with_ephemeral(recipient: &PublicKey, plain: &[u8], ephemeral: &SecretKey) -> Vec<u8> {
+    seal_in(ECIES_CONTEXT, recipient, plain, ephemeral)
+}
+
+fn seal_in(context: &str, recipient: &PublicKey, plain: &[u8], ephemeral: &SecretKey) -> Vec<u8> {
+    let ephemeral_pk = ephemeral.public_key().to_encoded_point(true);
+    let recipient_pk = recipient.to_encoded_point(true);
+    let shared = k256::ecdh::diffie_hellman(ephemeral.to_nonzero_scalar(), recipient.as_affine());
+    let mut session = one_shot(context, Role::Initiator, shared.raw_secret_bytes(), &ephemeral_pk, &recipient_pk);
+
+    let mut sealed = ephemeral_pk.as_bytes().to_vec();
+    sealed.extend_from_slice(&session.encrypt(plain.to_vec()).expect("a fresh one-shot session can send"));
+    sealed
+}
+
+/// Decrypts what [`seal`] encrypted to the public key of `recipient`.
+/// [`SessionError::MacMismatch`] if it was for someone else or tampered
+/// with, [`SessionError::InvalidPubKey`] if its ephemeral key isn't one.
+pub fn open(recipient: &SecretKey, sealed: &[u8]) -> Result<Vec<u8>, SessionError> {
+    open_in(ECIES_CONTEXT, recipient, sealed).map(|(_, plain)| plain)
+}
+
+// gives the ephemeral key too
+fn open_in(context: &str, recipient: &SecretKey, sealed: &[u8]) -> Result<(EncodedPoint, Vec<u8>), SessionError> {
+    if sealed.len() < SEALED_OVERHEAD {
+        return Err(SessionError::InvalidLength(sealed.len()));
+    }
+    let (ephemeral_pk, record) = sealed.split_at(EPHEMERAL_LEN);
+    let ephemeral = PublicKey::from_sec1_bytes(ephemeral_pk)?;
+    let ephemeral_pk = ephemeral.to_encoded_point(true);
+    let recipient_pk = recipient.public_key().to_encoded_point(true);
+    let shared = k256::ecdh::diffie_hellman(recipient.to_nonzero_scalar(), ephemeral.as_affine());
+    let plain = one_shot(context, Role::Responder, shared.raw_secret_bytes(), &ephemeral_pk, &recipient_pk).decrypt(record.to_vec())?;
+    Ok((ephemeral_pk, plain))
+}
+
+/// [`seal`] with the plaintext signed by `sender`, so the recipient knows
+/// who sent it and that it was sent to them. [`unsigncrypt`] reverses it.
+/// Who signed is encrypted along with the plaintext.
+pub fn signcrypt(sender: &SigningKey, recipient: &PublicKey, plain: &[u8], rng: &mut (impl CryptoRng + RngCore)) -> Vec<u8> {
+    let ephemeral = SecretKey::random(&mut *rng);
+    let hash = signed_hash(&ephemeral.public_key().to_encoded_point(true), &recipient.to_encoded_point(true), plain);
+    let signature = signature_bytes(sender.sign_prehash(&hash).expect("a 32 byte prehash"));
+    let signed = [sender.verifying_key().to_encoded_point(true).as_bytes(), &signature, plain].concat();
+    seal_in(SIGNCRYPT_CONTEXT, recipient, &signed, &ephemeral)
+}
+
+/// Decrypts what [`signcrypt`] encrypted to the public key of `recipient`,
+/// and checks `expected_sender` signed it, for this recipient.
+/// [`SessionError::MacMismatch`] if it doesn't decrypt,
+/// [`SessionError::UnexpectedSender`] if someone else signed it and
+/// [`SessionError::InvalidSignature`] if the signature doesn't check, as
+/// for a payload passed on sealed to another recipient.
+pub fn unsigncrypt(recipient: &SecretKey, expected_sender: &VerifyingKey, sealed: &[u8]) -> Result<Vec<u8>, SessionError> {
+    let (ephemeral_pk, mut signed) = open_in(SIGNCRYPT_CONTEXT, recipient, sealed)?;
+    if signed.len() < SIGNED_LEN {
+        return Err(SessionError::InvalidLength(sealed.len()));
+    }
+    if signed[..33] != *expected_sender.to_encoded_point(true).as_bytes() {
+        return Err(SessionError::UnexpectedSender);
+    }
+    let signature = Signature::try_from(&signed[33..SIGNED_LEN]).map_err(|_| SessionError::InvalidSignature)?;
+    let plain = signed.split_off(SIGNED_LEN);
+    let hash = signed_hash(&ephemeral_pk, &recipient.public_key().to_encoded_point(true), &plain);
+    match expected_sender.verify_prehash(&hash, &signature) {
+        Ok(()) => Ok(plain),
+        Err(_) => Err(SessionError::InvalidSignature),
+    }
+}
+
+fn signed_hash(ephemeral_pk: &EncodedPoint, recipient_pk: &EncodedPoint, plain: &[u8]) -> [u8; 32] {
+    let mut b3 = blake3::Hasher::new_derive_key(SIGNATURE_CONTEXT);
+    b3.update(ephemeral_pk.as_bytes());
+    b3.update(recipient_pk.as_bytes());
+    b3.update(plain);
+    *b3.finalize().as_bytes()
+}
+
+fn one_shot(context: &str, role: Role, shared: &[u8], ephemeral_pk: &EncodedPoint, recipient_pk: &EncodedPoint) -> Session {
+    let mut b3 = blake3::Hasher::new_derive_key(context);
+    b3.update(ephemeral_pk.as_bytes());
+    b3.update(recipient_pk.as_bytes());
+    let mut session = Session::from_secret(None);
+    session.set_version(record::V2);
+    // a payload is one record, as long as the cipher allows, with no counter
+    session.config.ordered = false;
+    session.config.max_message_len = usize::try_from(session.cipher.max_plaintext_len()).unwrap_or(usize::MAX);
+    session.install_keys(role, shared, b3.finalize().as_bytes());
+    session
+}
+
+#[cfg(test)]
+mod test {
+    use k256::{ecdsa::SigningKey, elliptic_curve::sec1::ToEncodedPoint, SecretKey};
+    use rand::thread_rng;
+
+    use crate::certificate::Certificate;
+    use crate::ecies::{
+        open, open_in, seal, seal_in, seal_to_certificate, signcrypt, unsigncrypt, SEALED_OVERHEAD, SIGNCRYPTED_OVERHEAD,
+        SIGNCRYPT_CONTEXT,
+    };
+    use crate::session::SessionError;
+
+    #[test]
+    fn test_seal_round_trip() {
+        let recipient = SecretKey::random(&mut thread_rng());
+        for plain in [&b""[..], b"config", &[0x5a; 100_000]] {
+            let sealed = seal(&recipient.public_key(), plain, &mut thread_rng());
+            assert_eq!(sealed.len(), plain.len() + SEALED_OVERHEAD);
+            assert_eq!(open(&recipient, &sealed).unwrap(), plain);
+        }
+        // a fresh ephemeral key every time
+        let pk = recipient.public_key();
+        assert_ne!(seal(&pk, b"same", &mut thread_rng()), seal(&pk, b"same", &mut thread_rng()));
+    }
+
+    #[test]
+    fn test_open_refuses() {
+        let recipient = SecretKey::random(&mut thread_rng());
+        let sealed = seal(&recipient.public_key(), b"config", &mut thread_rng());
+
+        let someone_else = SecretKey::random(&mut thread_rng());
+        assert!(matches!(open(&someone_else, &sealed), Err(SessionError::MacMismatch)));
+
+        // a different ephemeral key, where it's still a point, keys differently
+        let mut other_ephemeral = sealed.clone();
+        other_ephemeral[..33].copy_from_slice(&seal(&recipient.public_key(), b"", &mut thread_rng())[..33]);
+        assert!(matches!(open(&recipient, &other_ephemeral), Err(SessionError::MacMismatch)));
+        for i in 0..33 {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(open(&recipient, &tampered).is_err(), "{}", i);
+        }
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(open(&recipient, &tampered), Err(SessionError::MacMismatch)));
+        assert!(matches!(open(&recipient, &sealed[..40]), Err(SessionError::InvalidLength(40))));
+    }
+
+    #[test]
+    fn test_signcrypt_round_trip() {
+        let (sender, recipient) = (SigningKey::random(&mut thread_rng()), SecretKey::random(&mut thread_rng()));
+        let sealed = signcrypt(&sender, &recipient.public_key(), b"config", &mut thread_rng());
+        assert_eq!(sealed.len(), 6 + SIGNCRYPTED_OVERHEAD);
+        assert_eq!(unsigncrypt(&recipient, &sender.verifying_key(), &sealed).unwrap(), b"config");
+        // the sender's key is only inside the record
+        let sender_pk = sender.verifying_key().to_encoded_point(true);
+        assert!(!sealed.windows(33).any(|window| window == sender_pk.as_bytes()));
+        // and it's not an ecies payload
+        assert!(matches!(open(&recipient, &sealed), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_unsigncrypt_failures_are_told_apart() {
+        let rng = &mut thread_rng();
+        let (sender, stranger) = (SigningKey::random(&mut *rng), SigningKey::random(&mut *rng));
+        let keys: Vec<SecretKey> = (0..3).map(|_| SecretKey::random(&mut *rng)).collect();
+        let (recipient, other, eavesdropper) = (&keys[0], &keys[1], &keys[2]);
+        let sender_key = &sender.verifying_key();
+        let sealed = signcrypt(&sender, &recipient.public_key(), b"config", rng);
+
+        assert!(matches!(unsigncrypt(eavesdropper, sender_key, &sealed), Err(SessionError::MacMismatch)));
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(unsigncrypt(recipient, sender_key, &tampered), Err(SessionError::MacMismatch)));
+        let result = unsigncrypt(recipient, &stranger.verifying_key(), &sealed);
+        assert!(matches!(result, Err(SessionError::UnexpectedSender)));
+
+        // the recipient passes it on to someone else, signature and all
+        let (_, signed) = open_in(SIGNCRYPT_CONTEXT, recipient, &sealed).unwrap();
+        let replayed = seal_in(SIGNCRYPT_CONTEXT, &other.public_key(), &signed, &SecretKey::random(&mut *rng));
+        assert!(matches!(unsigncrypt(other, sender_key, &replayed), Err(SessionError::InvalidSignature)));
+
+        // or changes the plaintext under the sender's signature
+        let mut forged = signed.clone();
+        *forged.last_mut().unwrap() ^= 1;
+        let forged = seal_in(SIGNCRYPT_CONTEXT, &recipient.public_key(), &forged, &SecretKey::random(&mut *rng));
+        assert!(matches!(unsigncrypt(recipient, sender_key, &forged), Err(SessionError::InvalidSignature)));
+
+        let short = seal_in(SIGNCRYPT_CONTEXT, &recipient.public_key(), &signed[..50], &SecretKey::random(&mut *rng));
+        assert!(matches!(unsigncrypt(recipient, sender_key, &short), Err(SessionError::InvalidLength(_))));
+    }
+
+    #[test]
+    fn test_seal_to_certificate() {
+        let rng = &mut thread_rng();
+        let (root, recipient, impostor) = (SecretKey::random(&mut *rng), SecretKey::random(&mut *rng), SecretKey::random(&mut *rng));
+        let certificate = Certificate::issue(&root, &recipient.public_key());
+        let sealed = seal_to_certificate(&certificate, &root.public_key(), b"config", rng).unwrap();
+        assert_eq!(sealed.len(), 6 + SEALED_OVERHEAD);
+        assert_eq!(open(&recipient, &sealed).unwrap(), b"config");
+
+        // issued by someone else, or the signature moved onto another key
+        let self_signed = Certificate::issue(&impostor, &impostor.public_key());
+        let result = seal_to_certificate(&self_signed, &root.public_key(), b"config", rng);
+        assert!(matches!(result, Err(SessionError::InvalidCertificate(0))));
+        let mut swapped = certificate.to_bytes();
+        swapped[..33].copy_from_slice(impostor.public_key().to_encoded_point(true).as_bytes());
+        let swapped = Certificate::from_bytes(&swapped).unwrap();
+        let result = seal_to_certificate(&swapped, &root.public_key(), b"config", rng);
+        assert!(matches!(result, Err(SessionError::InvalidCertificate(0))));
+    }
+}
@@ -0,0 +1,230 @@
+// a message with metadata beside it, sealed together as one record's
+// plaintext:
+//
+//   le16(metadata length) || entries || body
+//   entry = le16(key length) || key || le16(value length) || value
+//
+// keys are UTF-8 and appear once each, in the order they were set. two are
+// known: "content-type", UTF-8 text, and "timestamp", le64 milliseconds since
+// the unix epoch. any other key is carried through as it came, so a reader
+// that doesn't know it still hands it back. the metadata, entries' length
+// prefixes included, is at most MAX_ENVELOPE_METADATA_LEN bytes
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::kex::KeyExchange;
+use crate::session::{Session, SessionError};
+
+/// Most bytes of metadata an [`Envelope`] can carry, encoded.
+pub const MAX_ENVELOPE_METADATA_LEN: usize = 4096;
+
+const CONTENT_TYPE: &str = "content-type";
+const TIMESTAMP: &str = "timestamp";
+
+/// A message and its metadata, see [`Session::encrypt_envelope`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    metadata: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new(body: Vec<u8>) -> Envelope {
+        Envelope { metadata: Vec::new(), body }
+    }
+
+    pub fn content_type(self, content_type: &str) -> Envelope {
+        self.set(CONTENT_TYPE, content_type.as_bytes().to_vec())
+    }
+
+    /// Kept to the millisecond.
+    pub fn timestamp(self, at: SystemTime) -> Envelope {
+        let millis = at.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        self.set(TIMESTAMP, millis.to_le_bytes().to_vec())
+    }
+
+    /// Metadata of the application's own. Setting a key again replaces it.
+    pub fn extra(self, key: &str, value: &str) -> Envelope {
+        self.set(key, value.as_bytes().to_vec())
+    }
+
+    fn set(mut self, key: &str, value: Vec<u8>) -> Envelope {
+        match self.metadata.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.metadata.push((key.to_string(), value)),
+        }
+        self
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    pub fn get_content_type(&self) -> Option<&str> {
+        self.get(CONTENT_TYPE).map(|value| core::str::from_utf8(value).unwrap())
+    }
+
+    pub fn get_timestamp(&self) -> Option<SystemTime> {
+        let millis = u64::from_le_bytes(self.get(TIMESTAMP)?.try_into().unwrap());
+        Some(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    /// The raw value of any key, known or not.
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.metadata.iter().find(|(k, _)| k == key).map(|(_, value)| &value[..])
+    }
+
+    /// Every key and its raw value, in the order they were set.
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.metadata.iter().map(|(key, value)| (&key[..], &value[..]))
+    }
+
+    /// The encoded metadata's length, what's held to
+    /// [`MAX_ENVELOPE_METADATA_LEN`].
+    pub fn metadata_len(&self) -> usize {
+        self.metadata.iter().map(|(key, value)| 4 + key.len() + value.len()).sum()
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, SessionError> {
+        let metadata_len = self.metadata_len();
+        if metadata_len > MAX_ENVELOPE_METADATA_LEN {
+            return Err(SessionError::MetadataTooLarge(metadata_len));
+        }
+        let mut out = Vec::with_capacity(2 + metadata_len + self.body.len());
+        out.extend_from_slice(&(metadata_len as u16).to_le_bytes());
+        for (key, value) in &self.metadata {
+            out.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            out.extend_from_slice(key.as_bytes());
+            out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            out.extend_from_slice(value);
+        }
+        out.extend_from_slice(&self.body);
+        Ok(out)
+    }
+
+    fn from_bytes(mut plain: Vec<u8>) -> Result<Envelope, SessionError> {
+        let metadata_len = read_len(&plain).ok_or(SessionError::MalformedEnvelope)?;
+        if metadata_len > MAX_ENVELOPE_METADATA_LEN || 2 + metadata_len > plain.len() {
+            return Err(SessionError::MalformedEnvelope);
+        }
+
+        let mut entries = &plain[2..2 + metadata_len];
+        let mut envelope = Envelope::new(Vec::new());
+        while !entries.is_empty() {
+            let key = take(&mut entries).and_then(|key| core::str::from_utf8(key).ok()).ok_or(SessionError::MalformedEnvelope)?;
+            let value = take(&mut entries).ok_or(SessionError::MalformedEnvelope)?;
+            let known_ok = match key {
+                CONTENT_TYPE => core::str::from_utf8(value).is_ok(),
+                TIMESTAMP => value.len() == 8,
+                _ => true,
+            };
+            if !known_ok || envelope.get(key).is_some() {
+                return Err(SessionError::MalformedEnvelope);
+            }
+            envelope.metadata.push((key.to_string(), value.to_vec()));
+        }
+
+        envelope.body = plain.split_off(2 + metadata_len);
+        Ok(envelope)
+    }
+}
+
+fn read_len(bytes: &[u8]) -> Option<usize> {
+    Some(u16::from_le_bytes(bytes.get(..2)?.try_into().unwrap()) as usize)
+}
+
+// one length-prefixed field off the front of `entries`
+fn take<'a>(entries: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = read_len(entries)?;
+    let field = entries.get(2..2 + len)?;
+    *entries = &entries[2 + len..];
+    Some(field)
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Encrypts `envelope`, metadata and body, as one record. Fails with
+    /// [`SessionError::MetadataTooLarge`] before anything is encrypted if the
+    /// metadata is over [`MAX_ENVELOPE_METADATA_LEN`].
+    pub fn encrypt_envelope(&mut self, envelope: &Envelope) -> Result<Vec<u8>, SessionError> {
+        self.encrypt(envelope.to_bytes()?)
+    }
+
+    /// Decrypts a record from [`Session::encrypt_envelope`]. The envelope is
+    /// only parsed once the record has authenticated, and one that isn't
+    /// well formed is [`SessionError::MalformedEnvelope`].
+    pub fn decrypt_envelope(&mut self, ciphertext: Vec<u8>) -> Result<Envelope, SessionError> {
+        Envelope::from_bytes(self.decrypt(ciphertext)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use rand::thread_rng;
+
+    use crate::envelope::{Envelope, MAX_ENVELOPE_METADATA_LEN};
+    use crate::session::{Session, SessionError};
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let at = UNIX_EPOCH + Duration::from_millis(1_666_000_000_123);
+        let envelope = Envelope::new(b"{\"hello\":1}".to_vec())
+            .content_type("application/json")
+            .timestamp(at)
+            .extra("schema", "3")
+            .extra("schema", "4");
+
+        let received = sesh2.decrypt_envelope(sesh1.encrypt_envelope(&envelope).unwrap()).unwrap();
+        assert_eq!(received, envelope);
+        assert_eq!(received.get_content_type(), Some("application/json"));
+        assert_eq!(received.get_timestamp(), Some(at));
+        assert_eq!(received.get("schema"), Some(&b"4"[..]));
+        assert_eq!(received.body(), b"{\"hello\":1}");
+
+        // no metadata at all
+        let bare = Envelope::new(Vec::new());
+        let received = sesh2.decrypt_envelope(sesh1.encrypt_envelope(&bare).unwrap()).unwrap();
+        assert_eq!((received.get_content_type(), received.get_timestamp(), received.metadata().count()), (None, None, 0));
+
+        // authenticates, but isn't an envelope
+        let record = sesh1.encrypt(vec![9, 0]).unwrap();
+        assert!(matches!(sesh2.decrypt_envelope(record), Err(SessionError::MalformedEnvelope)));
+    }
+
+    #[test]
+    fn test_unknown_keys_pass_through() {
+        // from a newer sender, with keys this side knows nothing about
+        let envelope = Envelope::new(b"body".to_vec()).extra("trace-id", "abc").content_type("text/plain").extra("z", "");
+        let bytes = envelope.to_bytes().unwrap();
+        let parsed = Envelope::from_bytes(bytes.clone()).unwrap();
+        let keys: Vec<&str> = parsed.metadata().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["trace-id", "content-type", "z"]);
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+
+        // a known key with a value it can't be
+        let mut bad = Envelope::new(Vec::new());
+        bad.metadata.push(("timestamp".to_string(), vec![1, 2, 3]));
+        assert!(matches!(Envelope::from_bytes(bad.to_bytes().unwrap()), Err(SessionError::MalformedEnvelope)));
+    }
+
+    #[test]
+    fn test_oversized_metadata_is_refused() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let value = "x".repeat(MAX_ENVELOPE_METADATA_LEN - 4 - 3);
+        let envelope = Envelope::new(Vec::new()).extra("big", &value);
+        assert_eq!(envelope.metadata_len(), MAX_ENVELOPE_METADATA_LEN);
+        let fits = sesh1.encrypt_envelope(&envelope).unwrap();
+
+        let envelope = envelope.extra("more", "");
+        let remaining = sesh1.messages_remaining();
+        assert!(matches!(sesh1.encrypt_envelope(&envelope), Err(SessionError::MetadataTooLarge(len)) if len == MAX_ENVELOPE_METADATA_LEN + 8));
+        assert_eq!(sesh1.messages_remaining(), remaining);
+        sesh2.decrypt_envelope(fits).unwrap();
+    }
+}
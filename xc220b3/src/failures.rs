@@ -0,0 +1,265 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use crate::kex::KeyExchange;
+use crate::session::{Session, SessionError};
+
+/// Where a session gets the time from for its failure window. Swap it out
+/// with [`Session::set_clock`] to drive the window by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The real clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// After `max_failures` authentication failures within `window` (counted
+/// from the first of them), every decrypt is refused with
+/// [`SessionError::TooManyFailures`] without touching the record, until the
+/// window runs out or [`Session::reset_failures`] is called.
+///
+/// Each successful decrypt forgives one failure in the current window, so a
+/// mostly healthy peer with the odd corrupted record never trips it, while a
+/// flood of garbage still does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FailurePolicy {
+    pub max_failures: u32,
+    pub window: Duration,
+}
+
+/// Counters for a session, see [`Session::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub sent: u64,
+    pub received: u64,
+    /// Authentication failures over the whole session.
+    pub auth_failures: u64,
+    /// Failures counted against the current [`FailurePolicy`] window.
+    pub window_failures: u32,
+    /// Whether decrypts are currently being refused.
+    pub tripped: bool,
+    /// See [`Session::session_id`].
+    pub session_id: [u8; 16],
+    /// Renegotiations completed, see [`Session::renegotiate`].
+    pub epoch: u64,
+}
+
+#[derive(Clone)]
+pub(crate) struct FailureWindow {
+    policy: Option<FailurePolicy>,
+    clock: Arc<dyn Clock>,
+    started: Option<Instant>,
+    count: u32,
+}
+
+impl FailureWindow {
+    pub(crate) fn new() -> FailureWindow {
+        FailureWindow {
+            policy: None,
+            clock: Arc::new(SystemClock),
+            started: None,
+            count: 0,
+        }
+    }
+
+    fn expired(&self, policy: &FailurePolicy) -> bool {
+        match self.started {
+            Some(started) => self.clock.now().saturating_duration_since(started) >= policy.window,
+            None => true,
+        }
+    }
+
+    fn tripped(&self) -> bool {
+        match &self.policy {
+            Some(policy) => self.count >= policy.max_failures && !self.expired(policy),
+            None => false,
+        }
+    }
+
+    /// Called before every decrypt.
+    pub(crate) fn check(&mut self) -> Result<(), SessionError> {
+        let policy = match self.policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        if self.started.is_some() && self.expired(&policy) {
+            self.reset();
+        }
+        match self.count >= policy.max_failures {
+            true => Err(SessionError::TooManyFailures),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn failed(&mut self) {
+        if self.policy.is_none() {
+            return;
+        }
+        if self.started.is_none() {
+            self.started = Some(self.clock.now());
+        }
+        self.count = self.count.saturating_add(1);
+    }
+
+    pub(crate) fn succeeded(&mut self) {
+        self.count = self.count.saturating_sub(1);
+        if self.count == 0 {
+            self.started = None;
+        }
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.started = None;
+        self.count = 0;
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Turns on failure flood protection, see [`FailurePolicy`]. Off by
+    /// default.
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failures.policy = Some(policy);
+        self.failures.reset();
+    }
+
+    /// Replaces the clock the failure window, the drain window of a
+    /// [renegotiation](Session::renegotiate) and the
+    /// [duplicate cache](Session::set_dedup_ttl)'s TTL are measured with.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.failures.clock = clock;
+    }
+
+    /// Clears the failure window, so decrypts are accepted again straight away.
+    pub fn reset_failures(&mut self) {
+        self.failures.reset();
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        SessionStats {
+            sent: self.sent,
+            received: self.received,
+            auth_failures: self.auth_failures,
+            window_failures: self.failures.count,
+            tripped: self.failures.tripped(),
+            session_id: self.session_id,
+            epoch: self.epoch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+
+    use crate::failures::{Clock, FailurePolicy};
+    use crate::session::{Session, SessionError};
+
+    struct TestClock(Mutex<Instant>);
+
+    impl TestClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn guarded_pair() -> (Session, Session, Arc<TestClock>) {
+        let (sesh1, mut sesh2) = Session::unordered_pair();
+        let clock = Arc::new(TestClock(Mutex::new(Instant::now())));
+        sesh2.set_clock(clock.clone());
+        sesh2.set_failure_policy(FailurePolicy { max_failures: 3, window: Duration::from_secs(10) });
+        (sesh1, sesh2, clock)
+    }
+
+    fn garbage(sesh: &mut Session) -> Vec<u8> {
+        let mut record = sesh.encrypt(b"hello".to_vec()).unwrap();
+        record[1] ^= 1;
+        record
+    }
+
+    #[test]
+    fn test_below_threshold_is_normal() {
+        let (mut sesh1, mut sesh2, _) = guarded_pair();
+        for _ in 0..2 {
+            let record = garbage(&mut sesh1);
+            assert!(matches!(sesh2.decrypt(record), Err(SessionError::MacMismatch)));
+        }
+        assert!(!sesh2.stats().tripped);
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_threshold_trips_breaker() {
+        let (mut sesh1, mut sesh2, _) = guarded_pair();
+        for _ in 0..3 {
+            let record = garbage(&mut sesh1);
+            assert!(matches!(sesh2.decrypt(record), Err(SessionError::MacMismatch)));
+        }
+
+        let stats = sesh2.stats();
+        assert!(stats.tripped);
+        assert_eq!(stats.window_failures, 3);
+        assert_eq!(stats.auth_failures, 3);
+
+        // even a good record is refused, it isn't looked at
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(sesh2.decrypt(record.clone()), Err(SessionError::TooManyFailures)));
+        assert_eq!(sesh2.stats().auth_failures, 3);
+
+        sesh2.reset_failures();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_breaker_resets_after_window() {
+        let (mut sesh1, mut sesh2, clock) = guarded_pair();
+        for _ in 0..3 {
+            let record = garbage(&mut sesh1);
+            assert!(matches!(sesh2.decrypt(record), Err(SessionError::MacMismatch)));
+        }
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+
+        clock.advance(Duration::from_secs(9));
+        assert!(matches!(sesh2.decrypt(record.clone()), Err(SessionError::TooManyFailures)));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!sesh2.stats().tripped);
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+        assert_eq!(sesh2.stats().window_failures, 0);
+    }
+
+    #[test]
+    fn test_success_forgives_one_failure() {
+        let (mut sesh1, mut sesh2, _) = guarded_pair();
+        // a bad record now and then is paid off by the good ones around it
+        for _ in 0..5 {
+            let record = garbage(&mut sesh1);
+            assert!(matches!(sesh2.decrypt(record), Err(SessionError::MacMismatch)));
+            let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+        }
+        assert_eq!(sesh2.stats().window_failures, 0);
+
+        for _ in 0..3 {
+            let record = garbage(&mut sesh1);
+            assert!(matches!(sesh2.decrypt(record), Err(SessionError::MacMismatch)));
+        }
+        assert!(sesh2.stats().tripped);
+    }
+}
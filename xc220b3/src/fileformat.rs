@@ -0,0 +1,527 @@
+// age-style encrypted files: a text header naming who can open the file, then
+// the payload as one chunked stream
+//
+//   xc220b3-file/v2
+//   -> secp256k1 <key id> <ephemeral public key> <wrapped file key>
+//   -> secp256k1 ...
+//   length <plaintext length>
+//   --- <header mac>
+//   <payload>
+//
+// keys and MACs are lowercase hex. every recipient's stanza wraps the random
+// file key under a key from ECDH with a fresh ephemeral key, and names the
+// recipient by a key id (see key_id) so someone holding many secret keys
+// goes straight to the right one. the header MAC is keyed from the file key
+// and covers everything up to and including `---`, key ids included, and
+// the payload key comes from the file key and the header MAC, so a
+// payload can't be moved under another header. the length is only there so
+// truncation can be told apart from tampering. the payload is version 1
+// records whatever sessions negotiate, the file's version fixes it
+//
+// v1 files are v2 without key ids, and every stanza has to be tried
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use k256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey};
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::cipher::{RecordCipher, XCHACHA20_BLAKE3};
+use crate::record::{self, OVERHEAD};
+use crate::session::{Session, SessionError};
+use crate::stream::{STREAM_CHUNK_LEN, STREAM_RECORD_LEN};
+
+const MAGIC: &str = "xc220b3-file/";
+const VERSION: &str = "v2";
+const V1: &str = "v1";
+const STANZA_TAG: &str = "-> secp256k1 ";
+const LENGTH_TAG: &str = "length ";
+const MAC_TAG: &str = "---";
+
+const WRAP_CONTEXT: &str = "xc220b3 2022-10 file key wrap";
+const HEADER_MAC_CONTEXT: &str = "xc220b3 2022-10 file header mac";
+const PAYLOAD_CONTEXT: &str = "xc220b3 2022-10 file payload";
+const KEY_ID_CONTEXT: &str = "xc220b3 2022-10 file key id";
+
+/// Length of a [`key_id`].
+pub const KEY_ID_LEN: usize = 8;
+
+/// Longest header [`open`] will read, which is plenty for a few hundred
+/// recipients.
+pub const MAX_HEADER_LEN: usize = 64 * 1024;
+
+const WRAPPED_KEY_LEN: usize = 32 + 24;
+
+#[derive(Debug)]
+pub enum FileError {
+    /// Not a file in this format, or a header that doesn't parse.
+    Malformed,
+    /// A file in this format, but a version we don't understand.
+    UnsupportedVersion(String),
+    /// The header was changed after it was written.
+    HeaderMac,
+    /// None of the stanzas are for any of the given secret keys.
+    NoRecipient,
+    /// [`seal`] was given no recipients.
+    NoRecipients,
+    /// The payload is shorter than the header says.
+    Truncated,
+    /// A payload chunk failed to decrypt.
+    Payload(SessionError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        FileError::Io(e)
+    }
+}
+
+struct Stanza {
+    // None in v1 files
+    key_id: Option<[u8; KEY_ID_LEN]>,
+    ephemeral_pk: [u8; 33],
+    wrapped: [u8; WRAPPED_KEY_LEN],
+}
+
+struct Header {
+    stanzas: Vec<Stanza>,
+    plaintext_len: u64,
+    mac: [u8; 32],
+    // the bytes the MAC covers
+    authenticated: Vec<u8>,
+    len: usize,
+}
+
+/// Encrypts the file at `path_in` to `path_out` so any of `recipients` can
+/// open it.
+#[cfg(feature = "std")]
+pub fn seal(path_in: impl AsRef<Path>, path_out: impl AsRef<Path>, recipients: &[PublicKey]) -> Result<(), FileError> {
+    let input = File::open(path_in)?;
+    let plaintext_len = input.metadata()?.len();
+    let mut output = BufWriter::new(File::create(path_out)?);
+    seal_stream(input, plaintext_len, &mut output, recipients, &mut OsRng)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Identifies a recipient in a file's header without giving away the key,
+/// the first bytes of a domain separated BLAKE3 hash of its compressed
+/// public key. Anyone holding the public key can work it out, so files to
+/// the same recipient can be linked by it.
+pub fn key_id(public: &PublicKey) -> [u8; KEY_ID_LEN] {
+    let hash = blake3::derive_key(KEY_ID_CONTEXT, public.to_encoded_point(true).as_bytes());
+    hash[..KEY_ID_LEN].try_into().unwrap()
+}
+
+/// Decrypts the file at `path_in` into `path_out` with `secret`. Nothing is
+/// left at `path_out` if the file doesn't open.
+pub fn open(path_in: impl AsRef<Path>, path_out: impl AsRef<Path>, secret: &SecretKey) -> Result<(), FileError> {
+    open_with_keys(path_in, path_out, core::slice::from_ref(secret))
+}
+
+/// [`open`] with whichever of `secrets` the file is for, picked by key id
+/// rather than by trying each.
+pub fn open_with_keys(path_in: impl AsRef<Path>, path_out: impl AsRef<Path>, secrets: &[SecretKey]) -> Result<(), FileError> {
+    let input = File::open(path_in)?;
+    let file_len = input.metadata()?.len();
+    let path_out = path_out.as_ref();
+    let mut output = BufWriter::new(File::create(path_out)?);
+    let result = open_stream_with_keys(BufReader::new(input), file_len, &mut output, secrets).and_then(|()| Ok(output.flush()?));
+    if result.is_err() {
+        drop(output);
+        let _ = fs::remove_file(path_out);
+    }
+    result
+}
+
+/// [`seal`] over any reader, which must yield exactly `plaintext_len` bytes.
+pub fn seal_stream(
+    mut input: impl Read,
+    plaintext_len: u64,
+    output: &mut impl Write,
+    recipients: &[PublicKey],
+    rng: &mut (impl CryptoRng + RngCore),
+) -> Result<(), FileError> {
+    if recipients.is_empty() {
+        return Err(FileError::NoRecipients);
+    }
+    let mut file_key = [0u8; 32];
+    rng.fill_bytes(&mut file_key);
+
+    let mut header = format!("{}{}\n", MAGIC, VERSION);
+    for recipient in recipients {
+        let ephemeral = EphemeralSecret::random(&mut *rng);
+        let ephemeral_pk = ephemeral.public_key().to_encoded_point(true);
+        let recipient_pk = recipient.to_encoded_point(true);
+        let shared = ephemeral.diffie_hellman(recipient);
+        let wrap_key = wrap_key(shared.raw_secret_bytes(), ephemeral_pk.as_bytes(), recipient_pk.as_bytes());
+
+        let mut wrapped = Vec::with_capacity(WRAPPED_KEY_LEN);
+        XCHACHA20_BLAKE3.seal(&wrap_key, &[&file_key], &[], &mut wrapped);
+        header.push_str(&format!("{}{} {} {}\n", STANZA_TAG, hex(&key_id(recipient)), hex(ephemeral_pk.as_bytes()), hex(&wrapped)));
+    }
+    header.push_str(&format!("{}{}\n{}", LENGTH_TAG, plaintext_len, MAC_TAG));
+    let mac = header_mac(&file_key, header.as_bytes());
+    header.push_str(&format!(" {}\n", hex(&mac)));
+    output.write_all(header.as_bytes())?;
+
+    let mut session = payload_session(&file_key, &mac);
+    let mut encryptor = session.unbound_stream_encryptor();
+    let mut buf = vec![0u8; STREAM_CHUNK_LEN];
+    let mut read = 0u64;
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        read += n as u64;
+        output.write_all(&encryptor.update(&buf[..n]).map_err(FileError::Payload)?)?;
+    }
+    if read != plaintext_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "input changed length while it was read").into());
+    }
+    output.write_all(&encryptor.finish().map_err(FileError::Payload)?)?;
+    Ok(())
+}
+
+/// [`open`] over any reader, `file_len` being its length header included.
+pub fn open_stream(input: impl BufRead, file_len: u64, output: &mut impl Write, secret: &SecretKey) -> Result<(), FileError> {
+    open_stream_with_keys(input, file_len, output, core::slice::from_ref(secret))
+}
+
+/// [`open_with_keys`] over any reader.
+pub fn open_stream_with_keys(
+    mut input: impl BufRead,
+    file_len: u64,
+    output: &mut impl Write,
+    secrets: &[SecretKey],
+) -> Result<(), FileError> {
+    let header = read_header(&mut input)?;
+    let file_key = unwrap_file_key(&header, secrets)?;
+    if header_mac(&file_key, &header.authenticated) != header.mac {
+        return Err(FileError::HeaderMac);
+    }
+
+    // checked before anything is decrypted
+    let payload_len = file_len.saturating_sub(header.len as u64);
+    let full = header.plaintext_len / STREAM_CHUNK_LEN as u64;
+    let rest = header.plaintext_len % STREAM_CHUNK_LEN as u64;
+    let expected = full
+        .checked_mul(STREAM_RECORD_LEN as u64)
+        .and_then(|len| len.checked_add(rest + OVERHEAD as u64))
+        .ok_or(FileError::Malformed)?;
+    if payload_len < expected {
+        return Err(FileError::Truncated);
+    }
+    if payload_len > expected {
+        return Err(FileError::Malformed);
+    }
+
+    let mut session = payload_session(&file_key, &header.mac);
+    let mut decryptor = session.unbound_stream_decryptor();
+    let mut buf = vec![0u8; STREAM_RECORD_LEN];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        output.write_all(&decryptor.update(&buf[..n]).map_err(FileError::Payload)?)?;
+    }
+    match decryptor.finish() {
+        Ok(plain) => output.write_all(&plain)?,
+        // the file shrank after its length was taken
+        Err(SessionError::TruncatedStream) => return Err(FileError::Truncated),
+        Err(e) => return Err(FileError::Payload(e)),
+    }
+    Ok(())
+}
+
+fn read_header(input: &mut impl BufRead) -> Result<Header, FileError> {
+    let mut header = Header { stanzas: Vec::new(), plaintext_len: 0, mac: [0; 32], authenticated: Vec::new(), len: 0 };
+    let mut read_line = |header: &mut Header| -> Result<String, FileError> {
+        let mut line = Vec::new();
+        let limit = (MAX_HEADER_LEN - header.len) as u64;
+        input.by_ref().take(limit).read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            return Err(FileError::Malformed);
+        }
+        header.len += line.len();
+        line.pop();
+        String::from_utf8(line).map_err(|_| FileError::Malformed)
+    };
+
+    let version = read_line(&mut header)?;
+    let version = version.strip_prefix(MAGIC).ok_or(FileError::Malformed)?;
+    if version != VERSION && version != V1 {
+        return Err(FileError::UnsupportedVersion(version.to_string()));
+    }
+    let has_key_ids = version == VERSION;
+    header.authenticated.extend_from_slice(format!("{}{}\n", MAGIC, version).as_bytes());
+
+    loop {
+        let line = read_line(&mut header)?;
+        if let Some(stanza) = line.strip_prefix(STANZA_TAG) {
+            let mut fields = stanza.split(' ');
+            let key_id = match has_key_ids {
+                true => Some(fields.next().and_then(unhex).and_then(|id| id.try_into().ok()).ok_or(FileError::Malformed)?),
+                false => None,
+            };
+            let ephemeral_pk = fields.next().and_then(unhex).and_then(|pk| pk.try_into().ok()).ok_or(FileError::Malformed)?;
+            let wrapped = fields.next().and_then(unhex).and_then(|key| key.try_into().ok()).ok_or(FileError::Malformed)?;
+            if fields.next().is_some() {
+                return Err(FileError::Malformed);
+            }
+            header.stanzas.push(Stanza { key_id, ephemeral_pk, wrapped });
+        } else if let Some(len) = line.strip_prefix(LENGTH_TAG) {
+            header.plaintext_len = len.parse().map_err(|_| FileError::Malformed)?;
+        } else if let Some(mac) = line.strip_prefix(MAC_TAG) {
+            let mac = mac.strip_prefix(' ').and_then(unhex).ok_or(FileError::Malformed)?;
+            header.mac = mac.try_into().map_err(|_| FileError::Malformed)?;
+            header.authenticated.extend_from_slice(MAC_TAG.as_bytes());
+            break;
+        } else {
+            return Err(FileError::Malformed);
+        }
+        header.authenticated.extend_from_slice(line.as_bytes());
+        header.authenticated.push(b'\n');
+    }
+
+    if header.stanzas.is_empty() {
+        return Err(FileError::Malformed);
+    }
+    Ok(header)
+}
+
+fn unwrap_file_key(header: &Header, secrets: &[SecretKey]) -> Result<[u8; 32], FileError> {
+    let by_id: HashMap<[u8; KEY_ID_LEN], &SecretKey> = secrets.iter().map(|secret| (key_id(&secret.public_key()), secret)).collect();
+    for stanza in &header.stanzas {
+        // a named stanza is for one key or none, only v1 stanzas need trying
+        let candidates: Vec<&SecretKey> = match stanza.key_id {
+            Some(id) => by_id.get(&id).copied().into_iter().collect(),
+            None => secrets.iter().collect(),
+        };
+        for secret in candidates {
+            if let Some(file_key) = unwrap_stanza(stanza, secret) {
+                return Ok(file_key);
+            }
+        }
+    }
+    Err(FileError::NoRecipient)
+}
+
+fn unwrap_stanza(stanza: &Stanza, secret: &SecretKey) -> Option<[u8; 32]> {
+    let recipient_pk = secret.public_key().to_encoded_point(true);
+    let ephemeral = PublicKey::from_sec1_bytes(&stanza.ephemeral_pk).ok()?;
+    let shared = k256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), ephemeral.as_affine());
+    let wrap_key = wrap_key(shared.raw_secret_bytes(), &stanza.ephemeral_pk, recipient_pk.as_bytes());
+    XCHACHA20_BLAKE3.open(&wrap_key, &stanza.wrapped, &[]).ok()?.try_into().ok()
+}
+
+fn wrap_key(shared: &[u8], ephemeral_pk: &[u8], recipient_pk: &[u8]) -> [u8; 32] {
+    blake3::derive_key(WRAP_CONTEXT, &[shared, ephemeral_pk, recipient_pk].concat())
+}
+
+fn header_mac(file_key: &[u8; 32], header: &[u8]) -> [u8; 32] {
+    *blake3::keyed_hash(&blake3::derive_key(HEADER_MAC_CONTEXT, file_key), header).as_bytes()
+}
+
+fn payload_session(file_key: &[u8; 32], header_mac: &[u8; 32]) -> Session {
+    let mut session = Session::with_key(blake3::derive_key(PAYLOAD_CONTEXT, &[&file_key[..], header_mac].concat()));
+    session.set_version(record::V1);
+    session
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, io::Cursor, path::PathBuf};
+
+    use k256::{elliptic_curve::sec1::ToEncodedPoint, SecretKey};
+    use rand::thread_rng;
+
+    #[cfg(feature = "std")]
+    use crate::fileformat::seal;
+    use crate::fileformat::{hex, key_id, open, open_stream, open_stream_with_keys, seal_stream, FileError};
+    use crate::session::SessionError;
+
+    // regenerate with XC220B3_WRITE_VECTORS=1, like the session vectors
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/file");
+
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_be_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn plaintext() -> Vec<u8> {
+        // a full chunk and a bit
+        (0..=255).cycle().take(64 * 1024 + 1000).collect()
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(FIXTURES).join(name)
+    }
+
+    fn sealed(recipients: &[SecretKey]) -> Vec<u8> {
+        let plain = plaintext();
+        let recipients: Vec<_> = recipients.iter().map(|secret| secret.public_key()).collect();
+        let mut out = Vec::new();
+        seal_stream(&plain[..], plain.len() as u64, &mut out, &recipients, &mut thread_rng()).unwrap();
+        out
+    }
+
+    fn opened(file: &[u8], secret: &SecretKey) -> Result<Vec<u8>, FileError> {
+        let mut out = Vec::new();
+        open_stream(Cursor::new(file), file.len() as u64, &mut out, secret)?;
+        Ok(out)
+    }
+
+    fn opened_with(file: &[u8], secrets: &[SecretKey]) -> Result<Vec<u8>, FileError> {
+        let mut out = Vec::new();
+        open_stream_with_keys(Cursor::new(file), file.len() as u64, &mut out, secrets)?;
+        Ok(out)
+    }
+
+    fn header_len(file: &[u8]) -> usize {
+        let mac_line = file.windows(4).position(|w| w == b"\n---").unwrap() + 1;
+        mac_line + file[mac_line..].iter().position(|&b| b == b'\n').unwrap() + 1
+    }
+
+    #[test]
+    fn test_fixtures_open() {
+        if env::var_os("XC220B3_WRITE_VECTORS").is_some() {
+            fs::create_dir_all(FIXTURES).unwrap();
+            fs::write(fixture("v2-one-recipient.xcf"), sealed(&[secret(0x11)])).unwrap();
+            fs::write(fixture("v2-two-recipients.xcf"), sealed(&[secret(0x11), secret(0x22)])).unwrap();
+        }
+
+        // v1 files have no key ids and are only ever read now
+        let temp = env::temp_dir().join(format!("xc220b3-fixture-{}", std::process::id()));
+        for (name, secrets) in [
+            ("v1-one-recipient.xcf", &[0x11][..]),
+            ("v1-two-recipients.xcf", &[0x11, 0x22]),
+            ("v2-one-recipient.xcf", &[0x11]),
+            ("v2-two-recipients.xcf", &[0x11, 0x22]),
+        ] {
+            for &byte in secrets {
+                open(fixture(name), &temp, &secret(byte)).unwrap();
+                assert_eq!(fs::read(&temp).unwrap(), plaintext(), "{}", name);
+            }
+            assert!(matches!(open(fixture(name), &temp, &secret(0x33)), Err(FileError::NoRecipient)));
+            assert!(!temp.exists());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_seal_and_open_files() {
+        let dir = env::temp_dir().join(format!("xc220b3-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (plain, sealed_path, opened_path) = (dir.join("plain"), dir.join("sealed"), dir.join("opened"));
+
+        for len in [0, 1, 64 * 1024, 64 * 1024 + 1] {
+            fs::write(&plain, vec![0x5a; len]).unwrap();
+            seal(&plain, &sealed_path, &[secret(0x11).public_key()]).unwrap();
+            open(&sealed_path, &opened_path, &secret(0x11)).unwrap();
+            assert_eq!(fs::read(&opened_path).unwrap().len(), len);
+        }
+        assert!(matches!(seal(&plain, &sealed_path, &[]), Err(FileError::NoRecipients)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_header_tampering_is_detected() {
+        let file = fs::read(fixture("v2-two-recipients.xcf")).unwrap();
+        let text = String::from_utf8_lossy(&file[..header_len(&file)]).to_string();
+
+        // the other recipient's stanza, which we can't unwrap ourselves,
+        // key id and all
+        let second_stanza = text.lines().nth(2).unwrap();
+        let start = text.find(second_stanza).unwrap();
+        for idx in [start + "-> secp256k1 ".len(), start + second_stanza.len() - 1] {
+            let mut tampered = file.clone();
+            tampered[idx] = if tampered[idx] == b'0' { b'1' } else { b'0' };
+            assert!(matches!(opened(&tampered, &secret(0x11)), Err(FileError::HeaderMac)));
+        }
+
+        // a changed length is caught by the MAC, not taken as truncation
+        let length_line = text.lines().find(|line| line.starts_with("length ")).unwrap();
+        let at = text.find(length_line).unwrap();
+        let tampered = [&file[..at], b"length 1\n", &file[at + length_line.len() + 1..]].concat();
+        assert!(matches!(opened(&tampered, &secret(0x11)), Err(FileError::HeaderMac)));
+
+        let mut tampered = file.clone();
+        tampered[header_len(&file) - 2] ^= 1;
+        assert!(matches!(opened(&tampered, &secret(0x11)), Err(FileError::Malformed) | Err(FileError::HeaderMac)));
+    }
+
+    #[test]
+    fn test_versions_and_garbage_are_refused() {
+        let file = fs::read(fixture("v2-one-recipient.xcf")).unwrap();
+        let v3 = [&b"xc220b3-file/v3"[..], &file[file.iter().position(|&b| b == b'\n').unwrap()..]].concat();
+        assert!(matches!(opened(&v3, &secret(0x11)), Err(FileError::UnsupportedVersion(v)) if v == "v3"));
+
+        // a v1 stanza in a v2 file, or the other way around
+        let v1 = [&b"xc220b3-file/v1"[..], &file[file.iter().position(|&b| b == b'\n').unwrap()..]].concat();
+        assert!(matches!(opened(&v1, &secret(0x11)), Err(FileError::Malformed)));
+        let old = fs::read(fixture("v1-one-recipient.xcf")).unwrap();
+        let relabeled = [&b"xc220b3-file/v2"[..], &old[old.iter().position(|&b| b == b'\n').unwrap()..]].concat();
+        assert!(matches!(opened(&relabeled, &secret(0x11)), Err(FileError::Malformed)));
+        assert!(matches!(opened(b"age-encryption.org/v1\n", &secret(0x11)), Err(FileError::Malformed)));
+        assert!(matches!(opened(&[0xff; 100], &secret(0x11)), Err(FileError::Malformed)));
+        assert!(matches!(opened(&file[..20], &secret(0x11)), Err(FileError::Malformed)));
+    }
+
+    #[test]
+    fn test_payload_truncation_and_tampering() {
+        let file = fs::read(fixture("v2-one-recipient.xcf")).unwrap();
+        for cut in [1, 25, 1000, 64 * 1024] {
+            assert!(matches!(opened(&file[..file.len() - cut], &secret(0x11)), Err(FileError::Truncated)), "cut {}", cut);
+        }
+        assert!(matches!(opened(&file[..header_len(&file)], &secret(0x11)), Err(FileError::Truncated)));
+        assert!(matches!(opened(&[&file[..], b"x"].concat(), &secret(0x11)), Err(FileError::Malformed)));
+
+        let mut tampered = file.clone();
+        let at = header_len(&file) + 10;
+        tampered[at] ^= 1;
+        assert!(matches!(opened(&tampered, &secret(0x11)), Err(FileError::Payload(SessionError::MacMismatch))));
+    }
+
+    #[test]
+    fn test_key_ids_select_the_key() {
+        let file = fs::read(fixture("v2-two-recipients.xcf")).unwrap();
+        let plain = plaintext();
+        assert_eq!(opened_with(&file, &[secret(0x33), secret(0x22)]).unwrap(), plain);
+        assert_eq!(opened_with(&file, &[secret(0x44), secret(0x33), secret(0x11)]).unwrap(), plain);
+        assert!(matches!(opened_with(&file, &[secret(0x33), secret(0x44)]), Err(FileError::NoRecipient)));
+        assert!(matches!(opened_with(&file, &[]), Err(FileError::NoRecipient)));
+
+        // the id is all that's looked at: relabel 0x11's stanza for 0x33 and
+        // holding both keys doesn't help, 0x11 is never tried on it
+        let file = fs::read(fixture("v2-one-recipient.xcf")).unwrap();
+        let (ours, other) = (hex(&key_id(&secret(0x11).public_key())), hex(&key_id(&secret(0x33).public_key())));
+        let text = String::from_utf8(file[..header_len(&file)].to_vec()).unwrap();
+        assert!(text.contains(&format!("-> secp256k1 {} ", ours)));
+        let relabeled = [text.replacen(&ours, &other, 1).as_bytes(), &file[header_len(&file)..]].concat();
+        assert!(matches!(opened_with(&relabeled, &[secret(0x11), secret(0x33)]), Err(FileError::NoRecipient)));
+
+        // ids don't give the key away, they're a hash of it
+        assert_ne!(key_id(&secret(0x11).public_key()), key_id(&secret(0x22).public_key()));
+        assert!(!hex(secret(0x11).public_key().to_encoded_point(true).as_bytes()).contains(&ours));
+    }
+}
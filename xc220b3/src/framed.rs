@@ -0,0 +1,133 @@
+// a record split into its parts, for storing apart (a nonce in a database
+// column, the body in blob storage) and putting back together to decrypt.
+// the parts are laid out one after another, each a big-endian u64 length
+// followed by that many bytes, so a body past 4 GiB keeps its length:
+//
+//   header || nonce || tag || body
+//
+// the header is what goes on the wire in front of the cipher's output, the
+// body is the ciphertext. with the default cipher the tag is the nonce, so
+// the nonce part is empty
+
+use crate::kex::KeyExchange;
+use crate::session::{Session, SessionError};
+
+/// A record in separate parts, see [`Session::encrypt_framed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FramedRecord {
+    pub header: Vec<u8>,
+    /// Empty unless the cipher carries a nonce apart from the tag.
+    pub nonce: Vec<u8>,
+    pub tag: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+impl FramedRecord {
+    /// The parts, each length-prefixed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let parts = [&self.header, &self.nonce, &self.tag, &self.body];
+        let mut out = Vec::with_capacity(parts.iter().map(|part| 8 + part.len()).sum());
+        for part in parts {
+            out.extend_from_slice(&(part.len() as u64).to_be_bytes());
+            out.extend_from_slice(part);
+        }
+        out
+    }
+
+    /// Reads what [`FramedRecord::to_bytes`] wrote, which must be all of
+    /// `bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<FramedRecord, SessionError> {
+        let total = bytes.len();
+        let mut part = || {
+            let len = bytes.get(..8).ok_or(SessionError::InvalidLength(total))?;
+            let len = u64::from_be_bytes(len.try_into().unwrap());
+            // past the end, however it compares to usize
+            let len = usize::try_from(len).ok().filter(|&len| len <= bytes.len() - 8);
+            let len = len.ok_or(SessionError::InvalidLength(total))?;
+            let part = bytes[8..8 + len].to_vec();
+            bytes = &bytes[8 + len..];
+            Ok::<_, SessionError>(part)
+        };
+        let framed = FramedRecord { header: part()?, nonce: part()?, tag: part()?, body: part()? };
+        match bytes.is_empty() {
+            true => Ok(framed),
+            false => Err(SessionError::InvalidLength(total)),
+        }
+    }
+
+    // the record they were split from
+    fn join(&self) -> Vec<u8> {
+        [&self.header[..], &self.body, &self.nonce, &self.tag].concat()
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// [`Session::encrypt`], with the record split into its parts.
+    pub fn encrypt_framed(&mut self, plain: Vec<u8>) -> Result<FramedRecord, SessionError> {
+        let plain_len = plain.len();
+        let mut record = self.encrypt(plain)?;
+        let tag = record.split_off(record.len() - self.cipher.tag_len());
+        let nonce = record.split_off(record.len() - self.cipher.nonce_len());
+        let body = record.split_off(record.len() - plain_len);
+        Ok(FramedRecord { header: record, nonce, tag, body })
+    }
+
+    /// [`Session::decrypt`] of a record from [`Session::encrypt_framed`],
+    /// put back together.
+    pub fn decrypt_framed(&mut self, framed: &FramedRecord) -> Result<Vec<u8>, SessionError> {
+        self.decrypt(framed.join())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use crate::builder::SessionBuilder;
+    use crate::framed::FramedRecord;
+    use crate::session::{Session, SessionError};
+
+    #[test]
+    fn test_framed_parts_reassemble() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let framed = sesh1.encrypt_framed(b"hello".to_vec()).unwrap();
+        assert_eq!((framed.header.len(), framed.nonce.len(), framed.tag.len(), framed.body.len()), (9, 0, 24, 5));
+
+        // stored apart and read back
+        let (tag, body) = (framed.tag.clone(), framed.body.clone());
+        let stored = FramedRecord::from_bytes(&framed.to_bytes()).unwrap();
+        assert_eq!(stored, framed);
+        let reassembled = FramedRecord { tag, body, ..stored };
+        assert_eq!(sesh2.decrypt_framed(&reassembled).unwrap(), b"hello");
+
+        // the same record as the compact format, just split
+        let mut compact = sesh1.clone();
+        let framed = sesh1.encrypt_framed(b"again".to_vec()).unwrap();
+        assert_eq!([&framed.header[..], &framed.body, &framed.tag].concat(), compact.encrypt(b"again".to_vec()).unwrap());
+        let mut swapped = framed.clone();
+        swapped.body[0] ^= 1;
+        assert!(matches!(sesh2.decrypt_framed(&swapped), Err(SessionError::MacMismatch)));
+        assert_eq!(sesh2.decrypt_framed(&framed).unwrap(), b"again");
+
+        let bytes = framed.to_bytes();
+        assert!(matches!(FramedRecord::from_bytes(&bytes[..bytes.len() - 1]), Err(SessionError::InvalidLength(_))));
+        assert!(matches!(FramedRecord::from_bytes(&[&bytes[..], &[0]].concat()), Err(SessionError::InvalidLength(_))));
+
+        // a length that can't fit is refused rather than wrapped
+        let mut huge = bytes.clone();
+        huge[..8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(FramedRecord::from_bytes(&huge), Err(SessionError::InvalidLength(_))));
+    }
+
+    #[test]
+    fn test_framed_obfuscated_records() {
+        let builder = SessionBuilder::new().obfuscate(true);
+        let (mut sesh1, mut sesh2) = (builder.clone().build(&mut thread_rng()).unwrap(), builder.build(&mut thread_rng()).unwrap());
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+        let framed = sesh1.encrypt_framed(b"hello".to_vec()).unwrap();
+        assert_eq!(framed.body.len(), 5);
+        assert_eq!(sesh2.decrypt_framed(&framed).unwrap(), b"hello");
+    }
+}
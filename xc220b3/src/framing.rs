@@ -0,0 +1,514 @@
+// length-prefixed framing for sending records over a byte stream, each frame
+// is a big-endian u32 length followed by that many bytes
+//
+// a session with SessionConfig::hide_lengths XORs each prefix with a mask
+// only the two ends can compute, so the stream is unstructured bytes:
+//
+//   mask = first 4 bytes of BLAKE3 keyed_hash(length key, le64(frame number))
+//   length key = BLAKE3 derive_key(LENGTH_CONTEXT) of the direction's key
+//
+// frames are numbered from zero in each direction. the length keys are
+// derived once, from the keys the session was first keyed with, and kept
+// through rekeys and renegotiations, so which mask a prefix is under never
+// depends on which record key the frame turns out to be under. the reader
+// unmasks exactly four bytes, refuses a length over the session's largest
+// record, and gives up on the stream at the first frame that doesn't
+// authenticate, since it can't know whether the prefix or the body was hit
+
+use std::io::{self, Read, Write};
+
+use zeroize::Zeroize;
+
+use crate::builder::DEFAULT_MAX_MESSAGE_LEN;
+use crate::kex::KeyExchange;
+use crate::record::ORDERED_OVERHEAD;
+use crate::session::{Session, SessionError};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const LENGTH_CONTEXT: &str = "xc220b3 2022-10 frame length";
+
+/// Largest frame [`read_frame`] will accept, so a hostile length prefix can't
+/// make us allocate arbitrarily much. It's the largest record a session with
+/// the default [`SessionConfig`](crate::SessionConfig) sends.
+pub const MAX_FRAME_LEN: usize = DEFAULT_MAX_MESSAGE_LEN + ORDERED_OVERHEAD;
+
+/// Writes `frame` with its length prefix in a single `write_all`.
+pub fn write_frame(w: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+    }
+
+    let mut buf = Vec::with_capacity(4 + frame.len());
+    buf.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+    buf.extend_from_slice(frame);
+    w.write_all(&buf)
+}
+
+/// Reads one frame written by [`write_frame`].
+pub fn read_frame(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    read_frame_max(r, MAX_FRAME_LEN)
+}
+
+/// [`read_frame`] with a tighter limit, usually a session's
+/// [`SessionConfig::max_record_len`](crate::SessionConfig::max_record_len).
+pub fn read_frame_max(r: &mut impl Read, max_len: usize) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len.min(MAX_FRAME_LEN) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+
+    let mut frame = vec![0u8; len];
+    r.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+/// [`write_frame`] for async writers.
+#[cfg(feature = "tokio")]
+pub async fn write_frame_async(w: &mut (impl AsyncWrite + Unpin), frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+    }
+
+    let mut buf = Vec::with_capacity(4 + frame.len());
+    buf.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+    buf.extend_from_slice(frame);
+    w.write_all(&buf).await?;
+    w.flush().await
+}
+
+/// [`read_frame`] for async readers.
+#[cfg(feature = "tokio")]
+pub async fn read_frame_async(r: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    read_frame_max_async(r, MAX_FRAME_LEN).await
+}
+
+/// [`read_frame_max`] for async readers.
+#[cfg(feature = "tokio")]
+pub async fn read_frame_max_async(r: &mut (impl AsyncRead + Unpin), max_len: usize) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len.min(MAX_FRAME_LEN) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+
+    let mut frame = vec![0u8; len];
+    r.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+// the keys masking each direction's length prefixes, and how many frames
+// each has carried
+#[derive(Clone, Default)]
+pub(crate) struct LengthMasks {
+    pub(crate) send: [u8; 32],
+    pub(crate) recv: [u8; 32],
+    sent: u64,
+    received: u64,
+    // a frame didn't authenticate, nothing after it can be found
+    desynced: bool,
+}
+
+impl LengthMasks {
+    pub(crate) fn new(send_key: &[u8; 32], recv_key: &[u8; 32]) -> LengthMasks {
+        LengthMasks {
+            send: blake3::derive_key(LENGTH_CONTEXT, send_key),
+            recv: blake3::derive_key(LENGTH_CONTEXT, recv_key),
+            sent: 0,
+            received: 0,
+            desynced: false,
+        }
+    }
+}
+
+impl Drop for LengthMasks {
+    fn drop(&mut self) {
+        self.send.zeroize();
+        self.recv.zeroize();
+    }
+}
+
+fn apply_mask(prefix: &mut [u8; 4], key: &[u8; 32], frame: u64) {
+    let mask = blake3::keyed_hash(key, &frame.to_le_bytes());
+    for (byte, mask) in prefix.iter_mut().zip(mask.as_bytes()) {
+        *byte ^= mask;
+    }
+}
+
+// whether a frame that opened as `e` is still known to have been read whole,
+// because it authenticated or was never looked at
+fn keeps_sync(e: &SessionError) -> bool {
+    matches!(
+        e,
+        SessionError::Heartbeat
+            | SessionError::PeerClosed(_)
+            | SessionError::Rekeyed
+            | SessionError::DuplicateMessage
+            | SessionError::OutOfOrder { .. }
+            | SessionError::Closed
+            | SessionError::NotReady
+    )
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// `record` with the length prefix this session puts on it, masked if
+    /// it [hides lengths](crate::SessionBuilder::hide_lengths). Frames have
+    /// to be written in the order they're made.
+    pub(crate) fn frame(&mut self, record: &[u8]) -> Vec<u8> {
+        let mut prefix = (record.len() as u32).to_be_bytes();
+        if self.config.hide_lengths {
+            apply_mask(&mut prefix, &self.lengths.send, self.lengths.sent);
+            self.lengths.sent += 1;
+        }
+        [&prefix[..], record].concat()
+    }
+
+    // the length of the next frame from its prefix. with hidden lengths
+    // one over the largest record ends the stream, as a frame that doesn't
+    // authenticate does
+    pub(crate) fn frame_len(&mut self, mut prefix: [u8; 4]) -> Result<usize, SessionError> {
+        if self.lengths.desynced {
+            return Err(SessionError::FrameDesync);
+        }
+        if self.config.hide_lengths {
+            apply_mask(&mut prefix, &self.lengths.recv, self.lengths.received);
+        }
+        let len = u32::from_be_bytes(prefix) as usize;
+        match len > self.config().max_record_len() {
+            true if self.config.hide_lengths => Err(self.desync()),
+            true => Err(SessionError::MessageTooLarge(len - self.ciphertext_len(0))),
+            false => Ok(len),
+        }
+    }
+
+    // opens the frame whose length frame_len just gave
+    pub(crate) fn open_frame<T>(&mut self, open: impl FnOnce(&mut Self) -> Result<T, SessionError>) -> Result<T, SessionError> {
+        self.lengths.received += 1;
+        match open(self) {
+            Err(e) if self.config.hide_lengths && !keeps_sync(&e) => {
+                self.desync();
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    fn desync(&mut self) -> SessionError {
+        self.lengths.desynced = true;
+        self.recv_closed = true;
+        SessionError::FrameDesync
+    }
+
+    // a whole frame from `r`, as frame_len and open_frame take it
+    pub(crate) fn read_frame_from(&mut self, r: &mut impl Read) -> Result<Vec<u8>, SessionError> {
+        let mut prefix = [0u8; 4];
+        r.read_exact(&mut prefix)?;
+        let mut frame = vec![0u8; self.frame_len(prefix)?];
+        r.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+
+    // read_frame_from for async readers
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn read_frame_from_async(&mut self, r: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, SessionError> {
+        let mut prefix = [0u8; 4];
+        r.read_exact(&mut prefix).await?;
+        let mut frame = vec![0u8; self.frame_len(prefix)?];
+        r.read_exact(&mut frame).await?;
+        Ok(frame)
+    }
+
+    /// Encrypts `plain` and writes it as one frame, length prefix and record
+    /// in a single `write_all`, so writers sharing a stream (behind a lock)
+    /// never interleave inside a frame. [`Session::recv_frame`] reads it.
+    ///
+    /// A failed write is [`SessionError::Io`]. The record was sealed and
+    /// counted by then, so give up on the connection rather than retry.
+    pub fn send_frame(&mut self, w: &mut impl Write, plain: &[u8]) -> Result<(), SessionError> {
+        let len = self.ciphertext_len(plain.len());
+        if len > u32::MAX as usize {
+            return Err(SessionError::MessageTooLarge(plain.len()));
+        }
+        let mut record = vec![0u8; len];
+        let written = self.encrypt_into(plain, &mut record)?;
+        Ok(w.write_all(&self.frame(&record[..written]))?)
+    }
+
+    /// Reads one frame written by [`Session::send_frame`] and decrypts it.
+    /// Anything the stream did is [`SessionError::Io`], a short read
+    /// included, and anything the record did is the error
+    /// [`Session::decrypt`] gives for it. A length prefix over
+    /// [`SessionConfig::max_record_len`](crate::SessionConfig::max_record_len)
+    /// is [`SessionError::MessageTooLarge`] without reading the frame, or
+    /// [`SessionError::FrameDesync`] with
+    /// [hidden lengths](crate::SessionBuilder::hide_lengths).
+    pub fn recv_frame(&mut self, r: &mut impl Read) -> Result<Vec<u8>, SessionError> {
+        let frame = self.read_frame_from(r)?;
+        self.open_frame(|session| session.decrypt(frame))
+    }
+
+    /// Decrypts every whole frame (as written by [`write_frame`], or by
+    /// [`Session::send_frame`] with hidden lengths) at the start of `buf`, for when one read returns several. Gives the
+    /// plaintexts and how many bytes of `buf` they took up, anything after
+    /// that is the start of a frame still to arrive, to keep and prepend to
+    /// the next read.
+    ///
+    /// Stops at the first frame that doesn't decrypt and returns its error.
+    /// The frames before it were accepted and counted, but their plaintexts
+    /// are lost with it, so call this on a connection that's given up on an
+    /// error. A length prefix over [`SessionConfig::max_record_len`](crate::SessionConfig::max_record_len)
+    /// is [`SessionError::MessageTooLarge`] without waiting for the frame,
+    /// as with [`Session::recv_frame`].
+    pub fn decrypt_all(&mut self, buf: &[u8]) -> Result<(Vec<Vec<u8>>, usize), SessionError> {
+        let mut messages = Vec::new();
+        let mut consumed = 0;
+        while let Some(prefix) = buf.get(consumed..consumed + 4) {
+            let len = self.frame_len(prefix.try_into().unwrap())?;
+            let frame = match buf.get(consumed + 4..consumed + 4 + len) {
+                Some(frame) => frame,
+                None => break,
+            };
+            messages.push(self.open_frame(|session| session.decrypt(frame.to_vec()))?);
+            consumed += 4 + len;
+        }
+        Ok((messages, consumed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, ErrorKind};
+
+    use rand::thread_rng;
+
+    use crate::builder::SessionBuilder;
+    use crate::framing::{read_frame, read_frame_max, write_frame, MAX_FRAME_LEN};
+    use crate::session::{Session, SessionError};
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"hello").unwrap();
+        write_frame(&mut wire, b"").unwrap();
+        assert_eq!(wire.len(), 4 + 5 + 4);
+
+        let mut r = Cursor::new(wire);
+        assert_eq!(read_frame(&mut r).unwrap(), b"hello");
+        assert_eq!(read_frame(&mut r).unwrap(), b"");
+        assert_eq!(read_frame(&mut r).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_frame_rejects_oversized_length() {
+        let wire = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        let err = read_frame(&mut Cursor::new(wire)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_frame_limit_follows_session_config() {
+        let session = SessionBuilder::new().max_message_len(16).build(&mut thread_rng()).unwrap();
+        let max = session.config().max_record_len();
+        assert_eq!(max, 16 + 33);
+
+        let mut wire = Vec::new();
+        write_frame(&mut wire, &[0; 49]).unwrap();
+        write_frame(&mut wire, &[0; 50]).unwrap();
+        let mut r = Cursor::new(wire);
+        assert_eq!(read_frame_max(&mut r, max).unwrap().len(), 49);
+        // refused on the length prefix alone, nothing is allocated for it
+        assert_eq!(read_frame_max(&mut r, max).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_decrypt_all_frames_in_a_buffer() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let mut wire = Vec::new();
+        for message in [&b"one"[..], b"", b"three"] {
+            write_frame(&mut wire, &sesh1.encrypt(message.to_vec()).unwrap()).unwrap();
+        }
+        let whole = wire.len();
+        let mut fourth = Vec::new();
+        write_frame(&mut fourth, &sesh1.encrypt(b"four".to_vec()).unwrap()).unwrap();
+        wire.extend_from_slice(&fourth[..10]);
+
+        let (messages, consumed) = sesh2.decrypt_all(&wire).unwrap();
+        assert_eq!(messages, [&b"one"[..], b"", b"three"]);
+        assert_eq!(consumed, whole);
+
+        // the rest arrives
+        let rest = [&wire[consumed..], &fourth[10..]].concat();
+        assert_eq!(sesh2.decrypt_all(&rest).unwrap(), (vec![b"four".to_vec()], rest.len()));
+        // a partial length prefix, or nothing, is nothing yet
+        assert_eq!(sesh2.decrypt_all(&[0, 0]).unwrap(), (Vec::new(), 0));
+        assert_eq!(sesh2.decrypt_all(&[]).unwrap(), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn test_decrypt_all_stops_at_a_bad_frame() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let mut wire = Vec::new();
+        write_frame(&mut wire, &sesh1.encrypt(b"good".to_vec()).unwrap()).unwrap();
+        let mut bad = sesh1.encrypt(b"bad".to_vec()).unwrap();
+        bad[2] ^= 1;
+        write_frame(&mut wire, &bad).unwrap();
+        write_frame(&mut wire, &sesh1.encrypt(b"never read".to_vec()).unwrap()).unwrap();
+
+        assert!(matches!(sesh2.decrypt_all(&wire), Err(SessionError::MacMismatch)));
+        assert_eq!(sesh2.stats().received, 1);
+
+        let huge = (MAX_FRAME_LEN as u32 + 1).to_be_bytes();
+        assert!(matches!(sesh2.decrypt_all(&huge), Err(SessionError::MessageTooLarge(_))));
+    }
+
+    #[test]
+    fn test_default_session_records_fit_a_frame() {
+        let (mut sesh1, _) = Session::pair(&mut thread_rng());
+        assert_eq!(sesh1.config().max_record_len(), MAX_FRAME_LEN);
+        assert!(matches!(
+            sesh1.encrypt(vec![0; MAX_FRAME_LEN]),
+            Err(SessionError::MessageTooLarge(len)) if len == MAX_FRAME_LEN
+        ));
+    }
+
+    #[test]
+    fn test_send_and_recv_over_a_pipe() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let (mut reader, mut writer) = std::io::pipe().unwrap();
+        for message in [&b"one"[..], b"", &[7; 1000]] {
+            sesh1.send_frame(&mut writer, message).unwrap();
+        }
+        // any frame will do, this one tampered with
+        let mut tampered = sesh1.encrypt(b"tampered".to_vec()).unwrap();
+        tampered[3] ^= 1;
+        write_frame(&mut writer, &tampered).unwrap();
+        drop(writer);
+
+        assert_eq!(sesh2.recv_frame(&mut reader).unwrap(), b"one");
+        assert_eq!(sesh2.recv_frame(&mut reader).unwrap(), b"");
+        assert_eq!(sesh2.recv_frame(&mut reader).unwrap(), [7; 1000]);
+        assert!(matches!(sesh2.recv_frame(&mut reader), Err(SessionError::MacMismatch)));
+        assert!(matches!(sesh2.recv_frame(&mut reader), Err(SessionError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof));
+
+        // and a write that fails is the stream's error too
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        drop(reader);
+        assert!(matches!(sesh1.send_frame(&mut writer, b"nobody listening"), Err(SessionError::Io(_))));
+    }
+
+    fn hidden_pair() -> (Session, Session) {
+        Session::pair_with(SessionBuilder::new().hide_lengths(true))
+    }
+
+    #[test]
+    fn test_hidden_lengths_leave_no_lengths_on_the_wire() {
+        let (mut sesh1, mut sesh2) = hidden_pair();
+        let messages: Vec<Vec<u8>> = [0, 1, 5, 5, 100, 1000, 4096, 0].iter().map(|&len| vec![0x5a; len]).collect();
+        let mut wire = Vec::new();
+        for message in &messages {
+            sesh1.send_frame(&mut wire, message).unwrap();
+        }
+
+        // no record's length is anywhere in the stream, at a frame boundary
+        // or otherwise
+        for message in &messages {
+            let len = (sesh1.ciphertext_len(message.len()) as u32).to_be_bytes();
+            assert!(!wire.windows(4).any(|window| window == len));
+        }
+        // and equal lengths don't mask alike
+        let prefix = |at: usize| wire[at..at + 4].to_vec();
+        let third = 2 * 4 + sesh1.ciphertext_len(0) + sesh1.ciphertext_len(1);
+        assert_ne!(prefix(third), prefix(third + 4 + sesh1.ciphertext_len(5)));
+
+        let mut r = Cursor::new(&wire);
+        for message in &messages[..4] {
+            assert_eq!(&sesh2.recv_frame(&mut r).unwrap(), message);
+        }
+        // the rest, a byte at a time as it might arrive
+        let mut pending = Vec::new();
+        let mut received = Vec::new();
+        for &byte in &wire[r.position() as usize..] {
+            pending.push(byte);
+            let (messages, consumed) = sesh2.decrypt_all(&pending).unwrap();
+            received.extend(messages);
+            pending.drain(..consumed);
+        }
+        assert_eq!(received, messages[4..]);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_hidden_frames_have_no_fixed_bytes() {
+        const FRAMES: usize = 2048;
+        let (mut sesh1, _) = hidden_pair();
+        let frame_len = 4 + sesh1.ciphertext_len(18);
+        let mut wire = Vec::new();
+        for _ in 0..FRAMES {
+            sesh1.send_frame(&mut wire, b"the same plaintext").unwrap();
+        }
+        assert_eq!(wire.len(), FRAMES * frame_len);
+
+        // prefix, salt, ciphertext and tag alike: by chance the most common
+        // byte at an offset turns up around 20 times in 2048
+        for i in 0..frame_len {
+            let mut counts = [0usize; 256];
+            wire.chunks(frame_len).for_each(|frame| counts[frame[i] as usize] += 1);
+            let most = counts.iter().max().unwrap();
+            assert!(*most < FRAMES / 32, "byte {} is {} in {} of {} frames", i, counts.iter().position(|c| c == most).unwrap(), most, FRAMES);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_hidden_length_ends_the_stream() {
+        let (mut sesh1, sesh2) = hidden_pair();
+        let mut wire = Vec::new();
+        for message in [&b"one"[..], b"two", b"three"] {
+            sesh1.send_frame(&mut wire, message).unwrap();
+        }
+        let second = 4 + sesh1.ciphertext_len(3);
+
+        // too long for the session, or a little off and read wrong, the
+        // stream is given up on either way, and nothing is guessed after
+        for (byte, bit) in [(0, 0x80), (3, 0x01)] {
+            let mut corrupt = wire.clone();
+            corrupt[second + byte] ^= bit;
+            let mut sesh2 = sesh2.clone();
+            let mut r = Cursor::new(&corrupt);
+            assert_eq!(sesh2.recv_frame(&mut r).unwrap(), b"one");
+            let err = sesh2.recv_frame(&mut r).unwrap_err();
+            assert!(matches!(err, SessionError::FrameDesync | SessionError::MacMismatch), "{:?}", err);
+            assert!(matches!(sesh2.recv_frame(&mut Cursor::new(&wire[second..])), Err(SessionError::FrameDesync)));
+            assert!(matches!(sesh2.decrypt_all(&wire[second..]), Err(SessionError::FrameDesync)));
+        }
+
+        // a damaged body is the same, there's no telling it from a bad length
+        let mut sesh2 = sesh2.clone();
+        let mut corrupt = wire.clone();
+        corrupt[second + 6] ^= 1;
+        let mut r = Cursor::new(&corrupt);
+        assert_eq!(sesh2.recv_frame(&mut r).unwrap(), b"one");
+        assert!(matches!(sesh2.recv_frame(&mut r), Err(SessionError::MacMismatch)));
+        assert!(matches!(sesh2.recv_frame(&mut r), Err(SessionError::FrameDesync)));
+        assert!(matches!(sesh2.decrypt(sesh1.encrypt(b"four".to_vec()).unwrap()), Err(SessionError::Closed)));
+    }
+
+    #[test]
+    fn test_hidden_lengths_survive_records_that_arent_messages() {
+        let (mut sesh1, mut sesh2) = hidden_pair();
+        let mut wire = Vec::new();
+        sesh1.send_frame(&mut wire, b"one").unwrap();
+        for record in [sesh1.heartbeat().unwrap(), sesh1.send_rekey().unwrap()] {
+            wire.extend(sesh1.frame(&record));
+        }
+        sesh1.send_frame(&mut wire, b"two").unwrap();
+
+        let mut r = Cursor::new(&wire);
+        assert_eq!(sesh2.recv_frame(&mut r).unwrap(), b"one");
+        assert!(matches!(sesh2.recv_frame(&mut r), Err(SessionError::Heartbeat)));
+        assert!(matches!(sesh2.recv_frame(&mut r), Err(SessionError::Rekeyed)));
+        assert_eq!(sesh2.recv_frame(&mut r).unwrap(), b"two");
+    }
+}
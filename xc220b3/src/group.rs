@@ -0,0 +1,374 @@
+// sender keys, for broadcasting to a group without sealing every message
+// once per member. each member has a chain of its own, handed to every other
+// member once over their pairwise session:
+//
+//   distribution:  sender id (16) || le32(generation) || le32(iteration) || chain key (32)
+//
+// sealed as an ordinary record of that session. a broadcast is then sealed
+// once, under the next message key of the sender's chain:
+//
+//   sender id (16) || le32(generation) || le32(iteration) || ciphertext || tag (24)
+//
+// with the header bound as aad. the chain steps as the ratchet's do, so a
+// member given the chain at some iteration reads nothing from before it.
+// there's no DH in it: a member who leaves keeps reading a chain for as long
+// as it's used, which is why every sender must rotate to a new generation,
+// and distribute it to who's left, when the membership changes
+
+use std::collections::{BTreeMap, HashMap};
+
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::cipher::{RecordCipher, XCHACHA20_BLAKE3};
+use crate::kex::KeyExchange;
+use crate::ratchet::step_chain;
+use crate::session::{Session, SessionError};
+
+/// Length of the header in front of every [`GroupSession`] broadcast.
+pub const GROUP_HEADER_LEN: usize = SENDER_ID_LEN + 4 + 4;
+/// How far ahead of the last broadcast received from a sender one may be by
+/// default, and how many skipped keys are kept per sender, see
+/// [`GroupSession::set_window`].
+pub const DEFAULT_GROUP_WINDOW: u32 = 1000;
+
+const SENDER_ID_LEN: usize = 16;
+const DISTRIBUTION_LEN: usize = GROUP_HEADER_LEN + 32;
+
+// a sender's chain as of its next message
+struct Chain {
+    generation: u32,
+    iteration: u32,
+    key: [u8; 32],
+}
+
+impl Chain {
+    fn encode(&self, sender_id: &[u8; SENDER_ID_LEN]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(DISTRIBUTION_LEN);
+        out.extend_from_slice(sender_id);
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        out.extend_from_slice(&self.iteration.to_le_bytes());
+        out.extend_from_slice(&self.key);
+        out
+    }
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+// what we hold of another member's chain
+struct Received {
+    chain: Chain,
+    // message keys skipped over, by iteration
+    skipped: BTreeMap<u32, [u8; 32]>,
+}
+
+impl Drop for Received {
+    fn drop(&mut self) {
+        self.skipped.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+/// One member's end of a group: its own sender key, and the sender keys the
+/// other members gave it.
+///
+/// A member sends everyone its key with [`GroupSession::distribution`], one
+/// record over each pairwise [`Session`], and takes theirs with
+/// [`GroupSession::accept_distribution`]. From then on a broadcast is sealed
+/// once and opens for every member holding the key. When someone leaves,
+/// [`GroupSession::mark_dirty`]: it can't [`encrypt`](GroupSession::encrypt)
+/// again until it [`rotate`](GroupSession::rotate)s and distributes the new
+/// key to the members that are left. Every remaining member has to do the
+/// same, or the one who left still reads them.
+pub struct GroupSession {
+    sender_id: [u8; SENDER_ID_LEN],
+    ours: Chain,
+    dirty: bool,
+    theirs: HashMap<[u8; SENDER_ID_LEN], Received>,
+    window: u32,
+}
+
+impl GroupSession {
+    pub fn new(rng: &mut (impl CryptoRng + RngCore)) -> GroupSession {
+        let mut sender_id = [0u8; SENDER_ID_LEN];
+        rng.fill_bytes(&mut sender_id);
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        GroupSession {
+            sender_id,
+            ours: Chain { generation: 0, iteration: 0, key },
+            dirty: false,
+            theirs: HashMap::new(),
+            window: DEFAULT_GROUP_WINDOW,
+        }
+    }
+
+    /// Names this member on its broadcasts, the same across rotations.
+    pub fn sender_id(&self) -> [u8; 16] {
+        self.sender_id
+    }
+
+    /// The generation of the current sender key, one more with every
+    /// [`GroupSession::rotate`].
+    pub fn generation(&self) -> u32 {
+        self.ours.generation
+    }
+
+    /// How far ahead of the last broadcast received from a sender one may
+    /// be, further is [`SessionError::TooManySkipped`]. It's also how many
+    /// skipped keys are kept per sender, the oldest forgotten first.
+    pub fn set_window(&mut self, window: u32) {
+        self.window = window;
+    }
+
+    /// The sender key as of the next broadcast, sealed as a record of
+    /// `session`, the pairwise session with one member.
+    pub fn distribution<K: KeyExchange>(&self, session: &mut Session<K>) -> Result<Vec<u8>, SessionError> {
+        let mut plain = self.ours.encode(&self.sender_id);
+        let record = session.encrypt(plain.clone());
+        plain.zeroize();
+        record
+    }
+
+    /// Takes a member's sender key from a [`GroupSession::distribution`]
+    /// record of `session`, replacing what was held for them. A generation
+    /// older than the one held is [`SessionError::StaleEpoch`].
+    pub fn accept_distribution<K: KeyExchange>(&mut self, session: &mut Session<K>, record: Vec<u8>) -> Result<(), SessionError> {
+        let mut plain = session.decrypt(record)?;
+        if plain.len() != DISTRIBUTION_LEN {
+            let len = plain.len();
+            plain.zeroize();
+            return Err(SessionError::InvalidLength(len));
+        }
+        let (sender_id, generation, iteration) = parse_header(&plain);
+        let chain = Chain { generation, iteration, key: plain[GROUP_HEADER_LEN..].try_into().unwrap() };
+        plain.zeroize();
+        if sender_id == self.sender_id {
+            return Err(SessionError::InvalidPubKey);
+        }
+        if self.theirs.get(&sender_id).is_some_and(|held| held.chain.generation > generation) {
+            return Err(SessionError::StaleEpoch);
+        }
+        self.theirs.insert(sender_id, Received { chain, skipped: BTreeMap::new() });
+        Ok(())
+    }
+
+    /// Forgets a member's sender key, its broadcasts no longer open.
+    pub fn remove_sender(&mut self, sender_id: &[u8; 16]) {
+        self.theirs.remove(sender_id);
+    }
+
+    /// Records that the membership changed, so the sender key must be
+    /// rotated before broadcasting again.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// A new sender key, in a new generation, unrelated to the old one.
+    /// Distribute it to every member again, as none of them can read it yet.
+    pub fn rotate(&mut self, rng: &mut (impl CryptoRng + RngCore)) -> Result<(), SessionError> {
+        let generation = self.ours.generation.checked_add(1).ok_or(SessionError::CounterExhausted)?;
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        self.ours = Chain { generation, iteration: 0, key };
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Seals `plain` once for every member holding the sender key.
+    /// [`SessionError::NotReady`] while [dirty](GroupSession::mark_dirty).
+    pub fn encrypt(&mut self, plain: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if self.dirty {
+            return Err(SessionError::NotReady);
+        }
+        if self.ours.iteration == u32::MAX {
+            return Err(SessionError::CounterExhausted);
+        }
+        let mut key = step_chain(&mut self.ours.key);
+        let mut broadcast = Vec::with_capacity(GROUP_HEADER_LEN + plain.len() + XCHACHA20_BLAKE3.overhead());
+        broadcast.extend_from_slice(&self.sender_id);
+        broadcast.extend_from_slice(&self.ours.generation.to_le_bytes());
+        broadcast.extend_from_slice(&self.ours.iteration.to_le_bytes());
+        let header = broadcast.clone();
+        XCHACHA20_BLAKE3.seal(&key, &[plain], &header, &mut broadcast);
+        key.zeroize();
+        self.ours.iteration += 1;
+        Ok(broadcast)
+    }
+
+    /// Opens a broadcast, giving who sent it. Within the window they open in
+    /// any order, each once. A sender whose key isn't held is
+    /// [`SessionError::NotReady`], a generation other than the one held
+    /// [`SessionError::StaleEpoch`]. A broadcast that doesn't open changes
+    /// nothing.
+    pub fn decrypt(&mut self, broadcast: &[u8]) -> Result<([u8; 16], Vec<u8>), SessionError> {
+        if broadcast.len() < GROUP_HEADER_LEN + XCHACHA20_BLAKE3.overhead() {
+            return Err(SessionError::InvalidLength(broadcast.len()));
+        }
+        let (header, sealed) = broadcast.split_at(GROUP_HEADER_LEN);
+        let (sender_id, generation, iteration) = parse_header(header);
+        let held = self.theirs.get_mut(&sender_id).ok_or(SessionError::NotReady)?;
+        if generation != held.chain.generation {
+            return Err(SessionError::StaleEpoch);
+        }
+
+        if iteration < held.chain.iteration {
+            let key = held.skipped.get(&iteration).ok_or(SessionError::DuplicateMessage)?;
+            let plain = XCHACHA20_BLAKE3.open(key, sealed, header)?;
+            if let Some(mut key) = held.skipped.remove(&iteration) {
+                key.zeroize();
+            }
+            return Ok((sender_id, plain));
+        }
+        if iteration - held.chain.iteration > self.window {
+            return Err(SessionError::TooManySkipped(iteration - held.chain.iteration));
+        }
+
+        // step a copy, kept only if the broadcast opens
+        let mut chain = held.chain.key;
+        let mut skipped = Vec::new();
+        for at in held.chain.iteration..iteration {
+            skipped.push((at, step_chain(&mut chain)));
+        }
+        let mut key = step_chain(&mut chain);
+        let plain = XCHACHA20_BLAKE3.open(&key, sealed, header);
+        key.zeroize();
+        let plain = match plain {
+            Ok(plain) => plain,
+            Err(e) => {
+                chain.zeroize();
+                skipped.iter_mut().for_each(|(_, key)| key.zeroize());
+                return Err(e);
+            }
+        };
+
+        held.chain.key = chain;
+        chain.zeroize();
+        held.chain.iteration = iteration + 1;
+        held.skipped.extend(skipped);
+        while held.skipped.len() > self.window as usize {
+            if let Some((_, mut oldest)) = held.skipped.pop_first() {
+                oldest.zeroize();
+            }
+        }
+        Ok((sender_id, plain))
+    }
+}
+
+fn parse_header(header: &[u8]) -> ([u8; SENDER_ID_LEN], u32, u32) {
+    let sender_id = header[..SENDER_ID_LEN].try_into().unwrap();
+    let generation = u32::from_le_bytes(header[SENDER_ID_LEN..SENDER_ID_LEN + 4].try_into().unwrap());
+    let iteration = u32::from_le_bytes(header[SENDER_ID_LEN + 4..GROUP_HEADER_LEN].try_into().unwrap());
+    (sender_id, generation, iteration)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use rand::thread_rng;
+
+    use crate::group::GroupSession;
+    use crate::session::{Session, SessionError};
+
+    // every member with a pairwise session to every other, and everyone's
+    // sender key handed out over them
+    fn group(n: usize) -> (Vec<GroupSession>, HashMap<(usize, usize), Session>) {
+        let mut members: Vec<GroupSession> = (0..n).map(|_| GroupSession::new(&mut thread_rng())).collect();
+        let mut links = HashMap::new();
+        for i in 0..n {
+            for j in i + 1..n {
+                let (a, b) = Session::pair(&mut thread_rng());
+                links.insert((i, j), a);
+                links.insert((j, i), b);
+            }
+        }
+        for i in 0..n {
+            distribute(&mut members, &mut links, i, &(0..n).collect::<Vec<_>>());
+        }
+        (members, links)
+    }
+
+    fn distribute(members: &mut [GroupSession], links: &mut HashMap<(usize, usize), Session>, from: usize, to: &[usize]) {
+        for &j in to.iter().filter(|&&j| j != from) {
+            let record = members[from].distribution(links.get_mut(&(from, j)).unwrap()).unwrap();
+            members[j].accept_distribution(links.get_mut(&(j, from)).unwrap(), record).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_three_member_broadcast() {
+        let (mut members, _) = group(3);
+        let ids: Vec<[u8; 16]> = members.iter().map(GroupSession::sender_id).collect();
+        for sender in 0..3 {
+            for round in 0..3u8 {
+                let broadcast = members[sender].encrypt(&[sender as u8, round]).unwrap();
+                for member in members.iter_mut().filter(|member| member.sender_id() != ids[sender]) {
+                    assert_eq!(member.decrypt(&broadcast).unwrap(), (ids[sender], vec![sender as u8, round]));
+                }
+                // each opens once
+                assert!(matches!(members[(sender + 1) % 3].decrypt(&broadcast), Err(SessionError::DuplicateMessage)));
+            }
+        }
+        assert!(matches!(GroupSession::new(&mut thread_rng()).decrypt(&members[0].encrypt(b"hi").unwrap()), Err(SessionError::NotReady)));
+    }
+
+    #[test]
+    fn test_removed_member_is_locked_out_after_rotation() {
+        let (mut members, mut links) = group(3);
+        let before = members[0].encrypt(b"before").unwrap();
+        assert_eq!(members[2].decrypt(&before).unwrap().1, b"before");
+
+        // member 2 leaves
+        let removed = members[2].sender_id();
+        for member in &mut members[..2] {
+            member.remove_sender(&removed);
+            member.mark_dirty();
+        }
+        assert!(matches!(members[0].encrypt(b"too soon"), Err(SessionError::NotReady)));
+        for i in 0..2 {
+            members[i].rotate(&mut thread_rng()).unwrap();
+            distribute(&mut members, &mut links, i, &[0, 1]);
+        }
+        assert_eq!(members[0].generation(), 1);
+
+        let after = members[0].encrypt(b"after").unwrap();
+        assert_eq!(members[1].decrypt(&after).unwrap().1, b"after");
+        assert!(matches!(members[2].decrypt(&after), Err(SessionError::StaleEpoch)));
+        let from_removed = members[2].encrypt(b"still here?").unwrap();
+        assert!(matches!(members[0].decrypt(&from_removed), Err(SessionError::NotReady)));
+
+        // nor does an old generation come back
+        let old = GroupSession { sender_id: members[0].sender_id(), ..GroupSession::new(&mut thread_rng()) };
+        let stale = old.distribution(links.get_mut(&(0, 1)).unwrap()).unwrap();
+        assert!(matches!(members[1].accept_distribution(links.get_mut(&(1, 0)).unwrap(), stale), Err(SessionError::StaleEpoch)));
+    }
+
+    #[test]
+    fn test_out_of_order_within_the_window() {
+        let (mut members, _) = group(2);
+        members[1].set_window(4);
+        let broadcasts: Vec<Vec<u8>> = (0..8u8).map(|i| members[0].encrypt(&[i]).unwrap()).collect();
+        assert!(matches!(members[1].decrypt(&broadcasts[5]), Err(SessionError::TooManySkipped(5))));
+        for i in [3, 1, 0, 2, 4, 7] {
+            assert_eq!(members[1].decrypt(&broadcasts[i]).unwrap().1, [i as u8]);
+        }
+        // past the window, 5 was forgotten to keep 6
+        assert!(matches!(members[1].decrypt(&broadcasts[3]), Err(SessionError::DuplicateMessage)));
+        assert_eq!(members[1].decrypt(&broadcasts[6]).unwrap().1, [6]);
+        assert_eq!(members[1].decrypt(&broadcasts[5]).unwrap().1, [5]);
+
+        let mut tampered = members[0].encrypt(b"hello").unwrap();
+        tampered[20] ^= 1;
+        assert!(members[1].decrypt(&tampered).is_err());
+        tampered[20] ^= 1;
+        assert_eq!(members[1].decrypt(&tampered).unwrap().1, b"hello");
+    }
+}
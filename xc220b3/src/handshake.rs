@@ -0,0 +1,1104 @@
+// the whole key exchange over one stream, async or blocking, so callers
+// don't each write their own send/await/confirm dance
+//
+//   client -> server   frame(config || count (1) || versions || client pk [|| extensions])
+//   server -> client   frame(selected version (1) || server pk [|| extensions])
+//   client -> server   record(transcript hash || certificate)
+//   server -> client   record(transcript hash)
+//
+// the extensions area is there so the first two messages can grow without
+// breaking anyone:
+//
+//   0xe5 || le16(length) || entries
+//   entry = le16(type) || le16(data length) || data
+//
+// it's left out when there are none, which is how messages were laid out
+// before it existed. the marker byte can't start an identity key, so it's
+// told apart from a signature after an unsigned key. a type the receiver
+// doesn't know is skipped, and since the transcript hash is over the
+// messages as sent, an extension stripped or changed on the way still fails
+// key confirmation. an area is at most MAX_EXTENSIONS entries and
+// MAX_EXTENSIONS_LEN bytes, and holds each type once
+//
+// public keys are sent as compressed SEC1, 33 bytes, though either form is
+// accepted from the peer. a side with an identity key sends its public key
+// signed instead, see Session::signed_public_key. the versions are the record formats the client
+// speaks, most preferred first, and the server picks the first of them it
+// speaks too. the transcript hash covers both plaintext frames, offer and
+// selection included, so a peer that saw anything different (say an offer
+// with the newer version stripped out) can't produce the record the other
+// side expects
+
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use k256::EncodedPoint;
+use rand_core::{CryptoRng, RngCore};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+use xc220b3_core::protocol::{self, EXTENSIONS_MARKER};
+pub use xc220b3_core::protocol::{MAX_EXTENSIONS, MAX_EXTENSIONS_LEN};
+
+use crate::builder::{ConfigError, SessionBuilder, SessionConfig, CONFIG_ENCODED_LEN};
+use crate::framing::{read_frame, write_frame};
+#[cfg(feature = "tokio")]
+use crate::framing::{read_frame_async, write_frame_async};
+use crate::identity::{encode_signed_key, parse_signed_key, IdentitySignature, SIGNED_PUBLIC_KEY_LEN};
+use crate::kex::{KeyExchange, Secp256k1};
+use crate::pool::EphemeralPool;
+use crate::record;
+use crate::session::{Session, SessionError};
+
+const CONFIG_LEN: usize = CONFIG_ENCODED_LEN;
+
+/// The extension naming the server the client means to reach, UTF-8, see
+/// [`HandshakeConfig::server_name`].
+pub const EXTENSION_SERVER_NAME: u16 = 1;
+
+/// Decides whether the certificate a client presented is acceptable.
+///
+/// Anything `Fn(&[u8]) -> bool` is a verifier.
+pub trait CertificateVerifier: Send + Sync {
+    fn verify(&self, certificate: &[u8]) -> bool;
+}
+
+impl<F: Fn(&[u8]) -> bool + Send + Sync> CertificateVerifier for F {
+    fn verify(&self, certificate: &[u8]) -> bool {
+        self(certificate)
+    }
+}
+
+/// How [`client_sync`] and [`server_sync`] (or, with the `tokio` feature,
+/// `client` and `server`) run the exchange.
+#[derive(Clone)]
+pub struct HandshakeConfig {
+    /// The session both sides end up with. The client sends its
+    /// [`SessionConfig`] and the server refuses anything but its own.
+    pub builder: SessionBuilder,
+    /// For the whole exchange, not each message. Only the async side keeps
+    /// it, see [`client_sync`].
+    pub timeout: Duration,
+    /// What the client presents to the server's verifier, sent only once
+    /// it's encrypted. Servers ignore it.
+    pub certificate: Vec<u8>,
+    /// Record format versions this side speaks, most preferred first. The
+    /// client's order decides. Defaults to every version this build speaks,
+    /// newest first. See [`Session::negotiated_version`].
+    pub versions: Vec<u8>,
+    /// For a client, the name of the server it means to reach, sent as
+    /// [`EXTENSION_SERVER_NAME`]. For a server, the name it answers to: a
+    /// client naming another is [`HandshakeError::UnknownServerName`], one
+    /// naming none is let through. `None` unless set.
+    pub server_name: Option<String>,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        HandshakeConfig {
+            builder: SessionBuilder::default(),
+            timeout: Duration::from_secs(10),
+            certificate: Vec::new(),
+            versions: vec![record::VERSION, record::V2, record::V1],
+            server_name: None,
+        }
+    }
+}
+
+/// Where in the exchange a handshake failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakePhase {
+    /// The client's config and public key.
+    Hello,
+    /// The server's public key, and deriving the session keys.
+    KeyExchange,
+    /// The encrypted transcript hashes, and the certificate.
+    Confirm,
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// [`HandshakeConfig::timeout`] ran out during the given phase.
+    Timeout(HandshakePhase),
+    /// The peer sent something that isn't a handshake message.
+    Malformed(HandshakePhase),
+    /// The client's config isn't the server's.
+    ConfigMismatch,
+    /// No record format version is in both sides' [`HandshakeConfig::versions`].
+    NoCommonVersion,
+    /// The client named a server other than this one, see
+    /// [`HandshakeConfig::server_name`].
+    UnknownServerName,
+    /// The server's verifier refused the client's certificate.
+    BadCertificate,
+    /// The peer's public key wasn't signed by an identity this side trusts,
+    /// see [`SessionBuilder::trust_identity`].
+    UntrustedIdentity,
+    /// The peer's confirmation didn't decrypt, or was for a different
+    /// transcript.
+    ConfirmationMismatch,
+    /// The local [`SessionBuilder`] is invalid.
+    Config(ConfigError),
+    Io(HandshakePhase, io::Error),
+}
+
+/// The client's first message, as [`Session::initiate`] writes it:
+/// `config || count (1) || versions || public key [|| extensions]`, the key
+/// as [`Session::signed_public_key`] gives it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandshakeInit {
+    pub config: SessionConfig,
+    /// Record versions the client speaks, most preferred first, one to
+    /// 255 of them.
+    pub versions: Vec<u8>,
+    /// SEC1, either form, but compressed if there's an `identity`.
+    pub public_key: EncodedPoint,
+    /// The client's identity key and its signature over `public_key`, see
+    /// [`SessionBuilder::identity`].
+    pub identity: Option<IdentitySignature>,
+    /// Each extension's type and data, in the order they were sent, known
+    /// types or not.
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl HandshakeInit {
+    /// The extensions aren't checked against [`MAX_EXTENSIONS`] and
+    /// [`MAX_EXTENSIONS_LEN`] here, only when they're read.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let public_key = encode_signed_key(&self.public_key, self.identity.as_ref());
+        let extensions = encode_extensions(&self.extensions);
+        [&self.config.to_bytes()[..], &[self.versions.len() as u8], &self.versions, &public_key, &extensions].concat()
+    }
+
+    /// The data of the extension of type `kind`, if it was sent.
+    pub fn extension(&self, kind: u16) -> Option<&[u8]> {
+        find_extension(&self.extensions, kind)
+    }
+
+    /// Reads what [`HandshakeInit::to_bytes`] wrote, which must be all of
+    /// `bytes`. Only the layout is checked: a message that's cut short or
+    /// has a config this build can't read is
+    /// [`SessionError::InvalidLength`], as are extensions over the bounds or
+    /// with a type twice, and a key that isn't SEC1 is
+    /// [`SessionError::InvalidPubKey`]. Whether the key is a point on the
+    /// curve, and whether the signature verifies, is for the session keyed
+    /// with it to find out.
+    pub fn from_bytes(bytes: &[u8]) -> Result<HandshakeInit, SessionError> {
+        let malformed = SessionError::InvalidLength(bytes.len());
+        if bytes.len() <= CONFIG_LEN + 1 {
+            return Err(malformed);
+        }
+        let (config, rest) = bytes.split_at(CONFIG_LEN);
+        let Ok(config) = SessionConfig::from_bytes(config) else {
+            return Err(malformed);
+        };
+        let count = rest[0] as usize;
+        if count == 0 || rest.len() <= 1 + count {
+            return Err(malformed);
+        }
+        let (versions, rest) = rest[1..].split_at(count);
+        let (public_key, extensions) = split_key(rest)?;
+        let (public_key, identity) = parse_signed_key(public_key)?;
+        let extensions = parse_extensions(extensions).ok_or(malformed)?;
+        Ok(HandshakeInit { config, versions: versions.to_vec(), public_key, identity, extensions })
+    }
+}
+
+/// The server's answer to a [`HandshakeInit`]:
+/// `selected version (1) || public key [|| extensions]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandshakeResponse {
+    pub version: u8,
+    /// As in [`HandshakeInit::public_key`].
+    pub public_key: EncodedPoint,
+    pub identity: Option<IdentitySignature>,
+    /// As in [`HandshakeInit::extensions`].
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl HandshakeResponse {
+    /// As [`HandshakeInit::to_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let public_key = encode_signed_key(&self.public_key, self.identity.as_ref());
+        [&[self.version][..], &public_key, &encode_extensions(&self.extensions)].concat()
+    }
+
+    /// The data of the extension of type `kind`, if it was sent.
+    pub fn extension(&self, kind: u16) -> Option<&[u8]> {
+        find_extension(&self.extensions, kind)
+    }
+
+    /// Reads what [`HandshakeResponse::to_bytes`] wrote, checked as
+    /// [`HandshakeInit::from_bytes`] checks.
+    pub fn from_bytes(bytes: &[u8]) -> Result<HandshakeResponse, SessionError> {
+        let (&version, rest) = bytes.split_first().ok_or(SessionError::InvalidLength(0))?;
+        let (public_key, extensions) = split_key(rest)?;
+        let (public_key, identity) = parse_signed_key(public_key)?;
+        let extensions = parse_extensions(extensions).ok_or(SessionError::InvalidLength(bytes.len()))?;
+        Ok(HandshakeResponse { version, public_key, identity, extensions })
+    }
+}
+
+// a message's key, signed or not, and the extensions after it. the key's
+// first byte gives its form, and a signed one is compressed and followed by
+// the compressed identity key
+fn split_key(bytes: &[u8]) -> Result<(&[u8], &[u8]), SessionError> {
+    let len = match (bytes.first(), bytes.get(33)) {
+        (Some(0x04), _) => 65,
+        (Some(0x02 | 0x03), Some(0x02 | 0x03)) => SIGNED_PUBLIC_KEY_LEN,
+        (Some(0x02 | 0x03), _) => 33,
+        _ => return Err(SessionError::InvalidPubKey),
+    };
+    match bytes.len() >= len {
+        true => Ok(bytes.split_at(len)),
+        false => Err(SessionError::InvalidLength(bytes.len())),
+    }
+}
+
+fn encode_extensions(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    if extensions.is_empty() {
+        return Vec::new();
+    }
+    let len: usize = extensions.iter().map(|(_, data)| 4 + data.len()).sum();
+    let mut out = Vec::with_capacity(3 + len);
+    out.push(EXTENSIONS_MARKER);
+    out.extend_from_slice(&(len as u16).to_le_bytes());
+    for (kind, data) in extensions {
+        out.extend_from_slice(&kind.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+// None for anything but a well formed area within the bounds, or nothing
+fn parse_extensions(bytes: &[u8]) -> Option<Vec<(u16, Vec<u8>)>> {
+    let mut extensions = Vec::new();
+    let read = protocol::read_extensions(bytes, |kind, data| extensions.push((kind, data.to_vec())));
+    read.then_some(extensions)
+}
+
+fn find_extension(extensions: &[(u16, Vec<u8>)], kind: u16) -> Option<&[u8]> {
+    extensions.iter().find(|(k, _)| *k == kind).map(|(_, data)| &data[..])
+}
+
+// what a client with `config` sends
+fn client_extensions(config: &HandshakeConfig) -> Vec<(u16, Vec<u8>)> {
+    let mut extensions = Vec::new();
+    if let Some(name) = &config.server_name {
+        extensions.push((EXTENSION_SERVER_NAME, name.as_bytes().to_vec()));
+    }
+    extensions
+}
+
+/// Runs the client side of the handshake over `io`, and hands back the ready
+/// session along with `io` for the data phase.
+#[cfg(feature = "tokio")]
+pub async fn client<T>(mut io: T, config: &HandshakeConfig) -> Result<(Session, T), HandshakeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut phase = HandshakePhase::Hello;
+    let result = tokio::time::timeout(config.timeout, client_exchange(&mut io, config, &mut phase)).await;
+    match result {
+        Ok(session) => Ok((session?, io)),
+        Err(_) => Err(HandshakeError::Timeout(phase)),
+    }
+}
+
+/// Runs the server side of the handshake over `io`, checking the client's
+/// certificate with `verifier`.
+#[cfg(feature = "tokio")]
+pub async fn server<T>(
+    mut io: T,
+    config: &HandshakeConfig,
+    verifier: &impl CertificateVerifier,
+) -> Result<(Session, T), HandshakeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut phase = HandshakePhase::Hello;
+    let result = tokio::time::timeout(config.timeout, server_exchange(&mut io, config, verifier, &mut phase)).await;
+    match result {
+        Ok(session) => Ok((session?, io)),
+        Err(_) => Err(HandshakeError::Timeout(phase)),
+    }
+}
+
+/// [`client`] over a blocking transport. [`HandshakeConfig::timeout`] is
+/// left to the transport (say `TcpStream::set_read_timeout`), a read that
+/// times out is [`HandshakeError::Io`].
+pub fn client_sync<T: Read + Write>(mut io: T, config: &HandshakeConfig) -> Result<(Session, T), HandshakeError> {
+    let send = |io: &mut T, frame: &[u8], phase| write_frame(io, frame).map_err(|e| HandshakeError::Io(phase, e));
+    let receive = |io: &mut T, phase| read_frame(io).map_err(|e| HandshakeError::Io(phase, e));
+
+    let mut client = ClientExchange::start(config)?;
+    send(&mut io, &client.hello, HandshakePhase::Hello)?;
+    let response = receive(&mut io, HandshakePhase::KeyExchange)?;
+    let confirmation = client.confirm(config, &response)?;
+    send(&mut io, &confirmation, HandshakePhase::Confirm)?;
+    let reply = receive(&mut io, HandshakePhase::Confirm)?;
+    Ok((client.finish(reply)?, io))
+}
+
+/// [`server`] over a blocking transport, see [`client_sync`].
+pub fn server_sync<T: Read + Write>(
+    mut io: T,
+    config: &HandshakeConfig,
+    verifier: &impl CertificateVerifier,
+) -> Result<(Session, T), HandshakeError> {
+    let send = |io: &mut T, frame: &[u8], phase| write_frame(io, frame).map_err(|e| HandshakeError::Io(phase, e));
+    let receive = |io: &mut T, phase| read_frame(io).map_err(|e| HandshakeError::Io(phase, e));
+
+    let mut server = ServerExchange::start(config)?;
+    let hello = receive(&mut io, HandshakePhase::Hello)?;
+    let response = server.respond(&hello)?;
+    send(&mut io, &response, HandshakePhase::KeyExchange)?;
+    let confirmation = receive(&mut io, HandshakePhase::Confirm)?;
+    let reply = server.confirm(verifier, confirmation)?;
+    send(&mut io, &reply, HandshakePhase::Confirm)?;
+    Ok((server.session, io))
+}
+
+#[cfg(feature = "tokio")]
+async fn client_exchange<T>(io: &mut T, config: &HandshakeConfig, phase: &mut HandshakePhase) -> Result<Session, HandshakeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    *phase = HandshakePhase::Hello;
+    let mut client = ClientExchange::start(config)?;
+    send(io, &client.hello, *phase).await?;
+
+    *phase = HandshakePhase::KeyExchange;
+    let response = receive(io, *phase).await?;
+    let confirmation = client.confirm(config, &response)?;
+
+    *phase = HandshakePhase::Confirm;
+    send(io, &confirmation, *phase).await?;
+    let reply = receive(io, *phase).await?;
+    client.finish(reply)
+}
+
+#[cfg(feature = "tokio")]
+async fn server_exchange<T>(
+    io: &mut T,
+    config: &HandshakeConfig,
+    verifier: &impl CertificateVerifier,
+    phase: &mut HandshakePhase,
+) -> Result<Session, HandshakeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    *phase = HandshakePhase::Hello;
+    let mut server = ServerExchange::start(config)?;
+    let hello = receive(io, *phase).await?;
+    let response = server.respond(&hello)?;
+
+    *phase = HandshakePhase::KeyExchange;
+    send(io, &response, *phase).await?;
+
+    *phase = HandshakePhase::Confirm;
+    let confirmation = receive(io, *phase).await?;
+    let reply = server.confirm(verifier, confirmation)?;
+    send(io, &reply, *phase).await?;
+    Ok(server.session)
+}
+
+/// The server's side of the handshake with the slow parts taken off the
+/// accept path: ephemeral key pairs generated ahead of time with
+/// [`HandshakePool::prepare`], and hellos answered a batch at a time with
+/// [`HandshakePool::respond_batch`], in parallel with the `rayon` feature.
+///
+/// Each prepared key pair answers one hello and leaves the pool, whether the
+/// hello was good or not, and any still in the pool are zeroized when it's
+/// dropped. The exchange is driven by hand: send the client
+/// [`ServerHandshake::response`], and pass what it sends back to
+/// [`ServerHandshake::confirm`].
+pub struct HandshakePool {
+    config: HandshakeConfig,
+    keys: EphemeralPool,
+}
+
+impl HandshakePool {
+    pub fn new(config: HandshakeConfig) -> HandshakePool {
+        HandshakePool { config, keys: EphemeralPool::new() }
+    }
+
+    /// Generates `count` more key pairs.
+    pub fn prepare(&mut self, count: usize, rng: &mut (impl CryptoRng + RngCore)) {
+        self.keys.fill(rng, count);
+    }
+
+    /// How many prepared key pairs are left.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Answers a client's hello with the next prepared key pair. An empty
+    /// pool doesn't fail: the key pair is generated then, from the config's
+    /// random source, as [`server_sync`] does.
+    pub fn respond(&mut self, hello: &[u8]) -> Result<ServerHandshake, HandshakeError> {
+        let pair = self.keys.take();
+        ServerHandshake::respond(&self.config, pair, hello)
+    }
+
+    /// [`HandshakePool::respond`] to each of `hellos`, giving a result for
+    /// each in the same order. One that fails doesn't hold up the others.
+    pub fn respond_batch<H: AsRef<[u8]> + Sync>(&mut self, hellos: &[H]) -> Vec<Result<ServerHandshake, HandshakeError>> {
+        let pairs: Vec<_> = hellos.iter().map(|hello| (self.keys.take(), hello)).collect();
+        let config = &self.config;
+        map_batch(pairs, |(pair, hello)| ServerHandshake::respond(config, pair, hello.as_ref()))
+    }
+}
+
+/// A handshake from [`HandshakePool`], answered and waiting on the client's
+/// confirmation.
+pub struct ServerHandshake {
+    exchange: ServerExchange,
+    response: Vec<u8>,
+}
+
+impl ServerHandshake {
+    fn respond(config: &HandshakeConfig, pair: Option<KeyPair>, hello: &[u8]) -> Result<ServerHandshake, HandshakeError> {
+        let builder = config.builder.clone();
+        let session = match pair {
+            Some(pair) => builder.build_with_pair(pair),
+            None => builder.build_from_source(),
+        };
+        let mut exchange = ServerExchange::with_session(config, session.map_err(HandshakeError::Config)?)?;
+        let response = exchange.respond(hello)?;
+        Ok(ServerHandshake { exchange, response })
+    }
+
+    /// The frame to send the client.
+    pub fn response(&self) -> &[u8] {
+        &self.response
+    }
+
+    /// Checks the client's confirmation and its certificate, and gives the
+    /// ready session along with the frame to send back.
+    pub fn confirm(
+        mut self,
+        verifier: &impl CertificateVerifier,
+        confirmation: Vec<u8>,
+    ) -> Result<(Session, Vec<u8>), HandshakeError> {
+        let reply = self.exchange.confirm(verifier, confirmation)?;
+        Ok((self.exchange.session, reply))
+    }
+}
+
+/// [`ServerHandshake::confirm`] for each handshake and the confirmation its
+/// client sent, in parallel with the `rayon` feature. This is where
+/// certificates are verified, the client's only being sent once encrypted.
+pub fn confirm_batch(
+    pending: Vec<(ServerHandshake, Vec<u8>)>,
+    verifier: &impl CertificateVerifier,
+) -> Vec<Result<(Session, Vec<u8>), HandshakeError>> {
+    map_batch(pending, |(handshake, confirmation)| handshake.confirm(verifier, confirmation))
+}
+
+type KeyPair = (<Secp256k1 as KeyExchange>::Private, <Secp256k1 as KeyExchange>::Public);
+
+// each item through `f`, across threads with the `rayon` feature
+#[cfg(feature = "rayon")]
+fn map_batch<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Send + Sync) -> Vec<R> {
+    use rayon::prelude::*;
+    items.into_par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn map_batch<T, R>(items: Vec<T>, f: impl Fn(T) -> R) -> Vec<R> {
+    items.into_iter().map(f).collect()
+}
+
+// the client's side of the exchange, one step per message, for the async
+// and blocking drivers to share
+struct ClientExchange {
+    session: Session,
+    versions: Vec<u8>,
+    hello: Vec<u8>,
+    transcript: [u8; 32],
+}
+
+impl ClientExchange {
+    fn start(config: &HandshakeConfig) -> Result<ClientExchange, HandshakeError> {
+        let session = config.builder.clone().build_from_source().map_err(HandshakeError::Config)?;
+        let versions = offered_versions(config)?;
+        let mut hello = session.hello(&versions).map_err(|_| HandshakeError::Malformed(HandshakePhase::Hello))?;
+        hello.extend_from_slice(&encode_extensions(&client_extensions(config)));
+        Ok(ClientExchange { session, versions, hello, transcript: [0; 32] })
+    }
+
+    // takes the server's response, and gives the confirmation to send
+    fn confirm(&mut self, config: &HandshakeConfig, response: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        let phase = HandshakePhase::KeyExchange;
+        let theirs = HandshakeResponse::from_bytes(response).map_err(|_| HandshakeError::Malformed(phase))?;
+        if !self.versions.contains(&theirs.version) {
+            return Err(HandshakeError::Malformed(phase));
+        }
+        let identity = self.session.check_identity(&theirs.public_key, theirs.identity.as_ref());
+        let identity = identity.map_err(|e| identity_error(e, phase))?;
+        self.session.set_version(theirs.version);
+        self.session.set_sym_key(&theirs.public_key).map_err(|_| HandshakeError::Malformed(phase))?;
+        self.session.peer_identity_key = identity;
+        self.transcript = protocol::transcript(&self.hello, response);
+
+        let phase = HandshakePhase::Confirm;
+        self.session
+            .encrypt([&self.transcript[..], &config.certificate].concat())
+            .map_err(|e| session_error(e, phase))
+    }
+
+    fn finish(mut self, reply: Vec<u8>) -> Result<Session, HandshakeError> {
+        match self.session.decrypt(reply) {
+            Ok(theirs) if theirs == self.transcript => Ok(self.session),
+            _ => Err(HandshakeError::ConfirmationMismatch),
+        }
+    }
+}
+
+// the server's side, see ClientExchange
+struct ServerExchange {
+    session: Session,
+    versions: Vec<u8>,
+    server_name: Option<String>,
+    transcript: [u8; 32],
+}
+
+impl ServerExchange {
+    fn start(config: &HandshakeConfig) -> Result<ServerExchange, HandshakeError> {
+        let session = config.builder.clone().build_from_source().map_err(HandshakeError::Config)?;
+        ServerExchange::with_session(config, session)
+    }
+
+    fn with_session(config: &HandshakeConfig, session: Session) -> Result<ServerExchange, HandshakeError> {
+        Ok(ServerExchange { session, versions: offered_versions(config)?, server_name: config.server_name.clone(), transcript: [0; 32] })
+    }
+
+    // takes the client's hello, and gives the response to send
+    fn respond(&mut self, hello: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        let phase = HandshakePhase::Hello;
+        let theirs = HandshakeInit::from_bytes(hello).map_err(|_| HandshakeError::Malformed(phase))?;
+        if &theirs.config != self.session.config() {
+            return Err(HandshakeError::ConfigMismatch);
+        }
+        if let (Some(ours), Some(named)) = (&self.server_name, theirs.extension(EXTENSION_SERVER_NAME)) {
+            if named != ours.as_bytes() {
+                return Err(HandshakeError::UnknownServerName);
+            }
+        }
+        let identity = self.session.check_identity(&theirs.public_key, theirs.identity.as_ref());
+        let identity = identity.map_err(|e| identity_error(e, phase))?;
+        let selected = *theirs.versions.iter().find(|v| self.versions.contains(v)).ok_or(HandshakeError::NoCommonVersion)?;
+
+        let pk = self
+            .session
+            .signed_public_key()
+            .map_err(|_| HandshakeError::Malformed(HandshakePhase::KeyExchange))?;
+        let response = [&[selected][..], &pk].concat();
+        self.session.set_version(selected);
+        self.session.set_sym_key(&theirs.public_key).map_err(|_| HandshakeError::Malformed(phase))?;
+        self.session.peer_identity_key = identity;
+        self.transcript = protocol::transcript(hello, &response);
+        Ok(response)
+    }
+
+    // takes the client's confirmation, and gives the reply to send
+    fn confirm(&mut self, verifier: &impl CertificateVerifier, confirmation: Vec<u8>) -> Result<Vec<u8>, HandshakeError> {
+        let transcript = self.transcript;
+        let confirmation = self.session.decrypt(confirmation).map_err(|_| HandshakeError::ConfirmationMismatch)?;
+        if confirmation.len() < transcript.len() || confirmation[..transcript.len()] != transcript {
+            return Err(HandshakeError::ConfirmationMismatch);
+        }
+        if !verifier.verify(&confirmation[transcript.len()..]) {
+            return Err(HandshakeError::BadCertificate);
+        }
+        self.session.peer_identity = Some(confirmation[transcript.len()..].to_vec());
+
+        self.session.encrypt(transcript.to_vec()).map_err(|e| session_error(e, HandshakePhase::Confirm))
+    }
+}
+
+// what we'd offer, minus anything this build can't speak
+fn offered_versions(config: &HandshakeConfig) -> Result<Vec<u8>, HandshakeError> {
+    let versions: Vec<u8> = config.versions.iter().copied().filter(|&v| record::is_supported(v)).collect();
+    match versions.len() {
+        0 => Err(HandshakeError::NoCommonVersion),
+        len if len > u8::MAX as usize => Err(HandshakeError::Malformed(HandshakePhase::Hello)),
+        _ => Ok(versions),
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn send(io: &mut (impl AsyncWrite + Unpin), frame: &[u8], phase: HandshakePhase) -> Result<(), HandshakeError> {
+    write_frame_async(io, frame).await.map_err(|e| HandshakeError::Io(phase, e))
+}
+
+#[cfg(feature = "tokio")]
+async fn receive(io: &mut (impl AsyncRead + Unpin), phase: HandshakePhase) -> Result<Vec<u8>, HandshakeError> {
+    read_frame_async(io).await.map_err(|e| HandshakeError::Io(phase, e))
+}
+
+fn identity_error(e: SessionError, phase: HandshakePhase) -> HandshakeError {
+    match e {
+        SessionError::UntrustedIdentity => HandshakeError::UntrustedIdentity,
+        _ => HandshakeError::Malformed(phase),
+    }
+}
+
+// a fresh session can't fail to encrypt short of a bad config, which build
+// already refused
+fn session_error(e: SessionError, phase: HandshakePhase) -> HandshakeError {
+    match e {
+        SessionError::Io(e) => HandshakeError::Io(phase, e),
+        _ => HandshakeError::Malformed(phase),
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod test {
+    use std::time::Duration;
+
+    use k256::SecretKey;
+    use rand::thread_rng;
+    use tokio::io::duplex;
+
+    use crate::builder::SessionBuilder;
+    use crate::framing::{read_frame_async, write_frame_async};
+    use crate::handshake::{client, server, HandshakeConfig, HandshakeError, HandshakePhase, CONFIG_LEN};
+    use crate::record;
+    use crate::session::{Session, SessionError};
+
+    fn config(certificate: &[u8]) -> HandshakeConfig {
+        HandshakeConfig { certificate: certificate.to_vec(), ..HandshakeConfig::default() }
+    }
+
+    fn accept_ok(certificate: &[u8]) -> bool {
+        certificate == b"ok"
+    }
+
+    #[tokio::test]
+    async fn test_client_and_server_complete() {
+        let (client_io, server_io) = duplex(4096);
+        let (client_config, server_config) = (config(b"ok"), config(b""));
+
+        let (client_side, server_side) =
+            tokio::join!(client(client_io, &client_config), server(server_io, &server_config, &accept_ok));
+        let (mut client_session, mut client_io) = client_side.unwrap();
+        let (mut server_session, mut server_io) = server_side.unwrap();
+        assert_eq!(client_session.session_id(), server_session.session_id());
+        assert_eq!(server_session.peer_identity(), Some(&b"ok"[..]));
+        assert_eq!(client_session.peer_identity(), None);
+
+        // and the streams carry on into the data phase
+        write_frame_async(&mut client_io, &client_session.encrypt(b"hello".to_vec()).unwrap()).await.unwrap();
+        let record = read_frame_async(&mut server_io).await.unwrap();
+        assert_eq!(server_session.decrypt(record).unwrap(), b"hello");
+
+        write_frame_async(&mut server_io, &server_session.encrypt(b"hi back".to_vec()).unwrap()).await.unwrap();
+        let record = read_frame_async(&mut client_io).await.unwrap();
+        assert_eq!(client_session.decrypt(record).unwrap(), b"hi back");
+    }
+
+    fn with_versions(certificate: &[u8], versions: &[u8]) -> HandshakeConfig {
+        HandshakeConfig { versions: versions.to_vec(), ..config(certificate) }
+    }
+
+    async fn negotiate(client_versions: &[u8], server_versions: &[u8]) -> (Session, Session) {
+        let (client_io, server_io) = duplex(4096);
+        let (client_config, server_config) = (with_versions(b"ok", client_versions), with_versions(b"", server_versions));
+        let (client_side, server_side) =
+            tokio::join!(client(client_io, &client_config), server(server_io, &server_config, &accept_ok));
+        (client_side.unwrap().0, server_side.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn test_versions_are_negotiated() {
+        let both = [record::VERSION, record::V2, record::V1];
+
+        let (mut client_session, mut server_session) = negotiate(&both, &both).await;
+        assert_eq!(client_session.negotiated_version(), record::VERSION);
+        assert_eq!(server_session.negotiated_version(), record::VERSION);
+        let record = client_session.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(record[0], record::VERSION);
+        assert_eq!(server_session.decrypt(record).unwrap(), b"hello");
+
+        // an older peer on either end brings both down to what it speaks,
+        // keys derived as that version did
+        for older in [&[record::V1][..], &[record::V2, record::V1][..]] {
+            for (client_versions, server_versions) in [(older, &both[..]), (&both[..], older)] {
+                let (mut client_session, mut server_session) = negotiate(client_versions, server_versions).await;
+                assert_eq!(client_session.negotiated_version(), older[0]);
+                assert_eq!(server_session.negotiated_version(), older[0]);
+                let record = client_session.encrypt(b"hello".to_vec()).unwrap();
+                assert_eq!(record[0], older[0]);
+                assert_eq!(server_session.decrypt(record).unwrap(), b"hello");
+            }
+        }
+
+        let (client_io, server_io) = duplex(4096);
+        let (client_config, server_config) = (with_versions(b"ok", &[record::V1]), with_versions(b"", &[record::VERSION]));
+        let (_, server_side) =
+            tokio::join!(client(client_io, &client_config), server(server_io, &server_config, &accept_ok));
+        assert!(matches!(server_side, Err(HandshakeError::NoCommonVersion)));
+    }
+
+    #[tokio::test]
+    async fn test_records_of_another_version_are_refused() {
+        let both = [record::VERSION, record::V1];
+        let (mut new_client, _) = negotiate(&both, &both).await;
+        let (_, mut v1_server) = negotiate(&[record::V1], &both).await;
+
+        // different sessions, so these only get as far as the version check
+        let record = new_client.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(v1_server.decrypt(record), Err(SessionError::UnsupportedVersion(record::VERSION))));
+        let record = v1_server.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(new_client.decrypt(record), Err(SessionError::UnsupportedVersion(1))));
+    }
+
+    #[tokio::test]
+    async fn test_stripped_version_offer_is_detected() {
+        let (client_io, mut mitm_client) = duplex(4096);
+        let (mut mitm_server, server_io) = duplex(4096);
+        let both = with_versions(b"ok", &[record::VERSION, record::V1]);
+        let server_config = with_versions(b"", &[record::VERSION, record::V1]);
+
+        // rewrites the client's offer to only version 1, then relays
+        let mitm = async move {
+            let mut hello = read_frame_async(&mut mitm_client).await.unwrap();
+            // the count and versions follow the config
+            let at = CONFIG_LEN;
+            assert_eq!(hello[at], 2);
+            hello.splice(at..at + 3, [1, record::V1]);
+            write_frame_async(&mut mitm_server, &hello).await.unwrap();
+
+            let response = read_frame_async(&mut mitm_server).await.unwrap();
+            assert_eq!(response[0], record::V1);
+            write_frame_async(&mut mitm_client, &response).await.unwrap();
+            let confirmation = read_frame_async(&mut mitm_client).await.unwrap();
+            write_frame_async(&mut mitm_server, &confirmation).await.unwrap();
+            if let Ok(reply) = read_frame_async(&mut mitm_server).await {
+                write_frame_async(&mut mitm_client, &reply).await.unwrap();
+            }
+        };
+
+        let (client_side, server_side, ()) =
+            tokio::join!(client(client_io, &both), server(server_io, &server_config, &accept_ok), mitm);
+        assert!(matches!(server_side, Err(HandshakeError::ConfirmationMismatch)));
+        assert!(client_side.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bad_certificate_is_refused() {
+        let (client_io, server_io) = duplex(4096);
+        let (client_config, server_config) = (config(b"forged"), config(b""));
+
+        let (client_side, server_side) =
+            tokio::join!(client(client_io, &client_config), server(server_io, &server_config, &accept_ok));
+        assert!(matches!(server_side, Err(HandshakeError::BadCertificate)));
+        // the server hung up instead of confirming
+        assert!(matches!(client_side, Err(HandshakeError::Io(HandshakePhase::Confirm, _))));
+    }
+
+    #[tokio::test]
+    async fn test_identities_are_checked() {
+        let (alice, bob) = (SecretKey::random(&mut thread_rng()), SecretKey::random(&mut thread_rng()));
+        let client_config = |identity: &SecretKey| HandshakeConfig {
+            builder: SessionBuilder::new().identity(identity.clone()).trust_identity(bob.public_key()),
+            ..config(b"ok")
+        };
+        let server_config =
+            HandshakeConfig { builder: SessionBuilder::new().identity(bob.clone()).trust_identity(alice.public_key()), ..config(b"") };
+
+        let (client_io, server_io) = duplex(4096);
+        let trusted = client_config(&alice);
+        let (client_side, server_side) =
+            tokio::join!(client(client_io, &trusted), server(server_io, &server_config, &accept_ok));
+        assert_eq!(client_side.unwrap().0.peer_identity_key(), Some(&bob.public_key()));
+        assert_eq!(server_side.unwrap().0.peer_identity_key(), Some(&alice.public_key()));
+
+        // one the server doesn't know
+        let unknown = client_config(&SecretKey::random(&mut thread_rng()));
+        let (client_io, server_io) = duplex(4096);
+        let (_, server_side) = tokio::join!(client(client_io, &unknown), server(server_io, &server_config, &accept_ok));
+        assert!(matches!(server_side, Err(HandshakeError::UntrustedIdentity)));
+    }
+
+    #[tokio::test]
+    async fn test_config_mismatch_is_refused() {
+        let (client_io, server_io) = duplex(4096);
+        let client_config = HandshakeConfig { builder: SessionBuilder::new().max_message_len(1024), ..config(b"ok") };
+        let server_config = config(b"");
+
+        let (_, server_side) = tokio::join!(client(client_io, &client_config), server(server_io, &server_config, &accept_ok));
+        assert!(matches!(server_side, Err(HandshakeError::ConfigMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_hello_is_refused() {
+        let (mut client_io, server_io) = duplex(4096);
+        write_frame_async(&mut client_io, b"not a hello").await.unwrap();
+        let result = server(server_io, &config(b""), &accept_ok).await;
+        assert!(matches!(result, Err(HandshakeError::Malformed(HandshakePhase::Hello))));
+    }
+
+    #[tokio::test]
+    async fn test_slow_peer_times_out() {
+        // the server end is held open but never answers
+        let (client_io, _server_io) = duplex(4096);
+        let config = HandshakeConfig { timeout: Duration::from_millis(50), ..config(b"ok") };
+
+        let result = client(client_io, &config).await;
+        assert!(matches!(result, Err(HandshakeError::Timeout(HandshakePhase::KeyExchange))));
+    }
+}
+
+#[cfg(test)]
+mod sync_test {
+    use std::collections::HashSet;
+
+    use rand::thread_rng;
+
+    use k256::{EncodedPoint, SecretKey};
+    use rand::RngCore;
+
+    use crate::builder::SessionBuilder;
+    use crate::handshake::{
+        confirm_batch, ClientExchange, HandshakeConfig, HandshakeError, HandshakeInit, HandshakePhase, HandshakePool,
+        HandshakeResponse, ServerExchange, EXTENSION_SERVER_NAME, MAX_EXTENSIONS, MAX_EXTENSIONS_LEN,
+    };
+    use crate::identity::SIGNED_PUBLIC_KEY_LEN;
+    use crate::record;
+    use crate::session::{Session, SessionError};
+
+    fn config(certificate: &[u8]) -> HandshakeConfig {
+        HandshakeConfig { certificate: certificate.to_vec(), ..HandshakeConfig::default() }
+    }
+
+    fn accept_ok(certificate: &[u8]) -> bool {
+        certificate == b"ok"
+    }
+
+    #[test]
+    fn test_pool_keys_are_single_use() {
+        let mut pool = HandshakePool::new(config(b""));
+        pool.prepare(2, &mut thread_rng());
+        assert_eq!(pool.len(), 2);
+
+        // a hello that's refused still uses its key up
+        assert!(matches!(pool.respond(b"junk").err(), Some(HandshakeError::Malformed(HandshakePhase::Hello))));
+        assert_eq!(pool.len(), 1);
+
+        // and past the end, keys are made as they're needed
+        let hellos: Vec<Vec<u8>> = (0..3).map(|_| ClientExchange::start(&config(b"ok")).unwrap().hello).collect();
+        let answered = pool.respond_batch(&hellos);
+        assert!(pool.is_empty());
+        let pks: HashSet<Vec<u8>> = answered.iter().map(|handshake| handshake.as_ref().unwrap().response()[1..].to_vec()).collect();
+        assert_eq!(pks.len(), 3);
+        assert!(pool.respond(&hellos[0]).is_ok());
+    }
+
+    #[test]
+    fn test_batch_of_good_and_bad_hellos() {
+        let mut pool = HandshakePool::new(config(b""));
+        pool.prepare(8, &mut thread_rng());
+
+        let mismatched = HandshakeConfig { builder: SessionBuilder::new().max_message_len(1024), ..config(b"ok") };
+        let configs = [config(b"ok"), mismatched, config(b"ok"), config(b"not ok")];
+        let mut clients: Vec<ClientExchange> = configs.iter().map(|config| ClientExchange::start(config).unwrap()).collect();
+        let mut hellos: Vec<Vec<u8>> = clients.iter().map(|client| client.hello.clone()).collect();
+        hellos.push(hellos[0][..10].to_vec());
+        let answered = pool.respond_batch(&hellos);
+        assert_eq!((answered.len(), pool.len()), (5, 3));
+        assert!(matches!(answered[1].as_ref().err(), Some(HandshakeError::ConfigMismatch)));
+        assert!(matches!(answered[4].as_ref().err(), Some(HandshakeError::Malformed(HandshakePhase::Hello))));
+
+        // the ones answered go on to confirm, certificates checked there
+        let mut pending = Vec::new();
+        for (i, handshake) in answered.into_iter().enumerate() {
+            if let Ok(handshake) = handshake {
+                let confirmation = clients[i].confirm(&configs[i], handshake.response()).unwrap();
+                pending.push((handshake, confirmation));
+            }
+        }
+        let confirmed = confirm_batch(pending, &accept_ok);
+        assert_eq!(confirmed.len(), 3);
+        assert!(matches!(confirmed[2], Err(HandshakeError::BadCertificate)));
+
+        let (mut confirmed, mut clients) = (confirmed.into_iter(), clients.into_iter().step_by(2));
+        for _ in 0..2 {
+            let (mut server, reply) = confirmed.next().unwrap().unwrap();
+            let mut client = clients.next().unwrap().finish(reply).unwrap();
+            assert_eq!(server.peer_identity(), Some(&b"ok"[..]));
+            assert_eq!(server.decrypt(client.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+        }
+    }
+
+    #[test]
+    fn test_messages_roundtrip() {
+        let identity = SecretKey::random(&mut thread_rng());
+        let mut session = SessionBuilder::new().max_message_len(1024).identity(identity.clone()).build(&mut thread_rng()).unwrap();
+        let hello = session.initiate().unwrap();
+        let init = HandshakeInit::from_bytes(&hello).unwrap();
+        assert_eq!(init.config, *session.config());
+        assert_eq!(init.versions, [record::VERSION, record::V2, record::V1]);
+        assert_eq!(init.public_key.as_bytes(), session.public_key_sec1_compressed().unwrap());
+        assert_eq!(init.identity.unwrap().0, identity.public_key());
+        assert_eq!(init.to_bytes(), hello);
+
+        // unsigned, with the key uncompressed
+        let unsigned = HandshakeInit { identity: None, public_key: EncodedPoint::from_bytes(session.public_key_sec1_uncompressed().unwrap()).unwrap(), ..init.clone() };
+        assert_eq!(unsigned.public_key.as_bytes().len(), 65);
+        assert_eq!(HandshakeInit::from_bytes(&unsigned.to_bytes()).unwrap(), unsigned);
+
+        let response = HandshakeResponse { version: record::V2, public_key: init.public_key, identity: init.identity, extensions: Vec::new() };
+        assert_eq!(HandshakeResponse::from_bytes(&response.to_bytes()).unwrap(), response);
+        let response = HandshakeResponse { identity: None, ..response };
+        assert_eq!(HandshakeResponse::from_bytes(&response.to_bytes()).unwrap(), response);
+
+        // and the signature still checks out on the other side
+        let mut peer = Session::new(&mut thread_rng());
+        peer.trust_identity(identity.public_key());
+        assert_eq!(peer.check_identity(&init.public_key, init.identity.as_ref()).unwrap(), Some(identity.public_key()));
+        session.set_sym_key(&peer.pk().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_and_mangled_messages_dont_panic() {
+        let mut session = Session::new(&mut thread_rng());
+        session.set_identity(Some(SecretKey::random(&mut thread_rng())));
+        let hello = session.initiate().unwrap();
+        let response = [&[record::VERSION][..], &session.signed_public_key().unwrap()].concat();
+
+        // every prefix is refused, but for the one that's an unsigned key
+        // of its own
+        let header = hello.len() - SIGNED_PUBLIC_KEY_LEN;
+        for len in (0..hello.len()).filter(|&len| len != header + 33) {
+            assert!(matches!(
+                HandshakeInit::from_bytes(&hello[..len]),
+                Err(SessionError::InvalidLength(_) | SessionError::InvalidPubKey)
+            ));
+        }
+        for len in (0..response.len()).filter(|&len| len != 1 + 33) {
+            assert!(HandshakeResponse::from_bytes(&response[..len]).is_err());
+        }
+
+        let mut rng = thread_rng();
+        for _ in 0..2000 {
+            let mut mangled = hello.clone();
+            let at = rng.next_u32() as usize % mangled.len();
+            mangled[at] = rng.next_u32() as u8;
+            mangled.truncate(1 + rng.next_u32() as usize % mangled.len());
+            let _ = HandshakeInit::from_bytes(&mangled);
+            let _ = HandshakeResponse::from_bytes(&mangled);
+        }
+    }
+
+    fn named(name: &str) -> HandshakeConfig {
+        HandshakeConfig { server_name: Some(name.to_string()), ..config(b"ok") }
+    }
+
+    // a client and a server through to the end, the client's hello as `sent`
+    // leaves it to send, and as `in_transit` leaves it on the way
+    fn exchange(
+        client: &HandshakeConfig,
+        server: &HandshakeConfig,
+        sent: impl FnOnce(&mut Vec<u8>),
+        in_transit: impl FnOnce(&mut Vec<u8>),
+    ) -> Result<(), HandshakeError> {
+        let mut client_exchange = ClientExchange::start(client).unwrap();
+        let mut server_exchange = ServerExchange::start(server).unwrap();
+        sent(&mut client_exchange.hello);
+        let mut hello = client_exchange.hello.clone();
+        in_transit(&mut hello);
+        let response = server_exchange.respond(&hello)?;
+        let confirmation = client_exchange.confirm(client, &response)?;
+        let reply = server_exchange.confirm(&accept_ok, confirmation)?;
+        client_exchange.finish(reply).map(drop)
+    }
+
+    #[test]
+    fn test_extensions_roundtrip() {
+        let hello = ClientExchange::start(&named("example.org")).unwrap().hello;
+        let mut init = HandshakeInit::from_bytes(&hello).unwrap();
+        assert_eq!(init.extensions, [(EXTENSION_SERVER_NAME, b"example.org".to_vec())]);
+        assert_eq!(init.extension(EXTENSION_SERVER_NAME), Some(&b"example.org"[..]));
+        assert_eq!(init.to_bytes(), hello);
+        init.extensions.push((0xbeef, vec![1, 2, 3]));
+        assert_eq!(HandshakeInit::from_bytes(&init.to_bytes()).unwrap(), init);
+
+        // without any, a message is what it was before there were extensions
+        let plain = ClientExchange::start(&config(b"ok")).unwrap().hello;
+        let session = Session::new(&mut thread_rng());
+        assert_eq!(plain.len(), session.hello(&[record::VERSION, record::V2, record::V1]).unwrap().len());
+        assert_eq!(HandshakeInit::from_bytes(&plain).unwrap().extensions, []);
+        assert_eq!(&hello[..plain.len()], &HandshakeInit { extensions: Vec::new(), ..init.clone() }.to_bytes()[..]);
+
+        let response = HandshakeResponse { version: record::VERSION, public_key: init.public_key, identity: None, extensions: vec![(7, Vec::new())] };
+        assert_eq!(HandshakeResponse::from_bytes(&response.to_bytes()).unwrap(), response);
+    }
+
+    #[test]
+    fn test_unknown_extensions_are_skipped() {
+        // a server that knows none of them, server name included, still
+        // completes, and one that does checks the name
+        assert!(exchange(&named("example.org"), &config(b""), |_| (), |_| ()).is_ok());
+        let unknown = |hello: &mut Vec<u8>| {
+            let mut init = HandshakeInit::from_bytes(hello).unwrap();
+            init.extensions.insert(0, (0x7f00, vec![0xaa; 100]));
+            *hello = init.to_bytes();
+        };
+        assert!(exchange(&named("example.org"), &named("example.org"), unknown, |_| ()).is_ok());
+        assert!(exchange(&config(b"ok"), &named("example.org"), |_| (), |_| ()).is_ok());
+        let mismatch = exchange(&named("example.com"), &named("example.org"), |_| (), |_| ());
+        assert!(matches!(mismatch, Err(HandshakeError::UnknownServerName)));
+    }
+
+    #[test]
+    fn test_stripped_extension_fails_confirmation() {
+        let strip = |hello: &mut Vec<u8>| {
+            let mut init = HandshakeInit::from_bytes(hello).unwrap();
+            init.extensions.clear();
+            *hello = init.to_bytes();
+        };
+        let stripped = exchange(&named("example.org"), &config(b""), |_| (), strip);
+        assert!(matches!(stripped, Err(HandshakeError::ConfirmationMismatch)));
+        let changed = exchange(&named("example.org"), &config(b""), |_| (), |hello| *hello.last_mut().unwrap() ^= 1);
+        assert!(matches!(changed, Err(HandshakeError::ConfirmationMismatch)));
+    }
+
+    #[test]
+    fn test_extensions_out_of_bounds_are_refused() {
+        let hello = ClientExchange::start(&config(b"ok")).unwrap().hello;
+        let mut init = HandshakeInit::from_bytes(&hello).unwrap();
+        let refused = |init: &HandshakeInit| matches!(HandshakeInit::from_bytes(&init.to_bytes()), Err(SessionError::InvalidLength(_)));
+
+        // one byte over the size
+        init.extensions = vec![(1, vec![0; MAX_EXTENSIONS_LEN - 4])];
+        assert!(!refused(&init));
+        init.extensions = vec![(1, vec![0; MAX_EXTENSIONS_LEN - 3])];
+        assert!(refused(&init));
+        // one over the count, and a type twice
+        init.extensions = (0..MAX_EXTENSIONS as u16).map(|kind| (kind, Vec::new())).collect();
+        assert!(!refused(&init));
+        init.extensions.push((MAX_EXTENSIONS as u16, Vec::new()));
+        assert!(refused(&init));
+        init.extensions = vec![(1, Vec::new()), (1, Vec::new())];
+        assert!(refused(&init));
+
+        // a length past the end, an empty area, and no marker
+        init.extensions = vec![(1, vec![0; 8])];
+        let bytes = init.to_bytes();
+        assert!(HandshakeInit::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let empty = [&hello[..], &[0xe5, 0, 0]].concat();
+        assert!(matches!(HandshakeInit::from_bytes(&empty), Err(SessionError::InvalidLength(_))));
+        let mut unmarked = bytes.clone();
+        unmarked[hello.len()] = 0;
+        assert!(matches!(HandshakeInit::from_bytes(&unmarked), Err(SessionError::InvalidLength(_))));
+    }
+}
@@ -0,0 +1,184 @@
+// a long-term identity key vouching for a session's ephemeral one, for peers
+// that know each other's identity keys ahead of time and need no certificate
+// authority between them:
+//
+//   signed public key = ephemeral pk (33) || identity pk (33) || r (32) || s (32)
+//
+// both keys compressed SEC1. the signature is ECDSA over secp256k1 as for a
+// certificate, of the BLAKE3 hash of the ephemeral key under IDENTITY_CONTEXT.
+// a session with no identity sends its ephemeral key alone
+
+use std::sync::Arc;
+
+use k256::{
+    ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    EncodedPoint, PublicKey, SecretKey,
+};
+
+use crate::provider::{self, SecretProvider};
+use crate::session::{Session, SessionError};
+
+const IDENTITY_CONTEXT: &str = "xc220b3 2022-10 identity signature";
+
+/// Length of a [`Session::signed_public_key`] from a session with an
+/// identity.
+pub const SIGNED_PUBLIC_KEY_LEN: usize = xc220b3_core::protocol::SIGNED_PUBLIC_KEY_LEN;
+
+/// The identity key that signed an ephemeral public key, and the signature.
+pub type IdentitySignature = (PublicKey, [u8; 64]);
+
+impl Session {
+    /// The long-term key [`Session::signed_public_key`] signs with, or none
+    /// to send the ephemeral key unsigned.
+    pub fn set_identity(&mut self, identity: Option<SecretKey>) {
+        self.identity = identity.map(|key| Arc::new(key) as Arc<dyn SecretProvider>);
+    }
+
+    /// [`Session::set_identity`] with a key kept by a [`SecretProvider`].
+    pub fn set_identity_provider(&mut self, identity: Option<Arc<dyn SecretProvider>>) {
+        self.identity = identity;
+    }
+
+    /// Accepts peers whose ephemeral key `identity` signed. Once any
+    /// identity is trusted, [`Session::set_sym_key_signed`] refuses peers
+    /// signed by another, and unsigned ones.
+    pub fn trust_identity(&mut self, identity: PublicKey) {
+        if !self.trusted_identities.contains(&identity) {
+            self.trusted_identities.push(identity);
+        }
+    }
+
+    /// The identity key that signed the peer's ephemeral key, once
+    /// [`Session::set_sym_key_signed`] has keyed the session with it.
+    pub fn peer_identity_key(&self) -> Option<&PublicKey> {
+        self.peer_identity_key.as_ref()
+    }
+
+    /// This session's public key to send the peer: compressed SEC1, then
+    /// with an [identity](Session::set_identity) set, the identity key and
+    /// its signature over the first. A provider that fails is
+    /// [`SessionError::ProviderFailure`], and the session is as it was.
+    pub fn signed_public_key(&self) -> Result<Vec<u8>, SessionError> {
+        let pk = self.public_key_sec1_compressed()?;
+        let Some(identity) = &self.identity else {
+            return Ok(pk.to_vec());
+        };
+        let identity_pk = provider::compressed(&**identity)?;
+        let signature = identity.sign(&blake3::derive_key(IDENTITY_CONTEXT, &pk))?;
+        Ok([&pk[..], &identity_pk, &signature].concat())
+    }
+
+    /// [`Session::set_sym_key`] with the peer's
+    /// [`Session::signed_public_key`]. A key that isn't signed by a trusted
+    /// identity, or whose signature doesn't verify, is
+    /// [`SessionError::UntrustedIdentity`]. With no identities trusted, any
+    /// that signs correctly is taken, and so is an unsigned key.
+    pub fn set_sym_key_signed(&mut self, signed: &[u8]) -> Result<(), SessionError> {
+        let (peer, identity) = self.check_signed_key(signed)?;
+        self.set_sym_key(&peer)?;
+        self.peer_identity_key = identity;
+        Ok(())
+    }
+
+    // the ephemeral key in `signed`, and the identity it was signed by
+    pub(crate) fn check_signed_key(&self, signed: &[u8]) -> Result<(EncodedPoint, Option<PublicKey>), SessionError> {
+        let (peer, signature) = parse_signed_key(signed)?;
+        let identity = self.check_identity(&peer, signature.as_ref())?;
+        Ok((peer, identity))
+    }
+
+    // the identity that signed `peer`, if it's one this session takes
+    pub(crate) fn check_identity(
+        &self,
+        peer: &EncodedPoint,
+        signature: Option<&IdentitySignature>,
+    ) -> Result<Option<PublicKey>, SessionError> {
+        let Some((identity, signature)) = signature else {
+            return match self.trusted_identities.is_empty() {
+                true => Ok(None),
+                false => Err(SessionError::UntrustedIdentity),
+            };
+        };
+        let trusted = self.trusted_identities.is_empty() || self.trusted_identities.contains(identity);
+        let hash = blake3::derive_key(IDENTITY_CONTEXT, peer.as_bytes());
+        let verified = Signature::try_from(&signature[..]).is_ok_and(|signature| {
+            VerifyingKey::from(identity).verify_prehash(&hash, &signature).is_ok()
+        });
+        if !trusted || !verified {
+            return Err(SessionError::UntrustedIdentity);
+        }
+        Ok(Some(*identity))
+    }
+}
+
+// a public key as Session::signed_public_key writes it, split into the
+// ephemeral key and, if signed, the identity key and signature. nothing is
+// verified beyond the encoding
+pub(crate) fn parse_signed_key(signed: &[u8]) -> Result<(EncodedPoint, Option<IdentitySignature>), SessionError> {
+    let (pk, signature) = match signed.len() {
+        SIGNED_PUBLIC_KEY_LEN => signed.split_at(33),
+        _ => (signed, &[][..]),
+    };
+    let pk = EncodedPoint::from_bytes(pk).map_err(|_| SessionError::InvalidPubKey)?;
+    if signature.is_empty() {
+        return Ok((pk, None));
+    }
+    let (identity, signature) = signature.split_at(33);
+    let identity = PublicKey::from_sec1_bytes(identity)?;
+    Ok((pk, Some((identity, signature.try_into().unwrap()))))
+}
+
+// the other way, what parse_signed_key reads
+pub(crate) fn encode_signed_key(pk: &EncodedPoint, signature: Option<&IdentitySignature>) -> Vec<u8> {
+    match signature {
+        Some((identity, signature)) => [pk.as_bytes(), identity.to_encoded_point(true).as_bytes(), signature].concat(),
+        None => pk.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use k256::SecretKey;
+    use rand::thread_rng;
+
+    use crate::identity::SIGNED_PUBLIC_KEY_LEN;
+    use crate::session::{Session, SessionError};
+
+    #[test]
+    fn test_trusted_and_unknown_identities() {
+        let alice = SecretKey::random(&mut thread_rng());
+        let mallory = SecretKey::random(&mut thread_rng());
+
+        let mut sender = Session::new(&mut thread_rng());
+        sender.set_identity(Some(alice.clone()));
+        let signed = sender.signed_public_key().unwrap();
+        assert_eq!(signed.len(), SIGNED_PUBLIC_KEY_LEN);
+
+        let mut receiver = Session::new(&mut thread_rng());
+        receiver.trust_identity(alice.public_key());
+        sender.set_sym_key(&receiver.pk().unwrap()).unwrap();
+        receiver.set_sym_key_signed(&signed).unwrap();
+        assert_eq!(receiver.peer_identity_key(), Some(&alice.public_key()));
+        assert_eq!(receiver.decrypt(sender.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+
+        // an identity nobody said to trust, someone else's key under a
+        // trusted identity, and no identity at all
+        let mut unknown = Session::new(&mut thread_rng());
+        unknown.set_identity(Some(mallory));
+        let mut swapped = unknown.signed_public_key().unwrap();
+        swapped[33..].copy_from_slice(&signed[33..]);
+        let unsigned = Session::new(&mut thread_rng()).signed_public_key().unwrap();
+        for peer in [unknown.signed_public_key().unwrap(), swapped, unsigned] {
+            let mut receiver = Session::new(&mut thread_rng());
+            receiver.trust_identity(alice.public_key());
+            assert!(matches!(receiver.set_sym_key_signed(&peer), Err(SessionError::UntrustedIdentity)));
+            assert!(matches!(receiver.encrypt(Vec::new()), Err(SessionError::NotReady)));
+        }
+
+        // trusting no one takes any key that's signed right, or unsigned
+        let mut open = Session::new(&mut thread_rng());
+        open.set_sym_key_signed(&unknown.signed_public_key().unwrap()).unwrap();
+        assert!(open.peer_identity_key().is_some());
+    }
+}
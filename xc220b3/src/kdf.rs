@@ -0,0 +1,154 @@
+#[cfg(feature = "hkdf-sha256")]
+use xc220b3_core::protocol::KDF_HKDF_SHA256;
+use xc220b3_core::protocol::KDF_BLAKE3;
+
+use crate::cipher::private;
+
+/// Turns a key exchange's shared secret into session keys.
+///
+/// `transcript` binds the keys to the handshake that produced them and
+/// `label` tells apart keys derived from the same secret, either may be
+/// empty. Sealed like [`RecordCipher`](crate::RecordCipher).
+pub trait SessionKdf: private::Sealed + Send + Sync {
+    fn derive(&self, shared_secret: &[u8], transcript: &[u8], label: &[u8], out: &mut [u8]);
+}
+
+/// Which KDF a session uses, agreed on through
+/// [`SessionConfig`](crate::SessionConfig).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KdfAlgorithm {
+    #[default]
+    Blake3,
+    #[cfg(feature = "hkdf-sha256")]
+    HkdfSha256,
+}
+
+impl KdfAlgorithm {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            KdfAlgorithm::Blake3 => KDF_BLAKE3,
+            #[cfg(feature = "hkdf-sha256")]
+            KdfAlgorithm::HkdfSha256 => KDF_HKDF_SHA256,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<KdfAlgorithm> {
+        match id {
+            KDF_BLAKE3 => Some(KdfAlgorithm::Blake3),
+            #[cfg(feature = "hkdf-sha256")]
+            KDF_HKDF_SHA256 => Some(KdfAlgorithm::HkdfSha256),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn kdf(self) -> &'static dyn SessionKdf {
+        match self {
+            KdfAlgorithm::Blake3 => &BLAKE3_KDF,
+            #[cfg(feature = "hkdf-sha256")]
+            KdfAlgorithm::HkdfSha256 => &HKDF_SHA256,
+        }
+    }
+}
+
+/// BLAKE3 in key derivation mode.
+///
+/// With an empty transcript and label this is the plain BLAKE3 hash of the
+/// shared secret, which is how version 1 sessions have always been keyed.
+pub struct Blake3Kdf;
+
+pub(crate) static BLAKE3_KDF: Blake3Kdf = Blake3Kdf;
+
+impl private::Sealed for Blake3Kdf {}
+
+impl SessionKdf for Blake3Kdf {
+    fn derive(&self, shared_secret: &[u8], transcript: &[u8], label: &[u8], out: &mut [u8]) {
+        if transcript.is_empty() && label.is_empty() {
+            blake3::Hasher::new().update(shared_secret).finalize_xof().fill(out);
+            return;
+        }
+        xc220b3_core::protocol::derive(shared_secret, transcript, label, out);
+    }
+}
+
+/// HKDF-SHA256, with the transcript as salt and the label as info.
+#[cfg(feature = "hkdf-sha256")]
+pub struct HkdfSha256;
+
+#[cfg(feature = "hkdf-sha256")]
+pub(crate) static HKDF_SHA256: HkdfSha256 = HkdfSha256;
+
+#[cfg(feature = "hkdf-sha256")]
+impl private::Sealed for HkdfSha256 {}
+
+#[cfg(feature = "hkdf-sha256")]
+impl SessionKdf for HkdfSha256 {
+    fn derive(&self, shared_secret: &[u8], transcript: &[u8], label: &[u8], out: &mut [u8]) {
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(transcript), shared_secret)
+            .expand(label, out)
+            .expect("session keys are far below the HKDF output limit");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kdf::{KdfAlgorithm, SessionKdf, BLAKE3_KDF};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn derive(kdf: &dyn SessionKdf, transcript: &[u8], label: &[u8]) -> String {
+        let mut out = [0u8; 32];
+        kdf.derive(&[0x11; 32], transcript, label, &mut out);
+        hex(&out)
+    }
+
+    // (algorithm, transcript, label, expected)
+    fn vectors() -> Vec<(KdfAlgorithm, &'static [u8], &'static [u8], &'static str)> {
+        vec![
+            (KdfAlgorithm::Blake3, b"", b"", BLAKE3_EMPTY),
+            (KdfAlgorithm::Blake3, b"transcript", b"", BLAKE3_TRANSCRIPT),
+            (KdfAlgorithm::Blake3, b"transcript", b"label", BLAKE3_BOTH),
+            #[cfg(feature = "hkdf-sha256")]
+            (KdfAlgorithm::HkdfSha256, b"", b"", HKDF_EMPTY),
+            #[cfg(feature = "hkdf-sha256")]
+            (KdfAlgorithm::HkdfSha256, b"transcript", b"label", HKDF_BOTH),
+        ]
+    }
+
+    const BLAKE3_EMPTY: &str = "91f47563f3da92036f6fb227245b2833d0b42d76b1cc04afe198e92cf3749f61";
+    const BLAKE3_TRANSCRIPT: &str = "dd8e96ce9ad3072a77e03f54579d008608e9a5ea787dcbde70a885ab1c5a8133";
+    const BLAKE3_BOTH: &str = "1128e330f63c9b893e35f0f81b0ad3201d206e83e22e9c2faf0263bdc8ffbee0";
+    #[cfg(feature = "hkdf-sha256")]
+    const HKDF_EMPTY: &str = "b4b09dbbb0de17e51409e08043f9cd1c814d17c5c482a21fa6a9c0c274bd4cd6";
+    #[cfg(feature = "hkdf-sha256")]
+    const HKDF_BOTH: &str = "8e84f942cb0cbed1a9497463d14120cea6611ba1376dffdc8da2523e3db3a537";
+
+    #[test]
+    fn test_kdf_vectors() {
+        for (algorithm, transcript, label, expected) in vectors() {
+            let kdf = algorithm.kdf();
+            assert_eq!(derive(kdf, transcript, label), expected, "{:?}", algorithm);
+            assert_eq!(KdfAlgorithm::from_id(algorithm.id()), Some(algorithm));
+        }
+    }
+
+    #[test]
+    fn test_blake3_default_is_unchanged() {
+        // how version 1 sessions derived their key before there was a KDF
+        let mut out = [0u8; 32];
+        BLAKE3_KDF.derive(&[0x11; 32], &[], &[], &mut out);
+        assert_eq!(&out, blake3::hash(&[0x11; 32]).as_bytes());
+    }
+
+    #[test]
+    fn test_label_and_transcript_separate_keys() {
+        let kdf = KdfAlgorithm::default().kdf();
+        let keys = [derive(kdf, b"", b""), derive(kdf, b"ab", b"c"), derive(kdf, b"a", b"bc"), derive(kdf, b"abc", b"")];
+        for (i, a) in keys.iter().enumerate() {
+            for b in &keys[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+}
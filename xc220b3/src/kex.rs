@@ -0,0 +1,298 @@
+use k256::{ecdh::EphemeralSecret, elliptic_curve::{sec1::ToEncodedPoint, PublicKey}, EncodedPoint};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::session::SessionError;
+
+/// A key agreement a [`Session`](crate::Session) can be keyed with.
+///
+/// Each implementation validates peer public keys itself, `agree` and
+/// `canonical` must refuse anything that isn't a usable key.
+pub trait KeyExchange {
+    /// Identifies the algorithm in transcripts and KDF contexts.
+    const ID: u8;
+
+    type Private;
+    /// A public key as it's sent over the wire.
+    type Public: Clone + AsRef<[u8]>;
+
+    fn generate(rng: &mut (impl CryptoRng + RngCore)) -> (Self::Private, Self::Public);
+
+    /// The shared secret with `peer`. An agreement that lands on the
+    /// identity (or, on curves with them, in a small subgroup) must come out
+    /// all zeros, which sessions refuse with
+    /// [`SessionError::WeakSharedSecret`].
+    fn agree(private: &Self::Private, peer: &Self::Public) -> Result<SharedSecret, SessionError>;
+
+    /// One encoding per key, however it arrived. Used to pick roles.
+    fn canonical(public: &Self::Public) -> Result<Vec<u8>, SessionError>;
+}
+
+/// The output of [`KeyExchange::agree`], zeroed when dropped.
+pub struct SharedSecret(Vec<u8>);
+
+impl SharedSecret {
+    pub fn new(bytes: Vec<u8>) -> SharedSecret {
+        SharedSecret(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// what every agreement a session keys from goes through
+pub(crate) fn agree<K: KeyExchange>(private: &K::Private, peer: &K::Public) -> Result<SharedSecret, SessionError> {
+    let shared = K::agree(private, peer)?;
+    // no early exit, how much of it is zero isn't anyone's business
+    match shared.0.iter().fold(0u8, |acc, byte| acc | byte) {
+        0 => Err(SessionError::WeakSharedSecret),
+        _ => Ok(shared),
+    }
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Ephemeral ECDH over secp256k1, with SEC1 encoded public keys.
+pub struct Secp256k1;
+
+impl KeyExchange for Secp256k1 {
+    const ID: u8 = 1;
+
+    type Private = EphemeralSecret;
+    type Public = EncodedPoint;
+
+    fn generate(rng: &mut (impl CryptoRng + RngCore)) -> (EphemeralSecret, EncodedPoint) {
+        let secret = EphemeralSecret::random(rng);
+        let public = EncodedPoint::from(secret.public_key());
+        (secret, public)
+    }
+
+    // secp256k1 has no small subgroups and k256 won't parse the identity as
+    // a key, so a weak result can't happen. if it did, the x coordinate of
+    // k256's identity is zero
+    fn agree(private: &EphemeralSecret, peer: &EncodedPoint) -> Result<SharedSecret, SessionError> {
+        let peer = PublicKey::from_sec1_bytes(peer.as_ref())?;
+        let shared = private.diffie_hellman(&peer);
+        Ok(SharedSecret::new(shared.raw_secret_bytes().to_vec()))
+    }
+
+    fn canonical(public: &EncodedPoint) -> Result<Vec<u8>, SessionError> {
+        let public: PublicKey<k256::Secp256k1> = PublicKey::from_sec1_bytes(public.as_ref())?;
+        Ok(public.to_encoded_point(true).as_bytes().to_vec())
+    }
+}
+
+/// A secp256k1 public key read off the wire for
+/// [`Session::set_sym_key`](crate::Session::set_sym_key), checked to be a
+/// point on the curve. Reads what the `Session::public_key_*` accessors
+/// write, compressed or not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicKeyBytes(EncodedPoint);
+
+impl PublicKeyBytes {
+    /// Compressed (33 bytes) or uncompressed (65 bytes) SEC1.
+    pub fn from_sec1(bytes: &[u8]) -> Result<PublicKeyBytes, SessionError> {
+        PublicKey::<k256::Secp256k1>::from_sec1_bytes(bytes)?;
+        Ok(PublicKeyBytes(EncodedPoint::from_bytes(bytes).map_err(|_| SessionError::InvalidPubKey)?))
+    }
+
+    /// Hex of either SEC1 form, in either case.
+    pub fn from_hex(hex: &str) -> Result<PublicKeyBytes, SessionError> {
+        let nibble = |c: u8| (c as char).to_digit(16).map(|n| n as u8);
+        let bytes = match hex.len() % 2 {
+            0 => hex.as_bytes().chunks(2).map(|pair| Some(nibble(pair[0])? << 4 | nibble(pair[1])?)).collect::<Option<Vec<u8>>>(),
+            _ => None,
+        };
+        PublicKeyBytes::from_sec1(&bytes.ok_or(SessionError::InvalidPubKey)?)
+    }
+
+    pub fn point(&self) -> &EncodedPoint {
+        &self.0
+    }
+}
+
+impl From<PublicKeyBytes> for EncodedPoint {
+    fn from(public: PublicKeyBytes) -> EncodedPoint {
+        public.0
+    }
+}
+
+// deterministic and completely insecure, for testing Session on its own. a
+// peer key of all 0xff stands in for a low-order point
+#[cfg(test)]
+pub(crate) struct MockExchange;
+
+#[cfg(test)]
+pub(crate) const MOCK_LOW_ORDER: [u8; 8] = [0xff; 8];
+
+#[cfg(test)]
+impl KeyExchange for MockExchange {
+    const ID: u8 = 0xff;
+
+    type Private = [u8; 8];
+    type Public = [u8; 8];
+
+    fn generate(rng: &mut (impl CryptoRng + RngCore)) -> ([u8; 8], [u8; 8]) {
+        let mut key = [0u8; 8];
+        rng.fill_bytes(&mut key);
+        (key, key)
+    }
+
+    fn agree(private: &[u8; 8], peer: &[u8; 8]) -> Result<SharedSecret, SessionError> {
+        if peer == &[0; 8] {
+            return Err(SessionError::InvalidPubKey);
+        }
+        if peer == &MOCK_LOW_ORDER {
+            return Ok(SharedSecret::new(vec![0; 16]));
+        }
+        let (low, high) = if private < peer { (private, peer) } else { (peer, private) };
+        Ok(SharedSecret::new([&low[..], &high[..]].concat()))
+    }
+
+    fn canonical(public: &[u8; 8]) -> Result<Vec<u8>, SessionError> {
+        match public == &[0; 8] {
+            true => Err(SessionError::InvalidPubKey),
+            false => Ok(public.to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use k256::elliptic_curve::sec1::ToEncodedPoint;
+    use rand::thread_rng;
+
+    use crate::kdf::{SessionKdf, BLAKE3_KDF};
+    use crate::kex::{KeyExchange, MockExchange, PublicKeyBytes, Secp256k1, MOCK_LOW_ORDER};
+    use crate::record;
+    use crate::session::{Role, Session, SessionError};
+
+    #[test]
+    fn test_secp256k1_keys_are_unchanged() {
+        for version in [record::V2, record::V3] {
+            let mut sesh1 = Session::new(&mut thread_rng());
+            let mut sesh2 = Session::new(&mut thread_rng());
+            sesh1.set_version(version);
+            sesh2.set_version(version);
+            let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+
+            // what set_sym_key did before the exchange was pulled out, and
+            // from version 3 with both keys, the lower first
+            let peer = k256::PublicKey::from_sec1_bytes(pk2.as_ref()).unwrap();
+            let shared = sesh1.secret.as_ref().unwrap().0.diffie_hellman(&peer);
+            let mut keys = [Secp256k1::canonical(&pk1).unwrap(), Secp256k1::canonical(&pk2).unwrap()];
+            keys.sort();
+            let transcript = match version {
+                record::V2 => Vec::new(),
+                _ => keys.concat(),
+            };
+            let mut expected = [0u8; 32];
+            BLAKE3_KDF.derive(shared.raw_secret_bytes(), &transcript, b"xc220b3 initiator to responder", &mut expected);
+
+            sesh1.set_sym_key(&pk2).unwrap();
+            sesh2.set_sym_key(&pk1).unwrap();
+            let (initiator, responder) = match sesh1.role() {
+                Some(Role::Initiator) => (&sesh1, &sesh2),
+                _ => (&sesh2, &sesh1),
+            };
+            assert_eq!(initiator.send_key, expected);
+            assert_eq!(responder.recv_key, expected);
+        }
+    }
+
+    #[test]
+    fn test_session_over_mock_exchange() {
+        let mut sesh1 = Session::<MockExchange>::generate(&mut thread_rng());
+        let mut sesh2 = Session::<MockExchange>::generate(&mut thread_rng());
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+
+        assert!(matches!(sesh1.set_sym_key(&[0; 8]), Err(SessionError::InvalidPubKey)));
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+        assert!(matches!(sesh1.pk(), Err(SessionError::EmptySecret)));
+
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+        let record = sesh2.encrypt(b"hi back".to_vec()).unwrap();
+        assert_eq!(sesh1.decrypt(record).unwrap(), b"hi back");
+    }
+
+    #[test]
+    fn test_weak_shared_secret_is_refused() {
+        let mut sesh1 = Session::<MockExchange>::generate(&mut thread_rng());
+        let mut sesh2 = Session::<MockExchange>::generate(&mut thread_rng());
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+
+        assert!(matches!(sesh1.set_sym_key(&MOCK_LOW_ORDER), Err(SessionError::WeakSharedSecret)));
+        // nothing was installed, and the secret is still there for the real key
+        assert!(sesh1.role().is_none());
+        assert!(matches!(sesh1.encrypt(b"hello".to_vec()), Err(SessionError::NotReady)));
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+
+        // and renegotiating onto one is refused too
+        let pending = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        assert!(matches!(pending.complete(&MOCK_LOW_ORDER), Err(SessionError::WeakSharedSecret)));
+        assert_eq!(sesh1.stats().epoch, 0);
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_public_key_encodings_round_trip() {
+        let sesh = Session::new(&mut thread_rng());
+        let uncompressed = sesh.public_key_sec1_uncompressed().unwrap();
+        let compressed = sesh.public_key_sec1_compressed().unwrap();
+        let hex = sesh.public_key_hex().unwrap();
+        assert_eq!((uncompressed.len(), compressed.len(), hex.len()), (65, 33, 66));
+        assert_eq!(uncompressed[0], 0x04);
+        assert!(compressed[0] == 0x02 || compressed[0] == 0x03);
+        assert_eq!(uncompressed[1..33], compressed[1..]);
+
+        for encoding in 0..5 {
+            let mut ours = Session::new(&mut thread_rng());
+            let public = match encoding {
+                0 => PublicKeyBytes::from_sec1(&ours.public_key_sec1_uncompressed().unwrap()),
+                1 => PublicKeyBytes::from_sec1(&ours.public_key_sec1_compressed().unwrap()),
+                2 => PublicKeyBytes::from_hex(&ours.public_key_hex().unwrap()),
+                3 => PublicKeyBytes::from_hex(&ours.public_key_hex().unwrap().to_uppercase()),
+                _ => PublicKeyBytes::from_sec1(ours.public_key().unwrap().as_bytes()),
+            }
+            .unwrap();
+            let mut peer = Session::new(&mut thread_rng());
+            let peer_pk = peer.pk().unwrap();
+            peer.set_sym_key(public.point()).unwrap();
+            ours.set_sym_key(&peer_pk).unwrap();
+            let record = peer.encrypt(b"hello".to_vec()).unwrap();
+            assert_eq!(ours.decrypt(record).unwrap(), b"hello");
+        }
+
+        for bogus in ["", "02", &hex[..65], &format!("{}00", hex), &format!("01{}", &hex[2..])] {
+            assert!(matches!(PublicKeyBytes::from_hex(bogus), Err(SessionError::InvalidPubKey)), "{}", bogus);
+        }
+        // x is above the field prime
+        assert!(matches!(PublicKeyBytes::from_sec1(&[&[0x02][..], &[0xff; 32]].concat()), Err(SessionError::InvalidPubKey)));
+    }
+
+    #[test]
+    fn test_compressed_key_is_the_same_point() {
+        let sesh = Session::new(&mut thread_rng());
+        let compressed = sesh.public_key_sec1_compressed().unwrap();
+        let point = k256::PublicKey::from_sec1_bytes(&compressed).unwrap();
+        assert_eq!(point, k256::PublicKey::from_sec1_bytes(sesh.public_key().unwrap().as_bytes()).unwrap());
+        assert_eq!(point.to_encoded_point(false).as_bytes(), &sesh.public_key_sec1_uncompressed().unwrap()[..]);
+        assert_eq!(PublicKeyBytes::from_sec1(&compressed).unwrap().point().as_bytes(), &compressed[..]);
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        let (_, public) = Secp256k1::generate(&mut thread_rng());
+        assert_eq!(Secp256k1::canonical(&public).unwrap(), Secp256k1::canonical(&public.compress()).unwrap());
+        assert_eq!(Secp256k1::canonical(&public).unwrap().len(), 33);
+    }
+}
@@ -0,0 +1,160 @@
+// raw key export for decrypting captured traffic offline, only with the
+// dangerous-debug feature. a keylog line is, NSS keylog style, a label then
+// space separated lowercase hex fields:
+//
+//   XC220B3_KEYS <session id> <epoch> <version> <send key> <recv key>
+//
+// with the epoch as a decimal number. a session logs a line every time it's
+// asked to, so one per epoch is wanted after each renegotiation
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use zeroize::Zeroize;
+
+use crate::builder::SessionConfig;
+use crate::fileformat::{hex, unhex};
+use crate::kex::KeyExchange;
+use crate::record;
+use crate::session::Session;
+
+/// The environment variable naming the file [`Session::log_keys`] appends
+/// to.
+pub const KEYLOG_ENV: &str = "XC220B3_KEYLOGFILE";
+
+const KEYLOG_LABEL: &str = "XC220B3_KEYS";
+
+/// Everything needed to decrypt a session's records, in both directions.
+/// Zeroed on drop, but anything it's written to isn't.
+pub struct DebugKeys {
+    pub session_id: [u8; 16],
+    pub epoch: u64,
+    pub version: u8,
+    pub send_key: [u8; 32],
+    pub recv_key: [u8; 32],
+}
+
+impl DebugKeys {
+    pub fn to_keylog_line(&self) -> String {
+        format!(
+            "{} {} {} {} {} {}",
+            KEYLOG_LABEL,
+            hex(&self.session_id),
+            self.epoch,
+            self.version,
+            hex(&self.send_key),
+            hex(&self.recv_key)
+        )
+    }
+
+    /// Parses a line from [`DebugKeys::to_keylog_line`], `None` for any
+    /// other line.
+    pub fn from_keylog_line(line: &str) -> Option<DebugKeys> {
+        let mut fields = line.split_whitespace();
+        if fields.next()? != KEYLOG_LABEL {
+            return None;
+        }
+        let keys = DebugKeys {
+            session_id: unhex(fields.next()?)?.try_into().ok()?,
+            epoch: fields.next()?.parse().ok()?,
+            version: fields.next()?.parse().ok().filter(|&v| record::is_supported(v))?,
+            send_key: unhex(fields.next()?)?.try_into().ok()?,
+            recv_key: unhex(fields.next()?)?.try_into().ok()?,
+        };
+        fields.next().is_none().then_some(keys)
+    }
+}
+
+impl Drop for DebugKeys {
+    fn drop(&mut self) {
+        self.send_key.zeroize();
+        self.recv_key.zeroize();
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// The session's current keys, in the clear. Only with the
+    /// `dangerous-debug` feature, which a production build must never turn
+    /// on: whoever gets these reads and forges every record of the epoch.
+    pub fn export_raw_keys(&self) -> DebugKeys {
+        DebugKeys {
+            session_id: self.session_id,
+            epoch: self.epoch,
+            version: self.negotiated_version(),
+            send_key: self.send_key,
+            recv_key: self.recv_key,
+        }
+    }
+
+    /// Appends [`Session::export_raw_keys`] as a keylog line to the file
+    /// [`KEYLOG_ENV`] names, if it's set, giving whether it was. Only with
+    /// the `dangerous-debug` feature.
+    pub fn log_keys(&self) -> io::Result<bool> {
+        let Some(path) = std::env::var_os(KEYLOG_ENV) else {
+            return Ok(false);
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", self.export_raw_keys().to_keylog_line())?;
+        Ok(true)
+    }
+}
+
+impl Session {
+    /// A session with logged keys, which opens what the logging side
+    /// received and seals what it sent, for an offline decryptor. It has the
+    /// default record configuration, whatever the logging side's was. Only
+    /// with the `dangerous-debug` feature.
+    pub fn from_debug_keys(keys: &DebugKeys) -> Session {
+        let mut session = Session::with_key(keys.send_key);
+        session.config = SessionConfig::default();
+        session.recv_key = keys.recv_key;
+        session.session_id = keys.session_id;
+        session.epoch = keys.epoch;
+        session.recv_epoch = keys.epoch;
+        session.set_version(keys.version);
+        session
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use crate::keylog::{DebugKeys, KEYLOG_ENV};
+    use crate::session::Session;
+
+    #[test]
+    fn test_keylog_lines_round_trip() {
+        let (sesh1, _) = Session::pair(&mut thread_rng());
+        let line = sesh1.export_raw_keys().to_keylog_line();
+        assert!(line.starts_with("XC220B3_KEYS "));
+        let parsed = DebugKeys::from_keylog_line(&line).unwrap();
+        assert_eq!(parsed.to_keylog_line(), line);
+        assert_eq!((parsed.send_key, parsed.recv_key), (sesh1.send_key, sesh1.recv_key));
+
+        assert!(DebugKeys::from_keylog_line("CLIENT_RANDOM 00 11").is_none());
+        assert!(DebugKeys::from_keylog_line(&format!("{} extra", line)).is_none());
+        assert!(DebugKeys::from_keylog_line(&line[..line.len() - 2]).is_none());
+    }
+
+    #[test]
+    fn test_logged_keys_decrypt_both_directions() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let keylog = std::env::temp_dir().join(format!("xc220b3-keylog-{}", std::process::id()));
+        std::env::set_var(KEYLOG_ENV, &keylog);
+        assert!(sesh1.log_keys().unwrap());
+        std::env::remove_var(KEYLOG_ENV);
+        assert!(!sesh1.log_keys().unwrap());
+
+        let sent = sesh1.encrypt(b"from one".to_vec()).unwrap();
+        let received = sesh2.encrypt(b"from two".to_vec()).unwrap();
+        let lines = std::fs::read_to_string(&keylog).unwrap();
+        std::fs::remove_file(&keylog).unwrap();
+        let keys = DebugKeys::from_keylog_line(lines.trim_end()).unwrap();
+
+        // what it received opens as is, what it sent with the keys swapped
+        assert_eq!(Session::from_debug_keys(&keys).decrypt(received).unwrap(), b"from two");
+        let swapped = DebugKeys { send_key: keys.recv_key, recv_key: keys.send_key, ..keys };
+        assert_eq!(Session::from_debug_keys(&swapped).decrypt(sent).unwrap(), b"from one");
+    }
+}
@@ -0,0 +1,350 @@
+// identity keys kept in a key store, a platform keychain say, instead of in
+// key files that end up committed somewhere. an entry is text, to fit a
+// keychain's password field, and names the version that wrote it so the
+// format can change later:
+//
+//   "xc220b3-identity:" || version (decimal) || ":" || hex of the secret
+//
+// version 1 is the only one, its secret the 32 byte scalar
+
+use std::{collections::HashMap, sync::Mutex};
+
+use k256::{PublicKey, SecretKey};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+use crate::fileformat::{hex, unhex};
+use crate::kex::SharedSecret;
+use crate::provider::{ProviderError, SecretProvider};
+
+const ENTRY_PREFIX: &str = "xc220b3-identity:";
+const ENTRY_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyStoreError {
+    /// Nothing is stored under that name.
+    NotFound,
+    /// The store wouldn't let this process read or write the entry: a
+    /// locked keychain, or access refused by the user.
+    PermissionDenied,
+    /// The entry is there but isn't an identity this build can read.
+    Corrupted,
+    /// The entry was written by a newer format version, given.
+    UnsupportedVersion(u32),
+    /// Anything else the store reported, described.
+    Backend(String),
+}
+
+/// Where [`Identity`] entries are kept, by service and account name, the
+/// way platform keychains address them. The platform keychain is one
+/// implementation (`PlatformKeyStore`, with the `keyring` feature),
+/// [`MemoryKeyStore`] another.
+pub trait KeyStore {
+    /// The entry under `service` and `account`, or
+    /// [`KeyStoreError::NotFound`].
+    fn get(&self, service: &str, account: &str) -> Result<Zeroizing<String>, KeyStoreError>;
+
+    /// Stores `entry` under `service` and `account`, replacing what was
+    /// there.
+    fn set(&self, service: &str, account: &str, entry: &str) -> Result<(), KeyStoreError>;
+}
+
+/// A [`KeyStore`] in process memory, for tests and for processes that only
+/// need an identity while they run.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    entries: Mutex<HashMap<(String, String), Zeroizing<String>>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> MemoryKeyStore {
+        MemoryKeyStore::default()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn get(&self, service: &str, account: &str) -> Result<Zeroizing<String>, KeyStoreError> {
+        let entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.get(&(service.to_string(), account.to_string())).cloned().ok_or(KeyStoreError::NotFound)
+    }
+
+    fn set(&self, service: &str, account: &str, entry: &str) -> Result<(), KeyStoreError> {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.insert((service.to_string(), account.to_string()), Zeroizing::new(entry.to_string()));
+        Ok(())
+    }
+}
+
+/// The platform keychain as a [`KeyStore`], through the `keyring` crate:
+/// the macOS Keychain, Windows Credential Manager, or the Secret Service on
+/// Linux. An entry is a generic password under the service and account.
+#[cfg(feature = "keyring")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlatformKeyStore;
+
+#[cfg(feature = "keyring")]
+impl KeyStore for PlatformKeyStore {
+    fn get(&self, service: &str, account: &str) -> Result<Zeroizing<String>, KeyStoreError> {
+        let entry = keyring::Entry::new(service, account).map_err(keyring_error)?;
+        entry.get_password().map(Zeroizing::new).map_err(keyring_error)
+    }
+
+    fn set(&self, service: &str, account: &str, entry: &str) -> Result<(), KeyStoreError> {
+        let stored = keyring::Entry::new(service, account).map_err(keyring_error)?;
+        stored.set_password(entry).map_err(keyring_error)
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_error(e: keyring::Error) -> KeyStoreError {
+    match e {
+        keyring::Error::NoEntry => KeyStoreError::NotFound,
+        keyring::Error::NoStorageAccess(_) => KeyStoreError::PermissionDenied,
+        // not even text, so not an entry this wrote. the bytes could still
+        // be a secret of some kind
+        keyring::Error::BadEncoding(bytes) => {
+            drop(Zeroizing::new(bytes));
+            KeyStoreError::Corrupted
+        }
+        e => KeyStoreError::Backend(e.to_string()),
+    }
+}
+
+/// A long-term identity key that can be kept in a [`KeyStore`]. It's a
+/// [`SecretProvider`], so it goes straight into
+/// [`SessionBuilder::identity_provider`](crate::SessionBuilder::identity_provider).
+#[derive(Clone)]
+pub struct Identity {
+    secret: SecretKey,
+}
+
+impl Identity {
+    pub fn generate(rng: &mut (impl CryptoRng + RngCore)) -> Identity {
+        Identity { secret: SecretKey::random(rng) }
+    }
+
+    pub fn from_secret_key(secret: SecretKey) -> Identity {
+        Identity { secret }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.secret.public_key()
+    }
+
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.secret
+    }
+
+    /// Stores this identity in `store` under `service` and `account`.
+    pub fn store_in(&self, store: &impl KeyStore, service: &str, account: &str) -> Result<(), KeyStoreError> {
+        let secret = Zeroizing::new(hex(&self.secret.to_be_bytes()));
+        let entry = Zeroizing::new(format!("{}{}:{}", ENTRY_PREFIX, ENTRY_VERSION, &*secret));
+        store.set(service, account, &entry)
+    }
+
+    /// Loads the identity [`Identity::store_in`] stored.
+    pub fn from_store(store: &impl KeyStore, service: &str, account: &str) -> Result<Identity, KeyStoreError> {
+        let entry = store.get(service, account)?;
+        let (version, secret) = entry
+            .strip_prefix(ENTRY_PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .ok_or(KeyStoreError::Corrupted)?;
+        let version: u32 = version.parse().map_err(|_| KeyStoreError::Corrupted)?;
+        if version != ENTRY_VERSION {
+            return Err(KeyStoreError::UnsupportedVersion(version));
+        }
+        let secret = Zeroizing::new(unhex(secret).filter(|secret| secret.len() == 32).ok_or(KeyStoreError::Corrupted)?);
+        let secret = SecretKey::from_be_bytes(&secret).map_err(|_| KeyStoreError::Corrupted)?;
+        Ok(Identity { secret })
+    }
+
+    /// [`Identity::store_in`] the platform keychain, see [`PlatformKeyStore`].
+    #[cfg(feature = "keyring")]
+    pub fn store_in_keyring(&self, service: &str, account: &str) -> Result<(), KeyStoreError> {
+        self.store_in(&PlatformKeyStore, service, account)
+    }
+
+    /// [`Identity::from_store`] the platform keychain, see
+    /// [`PlatformKeyStore`].
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(service: &str, account: &str) -> Result<Identity, KeyStoreError> {
+        Identity::from_store(&PlatformKeyStore, service, account)
+    }
+}
+
+impl SecretProvider for Identity {
+    fn public_key(&self) -> Result<PublicKey, ProviderError> {
+        SecretProvider::public_key(&self.secret)
+    }
+
+    fn ecdh(&self, peer: &PublicKey) -> Result<SharedSecret, ProviderError> {
+        self.secret.ecdh(peer)
+    }
+
+    fn sign(&self, prehash: &[u8; 32]) -> Result<[u8; 64], ProviderError> {
+        self.secret.sign(prehash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+    use zeroize::Zeroizing;
+
+    use crate::keystore::{Identity, KeyStore, KeyStoreError, MemoryKeyStore};
+
+    // a keychain that's locked
+    struct Locked;
+
+    impl KeyStore for Locked {
+        fn get(&self, _: &str, _: &str) -> Result<Zeroizing<String>, KeyStoreError> {
+            Err(KeyStoreError::PermissionDenied)
+        }
+
+        fn set(&self, _: &str, _: &str, _: &str) -> Result<(), KeyStoreError> {
+            Err(KeyStoreError::PermissionDenied)
+        }
+    }
+
+    #[test]
+    fn test_identity_roundtrips_through_a_store() {
+        let store = MemoryKeyStore::new();
+        let identity = Identity::generate(&mut thread_rng());
+        identity.store_in(&store, "xc220b3", "server").unwrap();
+        let loaded = Identity::from_store(&store, "xc220b3", "server").unwrap();
+        assert_eq!(loaded.public_key(), identity.public_key());
+        assert!(store.get("xc220b3", "server").unwrap().starts_with("xc220b3-identity:1:"));
+
+        assert_eq!(Identity::from_store(&store, "xc220b3", "client").err(), Some(KeyStoreError::NotFound));
+        assert_eq!(Identity::from_store(&Locked, "xc220b3", "server").err(), Some(KeyStoreError::PermissionDenied));
+        assert_eq!(identity.store_in(&Locked, "xc220b3", "server").err(), Some(KeyStoreError::PermissionDenied));
+    }
+
+    #[test]
+    fn test_bad_entries_are_told_apart() {
+        let store = MemoryKeyStore::new();
+        let zero = format!("xc220b3-identity:1:{}", "0".repeat(64));
+        let newer = format!("xc220b3-identity:2:{}", "ab".repeat(32));
+        for (entry, expected) in [
+            ("hunter2", KeyStoreError::Corrupted),
+            ("xc220b3-identity:1:abcd", KeyStoreError::Corrupted),
+            ("xc220b3-identity:one:abcd", KeyStoreError::Corrupted),
+            (&zero[..], KeyStoreError::Corrupted),
+            (&newer[..], KeyStoreError::UnsupportedVersion(2)),
+        ] {
+            store.set("xc220b3", "server", entry).unwrap();
+            assert_eq!(Identity::from_store(&store, "xc220b3", "server").err(), Some(expected));
+        }
+    }
+
+    // the keyring crate's mock keeps a password in the one entry it was set
+    // through, this keeps one mock credential per name so a later entry
+    // finds it, and so a test can make that credential fail
+    #[cfg(feature = "keyring")]
+    mod mock_keychain {
+        use std::any::Any;
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex, Once};
+
+        use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+        use keyring::mock::MockCredential;
+
+        type Credentials = HashMap<(String, String), Arc<MockCredential>>;
+
+        static CREDENTIALS: Mutex<Option<Credentials>> = Mutex::new(None);
+
+        pub(super) fn credential(service: &str, account: &str) -> Arc<MockCredential> {
+            static INSTALL: Once = Once::new();
+            INSTALL.call_once(|| keyring::set_default_credential_builder(Box::new(Builder)));
+            let mut credentials = CREDENTIALS.lock().unwrap();
+            let credentials = credentials.get_or_insert_with(HashMap::new);
+            credentials.entry((service.to_string(), account.to_string())).or_default().clone()
+        }
+
+        struct Builder;
+
+        impl CredentialBuilderApi for Builder {
+            fn build(&self, _: Option<&str>, service: &str, account: &str) -> keyring::Result<Box<Credential>> {
+                Ok(Box::new(Shared(credential(service, account))))
+            }
+
+            fn as_any(&self) -> &dyn Any {
+                self
+            }
+        }
+
+        #[derive(Debug)]
+        struct Shared(Arc<MockCredential>);
+
+        impl CredentialApi for Shared {
+            fn set_password(&self, password: &str) -> keyring::Result<()> {
+                self.0.set_password(password)
+            }
+
+            fn get_password(&self) -> keyring::Result<String> {
+                self.0.get_password()
+            }
+
+            fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+                self.0.set_secret(secret)
+            }
+
+            fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+                self.0.get_secret()
+            }
+
+            fn delete_credential(&self) -> keyring::Result<()> {
+                self.0.delete_credential()
+            }
+
+            fn as_any(&self) -> &dyn Any {
+                self
+            }
+        }
+    }
+
+    #[cfg(feature = "keyring")]
+    #[test]
+    fn test_identity_roundtrips_through_the_keyring() {
+        use keyring::credential::CredentialApi;
+
+        use crate::keystore::PlatformKeyStore;
+
+        let credential = mock_keychain::credential("xc220b3-test", "server");
+        let identity = Identity::generate(&mut thread_rng());
+        identity.store_in_keyring("xc220b3-test", "server").unwrap();
+        let loaded = Identity::from_keyring("xc220b3-test", "server").unwrap();
+        assert_eq!(loaded.public_key(), identity.public_key());
+        assert_eq!(*PlatformKeyStore.get("xc220b3-test", "server").unwrap(), credential.get_password().unwrap());
+        assert!(credential.get_password().unwrap().starts_with("xc220b3-identity:1:"));
+
+        mock_keychain::credential("xc220b3-test", "client");
+        assert_eq!(Identity::from_keyring("xc220b3-test", "client").err(), Some(KeyStoreError::NotFound));
+    }
+
+    #[cfg(feature = "keyring")]
+    #[test]
+    fn test_keyring_failures_are_told_apart() {
+        use keyring::credential::CredentialApi;
+
+        let credential = mock_keychain::credential("xc220b3-test", "failing");
+        let identity = Identity::generate(&mut thread_rng());
+        let denied = || keyring::Error::NoStorageAccess(std::io::Error::other("locked").into());
+        credential.set_error(denied());
+        assert_eq!(identity.store_in_keyring("xc220b3-test", "failing").err(), Some(KeyStoreError::PermissionDenied));
+        identity.store_in_keyring("xc220b3-test", "failing").unwrap();
+        credential.set_error(denied());
+        assert_eq!(Identity::from_keyring("xc220b3-test", "failing").err(), Some(KeyStoreError::PermissionDenied));
+        credential.set_error(keyring::Error::PlatformFailure(std::io::Error::other("bus closed").into()));
+        assert!(matches!(Identity::from_keyring("xc220b3-test", "failing"), Err(KeyStoreError::Backend(_))));
+        assert_eq!(Identity::from_keyring("xc220b3-test", "failing").unwrap().public_key(), identity.public_key());
+
+        let newer = format!("xc220b3-identity:2:{}", "ab".repeat(32));
+        credential.set_secret(newer.as_bytes()).unwrap();
+        assert_eq!(Identity::from_keyring("xc220b3-test", "failing").err(), Some(KeyStoreError::UnsupportedVersion(2)));
+        for stored in [&b"hunter2"[..], &[0xff, 0xfe][..]] {
+            credential.set_secret(stored).unwrap();
+            assert_eq!(Identity::from_keyring("xc220b3-test", "failing").err(), Some(KeyStoreError::Corrupted));
+        }
+    }
+}
@@ -1,14 +1,104 @@
+mod builder;
+mod certificate;
+mod channel;
+mod cipher;
+mod close;
+mod dedup;
+mod direction;
+mod envelope;
+mod failures;
+mod framed;
+mod group;
+mod identity;
+mod kdf;
+mod kex;
+mod keystore;
 mod mac;
+mod pool;
+mod provider;
+mod random;
+mod ratchet;
+mod record;
+mod rekey;
+mod renegotiate;
 mod session;
+mod stream;
+mod token;
+mod transcript;
 mod xc220;
+mod zero_rtt;
+// vendored from rust-crypto, not all of it is wired up yet
+#[allow(dead_code)]
 mod buffer;
+#[allow(dead_code)]
 mod cryptoutil;
+#[allow(dead_code)]
 mod symmetriccipher;
+#[allow(dead_code)]
 mod simd;
 mod lockedbox;
+#[cfg(test)]
+mod vectors;
+#[cfg(all(test, feature = "sodium-interop"))]
+mod sodium_interop;
+#[cfg(feature = "std")]
+pub mod bindings;
+pub mod framing;
+pub mod handshake;
+#[cfg(feature = "noise")]
+pub mod noise;
+pub mod ecies;
+pub mod fileformat;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "bytes")]
+mod zero_copy;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "dangerous-debug")]
+mod keylog;
+#[cfg(feature = "argon2")]
+mod argon2;
 
+pub use builder::*;
+pub use certificate::*;
+pub use channel::{ChannelError, SecureChannel};
+pub use cipher::{RecordCipher, XChaCha20Blake3};
+#[cfg(feature = "rayon")]
+pub use cipher::{set_parallel_mac_threshold, DEFAULT_PARALLEL_MAC_THRESHOLD};
+pub use close::*;
+pub use direction::{SessionReceiver, SessionSender};
+pub use envelope::{Envelope, MAX_ENVELOPE_METADATA_LEN};
+pub use failures::*;
+pub use framed::FramedRecord;
+pub use group::*;
+pub use identity::{IdentitySignature, SIGNED_PUBLIC_KEY_LEN};
+pub use kdf::*;
+pub use kex::*;
+pub use keystore::{Identity, KeyStore, KeyStoreError, MemoryKeyStore};
+#[cfg(feature = "keyring")]
+pub use keystore::PlatformKeyStore;
+pub use pool::EphemeralPool;
+pub use provider::{ProviderError, SecretProvider};
+#[cfg(feature = "std")]
+pub use random::OsRandom;
+pub use random::{RandomSource, SharedRng};
+pub use ratchet::{RatchetKeyPair, RatchetSession, DEFAULT_MAX_SKIP, DEFAULT_SKIPPED_CAPACITY, RATCHET_HEADER_LEN};
+pub use renegotiate::*;
 pub use session::*;
+pub use stream::*;
+pub use zero_rtt::ZERO_RTT_KEY_LEN;
+#[cfg(feature = "bytes")]
+pub use zero_copy::EncryptedMessage;
+#[cfg(feature = "dangerous-debug")]
+pub use keylog::{DebugKeys, KEYLOG_ENV};
+#[cfg(feature = "argon2")]
+pub use self::argon2::{PasswordConfig, MIN_SALT_LEN};
 pub use lockedbox::*;
+pub use k256::{EncodedPoint, PublicKey, SecretKey};
 
 #[macro_use]
-extern crate cfg_if;
\ No newline at end of file
+extern crate cfg_if;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
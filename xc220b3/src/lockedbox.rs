@@ -1,5 +1,6 @@
 use blake3::Hasher;
-use core::iter::repeat;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
 #[cfg(feature = "tracing")]
 use tracing::{info_span, trace};
 
@@ -14,8 +15,17 @@ pub struct LockedBox {
 #[derive(Debug)]
 pub enum LockedBoxError {
     MacMismatch,
+    /// Too short to hold a MAC, the length is given.
+    InvalidLength(usize),
+    /// A routing hint longer than [`MAX_ROUTING_HINT_LEN`], the length is given.
+    HintTooLong(usize),
 }
 
+/// Longest routing hint [`LockedBox::encrypt_routed`] accepts.
+pub const MAX_ROUTING_HINT_LEN: usize = u16::MAX as usize;
+
+const HINT_LEN_LEN: usize = 2;
+
 impl LockedBox {
     /// Creates a new LockedBox with a random ephemeral secret using provided RNG.
     pub fn new(seed: &[u8; 32]) -> LockedBox {
@@ -34,20 +44,17 @@ impl LockedBox {
     pub fn encrypt(&mut self, plain: Vec<u8>) -> Vec<u8> {
         cfg_if!(
             if #[cfg(feature = "tracing")] {
-                let span = info_span!("encrypt");
+                let start = Instant::now();
+                let span = info_span!("encrypt", record = "box", len = plain.len());
                 let _enter = span.enter();
             }
         );
 
-        #[cfg(feature = "tracing")]
-        trace!("start");
-        let mac = self.mac(&plain);
-        #[cfg(feature = "tracing")]
-        trace!("MAC: {}", mac.to_hex());
+        let mac = self.mac(&plain, &[]);
 
         #[cfg(feature = "tracing")]
         trace!("allocating for {}byte output", plain.len());
-        let mut output: Vec<u8> = repeat(0).take(plain.len()).collect();
+        let mut output: Vec<u8> = vec![0; plain.len()];
         #[cfg(feature = "tracing")]
         trace!("encrypting");
         self.xcc20 = XC220::new(&self.key, mac.as_bytes());
@@ -56,25 +63,26 @@ impl LockedBox {
         trace!("extending with mac");
         output.extend_from_slice(mac.as_bytes());
         #[cfg(feature = "tracing")]
-        trace!("done");
+        trace!(elapsed_us = start.elapsed().as_micros() as u64, "done");
         output
     }
 
     pub fn decrypt(&mut self, mut ciphertext: Vec<u8>) -> Result<Vec<u8>, LockedBoxError> {
         cfg_if!(
             if #[cfg(feature = "tracing")] {
-                let span = info_span!("decrypt");
+                let start = Instant::now();
+                let span = info_span!("decrypt", record = "box", len = ciphertext.len());
                 let _enter = span.enter();
             }
         );
 
-        #[cfg(feature = "tracing")]
-        trace!("start");
-
+        if ciphertext.len() < 24 {
+            return Err(LockedBoxError::InvalidLength(ciphertext.len()));
+        }
         let claimed_mac = MAC::from(ciphertext.split_off(ciphertext.len() - 24));
         #[cfg(feature = "tracing")]
         trace!("allocating for {}byte output", ciphertext.len());
-        let mut output: Vec<u8> = repeat(0).take(ciphertext.len()).collect();
+        let mut output: Vec<u8> = vec![0; ciphertext.len()];
         #[cfg(feature = "tracing")]
         trace!("creating new chacha");
         self.xcc20 = XC220::new(&self.key, claimed_mac.as_bytes());
@@ -84,27 +92,105 @@ impl LockedBox {
 
         #[cfg(feature = "tracing")]
         trace!("calculating our own mac");
-        let calculated_mac = self.mac(&output);
+        let calculated_mac = self.mac(&output, &[]);
         #[cfg(feature = "tracing")]
         trace!("checking mac");
         if claimed_mac != calculated_mac {
             #[cfg(feature = "tracing")]
-            trace!("Claimed MAC: {}", claimed_mac.to_hex());
-            #[cfg(feature = "tracing")]
-            trace!("Calculated MAC: {}", calculated_mac.to_hex());
+            trace!(elapsed_us = start.elapsed().as_micros() as u64, "mac mismatch");
             return Err(LockedBoxError::MacMismatch);
         } else {
             #[cfg(feature = "tracing")]
             trace!("mac good 👍");
         }
         #[cfg(feature = "tracing")]
-        trace!("done");
+        trace!(elapsed_us = start.elapsed().as_micros() as u64, "done");
         Ok(output)
     }
 
-    fn mac(&mut self, plain: &[u8]) -> MAC {
+    /// Encrypts `plain` with a cleartext `routing_hint` (a key id, say) in
+    /// front, as `le16(hint len) || hint || ciphertext || mac`. The hint is
+    /// covered by the MAC, so it can't be swapped, but it's **not encrypted**:
+    /// anyone who sees the box can read it. Relays read it with
+    /// [`LockedBox::routing_hint`] without needing the key.
+    pub fn encrypt_routed(&mut self, plain: Vec<u8>, routing_hint: &[u8]) -> Result<Vec<u8>, LockedBoxError> {
+        if routing_hint.len() > MAX_ROUTING_HINT_LEN {
+            return Err(LockedBoxError::HintTooLong(routing_hint.len()));
+        }
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let start = Instant::now();
+                let span = info_span!("encrypt", record = "routed_box", len = plain.len(), hint_len = routing_hint.len());
+                let _enter = span.enter();
+            }
+        );
+
+        let mac = self.mac(&plain, routing_hint);
+
+        let body = HINT_LEN_LEN + routing_hint.len();
+        let mut output: Vec<u8> = vec![0; body + plain.len()];
+        output[..HINT_LEN_LEN].copy_from_slice(&(routing_hint.len() as u16).to_le_bytes());
+        output[HINT_LEN_LEN..body].copy_from_slice(routing_hint);
+        self.xcc20 = XC220::new(&self.key, mac.as_bytes());
+        self.xcc20.process(&plain[..], &mut output[body..]);
+        output.extend_from_slice(mac.as_bytes());
+        #[cfg(feature = "tracing")]
+        trace!(elapsed_us = start.elapsed().as_micros() as u64, "done");
+        Ok(output)
+    }
+
+    /// Reads the routing hint of a box made by [`LockedBox::encrypt_routed`]
+    /// without decrypting or verifying it. Only [`LockedBox::decrypt_routed`]
+    /// tells whether the hint is genuine.
+    pub fn routing_hint(sealed: &[u8]) -> Result<&[u8], LockedBoxError> {
+        if sealed.len() < HINT_LEN_LEN + 24 {
+            return Err(LockedBoxError::InvalidLength(sealed.len()));
+        }
+        let hint_len = u16::from_le_bytes([sealed[0], sealed[1]]) as usize;
+        if sealed.len() < HINT_LEN_LEN + hint_len + 24 {
+            return Err(LockedBoxError::InvalidLength(sealed.len()));
+        }
+        Ok(&sealed[HINT_LEN_LEN..HINT_LEN_LEN + hint_len])
+    }
+
+    /// Reverses [`LockedBox::encrypt_routed`], returning the verified routing
+    /// hint and the plaintext.
+    pub fn decrypt_routed(&mut self, mut sealed: Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), LockedBoxError> {
+        let hint_len = LockedBox::routing_hint(&sealed)?.len();
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let start = Instant::now();
+                let span = info_span!("decrypt", record = "routed_box", len = sealed.len(), hint_len);
+                let _enter = span.enter();
+            }
+        );
+
+        let claimed_mac = MAC::from(sealed.split_off(sealed.len() - 24));
+        let ciphertext = sealed.split_off(HINT_LEN_LEN + hint_len);
+        let hint = sealed.split_off(HINT_LEN_LEN);
+        let mut output: Vec<u8> = vec![0; ciphertext.len()];
+        self.xcc20 = XC220::new(&self.key, claimed_mac.as_bytes());
+        self.xcc20.process(&ciphertext[..], &mut output[..]);
+
+        let calculated_mac = self.mac(&output, &hint);
+        if claimed_mac != calculated_mac {
+            #[cfg(feature = "tracing")]
+            trace!(elapsed_us = start.elapsed().as_micros() as u64, "mac mismatch");
+            return Err(LockedBoxError::MacMismatch);
+        }
+        #[cfg(feature = "tracing")]
+        trace!(elapsed_us = start.elapsed().as_micros() as u64, "done");
+        Ok((hint, output))
+    }
+
+    fn mac(&mut self, plain: &[u8], hint: &[u8]) -> MAC {
         self.b3.update(plain);
         self.b3.update(&self.key);
+        // same length-suffixed layout as session aad
+        if !hint.is_empty() {
+            self.b3.update(hint);
+            self.b3.update(&(hint.len() as u64).to_le_bytes());
+        }
 
         let mut reader = self.b3.finalize_xof();
         let hash = MAC::from_output_reader(&mut reader);
@@ -113,18 +199,52 @@ impl LockedBox {
     }
 }
 
-cfg_if! {
-    if #[cfg(feature = "tracing")] {
-        const HEX_CHARS: &[u8] = b"0123456789abcdef";
+#[cfg(test)]
+mod test {
+    use crate::lockedbox::{LockedBox, LockedBoxError, MAX_ROUTING_HINT_LEN};
 
-// u8 array to hex string using lookup table
-fn to_hex(bytes: &[u8]) -> String {
-    let mut hex = String::with_capacity(bytes.len() * 2);
-    for byte in bytes {
-        hex.push(HEX_CHARS[(byte >> 4) as usize] as char);
-        hex.push(HEX_CHARS[(byte & 0xf) as usize] as char);
+    #[test]
+    fn test_routed_roundtrip() {
+        let mut lb = LockedBox::new(&[7; 32]);
+        let sealed = lb.encrypt_routed(b"hello".to_vec(), b"key-id-1").unwrap();
+
+        // a relay needs no key to read the hint
+        assert_eq!(LockedBox::routing_hint(&sealed).unwrap(), b"key-id-1");
+
+        let (hint, plain) = lb.decrypt_routed(sealed).unwrap();
+        assert_eq!(hint, b"key-id-1");
+        assert_eq!(plain, b"hello");
     }
-    hex
-}
+
+    #[test]
+    fn test_routed_empty_hint() {
+        let mut lb = LockedBox::new(&[7; 32]);
+        let sealed = lb.encrypt_routed(b"hello".to_vec(), &[]).unwrap();
+        assert_eq!(LockedBox::routing_hint(&sealed).unwrap(), b"");
+        assert_eq!(lb.decrypt_routed(sealed).unwrap(), (Vec::new(), b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_routed_hint_is_authenticated() {
+        let mut lb = LockedBox::new(&[7; 32]);
+        let mut sealed = lb.encrypt_routed(b"hello".to_vec(), b"key-id-1").unwrap();
+        sealed[2 + 7] = b'2';
+
+        assert_eq!(LockedBox::routing_hint(&sealed).unwrap(), b"key-id-2");
+        assert!(matches!(lb.decrypt_routed(sealed), Err(LockedBoxError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_routed_rejects_bad_lengths() {
+        let mut lb = LockedBox::new(&[7; 32]);
+        let hint = vec![0; MAX_ROUTING_HINT_LEN + 1];
+        assert!(matches!(lb.encrypt_routed(Vec::new(), &hint), Err(LockedBoxError::HintTooLong(_))));
+
+        let sealed = lb.encrypt_routed(b"hello".to_vec(), b"key-id-1").unwrap();
+        // a hint length pointing past the end
+        let mut lying = sealed.clone();
+        lying[0] = 0xff;
+        assert!(matches!(LockedBox::routing_hint(&lying), Err(LockedBoxError::InvalidLength(_))));
+        assert!(matches!(lb.decrypt_routed(sealed[..10].to_vec()), Err(LockedBoxError::InvalidLength(10))));
     }
 }
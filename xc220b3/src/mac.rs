@@ -12,6 +12,7 @@ use arrayvec::ArrayString;
 use blake3::OutputReader;
 use constant_time_eq::constant_time_eq;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct MAC([u8; 24]);
 
 impl MAC {
@@ -0,0 +1,142 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use memmap2::Mmap;
+
+use crate::kex::KeyExchange;
+use crate::session::{Session, SessionError};
+use crate::stream::{KIND_LEN, STREAM_ID_LEN};
+
+// how many chunks of the mapping are handed to the stream at once, windows
+// line up with chunk boundaries so nothing gets copied into the stream buffer
+const WINDOW_CHUNKS: usize = 64;
+
+impl<K: KeyExchange> Session<K> {
+    /// Encrypts the file at `src` into `dst` with the chunked stream
+    /// construction (see [`crate::StreamEncryptor`]), mapping `src` read-only
+    /// instead of reading it into memory. `dst` is written sequentially.
+    ///
+    /// The caller must make sure nothing writes to or truncates `src` until
+    /// this returns, by locking it or owning it outright. The mapping is read
+    /// as a plain `&[u8]`, so a change underneath it is undefined behaviour,
+    /// and truncating the file raises `SIGBUS` on the next read past its end.
+    /// Even short of that, a chunk rewritten between being MACed and being
+    /// encrypted goes out under the keystream its old bytes' tag selects, so
+    /// the tag no longer commits to the plaintext it encrypts. If `src` can't
+    /// be locked, checking its length and modification time before and after
+    /// narrows the window without closing it, read the file instead.
+    pub fn encrypt_file_mmap(&mut self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), SessionError> {
+        let src = File::open(src)?;
+        let map = map(&src)?;
+        let mut dst = BufWriter::new(File::create(dst)?);
+
+        let window_len = WINDOW_CHUNKS * self.config.stream_chunk_len;
+        let mut encryptor = self.stream_encryptor();
+        for window in map_bytes(&map).chunks(window_len) {
+            dst.write_all(&encryptor.update(window)?)?;
+        }
+        dst.write_all(&encryptor.finish()?)?;
+        dst.flush()?;
+        Ok(())
+    }
+
+    /// Decrypts a file written by [`Session::encrypt_file_mmap`]. Only
+    /// authenticated chunks are written to `dst`, but on error `dst` holds a
+    /// partial file and should be discarded.
+    ///
+    /// `src` is mapped the same way, so the caller must hold it exclusively
+    /// here too, see [`Session::encrypt_file_mmap`].
+    pub fn decrypt_file_mmap(&mut self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), SessionError> {
+        let src = File::open(src)?;
+        let map = map(&src)?;
+        let mut dst = BufWriter::new(File::create(dst)?);
+
+        let window_len = WINDOW_CHUNKS * (KIND_LEN + self.ciphertext_len(self.config.stream_chunk_len));
+        let mut decryptor = self.stream_decryptor();
+        // the stream's id goes in first, so the windows start on chunks
+        let (id, chunks) = map_bytes(&map).split_at(map_bytes(&map).len().min(STREAM_ID_LEN));
+        dst.write_all(&decryptor.update(id)?)?;
+        for window in chunks.chunks(window_len) {
+            dst.write_all(&decryptor.update(window)?)?;
+        }
+        dst.write_all(&decryptor.finish()?)?;
+        dst.flush()?;
+        Ok(())
+    }
+}
+
+// zero-length files can't be mapped on every platform
+fn map(file: &File) -> Result<Option<Mmap>, SessionError> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the mapping is read-only, and the callers require that nothing
+    // writes to or truncates the file while it's alive, so the bytes behind
+    // the `&[u8]` it derefs to don't change and stay mapped. The map is never
+    // handed out past the call that made it
+    Ok(Some(unsafe { Mmap::map(file)? }))
+}
+
+fn map_bytes(map: &Option<Mmap>) -> &[u8] {
+    match map {
+        Some(map) => &map[..],
+        None => &[],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use rand::{thread_rng, RngCore};
+
+    use crate::session::{Session, SessionError};
+    use crate::stream::STREAM_CHUNK_LEN;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("xc220b3-{}-{}", std::process::id(), name))
+    }
+
+    fn roundtrip(name: &str, len: usize) {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let (plain, sealed, opened) = (temp_path(name), temp_path(&format!("{}.enc", name)), temp_path(&format!("{}.dec", name)));
+
+        let mut data = vec![0u8; len];
+        thread_rng().fill_bytes(&mut data);
+        fs::write(&plain, &data).unwrap();
+
+        sesh1.encrypt_file_mmap(&plain, &sealed).unwrap();
+        sesh2.decrypt_file_mmap(&sealed, &opened).unwrap();
+        assert_eq!(fs::read(&opened).unwrap(), data);
+
+        for path in [plain, sealed, opened] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mmap_roundtrip() {
+        roundtrip("empty", 0);
+        roundtrip("exact", 4 * STREAM_CHUNK_LEN);
+        // several windows with a ragged end
+        roundtrip("multi-window", 150 * STREAM_CHUNK_LEN + 123);
+    }
+
+    #[test]
+    fn test_mmap_detects_tampering() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let (plain, sealed, opened) = (temp_path("tamper"), temp_path("tamper.enc"), temp_path("tamper.dec"));
+
+        fs::write(&plain, vec![1u8; 3 * STREAM_CHUNK_LEN]).unwrap();
+        sesh1.encrypt_file_mmap(&plain, &sealed).unwrap();
+
+        let mut ciphertext = fs::read(&sealed).unwrap();
+        ciphertext[STREAM_CHUNK_LEN + 100] ^= 1;
+        fs::write(&sealed, ciphertext).unwrap();
+
+        assert!(matches!(sesh2.decrypt_file_mmap(&sealed, &opened), Err(SessionError::MacMismatch)));
+
+        for path in [plain, sealed, opened] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,644 @@
+// Noise_XX_secp256k1_ChaChaPoly_BLAKE3, for talking to existing Noise tooling
+// (snow, given a resolver for secp256k1 and BLAKE3) instead of our own
+// handshake and records
+//
+//   -> e
+//   <- e, ee, s, es
+//   -> s, se
+//
+// secp256k1 public keys are SEC1 compressed, and so is the DH output (the
+// shared point, not just its x coordinate), since Noise needs both to be
+// DHLEN = 33 bytes. BLAKE3 has 64 byte blocks and 32 byte output, so HMAC and
+// HKDF are exactly as the spec defines them for any hash
+
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305};
+use k256::{elliptic_curve::sec1::ToEncodedPoint, EncodedPoint, PublicKey, SecretKey};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::session::SessionError;
+
+/// The full Noise protocol name, the handshake hash starts from it.
+pub const NOISE_PROTOCOL_NAME: &str = "Noise_XX_secp256k1_ChaChaPoly_BLAKE3";
+
+/// Longest Noise message, handshake or transport.
+pub const NOISE_MAX_MESSAGE_LEN: usize = 65535;
+
+const DH_LEN: usize = 33;
+const HASH_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+const TAG_LEN: usize = 16;
+
+#[derive(Clone, Copy)]
+enum Token {
+    E,
+    S,
+    Ee,
+    Es,
+    Se,
+}
+
+const PATTERN: [&[Token]; 3] = [&[Token::E], &[Token::E, Token::Ee, Token::S, Token::Es], &[Token::S, Token::Se]];
+
+fn hash(parts: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut b3 = blake3::Hasher::new();
+    for part in parts {
+        b3.update(part);
+    }
+    *b3.finalize().as_bytes()
+}
+
+fn hmac(key: &[u8; HASH_LEN], parts: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut ipad = [0x36u8; BLOCK_LEN];
+    let mut opad = [0x5cu8; BLOCK_LEN];
+    for (i, byte) in key.iter().enumerate() {
+        ipad[i] ^= byte;
+        opad[i] ^= byte;
+    }
+    let inner = hash(&[&[&ipad[..]], parts].concat());
+    hash(&[&opad, &inner])
+}
+
+fn hkdf(ck: &[u8; HASH_LEN], ikm: &[u8]) -> ([u8; HASH_LEN], [u8; HASH_LEN]) {
+    let temp = hmac(ck, &[ikm]);
+    let first = hmac(&temp, &[&[1]]);
+    let second = hmac(&temp, &[&first, &[2]]);
+    (first, second)
+}
+
+fn dh(secret: &SecretKey, public: &PublicKey) -> [u8; DH_LEN] {
+    let shared = (public.to_projective() * *secret.to_nonzero_scalar()).to_affine();
+    let mut out = [0u8; DH_LEN];
+    out.copy_from_slice(shared.to_encoded_point(true).as_bytes());
+    out
+}
+
+fn public_bytes(secret: &SecretKey) -> [u8; DH_LEN] {
+    let mut out = [0u8; DH_LEN];
+    out.copy_from_slice(secret.public_key().to_encoded_point(true).as_bytes());
+    out
+}
+
+fn parse_public(bytes: &[u8]) -> Result<PublicKey, SessionError> {
+    if bytes.len() != DH_LEN {
+        return Err(SessionError::InvalidPubKey);
+    }
+    Ok(PublicKey::from_sec1_bytes(bytes)?)
+}
+
+struct CipherState {
+    k: Option<[u8; 32]>,
+    n: u64,
+}
+
+impl CipherState {
+    fn empty() -> CipherState {
+        CipherState { k: None, n: 0 }
+    }
+
+    fn keyed(k: [u8; 32]) -> CipherState {
+        CipherState { k: Some(k), n: 0 }
+    }
+
+    // 2^64 - 1 is reserved for rekeying
+    fn nonce(&self) -> Result<[u8; 12], SessionError> {
+        if self.n == u64::MAX {
+            return Err(SessionError::CounterExhausted);
+        }
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.n.to_le_bytes());
+        Ok(nonce)
+    }
+
+    fn encrypt(&mut self, ad: &[u8], plain: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let k = match &self.k {
+            Some(k) => k,
+            None => return Ok(plain.to_vec()),
+        };
+        let nonce = self.nonce()?;
+        let sealed = ChaCha20Poly1305::new(k.into())
+            .encrypt(&nonce.into(), Payload { msg: plain, aad: ad })
+            .expect("chacha20poly1305 encrypts anything below its length limit");
+        self.n += 1;
+        Ok(sealed)
+    }
+
+    // the nonce only moves on for a message that authenticated
+    fn decrypt(&mut self, ad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let k = match &self.k {
+            Some(k) => k,
+            None => return Ok(sealed.to_vec()),
+        };
+        if sealed.len() < TAG_LEN {
+            return Err(SessionError::InvalidLength(sealed.len()));
+        }
+        let nonce = self.nonce()?;
+        let plain = ChaCha20Poly1305::new(k.into())
+            .decrypt(&nonce.into(), Payload { msg: sealed, aad: ad })
+            .map_err(|_| SessionError::MacMismatch)?;
+        self.n += 1;
+        Ok(plain)
+    }
+
+    fn rekey(&mut self) {
+        if let Some(k) = &mut self.k {
+            let mut nonce = [0u8; 12];
+            nonce[4..].copy_from_slice(&u64::MAX.to_le_bytes());
+            let sealed = ChaCha20Poly1305::new((&*k).into())
+                .encrypt(&nonce.into(), Payload { msg: &[0; 32], aad: &[] })
+                .expect("chacha20poly1305 encrypts 32 bytes");
+            k.copy_from_slice(&sealed[..32]);
+        }
+    }
+}
+
+impl Drop for CipherState {
+    fn drop(&mut self) {
+        self.k.zeroize();
+    }
+}
+
+struct SymmetricState {
+    ck: [u8; HASH_LEN],
+    h: [u8; HASH_LEN],
+    cipher: CipherState,
+}
+
+impl SymmetricState {
+    fn new() -> SymmetricState {
+        let name = NOISE_PROTOCOL_NAME.as_bytes();
+        let mut h = [0u8; HASH_LEN];
+        match name.len() <= HASH_LEN {
+            true => h[..name.len()].copy_from_slice(name),
+            false => h = hash(&[name]),
+        }
+        // an empty prologue is still mixed in
+        let mut state = SymmetricState { ck: h, h, cipher: CipherState::empty() };
+        state.mix_hash(&[]);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.h = hash(&[&self.h, data]);
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let (ck, k) = hkdf(&self.ck, ikm);
+        self.ck = ck;
+        self.cipher = CipherState::keyed(k);
+    }
+
+    fn encrypt_and_hash(&mut self, plain: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let sealed = self.cipher.encrypt(&self.h, plain)?;
+        self.mix_hash(&sealed);
+        Ok(sealed)
+    }
+
+    fn decrypt_and_hash(&mut self, sealed: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let plain = self.cipher.decrypt(&self.h, sealed)?;
+        self.mix_hash(sealed);
+        Ok(plain)
+    }
+
+    fn split(&self) -> (CipherState, CipherState) {
+        let (first, second) = hkdf(&self.ck, &[]);
+        (CipherState::keyed(first), CipherState::keyed(second))
+    }
+}
+
+impl Drop for SymmetricState {
+    fn drop(&mut self) {
+        self.ck.zeroize();
+    }
+}
+
+/// One side of a Noise XX handshake. Call [`NoiseHandshake::write_message`]
+/// and [`NoiseHandshake::read_message`] in turn (the initiator writes first)
+/// until [`NoiseHandshake::is_finished`], then switch to the transport with
+/// [`NoiseHandshake::into_transport`].
+///
+/// Payloads ride along with handshake messages. The first is sent in the
+/// clear, the second is encrypted but the responder doesn't know who it's
+/// talking to yet, the third is fully protected.
+pub struct NoiseHandshake {
+    initiator: bool,
+    step: usize,
+    s: SecretKey,
+    e: SecretKey,
+    rs: Option<PublicKey>,
+    re: Option<PublicKey>,
+    symmetric: SymmetricState,
+}
+
+impl NoiseHandshake {
+    /// Starts a handshake as the initiator with the static key `s`.
+    pub fn initiator(s: SecretKey, rng: &mut (impl CryptoRng + RngCore)) -> NoiseHandshake {
+        NoiseHandshake::new(true, s, rng)
+    }
+
+    /// Starts a handshake as the responder with the static key `s`.
+    pub fn responder(s: SecretKey, rng: &mut (impl CryptoRng + RngCore)) -> NoiseHandshake {
+        NoiseHandshake::new(false, s, rng)
+    }
+
+    fn new(initiator: bool, s: SecretKey, rng: &mut (impl CryptoRng + RngCore)) -> NoiseHandshake {
+        NoiseHandshake {
+            initiator,
+            step: 0,
+            s,
+            e: SecretKey::random(rng),
+            rs: None,
+            re: None,
+            symmetric: SymmetricState::new(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.step == PATTERN.len()
+    }
+
+    // the initiator writes the even messages
+    fn our_turn(&self) -> bool {
+        !self.is_finished() && self.step.is_multiple_of(2) == self.initiator
+    }
+
+    /// The peer's static public key, once the peer has sent it.
+    pub fn remote_static(&self) -> Option<EncodedPoint> {
+        self.rs.map(|rs| rs.to_encoded_point(true))
+    }
+
+    /// Writes the next handshake message with `payload`. Out of turn this is
+    /// [`SessionError::NotReady`].
+    pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if !self.our_turn() {
+            return Err(SessionError::NotReady);
+        }
+
+        let mut message = Vec::new();
+        for token in PATTERN[self.step] {
+            match token {
+                Token::E => {
+                    let e = public_bytes(&self.e);
+                    self.symmetric.mix_hash(&e);
+                    message.extend_from_slice(&e);
+                }
+                Token::S => {
+                    let s = self.symmetric.encrypt_and_hash(&public_bytes(&self.s))?;
+                    message.extend_from_slice(&s);
+                }
+                token => self.mix_dh(*token)?,
+            }
+        }
+        message.extend(self.symmetric.encrypt_and_hash(payload)?);
+        if message.len() > NOISE_MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge(message.len()));
+        }
+
+        self.step += 1;
+        Ok(message)
+    }
+
+    /// Reads the peer's next handshake message, returning its payload. Out of
+    /// turn this is [`SessionError::NotReady`].
+    pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if self.is_finished() || self.our_turn() {
+            return Err(SessionError::NotReady);
+        }
+        if message.len() > NOISE_MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge(message.len()));
+        }
+
+        let mut rest = message;
+        let mut take = |len: usize| -> Result<&[u8], SessionError> {
+            if rest.len() < len {
+                return Err(SessionError::InvalidLength(message.len()));
+            }
+            let (taken, left) = rest.split_at(len);
+            rest = left;
+            Ok(taken)
+        };
+        for token in PATTERN[self.step] {
+            match token {
+                Token::E => {
+                    let re = take(DH_LEN)?;
+                    self.re = Some(parse_public(re)?);
+                    self.symmetric.mix_hash(re);
+                }
+                Token::S => {
+                    let len = DH_LEN + if self.symmetric.cipher.k.is_some() { TAG_LEN } else { 0 };
+                    let rs = self.symmetric.decrypt_and_hash(take(len)?)?;
+                    self.rs = Some(parse_public(&rs)?);
+                }
+                token => self.mix_dh(*token)?,
+            }
+        }
+        let payload = self.symmetric.decrypt_and_hash(rest)?;
+
+        self.step += 1;
+        Ok(payload)
+    }
+
+    fn mix_dh(&mut self, token: Token) -> Result<(), SessionError> {
+        let (ours, theirs) = match (token, self.initiator) {
+            (Token::Ee, _) => (&self.e, self.re),
+            (Token::Es, true) | (Token::Se, false) => (&self.e, self.rs),
+            (Token::Es, false) | (Token::Se, true) => (&self.s, self.re),
+            (Token::E | Token::S, _) => unreachable!("not a DH token"),
+        };
+        let theirs = theirs.ok_or(SessionError::InvalidPubKey)?;
+        self.symmetric.mix_key(&dh(ours, &theirs));
+        Ok(())
+    }
+
+    /// Finishes the handshake. Before [`NoiseHandshake::is_finished`] this
+    /// is [`SessionError::NotReady`].
+    pub fn into_transport(self) -> Result<NoiseTransport, SessionError> {
+        if !self.is_finished() {
+            return Err(SessionError::NotReady);
+        }
+        let remote_static = self.rs.ok_or(SessionError::InvalidPubKey)?;
+        let (initiator_to_responder, responder_to_initiator) = self.symmetric.split();
+        let (send, recv) = match self.initiator {
+            true => (initiator_to_responder, responder_to_initiator),
+            false => (responder_to_initiator, initiator_to_responder),
+        };
+        Ok(NoiseTransport {
+            send,
+            recv,
+            remote_static,
+            handshake_hash: self.symmetric.h,
+        })
+    }
+}
+
+/// The data phase after a [`NoiseHandshake`], following Noise's rules:
+/// messages are ChaCha20-Poly1305 under a per-direction counter nonce, are
+/// delivered in order, and each direction is rekeyed only when asked to.
+///
+/// Unlike [`Session`](crate::Session) records, transport messages carry no
+/// version header, they're exactly what any other Noise implementation
+/// sends.
+pub struct NoiseTransport {
+    send: CipherState,
+    recv: CipherState,
+    remote_static: PublicKey,
+    handshake_hash: [u8; HASH_LEN],
+}
+
+impl NoiseTransport {
+    pub fn encrypt(&mut self, plain: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if plain.len() + TAG_LEN > NOISE_MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge(plain.len()));
+        }
+        self.send.encrypt(&[], plain)
+    }
+
+    pub fn decrypt(&mut self, message: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if message.len() > NOISE_MAX_MESSAGE_LEN {
+            return Err(SessionError::MessageTooLarge(message.len()));
+        }
+        self.recv.decrypt(&[], message)
+    }
+
+    /// Rekeys what we send, the peer must call
+    /// [`NoiseTransport::rekey_incoming`] at the same point.
+    pub fn rekey_outgoing(&mut self) {
+        self.send.rekey();
+    }
+
+    pub fn rekey_incoming(&mut self) {
+        self.recv.rekey();
+    }
+
+    pub fn remote_static(&self) -> EncodedPoint {
+        self.remote_static.to_encoded_point(true)
+    }
+
+    /// Identifies this handshake, the same on both sides, for channel
+    /// binding.
+    pub fn handshake_hash(&self) -> &[u8; HASH_LEN] {
+        &self.handshake_hash
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use k256::{elliptic_curve::sec1::ToEncodedPoint, SecretKey};
+    use rand::thread_rng;
+    use snow::{params::NoiseParams, resolvers::{CryptoResolver, DefaultResolver}, types::{Dh, Hash, Random}};
+
+    use crate::noise::{dh, parse_public, public_bytes, NoiseHandshake, NoiseTransport, NOISE_PROTOCOL_NAME};
+    use crate::session::SessionError;
+
+    // what snow needs to speak our flavour of Noise
+    struct SnowSecp256k1 {
+        private: [u8; 32],
+        public: [u8; 33],
+    }
+
+    impl Dh for SnowSecp256k1 {
+        fn name(&self) -> &'static str {
+            "secp256k1"
+        }
+
+        fn pub_len(&self) -> usize {
+            33
+        }
+
+        fn priv_len(&self) -> usize {
+            32
+        }
+
+        fn set(&mut self, private: &[u8]) {
+            self.private.copy_from_slice(private);
+            self.public = public_bytes(&SecretKey::from_be_bytes(private).unwrap());
+        }
+
+        fn generate(&mut self, rng: &mut dyn Random) {
+            let secret = SecretKey::random(rng);
+            self.set(&secret.to_be_bytes());
+        }
+
+        fn pubkey(&self) -> &[u8] {
+            &self.public
+        }
+
+        fn privkey(&self) -> &[u8] {
+            &self.private
+        }
+
+        // snow hands over its whole key buffer, longer than the key
+        fn dh(&self, public: &[u8], out: &mut [u8]) -> Result<(), snow::Error> {
+            let public = parse_public(&public[..33]).map_err(|_| snow::Error::Dh)?;
+            let secret = SecretKey::from_be_bytes(&self.private).map_err(|_| snow::Error::Dh)?;
+            out[..33].copy_from_slice(&dh(&secret, &public));
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct SnowBlake3(blake3::Hasher);
+
+    impl Hash for SnowBlake3 {
+        fn name(&self) -> &'static str {
+            "BLAKE3"
+        }
+
+        fn block_len(&self) -> usize {
+            64
+        }
+
+        fn hash_len(&self) -> usize {
+            32
+        }
+
+        fn reset(&mut self) {
+            self.0.reset();
+        }
+
+        fn input(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        fn result(&mut self, out: &mut [u8]) {
+            out[..32].copy_from_slice(self.0.finalize().as_bytes());
+        }
+    }
+
+    struct Resolver;
+
+    // snow only knows its own primitives by name, so the DH and hash
+    // choices are stand-ins, and the protocol name is set by hand
+    impl CryptoResolver for Resolver {
+        fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+            DefaultResolver.resolve_rng()
+        }
+
+        fn resolve_dh(&self, _: &snow::params::DHChoice) -> Option<Box<dyn Dh>> {
+            Some(Box::new(SnowSecp256k1 { private: [0; 32], public: [0; 33] }))
+        }
+
+        fn resolve_hash(&self, _: &snow::params::HashChoice) -> Option<Box<dyn Hash>> {
+            Some(Box::<SnowBlake3>::default())
+        }
+
+        fn resolve_cipher(&self, choice: &snow::params::CipherChoice) -> Option<Box<dyn snow::types::Cipher>> {
+            DefaultResolver.resolve_cipher(choice)
+        }
+    }
+
+    fn snow_builder(private: &[u8]) -> snow::Builder<'_> {
+        let stand_in: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let params = NoiseParams { name: NOISE_PROTOCOL_NAME.to_string(), ..stand_in };
+        snow::Builder::with_resolver(params, Box::new(Resolver)).local_private_key(private)
+    }
+
+    fn snow_write(state: &mut snow::HandshakeState, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; 65535];
+        let len = state.write_message(payload, &mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    fn snow_read(state: &mut snow::HandshakeState, message: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; 65535];
+        let len = state.read_message(message, &mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    fn transport_both_ways(ours: &mut NoiseTransport, theirs: &mut snow::TransportState) {
+        let mut buf = vec![0u8; 65535];
+        for msg in [&b"hello"[..], b"", &[0x5a; 1000]] {
+            let len = theirs.write_message(msg, &mut buf).unwrap();
+            assert_eq!(ours.decrypt(&buf[..len]).unwrap(), msg);
+
+            let sealed = ours.encrypt(msg).unwrap();
+            let len = theirs.read_message(&sealed, &mut buf).unwrap();
+            assert_eq!(&buf[..len], msg);
+        }
+
+        // rekeying, in both directions
+        ours.rekey_outgoing();
+        theirs.rekey_incoming();
+        ours.rekey_incoming();
+        theirs.rekey_outgoing();
+        let sealed = ours.encrypt(b"after rekey").unwrap();
+        let len = theirs.read_message(&sealed, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"after rekey");
+        let len = theirs.write_message(b"after rekey too", &mut buf).unwrap();
+        assert_eq!(ours.decrypt(&buf[..len]).unwrap(), b"after rekey too");
+    }
+
+    #[test]
+    fn test_initiator_talks_to_snow() {
+        let (ours_s, theirs_s) = (SecretKey::random(&mut thread_rng()), SecretKey::random(&mut thread_rng()));
+        let mut ours = NoiseHandshake::initiator(ours_s.clone(), &mut thread_rng());
+        let theirs_private = theirs_s.to_be_bytes();
+        let mut theirs = snow_builder(&theirs_private).build_responder().unwrap();
+
+        assert_eq!(snow_read(&mut theirs, &ours.write_message(b"one").unwrap()), b"one");
+        assert_eq!(ours.read_message(&snow_write(&mut theirs, b"two")).unwrap(), b"two");
+        assert_eq!(snow_read(&mut theirs, &ours.write_message(b"three").unwrap()), b"three");
+        assert!(ours.is_finished() && theirs.is_handshake_finished());
+
+        assert_eq!(ours.remote_static().unwrap(), theirs_s.public_key().to_encoded_point(true));
+        assert_eq!(theirs.get_remote_static().unwrap(), ours_s.public_key().to_encoded_point(true).as_bytes());
+        assert_eq!(theirs.get_handshake_hash(), &ours.symmetric.h[..]);
+
+        let mut ours = ours.into_transport().unwrap();
+        let mut theirs = theirs.into_transport_mode().unwrap();
+        transport_both_ways(&mut ours, &mut theirs);
+    }
+
+    #[test]
+    fn test_responder_talks_to_snow() {
+        let (ours_s, theirs_s) = (SecretKey::random(&mut thread_rng()), SecretKey::random(&mut thread_rng()));
+        let mut ours = NoiseHandshake::responder(ours_s, &mut thread_rng());
+        let theirs_private = theirs_s.to_be_bytes();
+        let mut theirs = snow_builder(&theirs_private).build_initiator().unwrap();
+
+        assert_eq!(ours.read_message(&snow_write(&mut theirs, b"one")).unwrap(), b"one");
+        assert_eq!(snow_read(&mut theirs, &ours.write_message(b"two").unwrap()), b"two");
+        assert_eq!(ours.read_message(&snow_write(&mut theirs, b"three")).unwrap(), b"three");
+
+        let mut ours = ours.into_transport().unwrap();
+        assert_eq!(ours.remote_static(), theirs_s.public_key().to_encoded_point(true));
+        let mut theirs = theirs.into_transport_mode().unwrap();
+        transport_both_ways(&mut ours, &mut theirs);
+    }
+
+    #[test]
+    fn test_handshake_order_and_tampering() {
+        let mut initiator = NoiseHandshake::initiator(SecretKey::random(&mut thread_rng()), &mut thread_rng());
+        let mut responder = NoiseHandshake::responder(SecretKey::random(&mut thread_rng()), &mut thread_rng());
+
+        assert!(matches!(responder.write_message(b""), Err(SessionError::NotReady)));
+        assert!(matches!(initiator.read_message(&[0; 33]), Err(SessionError::NotReady)));
+
+        responder.read_message(&initiator.write_message(b"").unwrap()).unwrap();
+        let mut second = responder.write_message(b"").unwrap();
+        second[40] ^= 1;
+        assert!(matches!(initiator.read_message(&second), Err(SessionError::MacMismatch)));
+        assert!(matches!(initiator.read_message(&second[..20]), Err(SessionError::InvalidLength(20))));
+    }
+
+    #[test]
+    fn test_transport_refuses_replays() {
+        let mut initiator = NoiseHandshake::initiator(SecretKey::random(&mut thread_rng()), &mut thread_rng());
+        let mut responder = NoiseHandshake::responder(SecretKey::random(&mut thread_rng()), &mut thread_rng());
+        responder.read_message(&initiator.write_message(b"").unwrap()).unwrap();
+        initiator.read_message(&responder.write_message(b"").unwrap()).unwrap();
+        responder.read_message(&initiator.write_message(b"").unwrap()).unwrap();
+        assert!(matches!(initiator.write_message(b""), Err(SessionError::NotReady)));
+
+        let (mut initiator, mut responder) = (initiator.into_transport().unwrap(), responder.into_transport().unwrap());
+        assert_eq!(initiator.handshake_hash(), responder.handshake_hash());
+        let sealed = initiator.encrypt(b"hello").unwrap();
+        assert_eq!(responder.decrypt(&sealed).unwrap(), b"hello");
+        // the nonce has moved on
+        assert!(matches!(responder.decrypt(&sealed), Err(SessionError::MacMismatch)));
+        let sealed = initiator.encrypt(b"hi").unwrap();
+        assert_eq!(responder.decrypt(&sealed).unwrap(), b"hi");
+    }
+}
@@ -0,0 +1,103 @@
+// ephemeral key pairs generated ahead of the sessions that use them. each
+// pair is moved out of the pool into exactly one session, so the pool can't
+// hand the same key out twice however it's used
+
+use std::collections::VecDeque;
+
+use rand_core::{CryptoRng, RngCore};
+
+use crate::kex::{KeyExchange, Secp256k1};
+use crate::session::Session;
+
+/// A batch of pre-generated ephemeral key pairs for new sessions, so a busy
+/// server can generate keys when it's idle, or all at once, rather than one
+/// per connection.
+pub struct EphemeralPool<K: KeyExchange = Secp256k1> {
+    pairs: VecDeque<(K::Private, K::Public)>,
+}
+
+impl<K: KeyExchange> Default for EphemeralPool<K> {
+    fn default() -> Self {
+        EphemeralPool { pairs: VecDeque::new() }
+    }
+}
+
+impl<K: KeyExchange> EphemeralPool<K> {
+    pub fn new() -> EphemeralPool<K> {
+        EphemeralPool::default()
+    }
+
+    /// Generates `count` more key pairs.
+    pub fn fill(&mut self, rng: &mut (impl CryptoRng + RngCore), count: usize) {
+        self.pairs.extend((0..count).map(|_| K::generate(rng)));
+    }
+
+    /// How many key pairs are left.
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // the next key pair, which leaves the pool
+    pub(crate) fn take(&mut self) -> Option<(K::Private, K::Public)> {
+        self.pairs.pop_front()
+    }
+
+    /// A new session with the next key pair, which leaves the pool. An empty
+    /// pool doesn't fail: the session generates its own with `rng`.
+    pub fn session(&mut self, rng: &mut (impl CryptoRng + RngCore)) -> Session<K> {
+        match self.take() {
+            Some(pair) => Session::from_secret(Some(pair)),
+            None => Session::generate(rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use k256::ecdh::EphemeralSecret;
+    use rand::thread_rng;
+
+    use crate::pool::EphemeralPool;
+    use crate::session::Session;
+
+    #[test]
+    fn test_pool_never_reuses_a_key() {
+        let mut pool: EphemeralPool = EphemeralPool::new();
+        pool.fill(&mut thread_rng(), 8);
+        assert_eq!(pool.len(), 8);
+
+        // past the end too, where sessions make their own
+        let sessions: Vec<Session> = (0..10).map(|_| pool.session(&mut thread_rng())).collect();
+        assert!(pool.is_empty());
+        let pks: HashSet<Vec<u8>> = sessions.iter().map(|session| session.pk().unwrap().as_bytes().to_vec()).collect();
+        assert_eq!(pks.len(), 10);
+
+        // and they key as any session does
+        let mut client = Session::new(&mut thread_rng());
+        let mut server = pool.session(&mut thread_rng());
+        let (client_pk, server_pk) = (client.pk().unwrap(), server.pk().unwrap());
+        client.set_sym_key(&server_pk).unwrap();
+        server.set_sym_key(&client_pk).unwrap();
+        assert_eq!(server.decrypt(client.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_session_with_an_external_ephemeral() {
+        let secret = EphemeralSecret::random(&mut thread_rng());
+        let expected = k256::EncodedPoint::from(secret.public_key());
+        let mut sesh1 = Session::with_ephemeral(secret);
+        assert_eq!(sesh1.pk().unwrap(), expected);
+
+        let mut sesh2 = Session::new(&mut thread_rng());
+        let pk2 = sesh2.pk().unwrap();
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&expected).unwrap();
+        assert_eq!(sesh2.decrypt(sesh1.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+    }
+}
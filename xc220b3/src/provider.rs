@@ -0,0 +1,156 @@
+// the private-key operations on a long-term key, kept behind a trait so the
+// key can live somewhere it never leaves, a secure element or a TPM, and the
+// session only ever asks for the results
+
+use k256::{
+    ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey, SecretKey,
+};
+
+use crate::certificate::signature_bytes;
+use crate::kex::SharedSecret;
+use crate::session::SessionError;
+
+/// Why a [`SecretProvider`] couldn't do what it was asked, for the logs.
+/// Sessions hand it on as [`SessionError::ProviderFailure`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderError(pub String);
+
+impl From<ProviderError> for SessionError {
+    fn from(e: ProviderError) -> Self {
+        SessionError::ProviderFailure(e)
+    }
+}
+
+/// A long-term secp256k1 key that can be used but not necessarily read,
+/// such as an identity key for [`Session::set_identity_provider`](crate::Session::set_identity_provider).
+///
+/// [`SecretKey`] is one, held in memory. Any call may fail, hardware can
+/// refuse or be unplugged, and a session that gets an error leaves its own
+/// state as it was.
+pub trait SecretProvider: Send + Sync {
+    fn public_key(&self) -> Result<PublicKey, ProviderError>;
+
+    /// The x coordinate of the secret times `peer`, as
+    /// [`k256::ecdh::diffie_hellman`] gives it.
+    fn ecdh(&self, peer: &PublicKey) -> Result<SharedSecret, ProviderError>;
+
+    /// ECDSA of a 32 byte prehash, `r || s` with `s` low. No session
+    /// randomness is handed over, so a provider draws its nonces itself or,
+    /// as [`SecretKey`] does, derives them by RFC 6979.
+    fn sign(&self, prehash: &[u8; 32]) -> Result<[u8; 64], ProviderError>;
+}
+
+impl SecretProvider for SecretKey {
+    fn public_key(&self) -> Result<PublicKey, ProviderError> {
+        Ok(SecretKey::public_key(self))
+    }
+
+    fn ecdh(&self, peer: &PublicKey) -> Result<SharedSecret, ProviderError> {
+        let shared = k256::ecdh::diffie_hellman(self.to_nonzero_scalar(), peer.as_affine());
+        Ok(SharedSecret::new(shared.raw_secret_bytes().to_vec()))
+    }
+
+    fn sign(&self, prehash: &[u8; 32]) -> Result<[u8; 64], ProviderError> {
+        let signature = SigningKey::from(self).sign_prehash(prehash).map_err(|e| ProviderError(e.to_string()))?;
+        Ok(signature_bytes(signature))
+    }
+}
+
+// a provider's public key as the wire has it
+pub(crate) fn compressed(provider: &dyn SecretProvider) -> Result<[u8; 33], ProviderError> {
+    Ok(provider.public_key()?.to_encoded_point(true).as_bytes().try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use k256::{PublicKey, SecretKey};
+    use rand::thread_rng;
+
+    use crate::kex::SharedSecret;
+    use crate::provider::{ProviderError, SecretProvider};
+    use crate::session::{Session, SessionError};
+
+    // a software key that counts what it's asked and can be told to refuse
+    #[derive(Default)]
+    struct MockProvider {
+        key: Option<SecretKey>,
+        calls: AtomicUsize,
+        failing: AtomicBool,
+    }
+
+    impl MockProvider {
+        fn new() -> MockProvider {
+            MockProvider { key: Some(SecretKey::random(&mut thread_rng())), ..MockProvider::default() }
+        }
+
+        fn call(&self) -> Result<&SecretKey, ProviderError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.failing.load(Ordering::SeqCst) {
+                true => Err(ProviderError("unplugged".to_string())),
+                false => Ok(self.key.as_ref().unwrap()),
+            }
+        }
+    }
+
+    impl SecretProvider for MockProvider {
+        fn public_key(&self) -> Result<PublicKey, ProviderError> {
+            Ok(self.call()?.public_key())
+        }
+
+        fn ecdh(&self, peer: &PublicKey) -> Result<SharedSecret, ProviderError> {
+            SecretProvider::ecdh(self.call()?, peer)
+        }
+
+        fn sign(&self, prehash: &[u8; 32]) -> Result<[u8; 64], ProviderError> {
+            SecretProvider::sign(self.call()?, prehash)
+        }
+    }
+
+    #[test]
+    fn test_identity_provider_failures() {
+        let provider = Arc::new(MockProvider::new());
+        let identity = provider.key.as_ref().unwrap().public_key();
+        let mut sender = Session::new(&mut thread_rng());
+        sender.set_identity_provider(Some(provider.clone()));
+        let pk = sender.pk().unwrap();
+
+        provider.failing.store(true, Ordering::SeqCst);
+        assert!(matches!(sender.signed_public_key(), Err(SessionError::ProviderFailure(ProviderError(reason))) if reason == "unplugged"));
+        assert_eq!(sender.pk().unwrap(), pk);
+
+        // once it's back the same session signs as if nothing happened
+        provider.failing.store(false, Ordering::SeqCst);
+        let calls = provider.calls.load(Ordering::SeqCst);
+        let signed = sender.signed_public_key().unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), calls + 2);
+
+        let mut receiver = Session::new(&mut thread_rng());
+        receiver.trust_identity(identity);
+        sender.set_sym_key(&receiver.pk().unwrap()).unwrap();
+        receiver.set_sym_key_signed(&signed).unwrap();
+        assert_eq!(receiver.decrypt(sender.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_zero_rtt_provider_failures() {
+        let provider = MockProvider::new();
+        let responder = provider.key.as_ref().unwrap().public_key();
+        let (mut initiator, payload) = Session::initiate_with_payload(&mut thread_rng(), &responder, b"GET /").unwrap();
+
+        provider.failing.store(true, Ordering::SeqCst);
+        assert!(matches!(Session::open_with_ephemeral_provider(&provider, &payload), Err(SessionError::ProviderFailure(_))));
+
+        provider.failing.store(false, Ordering::SeqCst);
+        let (mut session, first) = Session::open_with_ephemeral_provider(&provider, &payload).unwrap();
+        assert_eq!(first, b"GET /");
+        assert_eq!(initiator.decrypt(session.encrypt(b"200".to_vec()).unwrap()).unwrap(), b"200");
+        assert!(provider.calls.load(Ordering::SeqCst) >= 3);
+    }
+}
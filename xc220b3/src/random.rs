@@ -0,0 +1,229 @@
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::kex::KeyExchange;
+use crate::session::Session;
+
+/// Where a session gets the random bytes it draws itself, as opposed to
+/// from an RNG passed to the call: the salts of
+/// [obfuscated](crate::SessionBuilder::obfuscate) records, and the
+/// ephemeral keys of sessions a [`handshake`](crate::handshake) builds.
+/// Swap it out with [`Session::set_random_source`] or
+/// [`SessionBuilder::random_source`](crate::SessionBuilder::random_source).
+///
+/// Without the `std` feature there's no [`OsRandom`] to default to: the
+/// builder refuses to build without a source, and a session made any other
+/// way panics the first time it draws unless it's been given one.
+pub trait RandomSource: Send + Sync {
+    fn fill(&self, dest: &mut [u8]);
+}
+
+/// The operating system's RNG, through `getrandom`. The default, with the
+/// `std` feature.
+#[cfg(feature = "std")]
+pub struct OsRandom;
+
+#[cfg(feature = "std")]
+impl RandomSource for OsRandom {
+    fn fill(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest)
+    }
+}
+
+/// Any RNG as a [`RandomSource`], to seed one for reproducible tests. Only
+/// ever with a predictable seed in tests: a seeded session's obfuscated
+/// records are only as random as the seed.
+pub struct SharedRng<R>(Mutex<R>);
+
+impl<R: CryptoRng + RngCore + Send> SharedRng<R> {
+    pub fn new(rng: R) -> SharedRng<R> {
+        SharedRng(Mutex::new(rng))
+    }
+}
+
+impl<R: CryptoRng + RngCore + Send> RandomSource for SharedRng<R> {
+    fn fill(&self, dest: &mut [u8]) {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).fill_bytes(dest)
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn os_random() -> Arc<dyn RandomSource> {
+    Arc::new(OsRandom)
+}
+
+// what a session draws from until it's given a source
+#[cfg(feature = "std")]
+pub(crate) fn default_source() -> Arc<dyn RandomSource> {
+    os_random()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_source() -> Arc<dyn RandomSource> {
+    Arc::new(NoRandom)
+}
+
+// the default without std, there being nothing to draw from
+#[cfg(not(feature = "std"))]
+struct NoRandom;
+
+#[cfg(not(feature = "std"))]
+impl RandomSource for NoRandom {
+    fn fill(&self, _: &mut [u8]) {
+        panic!("no random source, set one with Session::set_random_source or enable the std feature")
+    }
+}
+
+// a source as an RNG, for the APIs that take one
+pub(crate) struct SourceRng<'a>(pub(crate) &'a dyn RandomSource);
+
+impl RngCore for SourceRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.fill(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for SourceRng<'_> {}
+
+impl<K: KeyExchange> Session<K> {
+    /// Replaces where the session draws its own random bytes from, see
+    /// [`RandomSource`].
+    pub fn set_random_source(&mut self, source: Arc<dyn RandomSource>) {
+        self.random = source;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixStream;
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    use std::thread;
+
+    use k256::SecretKey;
+    use rand::{rngs::StdRng, thread_rng, CryptoRng, RngCore, SeedableRng};
+
+    use crate::builder::SessionBuilder;
+    use crate::handshake::{client_sync, server_sync, HandshakeConfig};
+    use crate::random::SharedRng;
+    use crate::session::Session;
+
+    // a seeded RNG that counts what's drawn from it
+    struct Counting(StdRng, Arc<AtomicUsize>);
+
+    impl RngCore for Counting {
+        fn next_u32(&mut self) -> u32 {
+            self.1.fetch_add(4, Ordering::SeqCst);
+            self.0.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.1.fetch_add(8, Ordering::SeqCst);
+            self.0.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.1.fetch_add(dest.len(), Ordering::SeqCst);
+            self.0.fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for Counting {}
+
+    fn counting(seed: u64) -> (Arc<SharedRng<Counting>>, Arc<AtomicUsize>) {
+        let drawn = Arc::new(AtomicUsize::new(0));
+        (Arc::new(SharedRng::new(Counting(StdRng::seed_from_u64(seed), drawn.clone()))), drawn)
+    }
+
+    #[test]
+    fn test_random_bytes_drawn_per_operation() {
+        let (source, drawn) = counting(1);
+        let (mut sesh1, mut sesh2) = Session::pair_with(SessionBuilder::new().random_source(source.clone()));
+
+        // records with a header draw nothing
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        sesh2.decrypt(record).unwrap();
+        sesh1.heartbeat().unwrap();
+        assert_eq!(drawn.load(Ordering::SeqCst), 0);
+
+        // obfuscated ones draw their 16 byte salt, and opening draws nothing
+        let (mut sesh1, mut sesh2) = Session::pair_with(SessionBuilder::new().obfuscate(true).random_source(source));
+        for i in 1..=3 {
+            let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            sesh2.decrypt(record).unwrap();
+            assert_eq!(drawn.load(Ordering::SeqCst), 16 * i);
+        }
+        sesh1.heartbeat().unwrap();
+        sesh1.encrypt_vectored(&[b"a", b"b"]).unwrap();
+        assert_eq!(drawn.load(Ordering::SeqCst), 16 * 5);
+
+        // a handshake with identities on both sides draws the two ephemeral
+        // keys and nothing for signing them, the nonces are RFC 6979's
+        let (source, drawn) = counting(2);
+        let config = |identity: SecretKey, certificate: &[u8]| HandshakeConfig {
+            builder: SessionBuilder::new().identity(identity).random_source(source.clone()),
+            certificate: certificate.to_vec(),
+            ..HandshakeConfig::default()
+        };
+        let client_config = config(SecretKey::random(&mut thread_rng()), b"ok");
+        let server_config = config(SecretKey::random(&mut thread_rng()), b"");
+        let (client_io, server_io) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || server_sync(server_io, &server_config, &|cert: &[u8]| cert == b"ok").unwrap().0);
+        let client = client_sync(client_io, &client_config).unwrap().0;
+        assert!(client.peer_identity_key().is_some() && server.join().unwrap().peer_identity_key().is_some());
+        assert_eq!(drawn.load(Ordering::SeqCst), 2 * 32);
+
+        // so the same seed signs the same, with nothing from elsewhere
+        let identity = SecretKey::random(&mut thread_rng());
+        let signed = |seed| {
+            let builder = SessionBuilder::new().identity(identity.clone()).random_source(counting(seed).0);
+            builder.build_from_source().unwrap().signed_public_key().unwrap()
+        };
+        assert_eq!(signed(3), signed(3));
+    }
+
+    #[test]
+    fn test_seeded_source_is_reproducible() {
+        let records = |seed| {
+            let (source, _) = counting(seed);
+            let builder = SessionBuilder::new().obfuscate(true).random_source(source);
+            let (mut sesh1, _) = Session::pair_with(builder);
+            let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            record[..16].to_vec()
+        };
+        assert_eq!(records(7), records(7));
+        assert_ne!(records(7), records(8));
+    }
+
+    #[test]
+    fn test_builder_source_keys_built_sessions() {
+        let (source, drawn) = counting(1);
+        let builder = SessionBuilder::new().random_source(source);
+        let first = builder.clone().build_from_source().unwrap();
+        // one scalar, short of a one in 2^128 rejection
+        assert_eq!(drawn.load(Ordering::SeqCst), 32);
+        let (again, _) = counting(1);
+        let second = SessionBuilder::new().random_source(again).build_from_source().unwrap();
+        assert_eq!(first.pk().unwrap(), second.pk().unwrap());
+    }
+}
@@ -0,0 +1,441 @@
+// a Signal style double ratchet, for store-and-forward messaging where
+// either side may be offline and messages arrive late or out of order. a
+// message is
+//
+//   ratchet pk (33) || le32(previous chain length) || le32(message number)
+//     || ciphertext || tag (24)
+//
+// with the header in the clear and bound as the cipher's aad. every message
+// key seals exactly one message. the ratchet pk is the key pair the sender
+// currently sends under, and a new one from the peer means it has heard
+// from us: both sides step the root key with a DH between their newest key
+// pairs, which is what heals a session whose state leaked, and the sender's
+// next reply brings a new pair of its own. the KDFs are
+//
+//   root:   root', chain = BLAKE3 derive_key(ROOT_CONTEXT) of root || dh, 64 bytes
+//   chain:  message key = keyed(chain, 0x01), chain' = keyed(chain, 0x02)
+//
+// message keys skipped over to reach a later message are kept, at most
+// max_skip at a time and capacity all told, the oldest evicted first, so a
+// message delivered late still opens as long as its key is still there
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use k256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint, EncodedPoint};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::cipher::{RecordCipher, XCHACHA20_BLAKE3};
+use crate::kex::{self, KeyExchange, Secp256k1};
+use crate::random::{self, RandomSource, SourceRng};
+use crate::session::SessionError;
+
+const ROOT_CONTEXT: &str = "xc220b3 2022-10 ratchet root";
+
+/// Length of the header in front of every [`RatchetSession`] message.
+pub const RATCHET_HEADER_LEN: usize = PK_LEN + 4 + 4;
+/// How far ahead of the last message received one may be, by default, see
+/// [`RatchetSession::set_max_skip`].
+pub const DEFAULT_MAX_SKIP: u32 = 1000;
+/// How many skipped message keys are kept, by default, see
+/// [`RatchetSession::set_skipped_capacity`].
+pub const DEFAULT_SKIPPED_CAPACITY: usize = 2000;
+
+const PK_LEN: usize = 33;
+
+type Pk = [u8; PK_LEN];
+
+/// A ratchet key pair. The responder's first one is published ahead of
+/// time, for the initiator to start a [`RatchetSession`] with.
+pub struct RatchetKeyPair {
+    secret: EphemeralSecret,
+    public: Pk,
+}
+
+impl RatchetKeyPair {
+    pub fn generate(rng: &mut (impl CryptoRng + RngCore)) -> RatchetKeyPair {
+        let secret = EphemeralSecret::random(rng);
+        let public = secret.public_key().to_encoded_point(true).as_bytes().try_into().unwrap();
+        RatchetKeyPair { secret, public }
+    }
+
+    /// The public key, compressed SEC1.
+    pub fn public(&self) -> EncodedPoint {
+        EncodedPoint::from_bytes(self.public).unwrap()
+    }
+
+    fn agree(&self, peer: &Pk) -> Result<[u8; 32], SessionError> {
+        let peer = EncodedPoint::from_bytes(peer).map_err(|_| SessionError::InvalidPubKey)?;
+        let shared = kex::agree::<Secp256k1>(&self.secret, &peer)?;
+        Ok(shared.as_bytes().try_into().unwrap())
+    }
+}
+
+// everything but the skipped keys, cheap to copy so a message that doesn't
+// open leaves the session as it was
+#[derive(Clone)]
+struct Chains {
+    root: [u8; 32],
+    send: Option<[u8; 32]>,
+    recv: Option<[u8; 32]>,
+    ours: Arc<RatchetKeyPair>,
+    theirs: Option<Pk>,
+    // the peer's ratchet pk before that, whose chain's skipped keys may
+    // still be waiting
+    retired: Option<Pk>,
+    // messages in the current sending and receiving chains, and in our
+    // sending chain before this one
+    sent: u32,
+    received: u32,
+    previous: u32,
+}
+
+impl Chains {
+    // the message keys of the receiving chain up to message `until`
+    fn skip_to(&mut self, until: u32, max_skip: u32, skipped: &mut Vec<((Pk, u32), [u8; 32])>) -> Result<(), SessionError> {
+        let (Some(recv), Some(theirs)) = (self.recv.as_mut(), self.theirs) else {
+            return Ok(());
+        };
+        if until.saturating_sub(self.received) > max_skip {
+            return Err(SessionError::TooManySkipped(until - self.received));
+        }
+        while self.received < until {
+            skipped.push(((theirs, self.received), step_chain(recv)));
+            self.received += 1;
+        }
+        Ok(())
+    }
+
+    fn dh_step(&mut self, theirs: Pk, random: &dyn RandomSource) -> Result<(), SessionError> {
+        self.previous = self.sent;
+        self.sent = 0;
+        self.received = 0;
+        self.retired = self.theirs.replace(theirs);
+        let (root, recv) = step_root(&self.root, &self.ours.agree(&theirs)?);
+        self.ours = Arc::new(RatchetKeyPair::generate(&mut SourceRng(random)));
+        let (root, send) = step_root(&root, &self.ours.agree(&theirs)?);
+        self.root = root;
+        self.recv = Some(recv);
+        self.send = Some(send);
+        Ok(())
+    }
+}
+
+impl Drop for Chains {
+    fn drop(&mut self) {
+        self.root.zeroize();
+        self.send.zeroize();
+        self.recv.zeroize();
+    }
+}
+
+// message keys skipped over, oldest first
+#[derive(Default)]
+struct SkippedKeys {
+    order: VecDeque<(Pk, u32)>,
+    keys: HashMap<(Pk, u32), [u8; 32]>,
+}
+
+impl SkippedKeys {
+    fn insert(&mut self, at: (Pk, u32), key: [u8; 32], capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        while self.order.len() >= capacity {
+            if let Some(mut oldest) = self.order.pop_front().and_then(|oldest| self.keys.remove(&oldest)) {
+                oldest.zeroize();
+            }
+        }
+        self.order.push_back(at);
+        self.keys.insert(at, key);
+    }
+
+    fn remove(&mut self, at: &(Pk, u32)) {
+        if let Some(mut key) = self.keys.remove(at) {
+            key.zeroize();
+            self.order.retain(|other| other != at);
+        }
+    }
+}
+
+impl Drop for SkippedKeys {
+    fn drop(&mut self) {
+        self.keys.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+/// A double ratchet session, every message under its own key, keys stepped
+/// forward with every message and a fresh DH with every round trip. One
+/// that leaks its state heals once a message from the peer has been
+/// answered: what's sent from then on is out of reach of the leaked copy.
+///
+/// Both sides start from a 32 byte secret they already share (from a
+/// [`Session`](crate::Session)'s handshake, say) and the responder's
+/// [`RatchetKeyPair`], published ahead of time. The initiator sends first.
+pub struct RatchetSession {
+    chains: Chains,
+    skipped: SkippedKeys,
+    max_skip: u32,
+    capacity: usize,
+    random: Arc<dyn RandomSource>,
+}
+
+impl RatchetSession {
+    pub fn initiate(rng: &mut (impl CryptoRng + RngCore), shared: &[u8; 32], responder: &EncodedPoint) -> Result<RatchetSession, SessionError> {
+        let theirs: Pk = Secp256k1::canonical(responder)?.try_into().map_err(|_| SessionError::InvalidPubKey)?;
+        let ours = RatchetKeyPair::generate(rng);
+        let (root, send) = step_root(shared, &ours.agree(&theirs)?);
+        let chains = Chains { root, send: Some(send), recv: None, ours: Arc::new(ours), theirs: Some(theirs), retired: None, sent: 0, received: 0, previous: 0 };
+        Ok(RatchetSession::from_chains(chains))
+    }
+
+    /// The responder's side, `ours` being the key pair whose public half the
+    /// initiator was given. It can't send until it has received.
+    pub fn respond(shared: &[u8; 32], ours: RatchetKeyPair) -> RatchetSession {
+        let chains = Chains { root: *shared, send: None, recv: None, ours: Arc::new(ours), theirs: None, retired: None, sent: 0, received: 0, previous: 0 };
+        RatchetSession::from_chains(chains)
+    }
+
+    fn from_chains(chains: Chains) -> RatchetSession {
+        RatchetSession {
+            chains,
+            skipped: SkippedKeys::default(),
+            max_skip: DEFAULT_MAX_SKIP,
+            capacity: DEFAULT_SKIPPED_CAPACITY,
+            random: random::default_source(),
+        }
+    }
+
+    /// How many messages one receiving chain may skip to reach a later one,
+    /// further than that is [`SessionError::TooManySkipped`].
+    pub fn set_max_skip(&mut self, max_skip: u32) {
+        self.max_skip = max_skip;
+    }
+
+    /// How many skipped message keys are kept for messages still to arrive,
+    /// the oldest is forgotten to make room. A message whose key was
+    /// forgotten is [`SessionError::DuplicateMessage`].
+    pub fn set_skipped_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.skipped.order.len() > capacity {
+            let oldest = self.skipped.order[0];
+            self.skipped.remove(&oldest);
+        }
+    }
+
+    /// Where new ratchet key pairs come from, see [`RandomSource`].
+    pub fn set_random_source(&mut self, source: Arc<dyn RandomSource>) {
+        self.random = source;
+    }
+
+    /// Seals `plain` under the next key of the sending chain.
+    /// [`SessionError::NotReady`] for a responder that hasn't received
+    /// anything yet.
+    pub fn encrypt(&mut self, plain: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let chains = &mut self.chains;
+        let send = chains.send.as_mut().ok_or(SessionError::NotReady)?;
+        if chains.sent == u32::MAX {
+            return Err(SessionError::CounterExhausted);
+        }
+        let mut key = step_chain(send);
+        let mut message = Vec::with_capacity(RATCHET_HEADER_LEN + plain.len() + XCHACHA20_BLAKE3.overhead());
+        message.extend_from_slice(&chains.ours.public);
+        message.extend_from_slice(&chains.previous.to_le_bytes());
+        message.extend_from_slice(&chains.sent.to_le_bytes());
+        let header = message.clone();
+        XCHACHA20_BLAKE3.seal(&key, &[plain], &header, &mut message);
+        key.zeroize();
+        chains.sent += 1;
+        Ok(message)
+    }
+
+    /// Opens a message from the peer, in whatever order they arrive. A
+    /// message that doesn't open changes nothing.
+    pub fn decrypt(&mut self, message: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if message.len() < RATCHET_HEADER_LEN + XCHACHA20_BLAKE3.overhead() {
+            return Err(SessionError::InvalidLength(message.len()));
+        }
+        let (header, sealed) = message.split_at(RATCHET_HEADER_LEN);
+        let theirs: Pk = header[..PK_LEN].try_into().unwrap();
+        let previous = u32::from_le_bytes(header[PK_LEN..PK_LEN + 4].try_into().unwrap());
+        let number = u32::from_le_bytes(header[PK_LEN + 4..].try_into().unwrap());
+
+        if let Some(key) = self.skipped.keys.get(&(theirs, number)) {
+            let plain = XCHACHA20_BLAKE3.open(key, sealed, header)?;
+            self.skipped.remove(&(theirs, number));
+            return Ok(plain);
+        }
+        if self.chains.retired == Some(theirs) {
+            return Err(SessionError::DuplicateMessage);
+        }
+
+        let mut chains = self.chains.clone();
+        let mut skipped = Vec::new();
+        if chains.theirs != Some(theirs) || chains.recv.is_none() {
+            chains.skip_to(previous, self.max_skip, &mut skipped)?;
+            chains.dh_step(theirs, &*self.random)?;
+        }
+        if number < chains.received {
+            return Err(SessionError::DuplicateMessage);
+        }
+        chains.skip_to(number, self.max_skip, &mut skipped)?;
+        let mut key = step_chain(chains.recv.as_mut().unwrap());
+        chains.received += 1;
+        let plain = XCHACHA20_BLAKE3.open(&key, sealed, header);
+        key.zeroize();
+
+        let plain = plain?;
+        self.chains = chains;
+        for (at, key) in skipped {
+            self.skipped.insert(at, key, self.capacity);
+        }
+        Ok(plain)
+    }
+}
+
+fn step_root(root: &[u8; 32], dh: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut b3 = blake3::Hasher::new_derive_key(ROOT_CONTEXT);
+    b3.update(root);
+    b3.update(dh);
+    let mut out = [0u8; 64];
+    b3.finalize_xof().fill(&mut out);
+    let keys = (out[..32].try_into().unwrap(), out[32..].try_into().unwrap());
+    out.zeroize();
+    keys
+}
+
+// gives the chain's next message key, and steps it
+pub(crate) fn step_chain(chain: &mut [u8; 32]) -> [u8; 32] {
+    let key = *blake3::keyed_hash(chain, &[0x01]).as_bytes();
+    *chain = *blake3::keyed_hash(chain, &[0x02]).as_bytes();
+    key
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{thread_rng, RngCore};
+
+    use crate::ratchet::{RatchetKeyPair, RatchetSession};
+    use crate::session::SessionError;
+
+    fn pair() -> (RatchetSession, RatchetSession) {
+        let mut shared = [0u8; 32];
+        thread_rng().fill_bytes(&mut shared);
+        let prekey = RatchetKeyPair::generate(&mut thread_rng());
+        let alice = RatchetSession::initiate(&mut thread_rng(), &shared, &prekey.public()).unwrap();
+        (alice, RatchetSession::respond(&shared, prekey))
+    }
+
+    #[test]
+    fn test_alternating_conversation() {
+        let (mut alice, mut bob) = pair();
+        assert!(matches!(bob.encrypt(b"too early"), Err(SessionError::NotReady)));
+        for round in 0..5u8 {
+            for i in 0..3u8 {
+                let message = alice.encrypt(&[round, i]).unwrap();
+                assert_eq!(bob.decrypt(&message).unwrap(), [round, i]);
+            }
+            let reply = bob.encrypt(&[round]).unwrap();
+            assert_eq!(alice.decrypt(&reply).unwrap(), [round]);
+        }
+
+        // every message its own key, so the same plaintext never repeats
+        assert_ne!(alice.encrypt(b"same").unwrap(), alice.encrypt(b"same").unwrap());
+    }
+
+    #[test]
+    fn test_out_of_order_within_bounds() {
+        let (mut alice, mut bob) = pair();
+        let first: Vec<Vec<u8>> = (0..4u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        assert_eq!(bob.decrypt(&first[2]).unwrap(), [2]);
+        assert_eq!(bob.decrypt(&first[0]).unwrap(), [0]);
+        alice.decrypt(&bob.encrypt(b"ack").unwrap()).unwrap();
+
+        // a new chain from alice, arriving before the rest of the old one
+        let second = alice.encrypt(b"new chain").unwrap();
+        assert_eq!(bob.decrypt(&second).unwrap(), b"new chain");
+        assert_eq!(bob.decrypt(&first[3]).unwrap(), [3]);
+        assert_eq!(bob.decrypt(&first[1]).unwrap(), [1]);
+
+        // each opens once
+        assert!(matches!(bob.decrypt(&first[1]), Err(SessionError::DuplicateMessage)));
+        assert!(matches!(bob.decrypt(&second), Err(SessionError::DuplicateMessage)));
+    }
+
+    #[test]
+    fn test_skipped_key_opens_late_message() {
+        let (mut alice, mut bob) = pair();
+        let messages: Vec<Vec<u8>> = (0..3u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        for i in [0, 2, 1] {
+            assert_eq!(bob.decrypt(&messages[i]).unwrap(), [i as u8]);
+        }
+        // the key for 1 was used up, nothing is left over
+        assert!(bob.skipped.order.is_empty());
+    }
+
+    #[test]
+    fn test_skipping_is_bounded() {
+        let (mut alice, mut bob) = pair();
+        bob.set_max_skip(3);
+        let messages: Vec<Vec<u8>> = (0..6u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        assert!(matches!(bob.decrypt(&messages[5]), Err(SessionError::TooManySkipped(5))));
+        // nothing changed, so it's all still there in order
+        for (i, message) in messages.iter().enumerate() {
+            assert_eq!(bob.decrypt(message).unwrap(), [i as u8]);
+        }
+
+        // the oldest skipped keys are evicted
+        bob.set_skipped_capacity(2);
+        let messages: Vec<Vec<u8>> = (0..4u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        assert_eq!(bob.decrypt(&messages[3]).unwrap(), [3]);
+        assert!(matches!(bob.decrypt(&messages[0]), Err(SessionError::DuplicateMessage)));
+        assert_eq!(bob.decrypt(&messages[1]).unwrap(), [1]);
+        assert_eq!(bob.decrypt(&messages[2]).unwrap(), [2]);
+    }
+
+    #[test]
+    fn test_tampering_changes_nothing() {
+        let (mut alice, mut bob) = pair();
+        let message = alice.encrypt(b"hello").unwrap();
+        for i in [0, 33, 37, message.len() - 1] {
+            let mut tampered = message.clone();
+            tampered[i] ^= 1;
+            assert!(bob.decrypt(&tampered).is_err(), "{}", i);
+        }
+        assert!(matches!(bob.decrypt(&message[..20]), Err(SessionError::InvalidLength(20))));
+        assert_eq!(bob.decrypt(&message).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_compromise_then_heal() {
+        let (mut alice, mut bob) = pair();
+        bob.decrypt(&alice.encrypt(b"hello").unwrap()).unwrap();
+        // everything bob knows, as of now
+        let mut stolen = RatchetSession {
+            chains: bob.chains.clone(),
+            skipped: Default::default(),
+            max_skip: bob.max_skip,
+            capacity: bob.capacity,
+            random: bob.random.clone(),
+        };
+
+        // the current chain is readable with it, and so is the next, whose
+        // DH is with a key pair bob already had
+        let exposed = alice.encrypt(b"exposed").unwrap();
+        assert_eq!(stolen.decrypt(&exposed).unwrap(), b"exposed");
+        assert_eq!(bob.decrypt(&exposed).unwrap(), b"exposed");
+        alice.decrypt(&bob.encrypt(b"reply").unwrap()).unwrap();
+        let exposed = alice.encrypt(b"still exposed").unwrap();
+        assert_eq!(stolen.decrypt(&exposed).unwrap(), b"still exposed");
+        assert_eq!(bob.decrypt(&exposed).unwrap(), b"still exposed");
+
+        // bob answers with a key pair made after the leak, and from then on
+        // the copy is locked out
+        let reply = bob.encrypt(b"safe reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"safe reply");
+        let healed = alice.encrypt(b"safe").unwrap();
+        assert!(matches!(stolen.decrypt(&healed), Err(SessionError::MacMismatch)));
+        assert_eq!(bob.decrypt(&healed).unwrap(), b"safe");
+    }
+}
@@ -0,0 +1,183 @@
+// wire layout of a session record:
+//
+//   version (1) || ciphertext || tag (24)
+//
+// with the default cipher the tag is the BLAKE3 MAC over the plaintext and
+// doubles as the XChaCha20 nonce. structural problems are reported before
+// any cryptography is done, so a sender using an incompatible format is told
+// apart from tampering
+//
+// from version 2 the header is authenticated too. the cipher is handed
+//
+//   le64(header len) || header || le64(aad len) || aad || le64(plaintext len)
+//
+// as its aad, so with the default cipher the whole MAC input is
+//
+//   plaintext || key || le64(header len) || header || le64(aad len) || aad
+//     || le64(plaintext len) || le64(len of everything after the key)
+//
+// every variable-length field is length prefixed, so no field's bytes can be
+// moved into another, and any header field added later is covered without
+// changing this. the plaintext comes first because the tag is the nonce, so
+// it has to be computed over the plaintext before there's a ciphertext. a
+// version 1 record binds only the caller's aad and leaves its header out,
+// obfuscated sessions (SessionConfig::obfuscate) send no header at all:
+//
+//   salt (16) || ciphertext || tag (24)
+//
+// the salt is fresh random bytes, and the header the tag covers is the
+// session's version followed by the salt. so nothing on the wire is fixed,
+// and the same plaintext never seals the same way twice. the cost is that
+// nothing can be checked before the MAC: a wrong version, a wrong key and
+// tampering are all MacMismatch, and a receiver juggling several sessions
+// has to try each. it's exclusive with the versioned header by design, a
+// cleartext version byte is exactly the marker it removes
+//
+// there's no such thing as a record without aad, only one with empty aad:
+// Session::encrypt is Session::encrypt_with_aad with &[], byte for byte. its
+// le64(aad len) is always in the MAC input, zero for empty, so aad can't be
+// dropped, added, or moved into the plaintext or header without the tag
+// changing. a version 1 record hands the cipher the aad alone, and the
+// default cipher leaves an empty one out, length and all, and suffixes any
+// other with its length, so the same holds there
+//
+// the top bit of the header byte is the key epoch's parity, flipped by every
+// renegotiation (Session::renegotiate), so a receiver holding the keys of
+// the epoch before for a while knows which to open a record with. it's zero
+// until a session renegotiates, so older peers never see it set. obfuscated
+// records carry it in the header the tag covers, and are tried under both
+//
+// ordered sessions (SessionConfig::ordered) put the sender's record counter
+// after the header byte:
+//
+//   version (1) || le64(sequence number) || ciphertext || tag (24)
+//
+// it's part of the header, so it's covered like the rest of it, and a
+// receiver seeing any number but the next one knows the record was
+// reordered, replayed or dropped rather than tampered with. a version 1
+// record with one is authenticated the same way, not by its aad alone.
+// obfuscated ordered sessions put the number the receiver expects into the
+// header the tag covers instead, so a record out of order is MacMismatch
+//
+// the bit below it marks a heartbeat (Session::heartbeat), an empty record
+// that isn't a message. like the epoch it's only on the wire in the header,
+// so only sessions from version 2 on send heartbeats, and an obfuscated session tries
+// an empty record both ways
+//
+// the bit below that marks a close record (Session::close), whose one byte
+// of plaintext is the reason code, and which is tried the same way by
+// obfuscated sessions on one byte records
+//
+// both bits together mark a rekey record (Session::send_rekey), whose eight
+// bytes of plaintext are le64(the epoch it starts). it's sealed under the
+// sender's key and epoch bit of the epoch it ends, so it's opened like any
+// record from then, and obfuscated sessions try it on eight byte records
+//
+// the bit below that marks a message sealed with an id
+// (Session::encrypt_with_id), whose plaintext is
+//
+//   le16(id len) || id || message
+//
+// so the id is encrypted and under the tag, and with the tag being the
+// nonce, a unique id makes a unique nonce whatever the message. the
+// receiver strips it off. obfuscated sessions try it on every record of two
+// bytes or more, which doubles what a record that doesn't open costs them
+//
+// version 3 records are laid out exactly as version 2 ones. what changed is
+// the key derivation of set_sym_key, which from version 3 binds both public
+// keys alongside the shared secret (see Session::set_sym_key), so the
+// version a session keyed with is the one on its records
+//
+// a session speaks exactly one version, the one its handshake negotiated,
+// and refuses records of any other. versions 1 and 2 are kept for older
+// peers, and version 1 for the file format, whose payload is version 1
+// records
+//
+// the constants and the layout of the header and the authenticated data are
+// xc220b3_core::protocol's, which the no_std crate seals by too
+
+use arrayvec::ArrayVec;
+use xc220b3_core::protocol::{self, FLAG_BITS};
+pub(crate) use xc220b3_core::protocol::{
+    CLOSE_BIT, EPOCH_BIT, HEADER_LEN, HEARTBEAT_BIT, ID_BIT, SALT_LEN, SEQ_LEN, TAG_LEN, V1, V2, V3,
+};
+
+use crate::session::SessionError;
+
+pub(crate) const VERSION: u8 = V3;
+pub(crate) const REKEY_BITS: u8 = HEARTBEAT_BIT | CLOSE_BIT;
+// the longest header the tag covers, an obfuscated ordered record's
+pub(crate) const MAX_HEADER_LEN: usize = 1 + SALT_LEN + SEQ_LEN;
+// the le64 lengths around the header, aad and plaintext
+const LENGTHS_LEN: usize = 24;
+/// Bytes a record adds on top of its plaintext.
+pub(crate) const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+/// Bytes an ordered record, as sessions send by default, adds.
+pub(crate) const ORDERED_OVERHEAD: usize = OVERHEAD + SEQ_LEN;
+
+pub(crate) fn header(version: u8, epoch: u64) -> [u8; HEADER_LEN] {
+    [protocol::header_byte(version, epoch)]
+}
+
+pub(crate) fn epoch_bit(epoch: u64) -> u8 {
+    protocol::header_byte(0, epoch)
+}
+
+pub(crate) fn is_supported(version: u8) -> bool {
+    matches!(version, V1 | V2 | V3)
+}
+
+/// A header as the tag covers it, built without allocating.
+pub(crate) type Header = ArrayVec<u8, MAX_HEADER_LEN>;
+
+pub(crate) fn obfuscated_header(version: u8, epoch: u64, salt: &[u8]) -> Header {
+    let mut header = Header::new();
+    header.push(protocol::header_byte(version, epoch));
+    header.try_extend_from_slice(salt).unwrap();
+    header
+}
+
+/// What the cipher binds under the tag for a record with `header`.
+pub(crate) fn authenticated_data(header: &[u8], aad: &[u8], plain_len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(LENGTHS_LEN + header.len() + aad.len());
+    write_authenticated_data(header, aad, plain_len, &mut out);
+    out
+}
+
+/// [`authenticated_data`] for a record with no aad of the caller's, on the
+/// stack.
+pub(crate) fn bare_authenticated_data(header: &[u8], plain_len: usize) -> ArrayVec<u8, { LENGTHS_LEN + MAX_HEADER_LEN }> {
+    let mut out = ArrayVec::new();
+    write_authenticated_data(header, &[], plain_len, &mut out);
+    out
+}
+
+fn write_authenticated_data(header: &[u8], aad: &[u8], plain_len: usize, out: &mut impl Extend<u8>) {
+    protocol::authenticated_data(header, aad, plain_len, |piece| out.extend(piece.iter().copied()));
+}
+
+/// Checks the structure of `record`, which must be of `version` (whatever
+/// its flags), and splits it into its `header_len` long header and what the
+/// cipher made.
+pub(crate) fn split_header(record: &[u8], version: u8, header_len: usize, cipher_overhead: usize) -> Result<(&[u8], &[u8]), SessionError> {
+    if record.len() < header_len + cipher_overhead {
+        return Err(SessionError::InvalidLength(record.len()));
+    }
+    if record[0] & !FLAG_BITS != version {
+        return Err(SessionError::UnsupportedVersion(record[0]));
+    }
+    Ok(record.split_at(header_len))
+}
+
+/// The sequence number in an ordered record's header.
+pub(crate) fn seq(header: &[u8]) -> u64 {
+    protocol::seq(header)
+}
+
+/// [`split_header`] for an obfuscated record, giving its salt.
+pub(crate) fn split_obfuscated(record: &[u8], cipher_overhead: usize) -> Result<(&[u8], &[u8]), SessionError> {
+    if record.len() < SALT_LEN + cipher_overhead {
+        return Err(SessionError::InvalidLength(record.len()));
+    }
+    Ok(record.split_at(SALT_LEN))
+}
@@ -0,0 +1,188 @@
+// an explicit rekey moves one direction's key on without a key exchange, the
+// way secretstream's REKEY tag does. the sender seals a rekey record (see
+// record.rs) announcing the epoch it starts, then derives
+//
+//   key' = BLAKE3 derive_key(REKEY_CONTEXT) of le64(epoch') || key
+//
+// and the receiver does the same on opening it. the receive key it replaces
+// is kept for the drain window, like a renegotiation's, for records sealed
+// before the rekey record that arrive after it. each direction keeps its own
+// epoch, so the two sides can rekey at once and still meet
+
+use zeroize::Zeroize;
+
+use crate::kex::KeyExchange;
+use crate::record;
+use crate::renegotiate::DEFAULT_DRAIN_WINDOW;
+use crate::session::{Session, SessionError};
+
+const REKEY_CONTEXT: &str = "xc220b3 2022-10 explicit rekey";
+
+impl<K: KeyExchange> Session<K> {
+    /// Seals a rekey record and moves the send key on to the next epoch, so
+    /// anything sealed after is under a key the records before it can't
+    /// give away. The peer's [`Session::receive`] gives
+    /// [`Received::Rekeyed`](crate::Received::Rekeyed) and moves its receive
+    /// key on the same way, still opening the records sealed before this for
+    /// [`DEFAULT_DRAIN_WINDOW`]. The
+    /// [`SessionConfig::rekey_after`](crate::SessionConfig::rekey_after)
+    /// count starts again, and the record may go one past it, so a session
+    /// that's used the count up can still rekey.
+    ///
+    /// The rekey record has to reach the peer, which can't open anything
+    /// sealed after it otherwise. One opened again is
+    /// [`SessionError::DuplicateMessage`]. Version 1 sessions can't send
+    /// one, the flags would be outside the MAC.
+    pub fn send_rekey(&mut self) -> Result<Vec<u8>, SessionError> {
+        if self.negotiated_version() == record::V1 {
+            return Err(SessionError::UnsupportedVersion(record::V1));
+        }
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("rekey", 8);
+                let _enter = span.enter();
+            }
+        );
+
+        let epoch = self.epoch + 1;
+        let record = self.seal_record(&[&epoch.to_le_bytes()], &[], record::REKEY_BITS)?;
+        ratchet(&mut self.send_key, epoch);
+        self.epoch = epoch;
+        self.epoch_sent = self.sent;
+        Ok(record)
+    }
+
+    // a rekey record's plaintext, which only moves the receive key on to the
+    // peer's next epoch. one for an earlier epoch is a replay, and short of
+    // a broken peer there's no later one, since the record after a lost rekey
+    // doesn't open
+    pub(crate) fn check_rekey(&self, plain: &[u8], record_len: usize) -> Result<(), SessionError> {
+        let announced = match plain.try_into() {
+            Ok(epoch) => u64::from_le_bytes(epoch),
+            Err(_) => return Err(SessionError::InvalidLength(record_len)),
+        };
+        let expected = self.recv_epoch + 1;
+        match announced {
+            _ if announced == expected => Ok(()),
+            _ if announced < expected => Err(SessionError::DuplicateMessage),
+            _ => Err(SessionError::OutOfOrder { expected, received: announced }),
+        }
+    }
+
+    pub(crate) fn rekeyed_by_peer(&mut self) {
+        self.keep_previous(DEFAULT_DRAIN_WINDOW, u64::MAX);
+        self.recv_epoch += 1;
+        ratchet(&mut self.recv_key, self.recv_epoch);
+    }
+}
+
+fn ratchet(key: &mut [u8; 32], epoch: u64) {
+    let mut b3 = blake3::Hasher::new_derive_key(REKEY_CONTEXT);
+    b3.update(&epoch.to_le_bytes());
+    b3.update(key);
+    key.zeroize();
+    *key = *b3.finalize().as_bytes();
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use crate::builder::SessionBuilder;
+    use crate::session::{Received, Session, SessionError};
+
+    // unordered, records sealed before a rekey are opened after it
+    fn pair(obfuscate: bool) -> (Session, Session) {
+        Session::pair_with(SessionBuilder::new().obfuscate(obfuscate).ordered(false))
+    }
+
+    #[test]
+    fn test_rekey_mid_stream() {
+        for obfuscate in [false, true] {
+            let (mut sesh1, mut sesh2) = pair(obfuscate);
+            let before = sesh1.encrypt(b"before".to_vec()).unwrap();
+            let key = sesh1.send_key;
+            let rekey = sesh1.send_rekey().unwrap();
+            assert_ne!(sesh1.send_key, key);
+            let after = sesh1.encrypt(b"after".to_vec()).unwrap();
+
+            // the one sealed before the rekey arrives after it, and still opens
+            assert_eq!(sesh2.receive(rekey).unwrap(), Received::Rekeyed);
+            assert_eq!(sesh2.recv_key, sesh1.send_key);
+            assert_eq!(sesh2.decrypt(after).unwrap(), b"after");
+            assert_eq!(sesh2.decrypt(before).unwrap(), b"before");
+
+            // the other direction is as it was
+            let reply = sesh2.encrypt(b"reply".to_vec()).unwrap();
+            assert_eq!(sesh1.decrypt(reply).unwrap(), b"reply");
+            assert!(matches!(sesh2.decrypt(sesh1.send_rekey().unwrap()), Err(SessionError::Rekeyed)));
+            assert_eq!(sesh2.decrypt(sesh1.encrypt(b"again".to_vec()).unwrap()).unwrap(), b"again");
+        }
+    }
+
+    #[test]
+    fn test_keys_differ_across_a_rekey() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let mut behind = sesh2.clone();
+        let before = sesh1.encrypt(b"same".to_vec()).unwrap();
+        let rekey = sesh1.send_rekey().unwrap();
+        let after = sesh1.encrypt(b"same".to_vec()).unwrap();
+
+        // the epoch bit is flipped, and a receiver that missed the rekey
+        // can't open what comes after it
+        assert_ne!(before[0], after[0]);
+        let mut flipped = after.clone();
+        flipped[0] = before[0];
+        assert!(matches!(behind.decrypt(flipped), Err(SessionError::MacMismatch)));
+        assert!(matches!(behind.decrypt(after.clone()), Err(SessionError::StaleEpoch)));
+
+        sesh2.receive(rekey).unwrap();
+        assert_eq!(sesh2.decrypt(after).unwrap(), b"same");
+        assert_eq!(sesh2.stats().epoch, 0);
+        assert_eq!(sesh1.stats().epoch, 1);
+        // the count started again at the rekey, `after` is the one since
+        assert_eq!(sesh1.messages_remaining(), sesh1.config().rekey_after - 1);
+    }
+
+    #[test]
+    fn test_replayed_rekey_is_rejected() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let rekey = sesh1.send_rekey().unwrap();
+        assert_eq!(sesh2.receive(rekey.clone()).unwrap(), Received::Rekeyed);
+        let key = sesh2.recv_key;
+        assert!(matches!(sesh2.receive(rekey), Err(SessionError::DuplicateMessage)));
+        assert_eq!(sesh2.recv_key, key);
+        assert_eq!(sesh2.decrypt(sesh1.encrypt(b"still".to_vec()).unwrap()).unwrap(), b"still");
+
+        // after one is lost nothing opens, the next rekey record included
+        let _lost = sesh1.send_rekey().unwrap();
+        let next = sesh1.send_rekey().unwrap();
+        assert!(matches!(sesh2.receive(next), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_crossed_rekeys_and_renegotiation_compose() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let from1 = sesh1.send_rekey().unwrap();
+        let from2 = sesh2.send_rekey().unwrap();
+        let from2_again = sesh2.send_rekey().unwrap();
+        assert_eq!(sesh1.receive(from2).unwrap(), Received::Rekeyed);
+        assert_eq!(sesh1.receive(from2_again).unwrap(), Received::Rekeyed);
+        assert_eq!(sesh2.receive(from1).unwrap(), Received::Rekeyed);
+        assert_eq!(sesh1.decrypt(sesh2.encrypt(b"one".to_vec()).unwrap()).unwrap(), b"one");
+        assert_eq!(sesh2.decrypt(sesh1.encrypt(b"two".to_vec()).unwrap()).unwrap(), b"two");
+
+        // the epochs don't match, and a renegotiation from here still meets
+        let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        let pk1 = *pending1.public_key();
+        let mut pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap();
+        let pk2 = *pending2.public_key();
+        let in_flight = pending2.session().encrypt(b"in flight".to_vec()).unwrap();
+        pending1.complete(&pk2).unwrap();
+        pending2.complete(&pk1).unwrap();
+        assert_eq!(sesh1.decrypt(in_flight).unwrap(), b"in flight");
+        assert_eq!(sesh1.decrypt(sesh2.encrypt(b"three".to_vec()).unwrap()).unwrap(), b"three");
+        assert_eq!(sesh2.decrypt(sesh1.encrypt(b"four".to_vec()).unwrap()).unwrap(), b"four");
+        assert_eq!((sesh1.stats().epoch, sesh2.stats().epoch), (2, 3));
+    }
+}
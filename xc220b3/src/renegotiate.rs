@@ -0,0 +1,383 @@
+// renegotiation runs a fresh ephemeral key exchange over an existing session
+// and swaps in keys derived from it and from the keys it replaces, so the new
+// epoch is only as weak as both exchanges together. both sides call
+// renegotiate, swap public keys over the session, and complete with the
+// other's. the record header carries the epoch's parity (see record.rs), and
+// the receive key of the epoch before is kept for the drain window, for
+// records the peer sealed before it completed
+
+use std::time::{Duration, Instant};
+
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::kex::{self, KeyExchange, Secp256k1};
+use crate::session::{Role, Session, SessionError};
+
+/// How long [`PendingRenegotiation::complete`] keeps accepting records
+/// under the old keys, unless set with
+/// [`PendingRenegotiation::drain_window`].
+pub const DEFAULT_DRAIN_WINDOW: Duration = Duration::from_secs(30);
+
+const RENEGOTIATION_CONTEXT: &str = "xc220b3 2022-10 renegotiation";
+
+// the receive key of the epoch before, zeroed when the drain window is over
+#[derive(Clone)]
+pub(crate) struct PreviousKey {
+    pub(crate) key: [u8; 32],
+    pub(crate) epoch: u64,
+    until: Instant,
+    // records it may still open
+    remaining: u64,
+}
+
+impl Drop for PreviousKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// A renegotiation started with [`Session::renegotiate`]. The session keeps
+/// working under its current keys, through [`session`](Self::session),
+/// until [`complete`](Self::complete).
+pub struct PendingRenegotiation<'a, K: KeyExchange = Secp256k1> {
+    session: &'a mut Session<K>,
+    secret: K::Private,
+    public: K::Public,
+    drain_window: Duration,
+    drain_records: u64,
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Starts renegotiating this session's keys with a fresh ephemeral key.
+    /// Send the peer [`PendingRenegotiation::public_key`], it does the same,
+    /// and both complete with the other's key. Counters, stats,
+    /// configuration and the [`session_id`](Session::session_id) carry over,
+    /// and the [`SessionConfig::rekey_after`](crate::SessionConfig::rekey_after)
+    /// count starts again.
+    ///
+    /// Only for sessions keyed by a key exchange, anything else is
+    /// [`SessionError::NotReady`].
+    pub fn renegotiate(&mut self, rng: &mut (impl CryptoRng + RngCore)) -> Result<PendingRenegotiation<'_, K>, SessionError> {
+        if self.role().is_none() {
+            return Err(SessionError::NotReady);
+        }
+        let (secret, public) = K::generate(rng);
+        Ok(PendingRenegotiation {
+            session: self,
+            secret,
+            public,
+            drain_window: DEFAULT_DRAIN_WINDOW,
+            drain_records: u64::MAX,
+        })
+    }
+
+    // drops the old epoch's key once its drain window is over
+    pub(crate) fn expire_previous(&mut self) {
+        let now = self.failures.now();
+        if matches!(&self.previous, Some(previous) if now >= previous.until) {
+            self.previous = None;
+        }
+    }
+
+    // keeps the receive key for the records sealed under it still on their
+    // way, for `drain_window` or `drain_records` of them
+    pub(crate) fn keep_previous(&mut self, drain_window: Duration, drain_records: u64) {
+        self.previous = match drain_records {
+            0 => None,
+            remaining => Some(PreviousKey {
+                key: self.recv_key,
+                epoch: self.recv_epoch,
+                until: self.failures.now() + drain_window,
+                remaining,
+            }),
+        };
+    }
+
+    // a record opened under the old epoch's key, which may have been its last
+    pub(crate) fn drained_one(&mut self) {
+        if let Some(previous) = &mut self.previous {
+            previous.remaining -= 1;
+            if previous.remaining == 0 {
+                self.previous = None;
+            }
+        }
+    }
+}
+
+impl<'a, K: KeyExchange> PendingRenegotiation<'a, K> {
+    /// The key to send the peer.
+    pub fn public_key(&self) -> &K::Public {
+        &self.public
+    }
+
+    /// How long records under the old keys are still accepted once this
+    /// completes, [`DEFAULT_DRAIN_WINDOW`] unless set. Zero drops them at
+    /// once.
+    pub fn drain_window(mut self, window: Duration) -> Self {
+        self.drain_window = window;
+        self
+    }
+
+    /// Also stops accepting records under the old keys once `records` of
+    /// them have been opened, for transports where the number in flight is
+    /// known. Whichever of this and the [`drain_window`](Self::drain_window)
+    /// runs out first ends it, there's no limit unless set. Zero drops them
+    /// at once.
+    pub fn drain_records(mut self, records: u64) -> Self {
+        self.drain_records = records;
+        self
+    }
+
+    /// The session, still on its old keys, for the records that go back and
+    /// forth while the public keys are swapped.
+    pub fn session(&mut self) -> &mut Session<K> {
+        self.session
+    }
+
+    /// Derives the next epoch's keys from the exchange with `peer_pk` and
+    /// the current keys, and switches to them. Records from the old epoch
+    /// are opened for the drain window, after which they're
+    /// [`SessionError::StaleEpoch`] and the old key is zeroed. The epoch a
+    /// record is from is authenticated, a record claiming the wrong one
+    /// fails its MAC.
+    pub fn complete(self, peer_pk: &K::Public) -> Result<(), SessionError> {
+        self.session.complete_renegotiation(&self.secret, &self.public, peer_pk, self.drain_window, self.drain_records)
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    // PendingRenegotiation::complete, for callers that keep the key pair
+    // themselves rather than a borrow of the session
+    pub(crate) fn complete_renegotiation(
+        &mut self,
+        secret: &K::Private,
+        public: &K::Public,
+        peer_pk: &K::Public,
+        drain_window: Duration,
+        drain_records: u64,
+    ) -> Result<(), SessionError> {
+        if K::canonical(peer_pk)? == K::canonical(public)? {
+            return Err(SessionError::InvalidPubKey);
+        }
+        let shared = kex::agree::<K>(secret, peer_pk)?;
+        let session = self;
+        let role = session.role().ok_or(SessionError::NotReady)?;
+        // each direction moves on an epoch, from wherever rekey records left
+        // it. the later of the two is the same number to both sides
+        let epoch = session.epoch.max(session.recv_epoch) + 1;
+        let mut chain = chain(role, &session.send_key, &session.recv_key, epoch);
+
+        session.keep_previous(drain_window, drain_records);
+        session.derive_keys(role, shared.as_bytes(), &chain);
+        chain.zeroize();
+        session.epoch += 1;
+        session.recv_epoch += 1;
+        session.epoch_sent = session.sent;
+        Ok(())
+    }
+}
+
+// what the new keys are bound to: the old ones, in the same order on both
+// sides, and the epoch they're for
+fn chain(role: Role, send_key: &[u8; 32], recv_key: &[u8; 32], epoch: u64) -> [u8; 32] {
+    let (initiator_key, responder_key) = match role {
+        Role::Initiator => (send_key, recv_key),
+        Role::Responder => (recv_key, send_key),
+    };
+    let mut b3 = blake3::Hasher::new_derive_key(RENEGOTIATION_CONTEXT);
+    b3.update(&epoch.to_le_bytes());
+    b3.update(initiator_key);
+    b3.update(responder_key);
+    *b3.finalize().as_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+    use rand::thread_rng;
+
+    use crate::builder::SessionBuilder;
+    use crate::failures::Clock;
+    use crate::session::{Received, Session, SessionError};
+
+    struct TestClock(Mutex<Instant>);
+
+    impl TestClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    // unordered, records sealed before a rekey are opened after it
+    fn pair(obfuscate: bool) -> (Session, Session) {
+        Session::pair_with(SessionBuilder::new().obfuscate(obfuscate).ordered(false))
+    }
+
+    #[test]
+    fn test_old_epoch_drains_then_is_refused() {
+        for obfuscate in [false, true] {
+            let (mut sesh1, mut sesh2) = pair(obfuscate);
+            let clock = Arc::new(TestClock(Mutex::new(Instant::now())));
+            sesh2.set_clock(clock.clone());
+            let (id, keys) = (sesh1.session_id(), (sesh1.send_key, sesh1.recv_key));
+            let before = sesh1.encrypt(b"before".to_vec()).unwrap();
+
+            let mut pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+            let mut pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap().drain_window(Duration::from_secs(5));
+            let (pk1, pk2) = (*pending1.public_key(), *pending2.public_key());
+
+            // in flight while the keys are swapped
+            let during = pending1.session().encrypt(b"during".to_vec()).unwrap();
+            assert_eq!(pending2.session().decrypt(before).unwrap(), b"before");
+            pending1.complete(&pk2).unwrap();
+            pending2.complete(&pk1).unwrap();
+            assert_ne!((sesh1.send_key, sesh1.recv_key), keys);
+            assert_eq!((sesh1.session_id(), sesh2.session_id()), (id, id));
+
+            let after = sesh1.encrypt(b"after".to_vec()).unwrap();
+            assert_eq!(sesh2.decrypt(after).unwrap(), b"after");
+            assert_eq!(sesh2.decrypt(during.clone()).unwrap(), b"during");
+            let reply = sesh2.encrypt(b"reply".to_vec()).unwrap();
+            assert_eq!(sesh1.decrypt(reply).unwrap(), b"reply");
+            assert_eq!((sesh1.stats().sent, sesh1.stats().epoch), (3, 1));
+
+            clock.advance(Duration::from_secs(5));
+            match obfuscate {
+                true => assert!(matches!(sesh2.decrypt(during), Err(SessionError::MacMismatch))),
+                false => assert!(matches!(sesh2.decrypt(during), Err(SessionError::StaleEpoch))),
+            }
+            assert!(sesh2.previous.is_none());
+        }
+    }
+
+    #[test]
+    fn test_old_epoch_drains_by_count() {
+        for obfuscate in [false, true] {
+            let (mut sesh1, mut sesh2) = pair(obfuscate);
+            let in_flight: Vec<Vec<u8>> = (0..3u8).map(|i| sesh1.encrypt(vec![i]).unwrap()).collect();
+
+            let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+            let pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap().drain_records(2);
+            let (pk1, pk2) = (*pending1.public_key(), *pending2.public_key());
+            pending1.complete(&pk2).unwrap();
+            pending2.complete(&pk1).unwrap();
+
+            let after = sesh1.encrypt(b"after".to_vec()).unwrap();
+            assert_eq!(sesh2.decrypt(after).unwrap(), b"after");
+            assert_eq!(sesh2.decrypt(in_flight[0].clone()).unwrap(), [0]);
+            assert_eq!(sesh2.decrypt(in_flight[1].clone()).unwrap(), [1]);
+            assert!(sesh2.previous.is_none());
+            match obfuscate {
+                true => assert!(matches!(sesh2.decrypt(in_flight[2].clone()), Err(SessionError::MacMismatch))),
+                false => assert!(matches!(sesh2.decrypt(in_flight[2].clone()), Err(SessionError::StaleEpoch))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tampered_epoch_bit_fails_cleanly() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let clock = Arc::new(TestClock(Mutex::new(Instant::now())));
+        sesh2.set_clock(clock.clone());
+        let old = sesh1.encrypt(b"old".to_vec()).unwrap();
+
+        let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        let pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap().drain_window(Duration::from_secs(5));
+        let (pk1, pk2) = (*pending1.public_key(), *pending2.public_key());
+        pending1.complete(&pk2).unwrap();
+        pending2.complete(&pk1).unwrap();
+        let new = sesh1.encrypt(b"new".to_vec()).unwrap();
+
+        // either way round, the other key is tried and the MAC fails
+        for record in [&old, &new] {
+            let mut flipped = record.clone();
+            flipped[0] ^= crate::record::EPOCH_BIT;
+            assert!(matches!(sesh2.decrypt(flipped), Err(SessionError::MacMismatch)));
+        }
+        assert!(sesh2.previous.is_some());
+        assert_eq!(sesh2.decrypt(old).unwrap(), b"old");
+        assert_eq!(sesh2.decrypt(new.clone()).unwrap(), b"new");
+
+        // and once the old key is gone, claiming the old epoch is refused
+        // without trying anything
+        clock.advance(Duration::from_secs(5));
+        let mut flipped = new;
+        flipped[0] ^= crate::record::EPOCH_BIT;
+        assert!(matches!(sesh2.decrypt(flipped), Err(SessionError::StaleEpoch)));
+        assert_eq!(sesh2.stats().auth_failures, 2);
+    }
+
+    #[test]
+    fn test_consecutive_renegotiations() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let mut old = Vec::new();
+        for epoch in 1..=3 {
+            old.push(sesh1.encrypt(b"old".to_vec()).unwrap());
+            let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+            let pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap();
+            let (pk1, pk2) = (*pending1.public_key(), *pending2.public_key());
+            pending1.complete(&pk2).unwrap();
+            pending2.complete(&pk1).unwrap();
+            let record = sesh1.encrypt(b"new".to_vec()).unwrap();
+            assert_eq!(record[0] & crate::record::EPOCH_BIT != 0, epoch % 2 == 1);
+            assert_eq!(sesh2.decrypt(record).unwrap(), b"new");
+        }
+
+        // only the epoch right before is kept, one with the same parity
+        // two back doesn't open under the current keys
+        assert_eq!(sesh2.decrypt(old.pop().unwrap()).unwrap(), b"old");
+        assert!(matches!(sesh2.decrypt(old.pop().unwrap()), Err(SessionError::MacMismatch)));
+        assert!(matches!(sesh2.decrypt(old.pop().unwrap()), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_renegotiation_resets_the_record_limit() {
+        let (mut sesh1, mut sesh2) = pair(false);
+        let limit = sesh1.config().rekey_after;
+        sesh1.sent = limit;
+        assert!(matches!(sesh1.encrypt(b"hello".to_vec()), Err(SessionError::CounterExhausted)));
+
+        let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        let pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap().drain_window(Duration::ZERO);
+        let (pk1, pk2) = (*pending1.public_key(), *pending2.public_key());
+        assert!(matches!(pending1.complete(&pk1), Err(SessionError::InvalidPubKey)));
+        let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        let pk1 = *pending1.public_key();
+        pending1.complete(&pk2).unwrap();
+        pending2.complete(&pk1).unwrap();
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+        assert_eq!(sesh1.stats().sent, limit + 1);
+
+        let mut unkeyed = Session::new(&mut thread_rng());
+        assert!(matches!(unkeyed.renegotiate(&mut thread_rng()), Err(SessionError::NotReady)));
+    }
+
+    #[test]
+    fn test_ordered_sessions_renegotiate_and_rekey() {
+        // the default, over a transport that keeps records in order
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        assert!(sesh1.config().ordered);
+        let before = sesh1.encrypt(b"before".to_vec()).unwrap();
+        let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        let pk1 = *pending1.public_key();
+        let mut pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap();
+        let pk2 = *pending2.public_key();
+        assert_eq!(pending2.session().decrypt(before).unwrap(), b"before");
+        pending1.complete(&pk2).unwrap();
+        pending2.complete(&pk1).unwrap();
+
+        let records = [sesh1.encrypt(b"after".to_vec()).unwrap(), sesh1.send_rekey().unwrap(), sesh1.encrypt(b"rekeyed".to_vec()).unwrap()];
+        let received: Vec<Received> = records.into_iter().map(|record| sesh2.receive(record).unwrap()).collect();
+        assert_eq!(received, [Received::Data(b"after".to_vec()), Received::Rekeyed, Received::Data(b"rekeyed".to_vec())]);
+        assert_eq!(sesh1.decrypt(sesh2.encrypt(b"reply".to_vec()).unwrap()).unwrap(), b"reply");
+    }
+}
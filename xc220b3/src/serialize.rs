@@ -0,0 +1,49 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::kex::KeyExchange;
+use crate::session::{Session, SessionError};
+
+impl<K: KeyExchange> Session<K> {
+    /// Encrypts `value` serialized as CBOR, for [`Session::decrypt_serde`] to
+    /// read back on the other side.
+    pub fn encrypt_serde<T: Serialize>(&mut self, value: &T) -> Result<Vec<u8>, SessionError> {
+        let mut plain = Vec::new();
+        ciborium::ser::into_writer(value, &mut plain).map_err(|e| SessionError::Serialization(e.to_string()))?;
+        self.encrypt(plain)
+    }
+
+    /// Decrypts a record and deserializes its plaintext as CBOR. The bytes
+    /// are only deserialized once they've authenticated.
+    pub fn decrypt_serde<T: DeserializeOwned>(&mut self, ciphertext: Vec<u8>) -> Result<T, SessionError> {
+        let plain = self.decrypt(ciphertext)?;
+        ciborium::de::from_reader(&plain[..]).map_err(|e| SessionError::Serialization(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+    use serde::{Deserialize, Serialize};
+
+    use crate::session::{Session, SessionError};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Login {
+        user: String,
+        attempt: u32,
+        scopes: Vec<String>,
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let login = Login { user: "asteria".to_string(), attempt: 3, scopes: vec!["read".to_string()] };
+
+        let record = sesh1.encrypt_serde(&login).unwrap();
+        assert_eq!(sesh2.decrypt_serde::<Login>(record).unwrap(), login);
+
+        // authenticates, but isn't a Login
+        let record = sesh1.encrypt_serde(&42u8).unwrap();
+        assert!(matches!(sesh2.decrypt_serde::<Login>(record), Err(SessionError::Serialization(_))));
+    }
+}
@@ -1,191 +1,2486 @@
-use blake3::Hasher;
-use k256::{ecdh::EphemeralSecret, EncodedPoint, elliptic_curve::PublicKey};
 use rand_core::{CryptoRng, RngCore};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+use zeroize::Zeroize;
+use k256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint, EncodedPoint, PublicKey};
+use xc220b3_core::protocol::{self, INITIATOR_TO_RESPONDER, RESPONDER_TO_INITIATOR};
 #[cfg(feature = "tracing")]
-use tracing::{trace, info_span};
-use core::iter::repeat;
+use std::time::Instant;
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace, info_span, field::{display, Empty}, Span};
+
+use crate::{builder::SessionConfig, cipher::{RecordCipher, XCHACHA20_BLAKE3}, close::CloseReason, dedup::TagCache, failures::FailureWindow, framing::LengthMasks, kdf::{SessionKdf, BLAKE3_KDF}, kex::{self, KeyExchange, Secp256k1}, provider::{ProviderError, SecretProvider}, random::{self, RandomSource}, record, renegotiate::PreviousKey, transcript::Transcript, xc220::InvalidCipherParams};
+
+// a trace! event that respects the session's log policy
+macro_rules! session_trace {
+    ($session:expr, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        if $session.log_policy != LogPolicy::Off {
+            trace!($($arg)+);
+        }
+    };
+}
+
+/// How much a session reveals in its tracing output.
+///
+/// Keys and plaintext are never logged under any policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogPolicy {
+    /// Full tags and peer public keys, for local debugging only. Tags double
+    /// as nonces, so these logs shouldn't leave the machine.
+    Full,
+    /// Records are identified by the first 4 bytes of their tag and peers by
+    /// the first 8 bytes of the BLAKE3 hash of their public key.
+    #[default]
+    Redacted,
+    /// No spans or events at all.
+    Off,
+}
+
+/// Which end of the exchange a session is, this picks which of the two
+/// per-direction keys it sends with. The side with the lower compressed
+/// public key is the initiator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+const SESSION_ID_CONTEXT: &str = "xc220b3 2022-10 session id";
+
+/// The most records a session sends under one key before it refuses to send
+/// more, see [`SessionConfig::rekey_after`]. This is far below where the counter could wrap, so a session must be
+/// rekeyed (or replaced) long before any counter value could repeat.
+pub const REKEY_AFTER_MESSAGES: u64 = protocol::REKEY_AFTER_MESSAGES;
+
+/// Length of the record [`Session::encrypt`] makes from `plaintext_len` bytes
+/// with the default [`SessionConfig`]. See [`Session::ciphertext_len`] for a
+/// particular session.
+pub fn ciphertext_len(plaintext_len: usize) -> usize {
+    plaintext_len.saturating_add(record::ORDERED_OVERHEAD)
+}
+
+/// What an [`Session::on_auth_failure`] hook is told about a record that
+/// failed authentication. Never carries plaintext or key material.
+#[derive(Clone, Debug)]
+pub struct AuthFailure {
+    /// Length of the whole record as received.
+    pub record_len: usize,
+    /// The sequence number the record would have had.
+    pub seq: u64,
+    /// First 4 bytes of the claimed tag, as in the logs.
+    pub record_id: [u8; 4],
+    pub at: SystemTime,
+    /// Authentication failures on this session so far, including this one.
+    pub failures: u64,
+}
 
-use crate::{mac::MAC, xc220::XC220, symmetriccipher::SynchronousStreamCipher};
+/// What [`Session::receive`] opened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Received {
+    Data(Vec<u8>),
+    /// A [`Session::heartbeat`] from the peer.
+    Heartbeat,
+    /// The peer [closed](Session::close) the session. Nothing more is
+    /// accepted from it.
+    Closed(CloseReason),
+    /// A [`Session::send_rekey`] from the peer. Its records from here on are
+    /// under the next key.
+    Rekeyed,
+}
+
+type AuthFailureHook = Arc<dyn Fn(&AuthFailure) + Send + Sync>;
 
-pub struct Session {
+/// An encrypted session with one peer, keyed with `K`.
+pub struct Session<K: KeyExchange = Secp256k1> {
     ready: bool,
-    secret: Option<EphemeralSecret>,
-    key: [u8; 32],
-    xcc20: XC220,
-    b3: Hasher,
+    pub(crate) sent: u64,
+    pub(crate) received: u64,
+    pub(crate) auth_failures: u64,
+    on_auth_failure: Option<AuthFailureHook>,
+    pub(crate) failures: FailureWindow,
+    pub(crate) random: Arc<dyn RandomSource>,
+    pub(crate) seen: TagCache,
+    pub(crate) config: SessionConfig,
+    log_policy: LogPolicy,
+    opaque_errors: bool,
+    pub(crate) secret: Option<(K::Private, K::Public)>,
+    // as the peer sent it
+    pub(crate) peer: Option<K::Public>,
+    // canonical, so a retransmitted key can be told from a new one
+    pub(crate) peer_key: Option<Vec<u8>>,
+    // the certificate a handshake verifier accepted
+    pub(crate) peer_identity: Option<Vec<u8>>,
+    // see Session::set_identity and trust_identity
+    pub(crate) identity: Option<Arc<dyn SecretProvider>>,
+    pub(crate) trusted_identities: Vec<PublicKey>,
+    pub(crate) peer_identity_key: Option<PublicKey>,
+    role: Option<Role>,
+    pub(crate) send_key: [u8; 32],
+    pub(crate) recv_key: [u8; 32],
+    pub(crate) cipher: &'static dyn RecordCipher,
+    pub(crate) kdf: &'static dyn SessionKdf,
+    // mixed into the keys, see SessionBuilder::bind_suite
+    pub(crate) suite_binding: Option<[u8; 4]>,
+    version: u8,
+    pub(crate) session_id: [u8; 16],
+    // the epoch of the send key, and `sent` when it started. renegotiations
+    // move both directions on, rekey records only the one they're sent in
+    pub(crate) epoch: u64,
+    pub(crate) epoch_sent: u64,
+    // the epoch of the receive key, the peer's send epoch
+    pub(crate) recv_epoch: u64,
+    pub(crate) previous: Option<PreviousKey>,
+    // set by sending, and receiving, a close record
+    pub(crate) send_closed: bool,
+    pub(crate) recv_closed: bool,
+    // Session::derive_token calls so far
+    pub(crate) tokens: AtomicU64,
+    pub(crate) transcript: Transcript,
+    pub(crate) lengths: LengthMasks,
 }
 
 #[derive(Debug)]
 pub enum SessionError {
+    /// The record is well formed but failed authentication, it was tampered
+    /// with or encrypted under a different key.
     MacMismatch,
+    /// The record's tag verified but its nonce isn't the one the tag commits
+    /// to, so it was swapped. Only ciphers that carry the nonce apart from
+    /// the tag can report this, with the default cipher the tag is the nonce.
+    NonceMismatch,
+    /// The record is too short to be one, the length is given.
+    InvalidLength(usize),
+    /// The buffer given to [`Session::encrypt_into`] or
+    /// [`Session::decrypt_into`] is too short, the length it needs is given.
+    /// Nothing about the session changed.
+    BufferTooSmall(usize),
+    /// The record's version byte isn't the session's
+    /// [`negotiated_version`](Session::negotiated_version).
+    UnsupportedVersion(u8),
     InvalidPubKey,
-    EmptySecret
+    /// The key exchange came out as the identity, or a point of small order,
+    /// so there's no secret to key from. The peer's key is hostile or broken.
+    WeakSharedSecret,
+    /// Link `n` of a certificate chain doesn't verify, see
+    /// [`verify_certificate_chain`](crate::verify_certificate_chain).
+    InvalidCertificate(usize),
+    /// A [`RatchetSession`](crate::RatchetSession) message or
+    /// [`GroupSession`](crate::GroupSession) broadcast is this far ahead of
+    /// the last one its chain received, more than
+    /// [`set_max_skip`](crate::RatchetSession::set_max_skip) or
+    /// [`set_window`](crate::GroupSession::set_window) allows. Nothing about
+    /// the session changed.
+    TooManySkipped(u32),
+    /// A signature didn't verify, see [`ecies::unsigncrypt`](crate::ecies::unsigncrypt).
+    InvalidSignature,
+    /// A payload was signed by someone other than the sender expected, see
+    /// [`ecies::unsigncrypt`](crate::ecies::unsigncrypt).
+    UnexpectedSender,
+    /// The peer's public key wasn't signed by an identity this session
+    /// trusts, or the signature didn't verify, see
+    /// [`Session::set_sym_key_signed`].
+    UntrustedIdentity,
+    /// A [`SecretProvider`] couldn't use its key.
+    ProviderFailure(ProviderError),
+    /// A secret key given to [`Session::reconstruct`] isn't one.
+    InvalidSecret,
+    EmptySecret,
+    /// The session was used before [`Session::set_sym_key`] succeeded.
+    NotReady,
+    /// [`Session::set_sym_key`] was called on a session that's already keyed,
+    /// with a different peer key than the one it was keyed with.
+    AlreadyReady,
+    /// The record is from the key epoch before this one and its drain window
+    /// is over, see [`Session::renegotiate`].
+    StaleEpoch,
+    /// The record authenticated but isn't the next one the peer sent, in an
+    /// [ordered](crate::SessionBuilder::ordered) session. Nothing about the
+    /// session changed.
+    OutOfOrder { expected: u64, received: u64 },
+    /// The record was accepted already, see [`Session::set_dedup_capacity`].
+    /// Nothing about the session changed.
+    DuplicateMessage,
+    /// The record is a valid [`Session::heartbeat`] rather than a message,
+    /// and was counted as received. Use [`Session::receive`] to take
+    /// heartbeats as they are.
+    Heartbeat,
+    /// The record is a valid close record from the peer, and the session
+    /// accepts nothing more. Use [`Session::receive`] to take it as
+    /// [`Received::Closed`].
+    PeerClosed(CloseReason),
+    /// The record is a valid [`Session::send_rekey`] record, and the
+    /// receive key has moved on. Use [`Session::receive`] to take it as
+    /// [`Received::Rekeyed`].
+    Rekeyed,
+    /// This side of the session was closed, by [`Session::close`] for
+    /// sending or by the peer's close record for receiving.
+    Closed,
+    /// A key of the wrong length was handed to the record cipher, the length
+    /// is given.
+    InvalidKeyLength(usize),
+    /// A chunked stream ended without its terminating chunk.
+    TruncatedStream,
+    /// This many bytes came after a chunked stream's terminating chunk.
+    TrailingData(usize),
+    /// [`StreamEncryptor::update_aad`](crate::StreamEncryptor::update_aad)
+    /// or its decryptor's was called after the stream's data had started.
+    AadAfterData,
+    /// A frame with a [hidden length](crate::SessionBuilder::hide_lengths)
+    /// didn't authenticate, or its length was over
+    /// [`SessionConfig::max_record_len`], so the stream can't be read any
+    /// further. The session receives nothing more.
+    FrameDesync,
+    /// The session has sent [`SessionConfig::rekey_after`] records and must be
+    /// rekeyed before sending more, see [`Session::renegotiate`].
+    ///
+    /// This caps how much one key seals, it isn't what keeps nonces apart:
+    /// the nonce is the record's MAC. An [ordered](crate::SessionBuilder::ordered)
+    /// record has the count under its MAC, so the cap also keeps any count
+    /// from being sealed twice, but an unordered session doesn't put the
+    /// count in its records at all.
+    CounterExhausted,
+    /// The plaintext is longer than [`SessionConfig::max_message_len`], the
+    /// length is given.
+    MessageTooLarge(usize),
+    /// Too many records failed authentication recently, see
+    /// [`FailurePolicy`](crate::FailurePolicy). The record wasn't looked at.
+    TooManyFailures,
+    /// A value couldn't be serialized, or a record's plaintext deserialized,
+    /// by `Session::encrypt_serde` or `decrypt_serde` (`serde` feature).
+    Serialization(String),
+    /// An [`Envelope`](crate::Envelope)'s metadata is longer than
+    /// [`MAX_ENVELOPE_METADATA_LEN`](crate::MAX_ENVELOPE_METADATA_LEN), the
+    /// length is given. Nothing was encrypted.
+    MetadataTooLarge(usize),
+    /// A record's plaintext authenticated but isn't an
+    /// [`Envelope`](crate::Envelope).
+    MalformedEnvelope,
+    /// The record was refused, for a reason kept from the caller, see
+    /// [`Session::set_opaque_errors`].
+    Refused,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for SessionError {
+    fn from(e: std::io::Error) -> Self {
+        SessionError::Io(e)
+    }
+}
+
+// k256 doesn't say what was wrong with a point, and it's always the peer's
+impl From<k256::elliptic_curve::Error> for SessionError {
+    fn from(_: k256::elliptic_curve::Error) -> Self {
+        SessionError::InvalidPubKey
+    }
+}
+
+impl From<InvalidCipherParams> for SessionError {
+    fn from(e: InvalidCipherParams) -> Self {
+        SessionError::InvalidKeyLength(e.key_len)
+    }
 }
 
 impl Session {
     /// Creates a new session with a random ephemeral secret using provided RNG.
     pub fn new(rng: &mut (impl CryptoRng + RngCore)) -> Session {
+        Session::generate(rng)
+    }
+
+    /// Creates two sessions and completes the key exchange between them, so
+    /// both are returned ready to talk to each other.
+    ///
+    /// This is only appropriate when both endpoints live in the same process
+    /// (tests, local IPC). Across a network each side must create its own
+    /// session and exchange public keys, otherwise one party holds both
+    /// secrets.
+    pub fn pair(rng: &mut (impl CryptoRng + RngCore)) -> (Session, Session) {
+        let mut first = Session::new(rng);
+        let mut second = Session::new(rng);
+
+        let first_pk = first.pk().expect("fresh session has a secret");
+        let second_pk = second.pk().expect("fresh session has a secret");
+
+        first.set_sym_key(&second_pk).expect("freshly generated key is valid");
+        second.set_sym_key(&first_pk).expect("freshly generated key is valid");
+
+        (first, second)
+    }
+
+    // Session::pair for tests of what an unordered session takes: records
+    // dropped, replayed, or opened in another order than they were sent
+    #[cfg(test)]
+    pub(crate) fn unordered_pair() -> (Session, Session) {
+        Session::pair_with(crate::SessionBuilder::new().ordered(false))
+    }
+
+    // Session::pair for tests, with both sessions built from `builder`
+    #[cfg(test)]
+    pub(crate) fn pair_with(builder: crate::SessionBuilder) -> (Session, Session) {
+        let mut first = builder.clone().build(&mut rand::thread_rng()).unwrap();
+        let mut second = builder.build(&mut rand::thread_rng()).unwrap();
+        let (first_pk, second_pk) = (first.pk().unwrap(), second.pk().unwrap());
+        first.set_sym_key(&second_pk).unwrap();
+        second.set_sym_key(&first_pk).unwrap();
+        (first, second)
+    }
+
+    /// A session with an ephemeral secret generated elsewhere, on another
+    /// thread or by an [`EphemeralPool`](crate::EphemeralPool), instead of
+    /// one of its own. The secret is moved in, so it can't key a second
+    /// session: forward secrecy depends on that.
+    pub fn with_ephemeral(secret: EphemeralSecret) -> Session {
+        let public = EncodedPoint::from(secret.public_key());
+        Session::from_secret(Some((secret, public)))
+    }
+
+    /// This session's public key, to send the peer, see [`Session::pk`].
+    /// There isn't one once the session is keyed.
+    pub fn public_key(&self) -> Result<&EncodedPoint, SessionError> {
+        match self.secret.as_ref() {
+            Some((_, public)) => Ok(public),
+            None => Err(SessionError::EmptySecret),
+        }
+    }
+
+    /// [`Session::public_key`] as uncompressed SEC1, `0x04 || x || y`.
+    pub fn public_key_sec1_uncompressed(&self) -> Result<[u8; 65], SessionError> {
+        let public = k256::PublicKey::from_sec1_bytes(self.public_key()?.as_bytes())?;
+        Ok(public.to_encoded_point(false).as_bytes().try_into().unwrap())
+    }
+
+    /// [`Session::public_key`] as compressed SEC1, `0x02 or 0x03 || x`, about
+    /// half the size on the wire.
+    pub fn public_key_sec1_compressed(&self) -> Result<[u8; 33], SessionError> {
+        let public = k256::PublicKey::from_sec1_bytes(self.public_key()?.as_bytes())?;
+        Ok(public.to_encoded_point(true).as_bytes().try_into().unwrap())
+    }
+
+    /// Lowercase hex of [`Session::public_key_sec1_compressed`], for config
+    /// files and the like. [`PublicKeyBytes::from_hex`](crate::PublicKeyBytes::from_hex)
+    /// reads it back.
+    pub fn public_key_hex(&self) -> Result<String, SessionError> {
+        Ok(to_hex(&self.public_key_sec1_compressed()?))
+    }
+
+    /// The client's first handshake message, as the `tokio` feature's
+    /// `handshake::client` sends it, offering every record version this
+    /// build speaks. It's made from the session's key pair, which only
+    /// [`Session::set_sym_key`] uses up, so over a transport that loses
+    /// messages call it again to retransmit: every retry is the same bytes
+    /// under the same key, and whichever copy the server answers, this
+    /// session can take the answer. [`SessionError::EmptySecret`] once keyed.
+    pub fn initiate(&self) -> Result<Vec<u8>, SessionError> {
+        self.hello(&[record::VERSION, record::V2, record::V1])
+    }
+
+    pub(crate) fn hello(&self, versions: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let pk = self.signed_public_key()?;
+        Ok([&self.config().to_bytes()[..], &[versions.len() as u8], versions, &pk].concat())
+    }
+
+    // a ready session with a fixed key both ways, for test vectors and keys
+    // that don't come from a key exchange. their records are laid out once
+    // and for all, so they're unordered whatever the default
+    pub(crate) fn with_key(key: [u8; 32]) -> Session {
+        let mut session = Session::from_secret(None);
+        session.config.ordered = false;
+        session.send_key = key;
+        session.recv_key = key;
+        session.lengths = LengthMasks::new(&key, &key);
+        session.ready = true;
+        session
+    }
+
+    /// Re-runs [`Session::set_sym_key`]'s derivation from a stored secret key
+    /// and the peer's public key, giving the session as it was just after
+    /// keying, for replaying a captured conversation. `context` is what the
+    /// keys were bound to besides the public keys: empty for sessions keyed
+    /// with `set_sym_key`, or the [`SessionConfig::suite_id`] if the session
+    /// was built with [`bind_suite`](crate::SessionBuilder::bind_suite). The
+    /// session is of the current record version, use a build of the version
+    /// the conversation was in to replay an older one.
+    ///
+    /// Only with the `forensics` feature: keeping ephemeral secrets around
+    /// to call this undoes forward secrecy. The copy of the secret made here
+    /// is zeroed, `my_secret_bytes` itself is the caller's to zero.
+    #[cfg(feature = "forensics")]
+    pub fn reconstruct(my_secret_bytes: &[u8], peer_pk: &EncodedPoint, context: &[u8]) -> Result<Session, SessionError> {
+        let secret = zeroize::Zeroizing::new(my_secret_bytes.to_vec());
+        // k256 zeroes its secret keys on drop
+        let secret = k256::SecretKey::from_be_bytes(&secret).map_err(|_| SessionError::InvalidSecret)?;
+        let peer = k256::PublicKey::from_sec1_bytes(peer_pk.as_ref())?;
+
+        let ours = Secp256k1::canonical(&EncodedPoint::from(secret.public_key()))?;
+        let theirs = Secp256k1::canonical(peer_pk)?;
+        let role = pick_role(&ours, &theirs)?;
+        let shared = k256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), peer.as_affine());
+
+        let mut session = Session::from_secret(None);
+        let transcript = bind_public_keys(session.version, context, role, &ours, &theirs);
+        session.install_keys(role, shared.raw_secret_bytes(), &transcript);
+        session.peer = Some(*peer_pk);
+        session.peer_key = Some(theirs);
+        Ok(session)
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Like [`Session::new`], for any key exchange.
+    pub fn generate(rng: &mut (impl CryptoRng + RngCore)) -> Session<K> {
+        Session::from_secret(Some(K::generate(rng)))
+    }
+
+    pub(crate) fn from_secret(secret: Option<(K::Private, K::Public)>) -> Session<K> {
         Session {
             ready: false,
-            secret: Some(EphemeralSecret::random(rng)),
-            key: [0; 32],
-            xcc20: XC220::new(&[0; 32], &[0; 24]),
-            b3: Hasher::new(),
+            sent: 0,
+            received: 0,
+            auth_failures: 0,
+            on_auth_failure: None,
+            failures: FailureWindow::new(),
+            random: random::default_source(),
+            seen: TagCache::default(),
+            config: SessionConfig::default(),
+            log_policy: LogPolicy::default(),
+            opaque_errors: false,
+            secret,
+            peer: None,
+            peer_key: None,
+            peer_identity: None,
+            identity: None,
+            trusted_identities: Vec::new(),
+            peer_identity_key: None,
+            role: None,
+            send_key: [0; 32],
+            recv_key: [0; 32],
+            cipher: &XCHACHA20_BLAKE3,
+            kdf: &BLAKE3_KDF,
+            suite_binding: None,
+            version: record::VERSION,
+            session_id: [0; 16],
+            epoch: 0,
+            epoch_sent: 0,
+            recv_epoch: 0,
+            previous: None,
+            send_closed: false,
+            recv_closed: false,
+            tokens: AtomicU64::new(0),
+            transcript: Transcript::default(),
+            lengths: LengthMasks::default(),
         }
     }
 
-    /// Sets the symmetric key for this session with the provided public key.
+    /// Sets the symmetric keys for this session with the provided public key.
     /// Once this is called to success, we're ready to encrypt/decrypt.
-    pub fn set_sym_key(&mut self, pk: &EncodedPoint) -> Result<(), SessionError> {
+    ///
+    /// Each direction gets its own key, so a record can't be reflected back
+    /// at its sender. From record version 3 the keys are bound to both
+    /// public keys as well as the shared secret, so another pair of keys
+    /// that comes to the same secret doesn't come to the same session.
+    ///
+    /// Calling it again with the key the session was keyed with, say for a
+    /// retransmitted handshake message, succeeds and changes nothing. Any
+    /// other key is [`SessionError::AlreadyReady`].
+    pub fn set_sym_key(&mut self, pk: &K::Public) -> Result<(), SessionError> {
         if self.ready {
-            panic!("Session already ready");
+            return match (&self.peer_key, K::canonical(pk)) {
+                (Some(peer_key), Ok(theirs)) if *peer_key == theirs => Ok(()),
+                _ => Err(SessionError::AlreadyReady),
+            };
         }
 
         cfg_if!(
             if #[cfg(feature = "tracing")] {
-                let span = info_span!("set_sym_key");
+                let start = Instant::now();
+                let span = match self.log_policy {
+                    LogPolicy::Off => Span::none(),
+                    _ => info_span!("set_sym_key", peer = %self.peer_label(K::canonical(pk).ok().as_deref())),
+                };
                 let _enter = span.enter();
             }
         );
 
-        let pk = match PublicKey::from_sec1_bytes(pk.as_ref()) {
-            Ok(pk) => pk,
-            Err(_) => return Err(SessionError::InvalidPubKey)
+        let theirs = K::canonical(pk)?;
+        let (secret, public) = match self.secret.as_ref() {
+            Some(secret) => secret,
+            None => return Err(SessionError::EmptySecret),
         };
-        let shared = {
-            let this = self.secret.as_ref();
-            match this {
-                Some(val) => val,
-                None => return Err(SessionError::EmptySecret),
-            }
-        }.diffie_hellman(&pk);
-        let shared_bytes = shared.raw_secret_bytes();
-
-        self.b3.update(shared_bytes);
-        self.key = self.b3.finalize().as_bytes().clone();
-        self.b3.reset();
-        self.xcc20 = XC220::new(&self.key, &[0; 24]);
-        #[cfg(feature = "tracing")]
-        trace!("key: {}***{}", to_hex(&self.key[0..2]), to_hex(&self.key[30..32]));
-        self.ready = true;
+        // compared canonically encoded, so it doesn't matter how either side
+        // encoded its key on the wire
+        let ours = K::canonical(public)?;
+        let role = pick_role(&ours, &theirs)?;
+        let shared = kex::agree::<K>(secret, pk)?;
+        let suite_id = self.suite_binding;
+        let transcript = bind_public_keys(self.version, suite_id.as_ref().map_or(&[], |id| &id[..]), role, &ours, &theirs);
+        self.install_keys(role, shared.as_bytes(), &transcript);
+        self.peer = Some(pk.clone());
+        self.peer_key = Some(theirs);
+        session_trace!(self, elapsed_us = start.elapsed().as_micros() as u64, "done");
         self.secret = None;
         Ok(())
     }
 
-    pub fn encrypt(&mut self, plain: Vec<u8>) -> Vec<u8> {
-        if !self.ready {
-            panic!("session not ready!")
+    // the derivation every way of keying a session goes through
+    pub(crate) fn install_keys(&mut self, role: Role, shared: &[u8], transcript: &[u8]) {
+        self.derive_keys(role, shared, transcript);
+        self.lengths = LengthMasks::new(&self.send_key, &self.recv_key);
+        self.session_id = session_id(shared, transcript);
+        self.role = Some(role);
+        self.ready = true;
+    }
+
+    // both directions' keys, all a renegotiation changes
+    pub(crate) fn derive_keys(&mut self, role: Role, shared: &[u8], transcript: &[u8]) {
+        let (send_label, recv_label) = match role {
+            Role::Initiator => (INITIATOR_TO_RESPONDER, RESPONDER_TO_INITIATOR),
+            Role::Responder => (RESPONDER_TO_INITIATOR, INITIATOR_TO_RESPONDER),
         };
+        self.kdf.derive(shared, transcript, send_label, &mut self.send_key);
+        self.kdf.derive(shared, transcript, recv_label, &mut self.recv_key);
+    }
+
+    /// Sets how much this session reveals in its tracing output, see
+    /// [`LogPolicy`]. Has no effect without the `tracing` feature.
+    pub fn set_log_policy(&mut self, policy: LogPolicy) {
+        self.log_policy = policy;
+    }
+
+    pub fn log_policy(&self) -> LogPolicy {
+        self.log_policy
+    }
+
+    /// Makes every refused record, whether too short, of the wrong version,
+    /// a replay or tampered with, fail as [`SessionError::Refused`], and only
+    /// after the work of authenticating a record of its length, so neither
+    /// the error nor how long it took tells a peer on the wire which check
+    /// failed. Off by default.
+    ///
+    /// The cost is in diagnosing: the caller can no longer tell a harmless
+    /// retransmission from an attack, and a record that's refused early
+    /// costs as much as one that fails its MAC. The cause still goes to the
+    /// tracing output unless the [`LogPolicy`] is `Off`, which stays local.
+    /// Errors that come from this side's own state, like
+    /// [`SessionError::NotReady`], [`SessionError::Closed`] or
+    /// [`SessionError::TooManyFailures`], are left as they are.
+    pub fn set_opaque_errors(&mut self, opaque: bool) {
+        self.opaque_errors = opaque;
+    }
+
+    pub fn opaque_errors(&self) -> bool {
+        self.opaque_errors
+    }
+
+    /// `None` until [`Session::set_sym_key`] succeeds.
+    pub fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    /// The public key this session was keyed with, as the peer sent it.
+    /// `None` until [`Session::set_sym_key`] succeeds, and for sessions that
+    /// weren't keyed from a peer's key.
+    pub fn peer_public_key(&self) -> Option<&K::Public> {
+        self.peer.as_ref()
+    }
+
+    /// Names the peer without giving its key, the first 8 bytes of the
+    /// BLAKE3 hash of its canonically encoded public key. It's what
+    /// [`LogPolicy::Redacted`] logs call the peer.
+    pub fn peer_fingerprint(&self) -> Option<[u8; 8]> {
+        self.peer_key.as_deref().map(fingerprint)
+    }
+
+    /// The certificate the peer presented in a `handshake::server`, which
+    /// its verifier accepted. `None` before then, and for clients, since
+    /// servers don't present one.
+    pub fn peer_identity(&self) -> Option<&[u8]> {
+        self.peer_identity.as_deref()
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// Identifies this connection in logs and metrics, the same on both
+    /// peers. It comes from the shared secret, so nobody who wasn't a party
+    /// to the key exchange can tell which connection an id belongs to, and
+    /// it's never used as key material. It's set once, when the session is
+    /// keyed, and nothing after that changes it. All zeros until then.
+    pub fn session_id(&self) -> [u8; 16] {
+        self.session_id
+    }
+
+    /// The record format version this session seals with, and the only one
+    /// it opens. The newest this build speaks unless a
+    /// [`handshake`](crate::handshake) settled on an older one.
+    pub fn negotiated_version(&self) -> u8 {
+        self.version
+    }
+
+    // the suite id carries the version, so a bound session follows it
+    pub(crate) fn set_version(&mut self, version: u8) {
+        debug_assert!(record::is_supported(version));
+        self.version = version;
+        if let Some(suite_id) = self.suite_binding.as_mut() {
+            suite_id[0] = version;
+        }
+    }
+
+    /// Length of the record this session makes from `plaintext_len` bytes:
+    /// the header, and whatever nonce and tag its cipher adds. It only
+    /// depends on the configuration, nothing is encrypted.
+    pub fn ciphertext_len(&self, plaintext_len: usize) -> usize {
+        plaintext_len.saturating_add(self.config.header_len() + self.cipher.overhead())
+    }
+
+    /// How many more records can be sealed before [`SessionError::CounterExhausted`],
+    /// to renegotiate (see [`Session::renegotiate`]) ahead of it rather
+    /// than have a send fail. Heartbeats and close records count too. Zero
+    /// once the session is closed for sending.
+    pub fn messages_remaining(&self) -> u64 {
+        if self.send_closed {
+            return 0;
+        }
+        let until_rekey = self.config.rekey_after.saturating_sub(self.sent - self.epoch_sent);
+        until_rekey.min(u64::MAX - self.sent)
+    }
+
+    /// Registers `hook` to be called whenever a record fails authentication,
+    /// just before the [`SessionError::MacMismatch`] is returned. The error is
+    /// returned regardless. Replaces any previously registered hook.
+    pub fn on_auth_failure(&mut self, hook: impl Fn(&AuthFailure) + Send + Sync + 'static) {
+        self.on_auth_failure = Some(Arc::new(hook));
+    }
 
+    pub fn encrypt(&mut self, plain: Vec<u8>) -> Result<Vec<u8>, SessionError> {
         cfg_if!(
             if #[cfg(feature = "tracing")] {
-                let span = info_span!("encrypt");
+                let span = self.seal_span("data", plain.len());
                 let _enter = span.enter();
             }
         );
 
-        #[cfg(feature = "tracing")]
-        trace!("start");
-        let mac = self.mac(&plain);
-        #[cfg(feature = "tracing")]
-        trace!("MAC: {}", mac.to_hex());
+        self.seal(&plain, &[])
+    }
 
+    /// Encrypts the concatenation of `parts` (a header and a body, say)
+    /// without concatenating them first. The record is identical to
+    /// encrypting the joined plaintext, however it's split.
+    pub fn encrypt_vectored(&mut self, parts: &[&[u8]]) -> Result<Vec<u8>, SessionError> {
+        self.encrypt_vectored_with_aad(parts, &[])
+    }
+
+    /// [`Session::encrypt_vectored`] with `aad` bound under the MAC but not
+    /// sent, for [`Session::decrypt_with_aad`] on the other side to be
+    /// given the same. An empty `aad` is the same as none.
+    pub fn encrypt_vectored_with_aad(&mut self, parts: &[&[u8]], aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("data", parts.iter().map(|part| part.len()).sum());
+                let _enter = span.enter();
+            }
+        );
+
+        self.seal_parts(parts, aad)
+    }
+
+    /// [`Session::encrypt`] with `aad` bound under the MAC but not sent, see
+    /// [`Session::encrypt_vectored_with_aad`]. Empty `aad` is how
+    /// [`Session::encrypt`] seals, so the two give the same record.
+    pub fn encrypt_with_aad(&mut self, plain: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        self.encrypt_vectored_with_aad(&[&plain], aad)
+    }
+
+    pub fn decrypt(&mut self, ciphertext: Vec<u8>) -> Result<Vec<u8>, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.open_span("data", ciphertext.len());
+                let _enter = span.enter();
+            }
+        );
+
+        self.open(ciphertext, &[])
+    }
+
+    /// [`Session::encrypt`] into `out` rather than a new `Vec`, giving the
+    /// length of the record written to its start. The record is identical.
+    /// `out` must be at least [`Session::ciphertext_len`] long, if it isn't
+    /// the error is [`SessionError::BufferTooSmall`] and nothing is sent.
+    ///
+    /// This and [`Session::decrypt_into`] don't allocate, unless a tracing
+    /// subscriber is recording their spans.
+    pub fn encrypt_into(&mut self, plain: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("data", plain.len());
+                let _enter = span.enter();
+            }
+        );
+
+        let record_len = self.ciphertext_len(plain.len());
+        if out.len() < record_len {
+            return Err(SessionError::BufferTooSmall(record_len));
+        }
+        let header = self.next_header(self.version, plain.len(), 0)?;
+        let front = self.wire_header(&header);
+        out[..front.len()].copy_from_slice(front);
+        let aad = record::bare_authenticated_data(&header, plain.len());
+        self.cipher.seal_into(&self.send_key, plain, &aad, &mut out[front.len()..record_len]);
+        self.transcript.sent(&out[record_len - self.cipher.tag_len()..record_len]);
         #[cfg(feature = "tracing")]
-        trace!("allocating for {}byte output", plain.len());
-        let mut output: Vec<u8> = repeat(0).take(plain.len()).collect();
-        #[cfg(feature = "tracing")]
-        trace!("encrypting");
-        self.xcc20 = XC220::new(&self.key, mac.as_bytes());
-        self.xcc20.process(&plain[..], &mut output[..]);
-        #[cfg(feature = "tracing")]
-        trace!("extending with mac");
-        output.extend_from_slice(mac.as_bytes());
-        #[cfg(feature = "tracing")]
-        trace!("done");
-        output
+        self.label_record(&out[record_len - self.cipher.tag_len()..record_len]);
+        Ok(record_len)
     }
 
-    pub fn decrypt(&mut self, mut ciphertext: Vec<u8>) -> Result<Vec<u8>, SessionError> {
-        if !self.ready {
-            panic!("session not ready!")
+    /// [`Session::decrypt`] into `out` rather than a new `Vec`, giving the
+    /// length of the plaintext written to its start. `out` must be at least
+    /// the record's length less its overhead, if it isn't the error is
+    /// [`SessionError::BufferTooSmall`] and the record isn't looked at. If
+    /// the record is refused for any reason, whatever was written to `out`
+    /// is zeroed again.
+    ///
+    /// The one allocation a refused or accepted record can still cause is
+    /// the [duplicate cache](Session::set_dedup_capacity)'s, when it's on.
+    pub fn decrypt_into(&mut self, record: &[u8], out: &mut [u8]) -> Result<usize, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.open_span("data", record.len());
+                let _enter = span.enter();
+            }
+        );
+
+        let plain_len = record.len().saturating_sub(self.ciphertext_len(0));
+        if out.len() < plain_len {
+            return Err(SessionError::BufferTooSmall(plain_len));
+        }
+        let out = &mut out[..plain_len];
+        let result = match self.open_record_into(record, &[], Some(&mut *out)) {
+            Ok((Received::Data(_), message_len)) => Ok(message_len),
+            Ok((Received::Heartbeat, _)) => Err(SessionError::Heartbeat),
+            Ok((Received::Closed(reason), _)) => Err(SessionError::PeerClosed(reason)),
+            Ok((Received::Rekeyed, _)) => Err(SessionError::Rekeyed),
+            Err(e) => Err(e),
         };
+        match result {
+            Ok(message_len) => out[message_len..].fill(0),
+            Err(_) => out.fill(0),
+        }
+        result
+    }
 
+    /// [`Session::encrypt`] with `id` sealed in alongside `plain`, for
+    /// systems that give every message a unique id already. The tag, which
+    /// is the cipher's nonce, is keyed BLAKE3 over the id and the plaintext,
+    /// so as long as ids don't repeat neither do nonces, even for the same
+    /// plaintext. Nothing random is needed.
+    ///
+    /// The id is encrypted and authenticated with the message, the receiver
+    /// needs only [`Session::decrypt`], which checks and strips it. It counts
+    /// towards [`SessionConfig::max_message_len`], and can be at most 65535
+    /// bytes. Version 1 sessions can't send these, the flag marking them
+    /// would be outside the MAC.
+    pub fn encrypt_with_id(&mut self, plain: Vec<u8>, id: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if self.version == record::V1 {
+            return Err(SessionError::UnsupportedVersion(record::V1));
+        }
+        let id_len = u16::try_from(id.len()).map_err(|_| SessionError::MessageTooLarge(id.len()))?;
         cfg_if!(
             if #[cfg(feature = "tracing")] {
-                let span = info_span!("decrypt");
+                let span = self.seal_span("data", plain.len());
                 let _enter = span.enter();
             }
         );
 
-        #[cfg(feature = "tracing")]
-        trace!("start");
+        self.seal_record(&[&id_len.to_le_bytes(), id, &plain], &[], record::ID_BIT)
+    }
 
-        let claimed_mac = MAC::from(ciphertext.split_off(ciphertext.len() - 24));
-        #[cfg(feature = "tracing")]
-        trace!("allocating for {}byte output", ciphertext.len());
-        let mut output: Vec<u8> = repeat(0).take(ciphertext.len()).collect();
-        #[cfg(feature = "tracing")]
-        trace!("creating new chacha");
-        self.xcc20 = XC220::new(&self.key, claimed_mac.as_bytes());
-        #[cfg(feature = "tracing")]
-        trace!("encrypting");
-        self.xcc20.process(&ciphertext[..], &mut output[..]);
+    /// [`Session::encrypt`] in record format `version` rather than the
+    /// [negotiated](Session::negotiated_version) one, for a sender rolling a
+    /// format out to receivers that don't all speak it yet. The record is
+    /// counted and keyed like any other, only its format differs, and a
+    /// receiver opens it only if that's the version it speaks. A version
+    /// this build doesn't speak is [`SessionError::UnsupportedVersion`].
+    pub fn encrypt_as(&mut self, plain: Vec<u8>, version: u8) -> Result<Vec<u8>, SessionError> {
+        if !record::is_supported(version) {
+            return Err(SessionError::UnsupportedVersion(version));
+        }
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("data", plain.len());
+                let _enter = span.enter();
+            }
+        );
 
-        #[cfg(feature = "tracing")]
-        trace!("calculating our own mac");
-        let calculated_mac = self.mac(&output);
-        #[cfg(feature = "tracing")]
-        trace!("checking mac");
-        if claimed_mac != calculated_mac {
-            #[cfg(feature = "tracing")]
-            trace!("Claimed MAC: {}", claimed_mac.to_hex());
-            #[cfg(feature = "tracing")]
-            trace!("Calculated MAC: {}", calculated_mac.to_hex());
-            return Err(SessionError::MacMismatch);
-        } else {
-            #[cfg(feature = "tracing")]
-            trace!("mac good 👍");
+        self.seal_record_as(version, &[&plain], &[], 0)
+    }
+
+    /// Decrypts a record from [`Session::encrypt_vectored_with_aad`], which
+    /// only opens with the `aad` it was sealed with.
+    pub fn decrypt_with_aad(&mut self, ciphertext: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.open_span("data", ciphertext.len());
+                let _enter = span.enter();
+            }
+        );
+
+        self.open(ciphertext, aad)
+    }
+
+    /// Seals an empty record marked as a heartbeat, to show the session is
+    /// alive without sending a message. It's counted like any other record,
+    /// and the peer's [`Session::receive`] tells it apart from an empty
+    /// message. Version 1 sessions can't send them, the flag would be outside
+    /// the MAC.
+    pub fn heartbeat(&mut self) -> Result<Vec<u8>, SessionError> {
+        if self.version == record::V1 {
+            return Err(SessionError::UnsupportedVersion(record::V1));
         }
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("heartbeat", 0);
+                let _enter = span.enter();
+            }
+        );
+
+        self.seal_record(&[], &[], record::HEARTBEAT_BIT)
+    }
+
+    /// [`Session::decrypt`], giving heartbeats as [`Received::Heartbeat`]
+    /// rather than an error.
+    pub fn receive(&mut self, ciphertext: Vec<u8>) -> Result<Received, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.open_span("data", ciphertext.len());
+                let _enter = span.enter();
+            }
+        );
+
+        self.open_record(&ciphertext, &[])
+    }
+
+    /// Encrypts `plain` with `aad` bound under the MAC but not included in
+    /// the output. An empty `aad` gives exactly the same output as no `aad`.
+    pub(crate) fn seal(&mut self, plain: &[u8], aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        self.seal_parts(&[plain], aad)
+    }
+
+    /// [`Session::seal`] over the concatenation of `parts`, without making it.
+    pub(crate) fn seal_parts(&mut self, parts: &[&[u8]], aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        self.seal_record(parts, aad, 0)
+    }
+
+    // `flags` are or'd into the header byte
+    pub(crate) fn seal_record(&mut self, parts: &[&[u8]], aad: &[u8], flags: u8) -> Result<Vec<u8>, SessionError> {
+        self.seal_record_as(self.version, parts, aad, flags)
+    }
+
+    fn seal_record_as(&mut self, version: u8, parts: &[&[u8]], aad: &[u8], flags: u8) -> Result<Vec<u8>, SessionError> {
+        let plain_len: usize = parts.iter().map(|part| part.len()).sum();
+        let header = self.next_header(version, plain_len, flags)?;
+
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+        session_trace!(self, "allocating for {}byte output", plain_len);
+        let mut output: Vec<u8> = Vec::with_capacity(self.ciphertext_len(plain_len));
+        output.extend_from_slice(self.wire_header(&header));
+        session_trace!(self, "encrypting");
+        let aad = record::authenticated_data(&header, aad, plain_len);
+        self.cipher.seal(&self.send_key, parts, &aad, &mut output);
+        self.transcript.sent(&output[output.len() - self.cipher.tag_len()..]);
         #[cfg(feature = "tracing")]
-        trace!("done");
+        self.label_record(&output[output.len() - self.cipher.tag_len()..]);
+        session_trace!(self, elapsed_us = start.elapsed().as_micros() as u64, "done");
         Ok(output)
     }
 
-    fn mac(&mut self, plain: &[u8]) -> MAC {
-        if !self.ready {
-            panic!("session not ready!")
+    // checks a record of `plain_len` can be sent, counts it, and gives the
+    // header its tag covers
+    fn next_header(&mut self, version: u8, plain_len: usize, flags: u8) -> Result<record::Header, SessionError> {
+        self.require_ready()?;
+        if self.send_closed {
+            return Err(SessionError::Closed);
+        }
+        if plain_len > self.config.max_message_len {
+            return Err(SessionError::MessageTooLarge(plain_len));
+        }
+
+        // never wrap. an ordered record carries the count under its MAC, and
+        // one sealed twice under a key is a replay the receiver would take.
+        // unordered records don't carry it, and this is only a cap on how
+        // much one key seals. a rekey record may go one past rekey_after,
+        // it's what ends the count
+        self.sent = match self.sent.checked_add(1) {
+            Some(sent) if sent - self.epoch_sent <= self.config.rekey_after || flags == record::REKEY_BITS => sent,
+            _ => return Err(SessionError::CounterExhausted),
+        };
+
+        let mut header = match self.config.obfuscate {
+            true => {
+                let mut salt = [0u8; record::SALT_LEN];
+                self.random.fill(&mut salt);
+                record::obfuscated_header(version, self.epoch, &salt)
+            }
+            false => record::header(version, self.epoch).into_iter().collect(),
         };
+        header[0] |= flags;
+        if self.config.ordered {
+            header.try_extend_from_slice(&self.sent.to_le_bytes()).unwrap();
+        }
+        Ok(header)
+    }
 
-        self.b3.update(plain);
-        self.b3.update(&self.key);
+    // what of `header` goes on the wire in front of the cipher's output
+    fn wire_header<'h>(&self, header: &'h [u8]) -> &'h [u8] {
+        match self.config.obfuscate {
+            // only the salt, the sequence number is implied
+            true => &header[1..1 + record::SALT_LEN],
+            false => header,
+        }
+    }
 
-        let mut reader = self.b3.finalize_xof();
-        let hash = MAC::from_output_reader(&mut reader);
-        self.b3.reset();
-        hash
+    /// Reverses [`Session::seal`], the same `aad` must be given.
+    pub(crate) fn open(&mut self, record: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        self.open_slice(&record, aad)
     }
 
-    pub fn pk(&self) -> Result<EncodedPoint, SessionError> {
-        if self.secret.is_none() {
-            Err(SessionError::EmptySecret)
-        } else {
-            Ok(EncodedPoint::from(self.secret.as_ref().unwrap().public_key()))
+    // the record is only ever read
+    pub(crate) fn open_slice(&mut self, record: &[u8], aad: &[u8]) -> Result<Vec<u8>, SessionError> {
+        match self.open_record(record, aad)? {
+            Received::Data(plain) => Ok(plain),
+            Received::Heartbeat => Err(SessionError::Heartbeat),
+            Received::Closed(reason) => Err(SessionError::PeerClosed(reason)),
+            Received::Rekeyed => Err(SessionError::Rekeyed),
         }
     }
-}
 
-cfg_if! {
-    if #[cfg(feature = "tracing")] {
-        const HEX_CHARS: &[u8] = b"0123456789abcdef";
+    fn open_record(&mut self, record: &[u8], aad: &[u8]) -> Result<Received, SessionError> {
+        self.open_record_into(record, aad, None).map(|(received, _)| received)
+    }
 
-// u8 array to hex string using lookup table
-fn to_hex(bytes: &[u8]) -> String {
-    let mut hex = String::with_capacity(bytes.len() * 2);
-    for byte in bytes {
-        hex.push(HEX_CHARS[(byte >> 4) as usize] as char);
-        hex.push(HEX_CHARS[(byte & 0xf) as usize] as char);
+    // with `out`, which only comes without aad, the message goes to its
+    // start and Received::Data is empty. gives the message's length too
+    fn open_record_into(&mut self, record: &[u8], aad: &[u8], out: Option<&mut [u8]>) -> Result<(Received, usize), SessionError> {
+        let result = self.try_open_record(record, aad, out);
+        #[cfg(feature = "tracing")]
+        if let (Err(e), false) = (&result, self.log_policy == LogPolicy::Off) {
+            // what failed and how long the record was, never its bytes
+            debug!(check = failed_check(e), len = record.len(), "record refused");
+        }
+        match result {
+            Err(e) if self.opaque_errors && refused_by_record(&e) => {
+                if !matches!(e, SessionError::MacMismatch | SessionError::NonceMismatch | SessionError::OutOfOrder { .. }) {
+                    // authentication never ran, so it's done here over as
+                    // many bytes as the record had, to take as long as it
+                    let mut sealed = record.to_vec();
+                    sealed.resize(record.len().max(self.cipher.overhead()), 0);
+                    let _ = core::hint::black_box(self.cipher.open(&vec![0; self.cipher.key_len()], &sealed, aad));
+                }
+                Err(SessionError::Refused)
+            }
+            result => result,
+        }
     }
-    hex
-}
+
+    fn try_open_record(&mut self, record: &[u8], aad: &[u8], mut out: Option<&mut [u8]>) -> Result<(Received, usize), SessionError> {
+        self.require_ready()?;
+        if self.recv_closed {
+            return Err(SessionError::Closed);
+        }
+        self.expire_previous();
+
+        self.failures.check()?;
+        // checked before anything is allocated for it
+        let overhead = self.config.header_len() + self.cipher.overhead();
+        let plain_len = record.len().saturating_sub(overhead);
+        if plain_len > self.config.max_message_len {
+            return Err(SessionError::MessageTooLarge(plain_len));
+        }
+
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+
+        // the header, or the salt of an obfuscated record
+        let (front, sealed) = match self.config.obfuscate {
+            true => record::split_obfuscated(record, self.cipher.overhead())?,
+            false => record::split_header(record, self.version, self.config.header_len(), self.cipher.overhead())?,
+        };
+        let tag = &sealed[sealed.len() - self.cipher.tag_len()..];
+        #[cfg(feature = "tracing")]
+        self.label_record(tag);
+        // the cache only holds tags that verified, so one in it is a record
+        // that was accepted already, and there's no need to open it again.
+        // the clock is read once, here, so a good record doesn't take a read
+        // longer than a bad one
+        let now = self.failures.now();
+        if self.seen.contains(tag, now) {
+            session_trace!(self, "duplicate");
+            return Err(SessionError::DuplicateMessage);
+        }
+        session_trace!(self, "decrypting and checking mac");
+        let expected = self.received + 1;
+        let transcript = self.transcript.next_received(tag);
+        let result = match self.config.obfuscate {
+            // neither the epoch nor the heartbeat, close and rekey flags are
+            // on the wire, so they're found by trying: the current epoch
+            // first, and a flag only on a record of its length. the sequence
+            // number is the expected one
+            true => {
+                let flags: &[u8] = match sealed.len() - self.cipher.overhead() {
+                    0 => &[0, record::HEARTBEAT_BIT],
+                    1 => &[0, record::CLOSE_BIT],
+                    8 => &[0, record::ID_BIT, record::REKEY_BITS],
+                    _ => &[0, record::ID_BIT],
+                };
+                let epochs = core::iter::once((&self.recv_key, self.recv_epoch)).chain(self.previous.iter().map(|previous| (&previous.key, previous.epoch)));
+                let mut result = Err(SessionError::MacMismatch);
+                'found: for (key, epoch) in epochs {
+                    for &flag in flags {
+                        let mut header = record::obfuscated_header(self.version, epoch, front);
+                        header[0] |= flag;
+                        if self.config.ordered {
+                            header.try_extend_from_slice(&expected.to_le_bytes()).unwrap();
+                        }
+                        result = self.open_with(key, &header, sealed, aad, out.as_deref_mut()).map(|plain| (plain, flag, epoch != self.recv_epoch));
+                        if result.is_ok() {
+                            break 'found;
+                        }
+                    }
+                }
+                result
+            }
+            false => {
+                let flags = front[0] & (record::HEARTBEAT_BIT | record::CLOSE_BIT | record::ID_BIT);
+                if flags != 0 && self.version == record::V1 {
+                    return Err(SessionError::UnsupportedVersion(front[0]));
+                }
+                // the bit is under the MAC, so flipping it buys one attempt
+                // under the old key that fails
+                let stale = front[0] & record::EPOCH_BIT != record::epoch_bit(self.recv_epoch);
+                let key = match stale {
+                    false => &self.recv_key,
+                    true => match &self.previous {
+                        Some(previous) => &previous.key,
+                        None => return Err(SessionError::StaleEpoch),
+                    },
+                };
+                self.open_with(key, front, sealed, aad, out.as_deref_mut()).map(|plain| (plain, flags, stale))
+            }
+        };
+        let (mut output, flags, stale) = match result {
+            Ok(output) => output,
+            Err(e) => {
+                session_trace!(self, elapsed_us = start.elapsed().as_micros() as u64, "mac mismatch");
+                self.auth_failures += 1;
+                self.failures.failed();
+                if let Some(hook) = &self.on_auth_failure {
+                    let mut record_id = [0u8; 4];
+                    record_id.copy_from_slice(&tag[..4]);
+                    hook(&AuthFailure {
+                        record_len: record.len(),
+                        seq: self.received + 1,
+                        record_id,
+                        at: SystemTime::now(),
+                        failures: self.auth_failures,
+                    });
+                }
+                return Err(e);
+            }
+        };
+        // one event either way, with the same fields
+        session_trace!(self, elapsed_us = start.elapsed().as_micros() as u64, "mac good 👍");
+        // a message with an id starts with it, the MAC was all there was to
+        // check of it
+        let id_len = match flags & record::ID_BIT {
+            0 => 0,
+            _ => {
+                let plain = match &out {
+                    Some(out) => &out[..],
+                    None => &output[..],
+                };
+                match plain.get(..2).map(|len| 2 + u16::from_le_bytes([len[0], len[1]]) as usize) {
+                    Some(len) if len <= plain.len() => len,
+                    _ => return Err(SessionError::InvalidLength(record.len())),
+                }
+            }
+        };
+        let flags = flags & !record::ID_BIT;
+        if self.config.ordered && !self.config.obfuscate && record::seq(front) != expected {
+            session_trace!(self, "out of order");
+            return Err(SessionError::OutOfOrder { expected, received: record::seq(front) });
+        }
+        if flags == record::REKEY_BITS {
+            let plain = match &out {
+                Some(out) => &out[..],
+                None => &output[..],
+            };
+            self.check_rekey(&plain[id_len..], record.len())?;
+        }
+        // only messages: heartbeats are all alike, and a repeated one (or
+        // close) does no harm
+        if flags == 0 && !self.seen.accept(tag, now) {
+            session_trace!(self, "duplicate");
+            return Err(SessionError::DuplicateMessage);
+        }
+        self.received += 1;
+        self.transcript.received(transcript);
+        self.failures.succeeded();
+        if stale {
+            self.drained_one();
+        }
+        session_trace!(self, elapsed_us = start.elapsed().as_micros() as u64, "done");
+        let message_len = sealed.len() - self.cipher.overhead() - id_len;
+        match flags {
+            0 => {
+                match out {
+                    Some(out) => out.copy_within(id_len.., 0),
+                    None => drop(output.drain(..id_len)),
+                }
+                Ok((Received::Data(output), message_len))
+            }
+            record::HEARTBEAT_BIT => Ok((Received::Heartbeat, 0)),
+            record::REKEY_BITS => {
+                self.rekeyed_by_peer();
+                Ok((Received::Rekeyed, 0))
+            }
+            _ => {
+                self.closed_by_peer();
+                let plain = match &out {
+                    Some(out) => &out[..],
+                    None => &output[..],
+                };
+                Ok((Received::Closed(CloseReason::from_code(plain.first().copied().unwrap_or(0))), 0))
+            }
+        }
+    }
+
+    // into `out` if there is one, which is exactly the plaintext's length,
+    // giving an empty Vec
+    fn open_with(&self, key: &[u8], header: &[u8], sealed: &[u8], aad: &[u8], out: Option<&mut [u8]>) -> Result<Vec<u8>, SessionError> {
+        let plain_len = sealed.len() - self.cipher.overhead();
+        match out {
+            Some(out) => {
+                let aad = record::bare_authenticated_data(header, plain_len);
+                self.cipher.open_into(key, sealed, &aad, out)?;
+                Ok(Vec::new())
+            }
+            None => {
+                let aad = record::authenticated_data(header, aad, plain_len);
+                self.cipher.open(key, sealed, &aad)
+            }
+        }
+    }
+
+    pub(crate) fn require_ready(&self) -> Result<(), SessionError> {
+        match self.ready {
+            true => Ok(()),
+            false => Err(SessionError::NotReady),
+        }
+    }
+
+    // spans never carry key material or plaintext, only sizes, counters and
+    // whatever identifiers the log policy allows
+    #[cfg(feature = "tracing")]
+    pub(crate) fn seal_span(&self, record: &'static str, len: usize) -> Span {
+        match self.log_policy {
+            LogPolicy::Off => Span::none(),
+            _ => info_span!("encrypt", record, len, seq = self.sent + 1, peer = %self.peer_label(self.peer_key.as_deref()),
+                session_id = %to_hex(&self.session_id), record_id = Empty, tag = Empty),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn open_span(&self, record: &'static str, len: usize) -> Span {
+        match self.log_policy {
+            LogPolicy::Off => Span::none(),
+            _ => info_span!("decrypt", record, len, seq = self.received + 1, peer = %self.peer_label(self.peer_key.as_deref()),
+                session_id = %to_hex(&self.session_id), record_id = Empty, tag = Empty),
+        }
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "tracing")]
+    fn label_record(&self, tag: &[u8]) {
+        let span = Span::current();
+        // nobody's listening, so don't format anything
+        if span.is_disabled() {
+            return;
+        }
+        match self.log_policy {
+            LogPolicy::Full => {
+                span.record("record_id", display(to_hex(&tag[..4])));
+                span.record("tag", display(to_hex(tag)));
+            }
+            LogPolicy::Redacted => {
+                span.record("record_id", display(to_hex(&tag[..4])));
+            }
+            LogPolicy::Off => (),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn peer_label(&self, pk: Option<&[u8]>) -> String {
+        match (self.log_policy, pk) {
+            (_, None) => "none".to_string(),
+            (LogPolicy::Full, Some(pk)) => to_hex(pk),
+            (_, Some(pk)) => to_hex(&fingerprint(pk)),
+        }
+    }
+
+    pub fn pk(&self) -> Result<K::Public, SessionError> {
+        match self.secret.as_ref() {
+            Some((_, public)) => Ok(public.clone()),
+            None => Err(SessionError::EmptySecret),
+        }
+    }
+
+    /// Whether `other` is the other end of this session: it sends under the
+    /// key this one receives under and the other way round. Compared in
+    /// constant time, and only the answer comes out, so a handshake
+    /// mismatch can be chased down without printing keys. `false` if either
+    /// isn't keyed. Only with the `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn keys_match<L: KeyExchange>(&self, other: &Session<L>) -> bool {
+        use subtle::ConstantTimeEq;
+        let ours = [&self.send_key[..], &self.recv_key, &self.session_id].concat();
+        let theirs = [&other.recv_key[..], &other.send_key, &other.session_id].concat();
+        self.ready && other.ready && bool::from(ours.ct_eq(&theirs))
+    }
+}
+
+/// Copies the session keys and counters, so a record can be tried against a
+/// clone (say, to find which of several sessions it belongs to) without
+/// touching the original. A hook registered with
+/// [`Session::on_auth_failure`] is shared with the clone.
+///
+/// The ephemeral secret is never copied, so a clone of a session that isn't
+/// ready yet can't be keyed.
+impl<K: KeyExchange> Clone for Session<K> {
+    fn clone(&self) -> Self {
+        Session {
+            ready: self.ready,
+            sent: self.sent,
+            received: self.received,
+            auth_failures: self.auth_failures,
+            on_auth_failure: self.on_auth_failure.clone(),
+            failures: self.failures.clone(),
+            random: self.random.clone(),
+            seen: self.seen.clone(),
+            config: self.config,
+            log_policy: self.log_policy,
+            opaque_errors: self.opaque_errors,
+            secret: None,
+            peer: self.peer.clone(),
+            peer_key: self.peer_key.clone(),
+            peer_identity: self.peer_identity.clone(),
+            identity: self.identity.clone(),
+            trusted_identities: self.trusted_identities.clone(),
+            peer_identity_key: self.peer_identity_key,
+            role: self.role,
+            send_key: self.send_key,
+            recv_key: self.recv_key,
+            cipher: self.cipher,
+            kdf: self.kdf,
+            suite_binding: self.suite_binding,
+            version: self.version,
+            session_id: self.session_id,
+            epoch: self.epoch,
+            epoch_sent: self.epoch_sent,
+            recv_epoch: self.recv_epoch,
+            previous: self.previous.clone(),
+            send_closed: self.send_closed,
+            recv_closed: self.recv_closed,
+            tokens: AtomicU64::new(self.tokens.load(Ordering::Relaxed)),
+            transcript: self.transcript.clone(),
+            lengths: self.lengths.clone(),
+        }
+    }
+}
+
+impl<K: KeyExchange> Drop for Session<K> {
+    fn drop(&mut self) {
+        self.send_key.zeroize();
+        self.recv_key.zeroize();
+    }
+}
+
+fn session_id(shared: &[u8], transcript: &[u8]) -> [u8; 16] {
+    let mut b3 = blake3::Hasher::new_derive_key(SESSION_ID_CONTEXT);
+    b3.update(&(shared.len() as u64).to_le_bytes());
+    b3.update(shared);
+    b3.update(transcript);
+    let mut id = [0u8; 16];
+    b3.finalize_xof().fill(&mut id);
+    id
+}
+
+// what set_sym_key binds its keys to: `context`, then from version 3 both
+// canonical public keys, the initiator's (the lower) first so both sides
+// agree. canonical keys are all one length, so no separator is needed
+fn bind_public_keys(version: u8, context: &[u8], role: Role, ours: &[u8], theirs: &[u8]) -> Vec<u8> {
+    if version < record::V3 {
+        return context.to_vec();
+    }
+    let (initiator, responder) = match role {
+        Role::Initiator => (ours, theirs),
+        Role::Responder => (theirs, ours),
+    };
+    [context, initiator, responder].concat()
+}
+
+// a failure caused by the record itself rather than by this side's state,
+// what opaque errors hide
+fn refused_by_record(e: &SessionError) -> bool {
+    matches!(
+        e,
+        SessionError::InvalidLength(_)
+            | SessionError::MessageTooLarge(_)
+            | SessionError::UnsupportedVersion(_)
+            | SessionError::MacMismatch
+            | SessionError::NonceMismatch
+            | SessionError::DuplicateMessage
+            | SessionError::OutOfOrder { .. }
+            | SessionError::StaleEpoch
+    )
+}
+
+// names the check a record failed, for logs
+#[cfg(feature = "tracing")]
+fn failed_check(e: &SessionError) -> &'static str {
+    match e {
+        SessionError::InvalidLength(_) => "too_short",
+        SessionError::MessageTooLarge(_) => "too_long",
+        SessionError::UnsupportedVersion(_) => "version",
+        SessionError::MacMismatch | SessionError::NonceMismatch => "tag",
+        SessionError::DuplicateMessage | SessionError::OutOfOrder { .. } => "replay",
+        SessionError::StaleEpoch => "stale_epoch",
+        SessionError::TooManyFailures => "too_many_failures",
+        SessionError::NotReady => "not_ready",
+        SessionError::Heartbeat => "heartbeat",
+        SessionError::Closed => "closed",
+        _ => "other",
+    }
+}
+
+// the side with the lower canonical public key initiates
+fn pick_role(ours: &[u8], theirs: &[u8]) -> Result<Role, SessionError> {
+    match ours.cmp(theirs) {
+        core::cmp::Ordering::Less => Ok(Role::Initiator),
+        core::cmp::Ordering::Greater => Ok(Role::Responder),
+        // that's our own key
+        core::cmp::Ordering::Equal => Err(SessionError::InvalidPubKey),
+    }
+}
+
+const HEX_CHARS: &[u8] = b"0123456789abcdef";
+
+// u8 array to hex string using lookup table
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push(HEX_CHARS[(byte >> 4) as usize] as char);
+        hex.push(HEX_CHARS[(byte & 0xf) as usize] as char);
+    }
+    hex
+}
+
+fn fingerprint(pk: &[u8]) -> [u8; 8] {
+    let mut out = [0u8; 8];
+    out.copy_from_slice(&blake3::hash(pk).as_bytes()[..8]);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use k256::{elliptic_curve::sec1::ToEncodedPoint, EncodedPoint};
+    use rand::thread_rng;
+
+    use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use crate::builder::{SessionBuilder, DEFAULT_MAX_MESSAGE_LEN};
+    use crate::record;
+    use crate::session::{bind_public_keys, fingerprint, pick_role, Received, Role, Session, SessionError, REKEY_AFTER_MESSAGES};
+    #[cfg(feature = "tracing")]
+    use crate::session::LogPolicy;
+
+    #[test]
+    fn test_pair_is_ready_both_ways() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+
+        let ciphertext = sesh1.encrypt(b"ping".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(ciphertext).unwrap(), b"ping");
+
+        let ciphertext = sesh2.encrypt(b"pong".to_vec()).unwrap();
+        assert_eq!(sesh1.decrypt(ciphertext).unwrap(), b"pong");
+    }
+
+    #[test]
+    fn test_directions_use_different_keys() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let mut roles = [sesh1.role().unwrap(), sesh2.role().unwrap()];
+        roles.sort_by_key(|role| *role == Role::Responder);
+        assert_eq!(roles, [Role::Initiator, Role::Responder]);
+        assert_ne!(sesh1.send_key, sesh1.recv_key);
+        assert_eq!(sesh1.send_key, sesh2.recv_key);
+
+        // a record reflected back at its sender doesn't verify
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(sesh1.decrypt(record.clone()), Err(SessionError::MacMismatch)));
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+
+        let record = sesh2.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(sesh2.decrypt(record.clone()), Err(SessionError::MacMismatch)));
+        assert_eq!(sesh1.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_roles_ignore_key_encoding() {
+        let mut sesh1 = Session::new(&mut thread_rng());
+        let mut sesh2 = Session::new(&mut thread_rng());
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2.compress()).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+
+        assert_ne!(sesh1.role(), sesh2.role());
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_own_key_is_refused() {
+        let mut sesh = Session::new(&mut thread_rng());
+        let pk = sesh.pk().unwrap();
+        assert!(matches!(sesh.set_sym_key(&pk), Err(SessionError::InvalidPubKey)));
+    }
+
+    #[test]
+    fn test_peer_accessors() {
+        let mut sesh1 = Session::new(&mut thread_rng());
+        let sesh2 = Session::new(&mut thread_rng());
+        assert_eq!((sesh1.peer_public_key(), sesh1.peer_fingerprint(), sesh1.peer_identity()), (None, None, None));
+
+        // kept as given, but fingerprinted canonically
+        let uncompressed = EncodedPoint::from_bytes(sesh2.public_key_sec1_uncompressed().unwrap()).unwrap();
+        sesh1.set_sym_key(&uncompressed).unwrap();
+        assert_eq!(sesh1.peer_public_key(), Some(&uncompressed));
+        assert_eq!(sesh1.peer_fingerprint(), Some(fingerprint(&sesh2.public_key_sec1_compressed().unwrap())));
+        assert_eq!(sesh1.peer_identity(), None);
+        assert_eq!(sesh1.clone().peer_public_key(), Some(&uncompressed));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_keys_match() {
+        let (sesh1, sesh2) = Session::pair(&mut thread_rng());
+        assert!(sesh1.keys_match(&sesh2) && sesh2.keys_match(&sesh1));
+        assert!(!sesh1.keys_match(&sesh1));
+
+        let (sesh3, _) = Session::pair(&mut thread_rng());
+        assert!(!sesh1.keys_match(&sesh3));
+        let unkeyed = Session::new(&mut thread_rng());
+        assert!(!unkeyed.keys_match(&Session::new(&mut thread_rng())));
+        assert!(!sesh1.keys_match(&unkeyed));
+    }
+
+    #[test]
+    fn test_initiate_is_idempotent() {
+        let mut client = Session::new(&mut thread_rng());
+        let first = client.initiate().unwrap();
+        assert_eq!(client.initiate().unwrap(), first);
+        assert!(first.ends_with(&client.public_key_sec1_compressed().unwrap()));
+
+        // the retry keys the same as the original would have
+        let mut server = Session::new(&mut thread_rng());
+        let (client_pk, server_pk) = (EncodedPoint::from_bytes(&first[first.len() - 33..]).unwrap(), server.pk().unwrap());
+        server.set_sym_key(&client_pk).unwrap();
+        client.set_sym_key(&server_pk).unwrap();
+        assert_eq!(client.decrypt(server.encrypt(b"hello".to_vec()).unwrap()).unwrap(), b"hello");
+        assert!(matches!(client.initiate(), Err(SessionError::EmptySecret)));
+    }
+
+    #[test]
+    fn test_repeated_set_sym_key() {
+        let mut sesh1 = Session::new(&mut thread_rng());
+        let mut sesh2 = Session::new(&mut thread_rng());
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+        let keys = (sesh1.send_key, sesh1.recv_key, sesh1.session_id());
+
+        // the same key again, however it's encoded, is a retransmit
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh1.set_sym_key(&pk2.compress()).unwrap();
+        assert_eq!((sesh1.send_key, sesh1.recv_key, sesh1.session_id()), keys);
+
+        let other = Session::new(&mut thread_rng()).pk().unwrap();
+        assert!(matches!(sesh1.set_sym_key(&other), Err(SessionError::AlreadyReady)));
+        assert!(matches!(sesh1.set_sym_key(&pk1), Err(SessionError::AlreadyReady)));
+        assert_eq!((sesh1.send_key, sesh1.recv_key, sesh1.session_id()), keys);
+
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_malformed_key_maps_to_invalid_pub_key() {
+        let mut sesh = Session::new(&mut thread_rng());
+        // x is above the field prime, so this isn't a point at all
+        let bogus = EncodedPoint::from_bytes([&[0x02][..], &[0xff; 32]].concat()).unwrap();
+        assert!(matches!(sesh.set_sym_key(&bogus), Err(SessionError::InvalidPubKey)));
+
+        let err = k256::PublicKey::from_sec1_bytes(&[0x04; 10]).unwrap_err();
+        assert!(matches!(SessionError::from(err), SessionError::InvalidPubKey));
+    }
+
+    #[test]
+    fn test_oversized_records_are_refused() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        assert_eq!(sesh1.config().max_message_len, DEFAULT_MAX_MESSAGE_LEN);
+        assert!(matches!(
+            sesh1.encrypt(vec![0; DEFAULT_MAX_MESSAGE_LEN + 1]),
+            Err(SessionError::MessageTooLarge(len)) if len == DEFAULT_MAX_MESSAGE_LEN + 1
+        ));
+        assert_eq!(sesh1.sent, 0);
+
+        // a receiver with a tighter limit refuses before decrypting
+        sesh2.config.max_message_len = 4;
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert!(matches!(sesh2.decrypt(record), Err(SessionError::MessageTooLarge(5))));
+        assert_eq!(sesh2.stats().auth_failures, 0);
+        let record = sesh1.encrypt(b"hi".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn test_speculative_decrypt_on_clones() {
+        let mut ours = Vec::new();
+        let mut theirs = Vec::new();
+        for _ in 0..4 {
+            let (sesh1, sesh2) = Session::pair(&mut thread_rng());
+            ours.push(sesh1);
+            theirs.push(sesh2);
+        }
+        let record = theirs[2].encrypt(b"hello".to_vec()).unwrap();
+
+        // which session is this for? ask clones, so failed guesses leave
+        // no trace on the real sessions
+        let found: Vec<usize> = (0..ours.len())
+            .filter(|&i| ours[i].clone().decrypt(record.clone()).is_ok())
+            .collect();
+        assert_eq!(found, [2]);
+        for sesh in &ours {
+            assert_eq!((sesh.stats().received, sesh.stats().auth_failures), (0, 0));
+        }
+
+        // a clone carries on independently of the original
+        let mut clone = ours[2].clone();
+        assert_eq!(clone.decrypt(record.clone()).unwrap(), b"hello");
+        assert_eq!(clone.stats().received, 1);
+        assert_eq!(ours[2].stats().received, 0);
+        assert_eq!(ours[2].decrypt(record).unwrap(), b"hello");
+    }
+
+    // the trait's method is only picked if there's no inherent one, so this
+    // wouldn't compile if the export were in a default build
+    #[cfg(not(feature = "dangerous-debug"))]
+    #[test]
+    fn test_raw_key_export_is_absent() {
+        trait Absent {
+            fn export_raw_keys(&self) -> &'static str {
+                "absent"
+            }
+        }
+        impl<T> Absent for T {}
+        assert_eq!(Session::with_key([9; 32]).export_raw_keys(), "absent");
+    }
+
+    #[cfg(feature = "forensics")]
+    #[test]
+    fn test_reconstructed_session_decrypts_prior_records() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // the secret as it would have been kept: drawn from the same rng state
+        let mut sesh1 = Session::new(&mut StdRng::seed_from_u64(7));
+        let secret = k256::SecretKey::random(&mut StdRng::seed_from_u64(7));
+        assert_eq!(EncodedPoint::from(secret.public_key()), sesh1.pk().unwrap());
+
+        let mut sesh2 = Session::new(&mut thread_rng());
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+        let captured: Vec<_> = [&b"first"[..], b"second"].iter().map(|m| sesh2.encrypt(m.to_vec()).unwrap()).collect();
+        let sent = sesh1.encrypt(b"reply".to_vec()).unwrap();
+
+        let secret_bytes = secret.to_be_bytes();
+        let mut replay = Session::reconstruct(&secret_bytes, &pk2, &[]).unwrap();
+        assert_eq!(replay.role(), sesh1.role());
+        assert_eq!(replay.decrypt(captured[0].clone()).unwrap(), b"first");
+        assert_eq!(replay.decrypt(captured[1].clone()).unwrap(), b"second");
+        // both directions come back
+        assert_eq!(replay.encrypt(b"reply".to_vec()).unwrap(), sent);
+
+        // the wrong context derives different keys
+        let mut wrong = Session::reconstruct(&secret_bytes, &pk2, b"other").unwrap();
+        assert!(matches!(wrong.decrypt(captured[0].clone()), Err(SessionError::MacMismatch)));
+
+        assert!(matches!(Session::reconstruct(&[0; 32], &pk2, &[]), Err(SessionError::InvalidSecret)));
+        assert!(matches!(Session::reconstruct(&secret_bytes[..31], &pk2, &[]), Err(SessionError::InvalidSecret)));
+        assert!(matches!(Session::reconstruct(&secret_bytes, &pk1, &[]), Err(SessionError::InvalidPubKey)));
+    }
+
+    #[test]
+    fn test_clone_of_pending_session_has_no_secret() {
+        let sesh1 = Session::new(&mut thread_rng());
+        let sesh2 = Session::new(&mut thread_rng());
+        let mut clone = sesh1.clone();
+        assert!(matches!(clone.pk(), Err(SessionError::EmptySecret)));
+        assert!(matches!(clone.set_sym_key(&sesh2.pk().unwrap()), Err(SessionError::EmptySecret)));
+        assert!(sesh1.pk().is_ok());
+    }
+
+    #[test]
+    fn test_pair_consumes_secrets() {
+        let (sesh1, sesh2) = Session::pair(&mut thread_rng());
+
+        assert!(sesh1.pk().is_err());
+        assert!(sesh2.pk().is_err());
+    }
+
+    #[test]
+    fn test_counter_refuses_to_pass_threshold() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        sesh1.sent = REKEY_AFTER_MESSAGES - 1;
+        sesh2.received = REKEY_AFTER_MESSAGES - 1;
+
+        let ciphertext = sesh1.encrypt(b"last one".to_vec()).unwrap();
+        assert_eq!(sesh2.decrypt(ciphertext).unwrap(), b"last one");
+
+        assert!(matches!(sesh1.encrypt(b"one too many".to_vec()), Err(SessionError::CounterExhausted)));
+        assert_eq!(sesh1.sent, REKEY_AFTER_MESSAGES);
+    }
+
+    #[test]
+    fn test_messages_remaining_counts_down() {
+        let builder = SessionBuilder::new().rekey_after(3);
+        let mut sesh1 = builder.clone().build(&mut thread_rng()).unwrap();
+        let mut sesh2 = builder.build(&mut thread_rng()).unwrap();
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+
+        for remaining in (1..=3).rev() {
+            assert_eq!(sesh1.messages_remaining(), remaining);
+            sesh1.encrypt(b"hello".to_vec()).unwrap();
+        }
+        assert_eq!(sesh1.messages_remaining(), 0);
+        assert!(matches!(sesh1.encrypt(b"hello".to_vec()), Err(SessionError::CounterExhausted)));
+        assert_eq!(sesh1.messages_remaining(), 0);
+
+        // a renegotiation starts the count again
+        let pending1 = sesh1.renegotiate(&mut thread_rng()).unwrap();
+        let pending2 = sesh2.renegotiate(&mut thread_rng()).unwrap();
+        let (pk1, pk2) = (*pending1.public_key(), *pending2.public_key());
+        pending1.complete(&pk2).unwrap();
+        pending2.complete(&pk1).unwrap();
+        assert_eq!(sesh1.messages_remaining(), 3);
+
+        sesh1.close(crate::CloseReason::Normal).unwrap();
+        assert_eq!(sesh1.messages_remaining(), 0);
+    }
+
+    #[test]
+    fn test_counter_never_wraps() {
+        let (mut sesh1, _) = Session::pair(&mut thread_rng());
+        sesh1.sent = u64::MAX;
+        assert_eq!(sesh1.messages_remaining(), 0);
+
+        assert!(matches!(sesh1.encrypt(Vec::new()), Err(SessionError::CounterExhausted)));
+        assert_eq!(sesh1.sent, u64::MAX);
+    }
+
+    #[test]
+    fn test_decrypt_failure_causes_are_distinct() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let ciphertext = sesh1.encrypt(b"hello".to_vec()).unwrap();
+
+        assert!(matches!(sesh2.decrypt(Vec::new()), Err(SessionError::InvalidLength(0))));
+        assert!(matches!(sesh2.decrypt(ciphertext[..24].to_vec()), Err(SessionError::InvalidLength(24))));
+
+        let mut wrong_version = ciphertext.clone();
+        wrong_version[0] = 0xff;
+        assert!(matches!(sesh2.decrypt(wrong_version), Err(SessionError::UnsupportedVersion(0xff))));
+
+        let mut tampered = ciphertext.clone();
+        tampered[1] ^= 1;
+        assert!(matches!(sesh2.decrypt(tampered), Err(SessionError::MacMismatch)));
+
+        let mut bad_tag = ciphertext.clone();
+        *bad_tag.last_mut().unwrap() ^= 1;
+        assert!(matches!(sesh2.decrypt(bad_tag), Err(SessionError::MacMismatch)));
+
+        assert_eq!(sesh2.decrypt(ciphertext).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_every_header_field_is_authenticated() {
+        let key = [5; 32];
+        let record = Session::with_key(key).seal(b"hello", b"aad").unwrap();
+
+        for i in 0..record::HEADER_LEN {
+            for value in 0..=255u8 {
+                if value == record[i] {
+                    continue;
+                }
+                let mut flipped = record.clone();
+                flipped[i] = value;
+                // only the negotiated version parses, and the tag covers it.
+                // the epoch bit alone picks a key this session doesn't have
+                match Session::with_key(key).open(flipped, b"aad") {
+                    Err(SessionError::UnsupportedVersion(v)) => assert!(i == 0 && v == value),
+                    Err(SessionError::StaleEpoch) => assert!(i == 0 && value & record::EPOCH_BIT != 0),
+                    Err(SessionError::MacMismatch) => {}
+                    other => panic!("header byte {} set to {} gave {:?}", i, value, other),
+                }
+            }
+        }
+
+        // relabelling as a version 1 record doesn't get it past a version 1
+        // session either
+        let mut downgraded = record.clone();
+        downgraded[0] = record::V1;
+        let mut v1 = Session::with_key(key);
+        v1.set_version(record::V1);
+        assert!(matches!(v1.open(downgraded, b"aad"), Err(SessionError::MacMismatch)));
+        assert_eq!(Session::with_key(key).open(record, b"aad").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_mac_input_layout() {
+        // the layout documented in record.rs, spelled out by hand
+        let (key, aad, plain) = ([6u8; 32], &b"aad"[..], &b"hello"[..]);
+        let record = Session::with_key(key).seal(plain, aad).unwrap();
+
+        let mut authenticated = Vec::new();
+        authenticated.extend_from_slice(&1u64.to_le_bytes());
+        authenticated.push(record::VERSION);
+        authenticated.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+        authenticated.extend_from_slice(aad);
+        authenticated.extend_from_slice(&(plain.len() as u64).to_le_bytes());
+        assert_eq!(record::authenticated_data(&record[..1], aad, plain.len()), authenticated);
+
+        let mut tag = [0u8; 24];
+        blake3::Hasher::new()
+            .update(plain)
+            .update(&key)
+            .update(&authenticated)
+            .update(&(authenticated.len() as u64).to_le_bytes())
+            .finalize_xof()
+            .fill(&mut tag);
+        assert_eq!(&record[record.len() - 24..], &tag);
+    }
+
+    #[test]
+    fn test_empty_aad_is_no_aad() {
+        // the decision is that they're the same record, in every version,
+        // and that any aad at all makes a different one
+        for version in [record::V1, record::V2, record::VERSION] {
+            let session = || {
+                let mut session = Session::with_key([8; 32]);
+                session.set_version(version);
+                session
+            };
+            let plain = b"hello".to_vec();
+            let bare = session().encrypt(plain.clone()).unwrap();
+            assert_eq!(session().encrypt_with_aad(plain.clone(), &[]).unwrap(), bare);
+            assert_eq!(session().encrypt_vectored_with_aad(&[b"he", b"llo"], &[]).unwrap(), bare);
+            assert_eq!(session().decrypt_with_aad(bare.clone(), &[]).unwrap(), plain);
+
+            // a record with aad doesn't open as one without, or the other way
+            for aad in [&[0u8][..], &0u64.to_le_bytes()[..], b"aad"] {
+                let with_aad = session().encrypt_with_aad(plain.clone(), aad).unwrap();
+                assert_ne!(with_aad, bare);
+                assert!(matches!(session().decrypt(with_aad.clone()), Err(SessionError::MacMismatch)));
+                assert!(matches!(session().decrypt_with_aad(bare.clone(), aad), Err(SessionError::MacMismatch)));
+                assert_eq!(session().decrypt_with_aad(with_aad, aad).unwrap(), plain);
+            }
+        }
+    }
+
+    fn ordered_pair(builder: SessionBuilder) -> (Session, Session) {
+        Session::pair_with(builder.ordered(true))
+    }
+
+    #[test]
+    fn test_ordered_records_must_arrive_in_order() {
+        let (mut sesh1, mut sesh2) = ordered_pair(SessionBuilder::new());
+        let records: Vec<Vec<u8>> = (1..=5u8).map(|i| sesh1.encrypt(vec![i]).unwrap()).collect();
+        assert_eq!(records[0].len(), sesh1.ciphertext_len(1));
+        assert_eq!(records[0].len(), 1 + 8 + 1 + 24);
+
+        // in order, so a clone sees them all
+        let mut in_order = sesh2.clone();
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(in_order.decrypt(record.clone()).unwrap(), [i as u8 + 1]);
+        }
+
+        // records 3 and 4 swapped: 4 is caught, and 3 is still wanted
+        for record in &records[..2] {
+            sesh2.decrypt(record.clone()).unwrap();
+        }
+        assert!(matches!(sesh2.decrypt(records[3].clone()), Err(SessionError::OutOfOrder { expected: 3, received: 4 })));
+        assert_eq!(sesh2.decrypt(records[2].clone()).unwrap(), [3]);
+        assert_eq!(sesh2.decrypt(records[3].clone()).unwrap(), [4]);
+        // and a replay is out of order too
+        assert!(matches!(sesh2.decrypt(records[0].clone()), Err(SessionError::OutOfOrder { expected: 5, received: 1 })));
+        assert_eq!(sesh2.stats().auth_failures, 0);
+
+        // the counter is under the MAC
+        let mut renumbered = records[4].clone();
+        renumbered[1..9].copy_from_slice(&6u64.to_le_bytes());
+        assert!(matches!(sesh2.decrypt(renumbered), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_ordered_record_from_another_session_fails() {
+        let (mut sesh1, mut sesh2) = ordered_pair(SessionBuilder::new());
+        let (mut other, _) = ordered_pair(SessionBuilder::new());
+        sesh1.encrypt(b"first".to_vec()).unwrap();
+        // the other session's first record, at the counter sesh2 expects
+        let spliced = other.encrypt(b"first".to_vec()).unwrap();
+        assert!(matches!(sesh2.decrypt(spliced), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_ordered_obfuscated_records_out_of_order_fail_the_mac() {
+        let (mut sesh1, mut sesh2) = ordered_pair(SessionBuilder::new().obfuscate(true));
+        let first = sesh1.encrypt(b"first".to_vec()).unwrap();
+        let second = sesh1.encrypt(b"second".to_vec()).unwrap();
+        assert_eq!(first.len(), sesh1.ciphertext_len(5));
+        assert!(matches!(sesh2.decrypt(second.clone()), Err(SessionError::MacMismatch)));
+        assert_eq!(sesh2.decrypt(first).unwrap(), b"first");
+        assert_eq!(sesh2.decrypt(second).unwrap(), b"second");
+    }
+
+    fn obfuscated_pair() -> (Session, Session) {
+        Session::pair_with(SessionBuilder::new().obfuscate(true).ordered(false))
+    }
+
+    #[test]
+    fn test_obfuscated_records_have_no_fixed_bytes() {
+        const RECORDS: usize = 2048;
+        let (mut sesh1, _) = obfuscated_pair();
+        let records: Vec<Vec<u8>> = (0..RECORDS).map(|_| sesh1.encrypt(b"the same plaintext".to_vec()).unwrap()).collect();
+        assert!(records.iter().all(|record| record.len() == sesh1.ciphertext_len(18)));
+
+        // no position keeps a value: by chance the most common byte at a
+        // position turns up around 20 times in 2048
+        for i in 0..records[0].len() {
+            let mut counts = [0usize; 256];
+            records.iter().for_each(|record| counts[record[i] as usize] += 1);
+            let most = counts.iter().max().unwrap();
+            assert!(*most < RECORDS / 32, "byte {} is {} in {} of {} records", i, counts.iter().position(|c| c == most).unwrap(), most, RECORDS);
+        }
+
+        // and all of it together is flat, for 255 degrees of freedom the
+        // statistic sits around 255 give or take 23
+        let mut counts = [0f64; 256];
+        records.iter().flatten().for_each(|&byte| counts[byte as usize] += 1.0);
+        let expected = counts.iter().sum::<f64>() / 256.0;
+        let chi_square: f64 = counts.iter().map(|count| (count - expected).powi(2) / expected).sum();
+        assert!(chi_square < 400.0, "chi-square {}", chi_square);
+
+        // which the versioned header fails straight away
+        let (mut plain, _) = Session::pair(&mut thread_rng());
+        let first = plain.encrypt(b"the same plaintext".to_vec()).unwrap();
+        assert_eq!(first[0], plain.encrypt(b"the same plaintext".to_vec()).unwrap()[0]);
+    }
+
+    #[test]
+    fn test_obfuscated_sessions_talk() {
+        let (mut sesh1, mut sesh2) = obfuscated_pair();
+        let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+        assert_eq!(record.len(), record::SALT_LEN + 5 + record::TAG_LEN);
+        assert_eq!(sesh2.decrypt(record.clone()).unwrap(), b"hello");
+
+        // nothing is told apart any more, every failure is the MAC
+        for i in [0, record::SALT_LEN, record.len() - 1] {
+            let mut tampered = record.clone();
+            tampered[i] ^= 1;
+            assert!(matches!(sesh2.decrypt(tampered), Err(SessionError::MacMismatch)));
+        }
+        let mut other_version = sesh2.clone();
+        other_version.set_version(record::V1);
+        assert!(matches!(other_version.decrypt(record.clone()), Err(SessionError::MacMismatch)));
+        assert!(matches!(sesh2.decrypt(record[..record::SALT_LEN + 23].to_vec()), Err(SessionError::InvalidLength(39))));
+
+        // a plain session sees garbage, and an obfuscated one can't read plain records
+        let (mut plain, _) = Session::pair(&mut thread_rng());
+        assert!(plain.decrypt(record).is_err());
+
+        // streams find their chunk boundaries with the longer records
+        let data = vec![7u8; 2 * crate::STREAM_CHUNK_LEN + 5];
+        let mut encryptor = sesh1.stream_encryptor();
+        let mut ciphertext = encryptor.update(&data).unwrap();
+        ciphertext.extend(encryptor.finish().unwrap());
+        let mut decryptor = sesh2.stream_decryptor();
+        let mut opened = decryptor.update(&ciphertext).unwrap();
+        opened.extend(decryptor.finish().unwrap());
+        assert_eq!(opened, data);
+    }
+
+    #[test]
+    fn test_heartbeats_are_not_empty_messages() {
+        for (mut sesh1, mut sesh2) in [Session::unordered_pair(), obfuscated_pair()] {
+            let heartbeat = sesh1.heartbeat().unwrap();
+            let empty = sesh1.encrypt(vec![]).unwrap();
+            assert_eq!(heartbeat.len(), empty.len());
+
+            assert_eq!(sesh2.receive(heartbeat.clone()).unwrap(), Received::Heartbeat);
+            assert_eq!(sesh2.receive(empty.clone()).unwrap(), Received::Data(vec![]));
+            assert!(matches!(sesh2.decrypt(heartbeat), Err(SessionError::Heartbeat)));
+            assert_eq!(sesh2.decrypt(empty.clone()).unwrap(), b"");
+            // heartbeats count like anything else
+            assert_eq!((sesh1.stats().sent, sesh2.stats().received), (2, 4));
+
+            if !sesh1.config().obfuscate {
+                // the flag is under the MAC, an empty message can't be passed off as one
+                let mut relabeled = empty;
+                relabeled[0] |= record::HEARTBEAT_BIT;
+                assert!(matches!(sesh2.receive(relabeled), Err(SessionError::MacMismatch)));
+            }
+        }
+
+        let mut v1 = Session::with_key([5; 32]);
+        v1.set_version(record::V1);
+        assert!(matches!(v1.heartbeat(), Err(SessionError::UnsupportedVersion(record::V1))));
+        let mut flagged = v1.encrypt(vec![]).unwrap();
+        flagged[0] |= record::HEARTBEAT_BIT;
+        assert!(matches!(v1.receive(flagged), Err(SessionError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_ids_make_distinct_nonces() {
+        let key = [5; 32];
+        let first = Session::with_key(key).encrypt_with_id(b"deposit 10".to_vec(), b"event-1").unwrap();
+        let second = Session::with_key(key).encrypt_with_id(b"deposit 10".to_vec(), b"event-2").unwrap();
+        assert_ne!(first[first.len() - 24..], second[second.len() - 24..]);
+        assert_ne!(first, second);
+        assert_eq!(first, Session::with_key(key).encrypt_with_id(b"deposit 10".to_vec(), b"event-1").unwrap());
+        assert_ne!(first, Session::with_key(key).encrypt(b"deposit 10".to_vec()).unwrap());
+
+        // the receiver needs no id
+        let mut receiver = Session::with_key(key);
+        assert_eq!(receiver.decrypt(first.clone()).unwrap(), b"deposit 10");
+        let mut plain = [0xffu8; 32];
+        assert_eq!(receiver.decrypt_into(&second, &mut plain).unwrap(), 10);
+        assert_eq!(&plain[..10], b"deposit 10");
+        // where the id was is zeroed, past the plaintext is left alone
+        assert_eq!(plain[10..19], [0; 9]);
+        assert_eq!(plain[19], 0xff);
+
+        // the flag is under the MAC, as is the id
+        let mut unflagged = first.clone();
+        unflagged[0] &= !record::ID_BIT;
+        assert!(matches!(receiver.decrypt(unflagged), Err(SessionError::MacMismatch)));
+        let mut tampered = first;
+        tampered[3] ^= 1;
+        assert!(matches!(receiver.decrypt(tampered), Err(SessionError::MacMismatch)));
+
+        let (mut sesh1, mut sesh2) = ordered_pair(SessionBuilder::new().obfuscate(true));
+        let record = sesh1.encrypt_with_id(b"hi".to_vec(), b"event-3").unwrap();
+        assert_eq!(sesh2.decrypt(record).unwrap(), b"hi");
+        let record = sesh1.encrypt_with_id(Vec::new(), b"").unwrap();
+        assert_eq!(sesh2.receive(record).unwrap(), Received::Data(Vec::new()));
+
+        let mut v1 = Session::with_key(key);
+        v1.set_version(record::V1);
+        assert!(matches!(v1.encrypt_with_id(b"hi".to_vec(), b"event-4"), Err(SessionError::UnsupportedVersion(record::V1))));
+        assert!(matches!(
+            Session::with_key(key).encrypt_with_id(b"hi".to_vec(), &[0; 65536]),
+            Err(SessionError::MessageTooLarge(65536))
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_as_pins_the_version() {
+        let key = [5; 32];
+        let mut sender = Session::with_key(key);
+        let mut v1 = Session::with_key(key);
+        v1.set_version(record::V1);
+        let mut v2 = Session::with_key(key);
+
+        let old = sender.encrypt_as(b"hello".to_vec(), record::V1).unwrap();
+        let new = sender.encrypt_as(b"hello".to_vec(), record::VERSION).unwrap();
+        assert_eq!((old[0], new[0]), (record::V1, record::VERSION));
+        assert_eq!(new, Session::with_key(key).encrypt(b"hello".to_vec()).unwrap());
+        assert_eq!(sender.stats().sent, 2);
+        assert_eq!(sender.negotiated_version(), record::VERSION);
+
+        assert_eq!(v1.decrypt(old.clone()).unwrap(), b"hello");
+        assert_eq!(v2.decrypt(new.clone()).unwrap(), b"hello");
+        assert!(matches!(v2.decrypt(old), Err(SessionError::UnsupportedVersion(record::V1))));
+        assert!(matches!(v1.decrypt(new), Err(SessionError::UnsupportedVersion(record::VERSION))));
+
+        for version in [0, 4, 0x81] {
+            assert!(matches!(sender.encrypt_as(b"hello".to_vec(), version), Err(SessionError::UnsupportedVersion(v)) if v == version));
+        }
+        assert_eq!(sender.stats().sent, 2);
+    }
+
+    #[test]
+    fn test_keys_are_bound_to_public_keys() {
+        let secret = |n: u8| {
+            let mut bytes = [0u8; 32];
+            bytes[31] = n;
+            k256::SecretKey::from_be_bytes(&bytes).unwrap()
+        };
+        // keyed as set_sym_key would, from our secret and their public key
+        let keyed = |version: u8, ours: u8, theirs: u8| {
+            let (ours, peer) = (secret(ours), secret(theirs).public_key());
+            let shared = k256::ecdh::diffie_hellman(ours.to_nonzero_scalar(), peer.as_affine());
+            let (ours, theirs) = (ours.public_key().to_encoded_point(true), peer.to_encoded_point(true));
+            let role = pick_role(ours.as_bytes(), theirs.as_bytes()).unwrap();
+            let mut session: Session = Session::from_secret(None);
+            session.set_version(version);
+            session.install_keys(role, shared.raw_secret_bytes(), &bind_public_keys(version, &[], role, ours.as_bytes(), theirs.as_bytes()));
+            (shared.raw_secret_bytes().to_vec(), session.send_key, session.recv_key)
+        };
+
+        // 10 * 3 and 15 * 2: two different pairs of keys, one shared secret.
+        // which side initiates depends on the keys, so either of the other
+        // pair's keys could be the one to match
+        let (first, second) = (keyed(record::V3, 10, 3), keyed(record::V3, 15, 2));
+        assert_eq!(first.0, second.0);
+        assert!(first.1 != second.1 && first.1 != second.2);
+        // which version 2 couldn't tell apart
+        let (first, second) = (keyed(record::V2, 10, 3), keyed(record::V2, 15, 2));
+        assert!(first.1 == second.1 || first.1 == second.2);
+        // and the two ends of one pair still agree, whichever side is which
+        assert_eq!(keyed(record::V3, 10, 3).1, keyed(record::V3, 3, 10).2);
+    }
+
+    #[test]
+    fn test_session_ids() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let id = sesh1.session_id();
+        assert_ne!(id, [0; 16]);
+        assert_eq!(sesh2.session_id(), id);
+        assert_eq!(sesh1.stats().session_id, id);
+
+        // another handshake, even between the same kind of sessions, differs
+        let (other, _) = Session::pair(&mut thread_rng());
+        assert_ne!(other.session_id(), id);
+        // and an unkeyed session has none yet
+        assert_eq!(Session::new(&mut thread_rng()).session_id(), [0; 16]);
+
+        // traffic doesn't move it
+        for _ in 0..3 {
+            let record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            sesh2.decrypt(record).unwrap();
+        }
+        assert_eq!(sesh1.session_id(), id);
+        assert_eq!(sesh2.stats().session_id, id);
+    }
+
+    #[test]
+    fn test_vectored_matches_concatenated() {
+        let key = [9; 32];
+        let body: Vec<u8> = (0..=255).cycle().take(300).collect();
+        let parts: [&[u8]; 4] = [b"header:", &[], &body[..13], &body[13..]];
+
+        let vectored = Session::with_key(key).encrypt_vectored(&parts).unwrap();
+        let concatenated = Session::with_key(key).encrypt(parts.concat()).unwrap();
+        assert_eq!(vectored, concatenated);
+        assert_eq!(Session::with_key(key).decrypt(vectored).unwrap(), parts.concat());
+
+        let empty = Session::with_key(key).encrypt_vectored(&[]).unwrap();
+        assert_eq!(empty, Session::with_key(key).encrypt(Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn test_vectored_across_keystream_blocks() {
+        let key = [9; 32];
+        let body: Vec<u8> = (0..=255).cycle().take(200).collect();
+        let concatenated = Session::with_key(key).encrypt(body.clone()).unwrap();
+
+        // one byte at a time, so every block boundary falls between parts
+        let bytes: Vec<&[u8]> = body.chunks(1).collect();
+        assert_eq!(Session::with_key(key).encrypt_vectored(&bytes).unwrap(), concatenated);
+        // splits either side of and on the 64 byte block
+        for split in [63, 64, 65, 127, 128, 129] {
+            let (head, tail) = body.split_at(split);
+            assert_eq!(Session::with_key(key).encrypt_vectored(&[head, tail]).unwrap(), concatenated, "{}", split);
+            let (mid, tail) = tail.split_at(1);
+            assert_eq!(Session::with_key(key).encrypt_vectored(&[head, &[], mid, tail]).unwrap(), concatenated, "{}", split);
+        }
+    }
+
+    #[test]
+    fn test_vectored_with_aad() {
+        let key = [9; 32];
+        let parts: [&[u8]; 3] = [b"head", &[0x5a; 65], b"trailer"];
+        let vectored = Session::with_key(key).encrypt_vectored_with_aad(&parts, b"stream 7").unwrap();
+        assert_eq!(vectored, Session::with_key(key).seal(&parts.concat(), b"stream 7").unwrap());
+        assert_ne!(vectored, Session::with_key(key).encrypt_vectored(&parts).unwrap());
+
+        assert_eq!(Session::with_key(key).decrypt_with_aad(vectored.clone(), b"stream 7").unwrap(), parts.concat());
+        assert!(matches!(Session::with_key(key).decrypt_with_aad(vectored.clone(), b"stream 8"), Err(SessionError::MacMismatch)));
+        assert!(matches!(Session::with_key(key).decrypt(vectored), Err(SessionError::MacMismatch)));
+        let plain = Session::with_key(key).encrypt_vectored_with_aad(&parts, &[]).unwrap();
+        assert_eq!(Session::with_key(key).decrypt(plain).unwrap(), parts.concat());
+    }
+
+    #[test]
+    fn test_into_buffers_match_vec_records() {
+        let key = [9; 32];
+        let mut record = [0u8; 128];
+        let mut plain = [0xffu8; 128];
+        for len in [0, 1, 64, 100] {
+            let message = vec![0x5a; len];
+            let written = Session::with_key(key).encrypt_into(&message, &mut record).unwrap();
+            assert_eq!(&record[..written], Session::with_key(key).encrypt(message.clone()).unwrap());
+            let read = Session::with_key(key).decrypt_into(&record[..written], &mut plain).unwrap();
+            assert_eq!(&plain[..read], message);
+        }
+
+        // ordered and obfuscated sessions too
+        for builder in [SessionBuilder::new().obfuscate(true), SessionBuilder::new().obfuscate(true).ordered(true)] {
+            let (mut sesh1, mut sesh2) = ordered_pair(builder);
+            for i in 0..3u8 {
+                let written = sesh1.encrypt_into(&[i; 10], &mut record).unwrap();
+                assert_eq!(sesh2.decrypt_into(&record[..written], &mut plain).unwrap(), 10);
+                assert_eq!(plain[..10], [i; 10]);
+            }
+            let heartbeat = sesh1.heartbeat().unwrap();
+            assert!(matches!(sesh2.decrypt_into(&heartbeat, &mut plain), Err(SessionError::Heartbeat)));
+        }
+    }
+
+    #[test]
+    fn test_into_buffers_too_small() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let mut record = [0u8; 64];
+        assert!(matches!(sesh1.encrypt_into(b"hello", &mut record[..37]), Err(SessionError::BufferTooSmall(38))));
+        // nothing was counted
+        assert_eq!(sesh1.stats().sent, 0);
+        let written = sesh1.encrypt_into(b"hello", &mut record).unwrap();
+
+        let mut plain = [0u8; 5];
+        assert!(matches!(sesh2.decrypt_into(&record[..written], &mut plain[..4]), Err(SessionError::BufferTooSmall(5))));
+        assert_eq!(sesh2.stats().received, 0);
+
+        // a refused record leaves nothing behind
+        record[1] ^= 1;
+        assert!(matches!(sesh2.decrypt_into(&record[..written], &mut plain), Err(SessionError::MacMismatch)));
+        assert_eq!(plain, [0; 5]);
+        record[1] ^= 1;
+        assert_eq!(sesh2.decrypt_into(&record[..written], &mut plain).unwrap(), 5);
+        assert_eq!(&plain, b"hello");
+        // duplicates are caught after the MAC, and still zeroed
+        sesh2.set_dedup_capacity(4);
+        let written = sesh1.encrypt_into(b"again", &mut record).unwrap();
+        sesh2.decrypt_into(&record[..written], &mut plain).unwrap();
+        assert!(matches!(sesh2.decrypt_into(&record[..written], &mut plain), Err(SessionError::DuplicateMessage)));
+        assert_eq!(plain, [0; 5]);
+    }
+
+    #[test]
+    fn test_unready_session_is_refused() {
+        let mut sesh = Session::new(&mut thread_rng());
+        let record = vec![1; crate::record::ORDERED_OVERHEAD];
+
+        assert!(matches!(sesh.encrypt(b"hello".to_vec()), Err(SessionError::NotReady)));
+        assert!(matches!(sesh.decrypt(record.clone()), Err(SessionError::NotReady)));
+        assert!(matches!(sesh.encrypt_iter(vec![b"hello".to_vec()]).next(), Some(Err(SessionError::NotReady))));
+        assert!(matches!(sesh.decrypt_iter(vec![record.clone()]).next(), Some(Err(SessionError::NotReady))));
+        assert!(matches!(sesh.stream_encryptor().finish(), Err(SessionError::NotReady)));
+
+        let mut decryptor = sesh.stream_decryptor();
+        // a stream id, and the final chunk's kind
+        decryptor.update(&[1; crate::STREAM_ID_LEN + 1]).unwrap();
+        decryptor.update(&record).unwrap();
+        assert!(matches!(decryptor.finish(), Err(SessionError::NotReady)));
+
+        // nothing was counted, so the session is still usable once keyed
+        assert_eq!(sesh.sent, 0);
+    }
+
+    #[test]
+    fn test_auth_failure_hook_counts_tampered_records() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        sesh2.on_auth_failure(move |failure| hook_seen.lock().unwrap().push(failure.clone()));
+
+        let mut records = Vec::new();
+        for _ in 0..3 {
+            let mut record = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            record[1] ^= 1;
+            records.push(record);
+        }
+        for record in records.iter() {
+            assert!(matches!(sesh2.decrypt(record.clone()), Err(SessionError::MacMismatch)));
+        }
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        for (i, (failure, record)) in seen.iter().zip(records.iter()).enumerate() {
+            assert_eq!(failure.failures, i as u64 + 1);
+            assert_eq!(failure.record_len, record.len());
+            // nothing was accepted in between
+            assert_eq!(failure.seq, 1);
+            assert_eq!(&failure.record_id[..], &record[record.len() - 24..][..4]);
+        }
+    }
+
+    #[test]
+    fn test_auth_failure_hook_not_called_on_success() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let calls = Arc::new(AtomicU64::new(0));
+        let hook_calls = calls.clone();
+        sesh2.on_auth_failure(move |_| {
+            hook_calls.fetch_add(1, Ordering::SeqCst);
+        });
+
+        for _ in 0..3 {
+            let ciphertext = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            assert_eq!(sesh2.decrypt(ciphertext).unwrap(), b"hello");
+        }
+        // malformed records aren't authentication failures
+        assert!(matches!(sesh2.decrypt(Vec::new()), Err(SessionError::InvalidLength(0))));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_fields_and_no_secrets() {
+        use crate::session::to_hex;
+        use crate::session::test::capture::capture;
+
+        let ((sesh1, ciphertext), lines) = capture(|| {
+            let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+            let ciphertext = sesh1.encrypt(b"secret plaintext".to_vec()).unwrap();
+            sesh2.decrypt(ciphertext.clone()).unwrap();
+            (sesh1, ciphertext)
+        });
+
+        let has = |prefix: &str, fields: &[&str]| {
+            lines.iter().any(|l| l.starts_with(prefix) && fields.iter().all(|f| l.contains(f)))
+        };
+        assert!(has("set_sym_key", &["peer="]));
+        assert!(has("encrypt", &["record=\"data\"", "len=16", "seq=1", "peer="]));
+        assert!(has("decrypt", &["record=\"data\"", "len=49", "seq=1", "peer="]));
+        let session_id = format!("session_id={}", to_hex(&sesh1.session_id()));
+        assert!(has("encrypt", &[session_id.as_str()]) && has("decrypt", &[session_id.as_str()]));
+        assert!(has("event", &["elapsed_us="]));
+
+        // the default policy only names records by the first 4 tag bytes
+        assert!(has("encrypt.record", &[format!("record_id={}", to_hex(&ciphertext[25..29])).as_str()]));
+
+        let log = lines.join("\n");
+        assert!(!log.contains("secret plaintext"));
+        assert!(!log.contains(to_hex(&sesh1.send_key[..4]).as_str()));
+        assert!(!log.contains(to_hex(&sesh1.recv_key[..4]).as_str()));
+        assert!(!log.contains(to_hex(&ciphertext[29..33]).as_str()));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_refused_records_log_the_check_only() {
+        use crate::session::to_hex;
+        use crate::session::test::capture::capture;
+
+        let (record, lines) = capture(|| {
+            let (mut sesh1, mut sesh2) = Session::unordered_pair();
+            sesh2.set_dedup_capacity(4);
+            let record = sesh1.encrypt(b"secret plaintext".to_vec()).unwrap();
+            let mut versioned = record.clone();
+            versioned[0] = 0x3f;
+            let mut tampered = record.clone();
+            tampered[3] ^= 1;
+
+            assert!(sesh2.decrypt(record[..10].to_vec()).is_err());
+            assert!(sesh2.decrypt(versioned).is_err());
+            assert!(sesh2.decrypt(tampered).is_err());
+            sesh2.decrypt(record.clone()).unwrap();
+            assert!(sesh2.decrypt(record.clone()).is_err());
+            record
+        });
+
+        let refused: Vec<&String> = lines.iter().filter(|l| l.contains("record refused")).collect();
+        assert_eq!(refused.len(), 4, "{:?}", lines);
+        let checks = ["check=\"too_short\" len=10", "check=\"version\" len=41", "check=\"tag\" len=41", "check=\"replay\" len=41"];
+        for (line, check) in refused.iter().zip(checks) {
+            assert!(line.starts_with("event") && line.contains(check), "{}", line);
+        }
+        let log = lines.join("\n");
+        assert!(!log.contains(to_hex(&record[record.len() - 20..]).as_str()));
+        assert!(!log.contains("secret plaintext"));
+    }
+
+    #[test]
+    fn test_opaque_errors() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        sesh2.set_opaque_errors(true);
+        sesh2.set_dedup_capacity(4);
+        let plain = vec![7u8; 64 * 1024];
+
+        let replayed = sesh1.encrypt(plain.clone()).unwrap();
+        sesh2.decrypt(replayed.clone()).unwrap();
+        let mut tampered = sesh1.encrypt(vec![8u8; plain.len()]).unwrap();
+        tampered[10] ^= 1;
+        let mut version = sesh1.encrypt(plain).unwrap();
+        version[0] = 0x7f;
+        let short = replayed[..3].to_vec();
+
+        // the same error whatever the cause, and without the option the
+        // causes as they were
+        let records = [tampered, version, replayed];
+        for record in records.iter().chain([&short]) {
+            assert!(matches!(sesh2.decrypt(record.clone()), Err(SessionError::Refused)));
+        }
+        let mut plain_errors = sesh2.clone();
+        plain_errors.set_opaque_errors(false);
+        let found: Vec<SessionError> = records.iter().chain([&short]).map(|record| plain_errors.decrypt(record.clone()).unwrap_err()).collect();
+        assert!(matches!(
+            &found[..],
+            [SessionError::MacMismatch, SessionError::UnsupportedVersion(0x7f), SessionError::DuplicateMessage, SessionError::InvalidLength(3)]
+        ));
+        // this side's own state isn't hidden
+        assert!(matches!(Session::new(&mut thread_rng()).decrypt(short), Err(SessionError::NotReady)));
+
+        // and as long to refuse a record of the same length, taking the
+        // fastest of a few tries at each so scheduling noise drops out
+        let fastest: Vec<Duration> = records
+            .iter()
+            .map(|record| {
+                (0..20)
+                    .map(|_| {
+                        let start = Instant::now();
+                        sesh2.decrypt(record.clone()).unwrap_err();
+                        start.elapsed()
+                    })
+                    .min()
+                    .unwrap()
+            })
+            .collect();
+        let (min, max) = (fastest.iter().min().unwrap(), fastest.iter().max().unwrap());
+        assert!(max.as_secs_f64() < min.as_secs_f64() * 2.0, "{:?}", fastest);
+    }
+
+    // sessions built by hand so the policy is in place before set_sym_key
+    #[cfg(feature = "tracing")]
+    fn pair_with_policy(policy: LogPolicy) -> (Session, Session) {
+        let mut sesh1 = Session::new(&mut thread_rng());
+        let mut sesh2 = Session::new(&mut thread_rng());
+        sesh1.set_log_policy(policy);
+        sesh2.set_log_policy(policy);
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+        (sesh1, sesh2)
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_redacted_policy_hides_tags_and_peer_keys() {
+        use crate::session::to_hex;
+        use crate::session::test::capture::capture;
+
+        let ((pk1, ciphertext), lines) = capture(|| {
+            let (mut sesh1, mut sesh2) = pair_with_policy(LogPolicy::Redacted);
+            let ciphertext = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            sesh2.decrypt(ciphertext.clone()).unwrap();
+            (sesh2.peer_key.clone().unwrap(), ciphertext)
+        });
+        let log = lines.join("\n");
+
+        let tag = &ciphertext[ciphertext.len() - 24..];
+        assert!(log.contains(format!("record_id={}", to_hex(&tag[..4])).as_str()));
+        assert!(!log.contains(to_hex(tag).as_str()));
+        assert!(!log.contains(to_hex(&pk1).as_str()));
+        assert!(log.contains(to_hex(&fingerprint(&pk1)).as_str()));
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_full_policy_logs_tags() {
+        use crate::session::to_hex;
+        use crate::session::test::capture::capture;
+
+        let (ciphertext, lines) = capture(|| {
+            let (mut sesh1, _) = pair_with_policy(LogPolicy::Full);
+            sesh1.encrypt(b"hello".to_vec()).unwrap()
+        });
+
+        let tag = &ciphertext[ciphertext.len() - 24..];
+        assert!(lines.join("\n").contains(format!("tag={}", to_hex(tag)).as_str()));
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_off_policy_is_silent() {
+        use crate::session::test::capture::capture;
+
+        let (_, lines) = capture(|| {
+            let (mut sesh1, mut sesh2) = pair_with_policy(LogPolicy::Off);
+            let ciphertext = sesh1.encrypt(b"hello".to_vec()).unwrap();
+            sesh2.decrypt(ciphertext).unwrap();
+        });
+        assert!(lines.is_empty(), "{:?}", lines);
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) mod capture {
+        use std::{fmt::Debug, sync::{Arc, Mutex}};
+
+        use tracing::{field::{Field, Visit}, span::{Attributes, Id, Record}, Event, Subscriber};
+        use tracing_subscriber::{layer::{Context, Layer, SubscriberExt}, registry::LookupSpan, Registry};
+
+        // records every span and event as `name field=value ...`, fields
+        // filled in later show up as `name.record field=value`
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<String>>>);
+
+        struct Line(String);
+
+        impl Visit for Line {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+            fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+                let mut line = Line(attrs.metadata().name().to_string());
+                attrs.record(&mut line);
+                self.0.lock().unwrap().push(line.0);
+            }
+
+            fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+                let name = ctx.span(id).map_or("?", |span| span.name());
+                let mut line = Line(format!("{}.record", name));
+                values.record(&mut line);
+                self.0.lock().unwrap().push(line.0);
+            }
+
+            fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+                let mut line = Line("event".to_string());
+                event.record(&mut line);
+                self.0.lock().unwrap().push(line.0);
+            }
+        }
+
+        pub(crate) fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+            let capture = Capture::default();
+            let out = tracing::subscriber::with_default(Registry::default().with(capture.clone()), f);
+            let lines = capture.0.lock().unwrap().clone();
+            (out, lines)
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// Checks against libsodium, run with
+//   cargo test -p xc220b3 --features sodium-interop sodium
+//
+// Records are XChaCha20 keyed with the session key and nonced with the tag,
+// so libsodium's crypto_stream_xchacha20 can decrypt them directly, which
+// pins down our HChaCha20 subkey and nonce ordering.
+//
+// Streams are deliberately not crypto_secretstream_xchacha20poly1305: there's
+// no header but a random id, every chunk is a self-contained record with its
+// index, its kind and the id under the tag instead of a ratcheted nonce, and
+// there's no in-stream rekey tag. So for streams both outputs are pinned
+// instead, divergence has to be a visible change to one of these hashes.
+
+use std::sync::Arc;
+
+use libsodium_sys as sodium;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::cipher::{RecordCipher, XCHACHA20_BLAKE3};
+use crate::random::SharedRng;
+use crate::session::Session;
+use crate::symmetriccipher::SynchronousStreamCipher;
+use crate::xc220::XC220;
+
+const TAG_MESSAGE: u8 = sodium::crypto_secretstream_xchacha20poly1305_TAG_MESSAGE as u8;
+const TAG_REKEY: u8 = sodium::crypto_secretstream_xchacha20poly1305_TAG_REKEY as u8;
+const TAG_FINAL: u8 = sodium::crypto_secretstream_xchacha20poly1305_TAG_FINAL as u8;
+const SECRETSTREAM_ABYTES: usize = sodium::crypto_secretstream_xchacha20poly1305_ABYTES as usize;
+
+fn init() {
+    assert!(unsafe { sodium::sodium_init() } >= 0);
+}
+
+fn sodium_xchacha20_xor(key: &[u8; 32], nonce: &[u8; 24], input: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; input.len()];
+    let ret = unsafe {
+        sodium::crypto_stream_xchacha20_xor(out.as_mut_ptr(), input.as_ptr(), input.len() as u64, nonce.as_ptr(), key.as_ptr())
+    };
+    assert_eq!(ret, 0);
+    out
+}
+
+// init_push draws its header at random, init_pull takes one and derives the
+// same state from it, and push only looks at the state. so this gives a
+// reproducible push
+fn sodium_secretstream_state(key: &[u8; 32], header: &[u8; 24]) -> sodium::crypto_secretstream_xchacha20poly1305_state {
+    let mut state = unsafe { core::mem::zeroed() };
+    let ret = unsafe { sodium::crypto_secretstream_xchacha20poly1305_init_pull(&mut state, header.as_ptr(), key.as_ptr()) };
+    assert_eq!(ret, 0);
+    state
+}
+
+fn sodium_push(state: &mut sodium::crypto_secretstream_xchacha20poly1305_state, chunk: &[u8], tag: u8) -> Vec<u8> {
+    let mut out = vec![0u8; chunk.len() + SECRETSTREAM_ABYTES];
+    let mut out_len = 0u64;
+    let ret = unsafe {
+        sodium::crypto_secretstream_xchacha20poly1305_push(
+            state,
+            out.as_mut_ptr(),
+            &mut out_len,
+            chunk.as_ptr(),
+            chunk.len() as u64,
+            core::ptr::null(),
+            0,
+            tag,
+        )
+    };
+    assert_eq!(ret, 0);
+    out.truncate(out_len as usize);
+    out
+}
+
+fn sodium_pull(state: &mut sodium::crypto_secretstream_xchacha20poly1305_state, record: &[u8]) -> Option<(Vec<u8>, u8)> {
+    let mut out = vec![0u8; record.len() - SECRETSTREAM_ABYTES];
+    let mut out_len = 0u64;
+    let mut tag = 0u8;
+    let ret = unsafe {
+        sodium::crypto_secretstream_xchacha20poly1305_pull(
+            state,
+            out.as_mut_ptr(),
+            &mut out_len,
+            &mut tag,
+            record.as_ptr(),
+            record.len() as u64,
+            core::ptr::null(),
+            0,
+        )
+    };
+    match ret {
+        0 => {
+            out.truncate(out_len as usize);
+            Some((out, tag))
+        }
+        _ => None,
+    }
+}
+
+fn hash(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+fn chunks() -> Vec<(Vec<u8>, u8)> {
+    let counting: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    vec![
+        (b"first chunk".to_vec(), TAG_MESSAGE),
+        (counting.clone(), TAG_REKEY),
+        (counting, TAG_MESSAGE),
+        (b"last chunk".to_vec(), TAG_FINAL),
+    ]
+}
+
+#[test]
+fn test_xchacha20_keystream_matches_libsodium() {
+    init();
+    let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+    let nonce: [u8; 24] = core::array::from_fn(|i| 0x40 + i as u8);
+
+    for len in [0, 1, 63, 64, 65, 1000, 4096] {
+        let input: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+        let mut ours = vec![0u8; len];
+        XC220::new(&key, &nonce).process(&input, &mut ours);
+        assert_eq!(ours, sodium_xchacha20_xor(&key, &nonce, &input), "{} bytes", len);
+
+        // and the keystream carries on across uneven calls
+        let mut split = vec![0u8; len];
+        let mut xcc20 = XC220::new(&key, &nonce);
+        let at = len / 3;
+        xcc20.process(&input[..at], &mut split[..at]);
+        xcc20.process(&input[at..], &mut split[at..]);
+        assert_eq!(split, ours, "{} bytes split at {}", len, at);
+    }
+}
+
+#[test]
+fn test_records_decrypt_with_libsodium() {
+    init();
+    let key = [0x42u8; 32];
+    for len in [0, 5, 64, 1000] {
+        let plain: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let mut sealed = Vec::new();
+        XCHACHA20_BLAKE3.seal(&key, &[&plain], &[], &mut sealed);
+
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - XCHACHA20_BLAKE3.overhead());
+        let nonce: [u8; 24] = tag.try_into().unwrap();
+        assert_eq!(sodium_xchacha20_xor(&key, &nonce, ciphertext), plain);
+    }
+}
+
+#[test]
+fn test_secretstream_output_is_pinned() {
+    init();
+    let key = [0x11u8; 32];
+    let header = [0x22u8; 24];
+
+    let mut push = sodium_secretstream_state(&key, &header);
+    let records: Vec<Vec<u8>> = chunks().iter().map(|(chunk, tag)| sodium_push(&mut push, chunk, *tag)).collect();
+
+    // libsodium reads its own stream back, tags, rekey and all
+    let mut pull = sodium_secretstream_state(&key, &header);
+    for (record, (chunk, tag)) in records.iter().zip(chunks()) {
+        assert_eq!(sodium_pull(&mut pull, record), Some((chunk, tag)));
+    }
+    // and a stream replayed from after the rekey doesn't decrypt
+    let mut replay = sodium_secretstream_state(&key, &header);
+    assert!(sodium_pull(&mut replay, &records[2]).is_none());
+
+    assert_eq!(hash(&records.concat()), SECRETSTREAM_HASH);
+}
+
+#[test]
+fn test_native_stream_output_is_pinned() {
+    let mut sesh1 = Session::with_key([0x11; 32]);
+    let mut sesh2 = Session::with_key([0x11; 32]);
+    // the stream's id is drawn from here
+    sesh1.set_random_source(Arc::new(SharedRng::new(StdRng::seed_from_u64(0x11))));
+
+    let mut stream = Vec::new();
+    let mut encryptor = sesh1.stream_encryptor();
+    for (chunk, _) in chunks() {
+        stream.extend(encryptor.update(&chunk).unwrap());
+    }
+    stream.extend(encryptor.finish().unwrap());
+
+    let mut decryptor = sesh2.stream_decryptor();
+    let mut plain = decryptor.update(&stream).unwrap();
+    plain.extend(decryptor.finish().unwrap());
+    assert_eq!(plain, chunks().into_iter().flat_map(|(chunk, _)| chunk).collect::<Vec<u8>>());
+
+    assert_eq!(hash(&stream), NATIVE_STREAM_HASH);
+    assert_ne!(NATIVE_STREAM_HASH, SECRETSTREAM_HASH);
+}
+
+const SECRETSTREAM_HASH: &str = "50fe1e1d9e987db2ab06340c640379347743bd52a988bf271b669a7b2c70a816";
+const NATIVE_STREAM_HASH: &str = "07377889a997a3d20f37f2d5603fc4cb46908e58520764b9be3756b1c6e88a96";
@@ -0,0 +1,1155 @@
+use core::iter::{FusedIterator, Peekable};
+
+use crate::kex::{KeyExchange, Secp256k1};
+use crate::record::OVERHEAD;
+use crate::session::{Session, SessionError};
+
+/// Default plaintext bytes per chunk for [`StreamEncryptor`], see
+/// [`SessionConfig::stream_chunk_len`](crate::SessionConfig::stream_chunk_len).
+/// Every chunk but the last, and any [`drain`](StreamEncryptor::drain)ed
+/// early, is exactly the chunk length, the last is shorter (possibly empty).
+pub const STREAM_CHUNK_LEN: usize = xc220b3_core::protocol::STREAM_CHUNK_LEN;
+
+/// Shortest [`SessionConfig::stream_chunk_len`](crate::SessionConfig::stream_chunk_len),
+/// below which the records' overhead swamps the data.
+pub const MIN_STREAM_CHUNK_LEN: usize = 1024;
+
+/// Longest [`SessionConfig::stream_chunk_len`](crate::SessionConfig::stream_chunk_len).
+pub const MAX_STREAM_CHUNK_LEN: usize = 16 * 1024 * 1024;
+
+/// Bytes of the random id in front of a stream's first record, see
+/// [`Session::stream_encryptor`] and [`Session::encrypt_iter`].
+pub const STREAM_ID_LEN: usize = 16;
+
+// a full chunk's record in a sealed file, whose sessions are unordered
+pub(crate) const STREAM_RECORD_LEN: usize = STREAM_CHUNK_LEN + OVERHEAD;
+
+// what a chunk is, in front of each of a byte stream's records after the id.
+// a drained chunk has its le32 plaintext length after it, so the decryptor
+// can find where it ends, and the final chunk runs to the end of the stream
+const FULL_CHUNK: u8 = 0;
+const FINAL_CHUNK: u8 = 1;
+const DRAINED_CHUNK: u8 = 2;
+
+pub(crate) const KIND_LEN: usize = 1;
+
+// the kind and a drained chunk's length
+const MAX_KIND_LEN: usize = KIND_LEN + 4;
+
+const STREAM_AAD_CONTEXT: &str = "xc220b3 2022-10 stream aad";
+
+// every chunk is sealed with its position in the stream, its kind, and the
+// stream's id under the MAC, so chunks can't be reordered, dropped, moved in
+// from another stream under the same keys, or have the stream cut short, by
+// another stream's terminator or otherwise, without the receiver noticing.
+// the id is random, and goes in the clear in front of the first chunk's
+// record. a sealed file's stream is the only one its payload key ever seals,
+// and has none, nor kinds in front of its records: its chunks are cut by
+// length alone, and only say whether they're the last
+fn chunk_aad(index: u64, kind: &[u8], id: Option<&[u8; STREAM_ID_LEN]>) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(8 + MAX_KIND_LEN + STREAM_ID_LEN);
+    aad.extend_from_slice(&index.to_le_bytes());
+    aad.extend_from_slice(kind);
+    if let Some(id) = id {
+        aad.extend_from_slice(id);
+    }
+    aad
+}
+
+// a stream cut into chunks of other than the default length has that length
+// under every chunk's MAC too, so a decryptor expecting other boundaries fails
+// on the first chunk rather than misreading them. default streams are sealed
+// without it
+fn sized_chunk_aad(
+    index: u64,
+    kind: &[u8],
+    id: Option<&[u8; STREAM_ID_LEN]>,
+    chunk_len: usize,
+    aad_hash: Option<&[u8; 32]>,
+) -> Vec<u8> {
+    let mut aad = chunk_aad(index, kind, id);
+    if chunk_len != STREAM_CHUNK_LEN {
+        aad.extend_from_slice(&(chunk_len as u32).to_le_bytes());
+    }
+    if let Some(hash) = aad_hash {
+        aad.extend_from_slice(hash);
+    }
+    aad
+}
+
+// associated data too big to hold, fed in pieces before the stream's data.
+// it's hashed as it comes, and the hash goes under every chunk's MAC after
+// the rest of its aad, so each chunk is bound to all of it. a stream without
+// any is sealed as it always was
+#[derive(Default)]
+struct StreamAad {
+    hasher: Option<blake3::Hasher>,
+    hash: Option<[u8; 32]>,
+    started: bool,
+}
+
+impl StreamAad {
+    fn update(&mut self, chunk: &[u8]) -> Result<(), SessionError> {
+        if self.started {
+            return Err(SessionError::AadAfterData);
+        }
+        self.hasher.get_or_insert_with(|| blake3::Hasher::new_derive_key(STREAM_AAD_CONTEXT)).update(chunk);
+        Ok(())
+    }
+
+    // the data has started, there's no more aad
+    fn finish(&mut self) -> Option<&[u8; 32]> {
+        if !self.started {
+            self.started = true;
+            self.hash = self.hasher.take().map(|hasher| *hasher.finalize().as_bytes());
+        }
+        self.hash.as_ref()
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// Lazily encrypts each chunk of `chunks` into its own record. Nothing is
+    /// encrypted until the returned iterator is advanced, and it stops after
+    /// the first error.
+    ///
+    /// After the last chunk an empty terminator record is yielded, so an empty
+    /// input still produces a valid (one record) stream. The first record
+    /// starts with the stream's random id, [`STREAM_ID_LEN`] bytes that every
+    /// record's MAC covers, so records can't be moved between two streams
+    /// from the same session.
+    pub fn encrypt_iter<I>(&mut self, chunks: I) -> EncryptIter<'_, I::IntoIter, K>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        EncryptIter {
+            id: self.stream_id(),
+            session: self,
+            chunks: chunks.into_iter(),
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Starts encrypting a byte stream of any length, see [`StreamEncryptor`].
+    pub fn stream_encryptor(&mut self) -> StreamEncryptor<'_, K> {
+        let id = self.stream_id();
+        self.stream_encryptor_with(Some(id))
+    }
+
+    /// Starts decrypting a byte stream from [`Session::stream_encryptor`].
+    pub fn stream_decryptor(&mut self) -> StreamDecryptor<'_, K> {
+        self.stream_decryptor_with(true)
+    }
+
+    // a sealed file's stream, laid out as streams were before they had ids
+    pub(crate) fn unbound_stream_encryptor(&mut self) -> StreamEncryptor<'_, K> {
+        self.stream_encryptor_with(None)
+    }
+
+    pub(crate) fn unbound_stream_decryptor(&mut self) -> StreamDecryptor<'_, K> {
+        self.stream_decryptor_with(false)
+    }
+
+    fn stream_id(&mut self) -> [u8; STREAM_ID_LEN] {
+        let mut id = [0u8; STREAM_ID_LEN];
+        self.random.fill(&mut id);
+        id
+    }
+
+    fn stream_encryptor_with(&mut self, id: Option<[u8; STREAM_ID_LEN]>) -> StreamEncryptor<'_, K> {
+        StreamEncryptor {
+            chunk_len: self.config.stream_chunk_len,
+            session: self,
+            buf: Vec::new(),
+            index: 0,
+            id,
+            aad: StreamAad::default(),
+        }
+    }
+
+    fn stream_decryptor_with(&mut self, bound: bool) -> StreamDecryptor<'_, K> {
+        StreamDecryptor {
+            chunk_len: self.config.stream_chunk_len,
+            session: self,
+            buf: Vec::new(),
+            index: 0,
+            bound,
+            id: None,
+            finished: false,
+            aborted: false,
+            aad: StreamAad::default(),
+        }
+    }
+
+    /// Lazily decrypts records produced by [`Session::encrypt_iter`], in the
+    /// same order.
+    ///
+    /// The iterator stops after the first error, so no plaintext is released
+    /// past a tampered, reordered or missing record. Input that ends without
+    /// the terminator yields [`SessionError::TruncatedStream`].
+    ///
+    /// One record is read ahead to tell whether the current record is the
+    /// terminator.
+    pub fn decrypt_iter<I>(&mut self, records: I) -> DecryptIter<'_, I::IntoIter, K>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        DecryptIter {
+            session: self,
+            records: records.into_iter().peekable(),
+            index: 0,
+            id: None,
+            done: false,
+        }
+    }
+}
+
+pub struct EncryptIter<'a, I: Iterator<Item = Vec<u8>>, K: KeyExchange = Secp256k1> {
+    session: &'a mut Session<K>,
+    chunks: I,
+    index: u64,
+    id: [u8; STREAM_ID_LEN],
+    done: bool,
+}
+
+impl<'a, I: Iterator<Item = Vec<u8>>, K: KeyExchange> Iterator for EncryptIter<'a, I, K> {
+    type Item = Result<Vec<u8>, SessionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let chunk = self.chunks.next();
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.session.seal_span("chunk", chunk.as_ref().map_or(0, |c| c.len()));
+                let _enter = span.enter();
+            }
+        );
+        let last = chunk.is_none();
+        let aad = chunk_aad(self.index, &[last as u8], Some(&self.id));
+        let mut record = self.session.seal(chunk.as_deref().unwrap_or_default(), &aad);
+        self.done = last;
+        if let (0, Ok(record)) = (self.index, &mut record) {
+            record.splice(..0, self.id);
+        }
+        self.index += 1;
+        if record.is_err() {
+            self.done = true;
+        }
+        Some(record)
+    }
+}
+
+impl<'a, I: Iterator<Item = Vec<u8>>, K: KeyExchange> FusedIterator for EncryptIter<'a, I, K> {}
+
+pub struct DecryptIter<'a, I: Iterator<Item = Vec<u8>>, K: KeyExchange = Secp256k1> {
+    session: &'a mut Session<K>,
+    records: Peekable<I>,
+    index: u64,
+    id: Option<[u8; STREAM_ID_LEN]>,
+    done: bool,
+}
+
+impl<'a, I: Iterator<Item = Vec<u8>>, K: KeyExchange> Iterator for DecryptIter<'a, I, K> {
+    type Item = Result<Vec<u8>, SessionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut record = match self.records.next() {
+            Some(record) => record,
+            None => {
+                self.done = true;
+                return Some(Err(SessionError::TruncatedStream));
+            }
+        };
+        let last = self.records.peek().is_none();
+        if self.id.is_none() {
+            if record.len() < STREAM_ID_LEN {
+                self.done = true;
+                return Some(Err(SessionError::InvalidLength(record.len())));
+            }
+            let rest = record.split_off(STREAM_ID_LEN);
+            self.id = Some(record.try_into().unwrap());
+            record = rest;
+        }
+
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.session.open_span("chunk", record.len());
+                let _enter = span.enter();
+            }
+        );
+        let result = self.session.open(record, &chunk_aad(self.index, &[last as u8], self.id.as_ref()));
+        self.index += 1;
+        match result {
+            // the terminator carries no data
+            Ok(_) if last => {
+                self.done = true;
+                None
+            }
+            Ok(plain) => Some(Ok(plain)),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Vec<u8>>, K: KeyExchange> FusedIterator for DecryptIter<'a, I, K> {}
+
+/// Splits a byte stream into chunks of [`SessionConfig::stream_chunk_len`](crate::SessionConfig::stream_chunk_len)
+/// bytes and seals each one as it fills, with the chunk index and a
+/// terminator flag under its MAC. The stream starts with a random id of
+/// [`STREAM_ID_LEN`] bytes, sent with the first chunk and under every
+/// chunk's MAC, so chunks can't be moved between two streams from the same
+/// session. Each record has a byte in front saying whether it's a full,
+/// drained or the final chunk.
+///
+/// [`drain`](Self::drain) seals what's buffered without ending the stream,
+/// for request/response over one long-lived stream. The cipher's nonce is
+/// the MAC of the whole chunk, so no ciphertext for a chunk exists until all
+/// of it is known: drained bytes go out as a short chunk of their own, with
+/// its length in front and under its MAC, and the stream goes on after it.
+pub struct StreamEncryptor<'a, K: KeyExchange = Secp256k1> {
+    session: &'a mut Session<K>,
+    chunk_len: usize,
+    buf: Vec<u8>,
+    index: u64,
+    id: Option<[u8; STREAM_ID_LEN]>,
+    aad: StreamAad,
+}
+
+impl<'a, K: KeyExchange> StreamEncryptor<'a, K> {
+    /// Feeds more associated data, authenticated under every chunk's MAC but
+    /// not encrypted or sent, for headers too big to hold in memory. All of
+    /// it comes before the first [`update`](Self::update), after which this
+    /// is [`SessionError::AadAfterData`]. The decryptor is fed the same
+    /// bytes with [`StreamDecryptor::update_aad`], in pieces of any size.
+    pub fn update_aad(&mut self, chunk: &[u8]) -> Result<(), SessionError> {
+        self.aad.update(chunk)
+    }
+
+    /// Feeds more plaintext, returning the records for any chunks it
+    /// completed. Partial chunks are held until more data or `finish`.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<Vec<u8>, SessionError> {
+        self.aad.finish();
+        let mut output = Vec::new();
+
+        if !self.buf.is_empty() {
+            let take = core::cmp::min(self.chunk_len - self.buf.len(), data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buf.len() < self.chunk_len {
+                return Ok(output);
+            }
+            let chunk = core::mem::take(&mut self.buf);
+            self.seal_chunk(&chunk, &[FULL_CHUNK], &mut output)?;
+        }
+
+        // seal straight out of the input where we can
+        while data.len() >= self.chunk_len {
+            self.seal_chunk(&data[..self.chunk_len], &[FULL_CHUNK], &mut output)?;
+            data = &data[self.chunk_len..];
+        }
+        self.buf.extend_from_slice(data);
+
+        Ok(output)
+    }
+
+    /// Seals whatever is buffered as a short chunk of its own and returns
+    /// it, leaving the stream open for more. The decryptor hands the chunk
+    /// out as soon as it has all of it. Nothing buffered, nothing is sealed:
+    /// a stream drained only then is the stream `update` and `finish` alone
+    /// would have made.
+    pub fn drain(&mut self) -> Result<Vec<u8>, SessionError> {
+        // a sealed file's decryptor cuts chunks by length, and never drains
+        debug_assert!(self.id.is_some());
+        self.aad.finish();
+        let mut output = Vec::new();
+        if !self.buf.is_empty() {
+            let chunk = core::mem::take(&mut self.buf);
+            let mut kind = vec![DRAINED_CHUNK];
+            kind.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            self.seal_chunk(&chunk, &kind, &mut output)?;
+        }
+        Ok(output)
+    }
+
+    /// Seals whatever is buffered as the final chunk and ends the stream.
+    pub fn finish(mut self) -> Result<Vec<u8>, SessionError> {
+        let mut output = Vec::new();
+        let chunk = core::mem::take(&mut self.buf);
+        self.seal_chunk(&chunk, &[FINAL_CHUNK], &mut output)?;
+        Ok(output)
+    }
+
+    fn seal_chunk(&mut self, chunk: &[u8], kind: &[u8], output: &mut Vec<u8>) -> Result<(), SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.session.seal_span("chunk", chunk.len());
+                let _enter = span.enter();
+            }
+        );
+        let aad = sized_chunk_aad(self.index, kind, self.id.as_ref(), self.chunk_len, self.aad.finish());
+        let record = self.session.seal(chunk, &aad)?;
+        if let Some(id) = &self.id {
+            if self.index == 0 {
+                output.extend_from_slice(id);
+            }
+            output.extend_from_slice(kind);
+        }
+        self.index += 1;
+        output.extend_from_slice(&record);
+        Ok(())
+    }
+}
+
+/// Reverses [`StreamEncryptor`], releasing each chunk only once its MAC has
+/// been checked. A chunk that fails its check aborts the stream: every call
+/// after it fails with [`SessionError::MacMismatch`] too.
+pub struct StreamDecryptor<'a, K: KeyExchange = Secp256k1> {
+    session: &'a mut Session<K>,
+    chunk_len: usize,
+    buf: Vec<u8>,
+    index: u64,
+    // whether the stream starts with an id, and the id once it has
+    bound: bool,
+    id: Option<[u8; STREAM_ID_LEN]>,
+    finished: bool,
+    aborted: bool,
+    aad: StreamAad,
+}
+
+impl<'a, K: KeyExchange> StreamDecryptor<'a, K> {
+    /// The associated data the encryptor was fed with
+    /// [`StreamEncryptor::update_aad`], all of it before the first
+    /// [`update`](Self::update) or [`next_chunk`](Self::next_chunk). Any
+    /// difference fails the first chunk's MAC.
+    pub fn update_aad(&mut self, chunk: &[u8]) -> Result<(), SessionError> {
+        self.aad.update(chunk)
+    }
+
+    /// Feeds more ciphertext, returning the plaintext of every full or
+    /// drained chunk it completed. The final chunk is only checked by
+    /// `finish`.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if self.aborted {
+            return Err(SessionError::MacMismatch);
+        }
+        self.aad.finish();
+        let mut output = Vec::new();
+
+        // top up a chunk, or what says how long it is, from an earlier call
+        while !self.buf.is_empty() && !data.is_empty() {
+            let want = match self.checked(self.next(&self.buf))? {
+                Next::Chunk(len) | Next::Header(len) => len,
+                Next::Final => break,
+            };
+            let take = core::cmp::min(want - self.buf.len(), data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if let Next::Chunk(len) = self.checked(self.next(&self.buf))? {
+                if self.buf.len() == len {
+                    let record = core::mem::take(&mut self.buf);
+                    self.open_chunk(record, false, &mut output)?;
+                }
+            }
+        }
+
+        if self.buf.is_empty() {
+            while let Next::Chunk(len) = self.checked(self.next(data))? {
+                if data.len() < len {
+                    break;
+                }
+                self.open_chunk(data[..len].to_vec(), false, &mut output)?;
+                data = &data[len..];
+            }
+        }
+        self.buf.extend_from_slice(data);
+
+        Ok(output)
+    }
+
+    /// Checks the final chunk and returns its plaintext, which is empty if
+    /// [`next_chunk`](StreamDecryptor::next_chunk) already took it. Fails
+    /// with [`SessionError::TruncatedStream`] if the stream was cut off
+    /// before it, however many whole chunks came first, or if nothing came
+    /// at all: even an empty stream has its final chunk.
+    pub fn finish(mut self) -> Result<Vec<u8>, SessionError> {
+        if self.aborted {
+            return Err(SessionError::MacMismatch);
+        }
+        if self.finished {
+            return Ok(Vec::new());
+        }
+        let complete = match self.bound {
+            true => matches!(self.checked(self.next(&self.buf))?, Next::Final),
+            false => true,
+        };
+        if !complete || self.buf.len() < self.full_len(0) {
+            return Err(SessionError::TruncatedStream);
+        }
+        let mut output = Vec::new();
+        let record = core::mem::take(&mut self.buf);
+        self.open_chunk(record, true, &mut output)?;
+        Ok(output)
+    }
+
+    /// Takes one chunk's record as it arrives, rather than a byte stream,
+    /// and gives its plaintext as soon as its MAC checks, for media and the
+    /// like that can't wait for the end. Each chunk's position in the stream
+    /// is under its MAC, so a reordered or dropped chunk fails as a tampered
+    /// one does. Records are as the encryptor gave them: the first has the
+    /// stream's id in front, and each its kind. Anything after the final
+    /// chunk is [`SessionError::TrailingData`]. Empty input, nothing having
+    /// arrived, is `None`. Not to be mixed with `update` on the same
+    /// decryptor.
+    pub fn next_chunk(&mut self, record: &[u8]) -> Result<Option<Vec<u8>>, SessionError> {
+        if self.aborted {
+            return Err(SessionError::MacMismatch);
+        }
+        self.aad.finish();
+        if record.is_empty() {
+            return Ok(None);
+        }
+        if self.finished {
+            return Err(SessionError::TrailingData(record.len()));
+        }
+        // a sealed file's final chunk is the one shorter than a full chunk
+        let last = match (self.bound, self.checked(self.next(record))?) {
+            (false, Next::Chunk(len)) if record.len() <= len => record.len() < len,
+            (true, Next::Chunk(len)) if record.len() == len => false,
+            (true, Next::Final) if record.len() >= self.full_len(0) => true,
+            _ => return Err(SessionError::InvalidLength(record.len())),
+        };
+        let mut output = Vec::new();
+        self.open_chunk(record.to_vec(), last, &mut output)?;
+        self.finished = last;
+        Ok(Some(output))
+    }
+
+    /// Whether the final chunk has been through
+    /// [`next_chunk`](StreamDecryptor::next_chunk).
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // the stream's id, if it hasn't been read yet
+    fn id_len(&self) -> usize {
+        match self.bound && self.id.is_none() {
+            true => STREAM_ID_LEN,
+            false => 0,
+        }
+    }
+
+    // the bytes of the next chunk, if it has `plaintext_len` bytes and is of
+    // the one-byte kinds, with the id in front of the first
+    fn full_len(&self, plaintext_len: usize) -> usize {
+        let kind_len = if self.bound { KIND_LEN } else { 0 };
+        self.id_len() + kind_len + self.session.ciphertext_len(plaintext_len)
+    }
+
+    // what `head`, the start of the next chunk, says about it
+    fn next(&self, head: &[u8]) -> Result<Next, SessionError> {
+        if !self.bound {
+            return Ok(Next::Chunk(self.full_len(self.chunk_len)));
+        }
+        let at = self.id_len();
+        let next = match head.get(at) {
+            None => Next::Header(at + KIND_LEN),
+            Some(&FULL_CHUNK) => Next::Chunk(self.full_len(self.chunk_len)),
+            Some(&FINAL_CHUNK) => Next::Final,
+            Some(&DRAINED_CHUNK) => match head.get(at + 1..at + MAX_KIND_LEN) {
+                None => Next::Header(at + MAX_KIND_LEN),
+                Some(len) => {
+                    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+                    if len == 0 || len >= self.chunk_len {
+                        return Err(SessionError::InvalidLength(len));
+                    }
+                    Next::Chunk(self.full_len(len) + MAX_KIND_LEN - KIND_LEN)
+                }
+            },
+            Some(_) => return Err(SessionError::MacMismatch),
+        };
+        Ok(next)
+    }
+
+    // a kind that's none of them, or a drained length no drain makes, fails
+    // the stream as a tampered chunk would
+    fn checked(&mut self, next: Result<Next, SessionError>) -> Result<Next, SessionError> {
+        if next.is_err() {
+            self.aborted = true;
+        }
+        next
+    }
+
+    fn open_chunk(&mut self, mut record: Vec<u8>, last: bool, output: &mut Vec<u8>) -> Result<(), SessionError> {
+        if self.id_len() > 0 {
+            let rest = record.split_off(STREAM_ID_LEN);
+            self.id = Some(record.try_into().unwrap());
+            record = rest;
+        }
+        let kind = match self.bound {
+            true => {
+                let kind_len = match record[0] {
+                    DRAINED_CHUNK => MAX_KIND_LEN,
+                    _ => KIND_LEN,
+                };
+                let rest = record.split_off(kind_len);
+                core::mem::replace(&mut record, rest)
+            }
+            false => vec![last as u8],
+        };
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.session.open_span("chunk", record.len());
+                let _enter = span.enter();
+            }
+        );
+        let aad = sized_chunk_aad(self.index, &kind, self.id.as_ref(), self.chunk_len, self.aad.finish());
+        let plain = match self.session.open(record, &aad) {
+            Ok(plain) => plain,
+            Err(e) => {
+                self.aborted = true;
+                return Err(e);
+            }
+        };
+        self.index += 1;
+        output.extend_from_slice(&plain);
+        Ok(())
+    }
+}
+
+// what the next chunk of a byte stream is
+enum Next {
+    // a full or drained chunk of this many bytes, all there is to it
+    Chunk(usize),
+    // more bytes are needed to tell, at least this many
+    Header(usize),
+    // the final chunk, the rest of the stream
+    Final,
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::Cell;
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, thread_rng, SeedableRng};
+
+    use crate::builder::SessionBuilder;
+    use crate::random::SharedRng;
+    use crate::session::{Session, SessionError};
+    use crate::record::ORDERED_OVERHEAD;
+    use crate::stream::{KIND_LEN, MIN_STREAM_CHUNK_LEN, STREAM_CHUNK_LEN, STREAM_ID_LEN};
+
+    // a full chunk's record from a default, ordered session, and its kind
+    const RECORD_LEN: usize = KIND_LEN + STREAM_CHUNK_LEN + ORDERED_OVERHEAD;
+
+    fn chunks() -> Vec<Vec<u8>> {
+        vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+    }
+
+    // a default stream's records, the first with the stream's id in front
+    fn split_records(session: &Session, ciphertext: &[u8]) -> Vec<Vec<u8>> {
+        let record_len = KIND_LEN + session.ciphertext_len(STREAM_CHUNK_LEN);
+        let first = core::cmp::min(STREAM_ID_LEN + record_len, ciphertext.len());
+        let mut records = vec![ciphertext[..first].to_vec()];
+        records.extend(ciphertext[first..].chunks(record_len).map(<[u8]>::to_vec));
+        records
+    }
+
+    #[test]
+    fn test_iter_roundtrip() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+
+        let records: Vec<Vec<u8>> = sesh1.encrypt_iter(chunks()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(records.len(), 4);
+
+        let plain: Result<Vec<Vec<u8>>, SessionError> = sesh2.decrypt_iter(records).collect();
+        assert_eq!(plain.unwrap(), chunks());
+    }
+
+    #[test]
+    fn test_iter_is_lazy() {
+        let (mut sesh1, _) = Session::pair(&mut thread_rng());
+        let pulled = Cell::new(0);
+
+        let mut iter = sesh1.encrypt_iter(chunks().into_iter().inspect(|_| pulled.set(pulled.get() + 1)));
+        assert_eq!(pulled.get(), 0);
+
+        iter.next();
+        assert_eq!(pulled.get(), 1);
+    }
+
+    #[test]
+    fn test_iter_stops_at_tampered_chunk() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+
+        let mut records: Vec<Vec<u8>> = sesh1.encrypt_iter(chunks()).collect::<Result<_, _>>().unwrap();
+        records[1][1] ^= 1;
+
+        let mut iter = sesh2.decrypt_iter(records);
+        assert_eq!(iter.next().unwrap().unwrap(), b"one");
+        assert!(matches!(iter.next(), Some(Err(SessionError::MacMismatch))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_iter_rejects_reordering_and_truncation() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let records: Vec<Vec<u8>> = sesh1.encrypt_iter(chunks()).collect::<Result<_, _>>().unwrap();
+
+        let mut swapped = records.clone();
+        swapped.swap(1, 2);
+        let mut receiver = sesh2.clone();
+        let mut iter = receiver.decrypt_iter(swapped);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(iter.next(), Some(Err(SessionError::MacMismatch))));
+
+        let mut iter = sesh2.decrypt_iter(records[..3].to_vec());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(iter.next(), Some(Err(SessionError::MacMismatch))));
+
+        let mut iter = sesh2.decrypt_iter(Vec::new());
+        assert!(matches!(iter.next(), Some(Err(SessionError::TruncatedStream))));
+    }
+
+    #[test]
+    fn test_iter_empty_stream() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+
+        let records: Vec<Vec<u8>> = sesh1.encrypt_iter(Vec::new()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].len(), STREAM_ID_LEN + ORDERED_OVERHEAD);
+
+        assert!(sesh2.decrypt_iter(records).next().is_none());
+    }
+
+    fn stream_roundtrip(len: usize, feed: usize) {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+        let mut encryptor = sesh1.stream_encryptor();
+        let mut ciphertext = Vec::new();
+        for piece in data.chunks(feed) {
+            ciphertext.extend(encryptor.update(piece).unwrap());
+        }
+        ciphertext.extend(encryptor.finish().unwrap());
+        assert_eq!(ciphertext.len(), STREAM_ID_LEN + len + (KIND_LEN + ORDERED_OVERHEAD) * (len / STREAM_CHUNK_LEN + 1));
+
+        let mut decryptor = sesh2.stream_decryptor();
+        let mut plain = Vec::new();
+        for piece in ciphertext.chunks(feed) {
+            plain.extend(decryptor.update(piece).unwrap());
+        }
+        plain.extend(decryptor.finish().unwrap());
+        assert_eq!(plain, data);
+    }
+
+    // cargo test --release -- --ignored
+    #[test]
+    #[ignore = "encrypts and decrypts over 4 GiB"]
+    fn test_stream_past_4_gib() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let len = (1u64 << 32) + STREAM_CHUNK_LEN as u64 + 17;
+        let zeros = vec![0u8; 1 << 20];
+
+        let mut encryptor = sesh1.stream_encryptor();
+        let mut decryptor = sesh2.stream_decryptor();
+        let (mut fed, mut received) = (0u64, 0u64);
+        while fed < len {
+            let piece = &zeros[..core::cmp::min(zeros.len() as u64, len - fed) as usize];
+            fed += piece.len() as u64;
+            let plain = decryptor.update(&encryptor.update(piece).unwrap()).unwrap();
+            assert!(plain.iter().all(|&b| b == 0));
+            received += plain.len() as u64;
+        }
+        let plain = decryptor.update(&encryptor.finish().unwrap()).unwrap();
+        received += plain.len() as u64;
+        received += decryptor.finish().unwrap().len() as u64;
+        assert_eq!(received, len);
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        stream_roundtrip(0, 1);
+        stream_roundtrip(10, 3);
+        stream_roundtrip(STREAM_CHUNK_LEN, 1000);
+        stream_roundtrip(3 * STREAM_CHUNK_LEN + 17, 50_000);
+        stream_roundtrip(2 * STREAM_CHUNK_LEN + 5, 4 * STREAM_CHUNK_LEN);
+    }
+
+    #[test]
+    fn test_stream_output_is_independent_of_feeding() {
+        let (sesh1, _) = Session::pair(&mut thread_rng());
+        let data: Vec<u8> = (0..=255).cycle().take(2 * STREAM_CHUNK_LEN + 300).collect();
+
+        // both draw the same stream id
+        let (mut oneshot, mut sesh1) = (seeded(&sesh1), seeded(&sesh1));
+        let mut encryptor = oneshot.stream_encryptor();
+        let mut expected = encryptor.update(&data).unwrap();
+        expected.extend(encryptor.finish().unwrap());
+
+        // whatever update hands out along the way is a prefix of the one-shot
+        // stream, and only whole records of it, the first with the id
+        let mut encryptor = sesh1.stream_encryptor();
+        let mut ciphertext = Vec::new();
+        for piece in data.chunks(1000) {
+            ciphertext.extend(encryptor.update(piece).unwrap());
+            assert!(ciphertext.is_empty() || (ciphertext.len() - STREAM_ID_LEN).is_multiple_of(RECORD_LEN));
+            assert_eq!(&expected[..ciphertext.len()], &ciphertext[..]);
+        }
+        assert_eq!(ciphertext.len(), STREAM_ID_LEN + 2 * RECORD_LEN);
+        ciphertext.extend(encryptor.finish().unwrap());
+        assert_eq!(ciphertext, expected);
+    }
+
+    #[test]
+    fn test_stream_detects_truncation() {
+        let (mut sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let data = vec![7u8; 2 * STREAM_CHUNK_LEN + 100];
+
+        let mut encryptor = sesh1.stream_encryptor();
+        let mut ciphertext = encryptor.update(&data).unwrap();
+        ciphertext.extend(encryptor.finish().unwrap());
+
+        // cut right after a full chunk, the terminator is gone
+        let cut = STREAM_ID_LEN + 2 * RECORD_LEN;
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        assert_eq!(decryptor.update(&ciphertext[..cut]).unwrap().len(), 2 * STREAM_CHUNK_LEN);
+        assert!(matches!(decryptor.finish(), Err(SessionError::TruncatedStream)));
+
+        // cut into the middle of the final chunk
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        decryptor.update(&ciphertext[..ciphertext.len() - 1]).unwrap();
+        assert!(matches!(decryptor.finish(), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_next_chunk_releases_each_chunk_once_checked() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let data: Vec<u8> = (0..=255).cycle().take(2 * STREAM_CHUNK_LEN + 5).collect();
+        let mut encryptor = sesh1.stream_encryptor();
+        let mut ciphertext = encryptor.update(&data).unwrap();
+        ciphertext.extend(encryptor.finish().unwrap());
+        let records = split_records(&sesh1, &ciphertext);
+        assert_eq!(records.len(), 3);
+
+        let (mut whole, mut corrupt) = (sesh2.clone(), sesh2.clone());
+        let mut decryptor = whole.stream_decryptor();
+        let mut plain = Vec::new();
+        for record in &records {
+            plain.extend(decryptor.next_chunk(record).unwrap().unwrap());
+        }
+        assert!(decryptor.is_finished());
+        assert!(decryptor.next_chunk(&[]).unwrap().is_none());
+        assert_eq!(plain, data);
+
+        // chunk 2 corrupted: chunk 1 is out already, and nothing after it is
+        let mut corrupted = records[1].to_vec();
+        corrupted[100] ^= 1;
+        let mut decryptor = corrupt.stream_decryptor();
+        assert_eq!(decryptor.next_chunk(&records[0]).unwrap().unwrap(), data[..STREAM_CHUNK_LEN]);
+        assert!(matches!(decryptor.next_chunk(&corrupted), Err(SessionError::MacMismatch)));
+        assert!(matches!(decryptor.next_chunk(&records[1]), Err(SessionError::MacMismatch)));
+        assert!(matches!(decryptor.next_chunk(&records[2]), Err(SessionError::MacMismatch)));
+        assert!(!decryptor.is_finished());
+
+        // nor does a chunk open anywhere but its own place, even behind the
+        // stream's id
+        let moved = [&records[0][..STREAM_ID_LEN], &records[1]].concat();
+        let mut decryptor = sesh2.stream_decryptor();
+        assert!(matches!(decryptor.next_chunk(&moved), Err(SessionError::MacMismatch)));
+    }
+
+    // two full chunks and a short final one
+    fn three_chunks(session: &mut Session) -> Vec<Vec<u8>> {
+        let mut encryptor = session.stream_encryptor();
+        let mut ciphertext = encryptor.update(&[9; 2 * STREAM_CHUNK_LEN + 5]).unwrap();
+        ciphertext.extend(encryptor.finish().unwrap());
+        split_records(session, &ciphertext)
+    }
+
+    #[test]
+    fn test_stream_needs_its_final_chunk() {
+        let (mut sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let records = three_chunks(&mut sesh1);
+
+        // the final chunk dropped, chunk by chunk or as bytes
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        for record in &records[..2] {
+            decryptor.next_chunk(record).unwrap();
+        }
+        assert!(!decryptor.is_finished());
+        assert!(matches!(decryptor.finish(), Err(SessionError::TruncatedStream)));
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        decryptor.update(&records[..2].concat()).unwrap();
+        assert!(matches!(decryptor.finish(), Err(SessionError::TruncatedStream)));
+
+        // a middle chunk dropped, the next one isn't where it was sealed
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        decryptor.next_chunk(&records[0]).unwrap();
+        assert!(matches!(decryptor.next_chunk(&records[2]), Err(SessionError::MacMismatch)));
+        assert!(matches!(decryptor.finish(), Err(SessionError::MacMismatch)));
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        decryptor.update(&[&records[0][..], &records[2]].concat()).unwrap();
+        assert!(matches!(decryptor.finish(), Err(SessionError::MacMismatch)));
+
+        // the whole stream, then finish
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        for record in &records {
+            decryptor.next_chunk(record).unwrap();
+        }
+        assert_eq!(decryptor.finish().unwrap(), b"");
+    }
+
+    #[test]
+    fn test_stream_refuses_chunks_after_the_final_one() {
+        let (mut sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let records = three_chunks(&mut sesh1);
+        let more = three_chunks(&mut sesh1);
+
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        for record in &records {
+            decryptor.next_chunk(record).unwrap();
+        }
+        assert!(matches!(decryptor.next_chunk(&more[0]), Err(SessionError::TrailingData(len)) if len == STREAM_ID_LEN + RECORD_LEN));
+
+        // as bytes the final chunk and what follows it are read as one
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        decryptor.update(&[records.concat(), b"extra".to_vec()].concat()).unwrap();
+        assert!(matches!(decryptor.finish(), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_empty_stream_still_has_its_final_chunk() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let terminator = sesh1.stream_encryptor().finish().unwrap();
+        assert_eq!(terminator.len(), STREAM_ID_LEN + KIND_LEN + ORDERED_OVERHEAD);
+        assert!(matches!(sesh2.stream_decryptor().finish(), Err(SessionError::TruncatedStream)));
+
+        let mut decryptor = sesh2.stream_decryptor();
+        assert_eq!(decryptor.next_chunk(&terminator).unwrap().unwrap(), b"");
+        assert!(decryptor.is_finished());
+        assert_eq!(decryptor.finish().unwrap(), b"");
+    }
+
+    fn chunked_pair(len1: usize, len2: usize) -> (Session, Session) {
+        let mut sesh1 = SessionBuilder::new().stream_chunk_len(len1).build(&mut thread_rng()).unwrap();
+        let mut sesh2 = SessionBuilder::new().stream_chunk_len(len2).build(&mut thread_rng()).unwrap();
+        let (pk1, pk2) = (sesh1.pk().unwrap(), sesh2.pk().unwrap());
+        sesh1.set_sym_key(&pk2).unwrap();
+        sesh2.set_sym_key(&pk1).unwrap();
+        (sesh1, sesh2)
+    }
+
+    #[test]
+    fn test_stream_chunk_lens() {
+        let data: Vec<u8> = (0..=255).cycle().take(3 * STREAM_CHUNK_LEN + 100).collect();
+        let mut plains = Vec::new();
+        for len in [MIN_STREAM_CHUNK_LEN, 256 * 1024] {
+            let (mut sesh1, mut sesh2) = chunked_pair(len, len);
+            let mut encryptor = sesh1.stream_encryptor();
+            let mut ciphertext = encryptor.update(&data).unwrap();
+            ciphertext.extend(encryptor.finish().unwrap());
+            assert_eq!(ciphertext.len(), STREAM_ID_LEN + data.len() + (KIND_LEN + ORDERED_OVERHEAD) * (data.len() / len + 1));
+
+            let mut decryptor = sesh2.stream_decryptor();
+            let mut plain = decryptor.update(&ciphertext).unwrap();
+            plain.extend(decryptor.finish().unwrap());
+            plains.push(plain);
+        }
+        assert_eq!(plains[0], data);
+        assert_eq!(plains[0], plains[1]);
+
+        // a decryptor expecting other boundaries fails on the first chunk,
+        // even one that happens to be a full chunk for it
+        for (len1, len2) in [(MIN_STREAM_CHUNK_LEN, STREAM_CHUNK_LEN), (2048, 1024)] {
+            let (mut sesh1, mut sesh2) = chunked_pair(len1, len2);
+            let mut encryptor = sesh1.stream_encryptor();
+            let ciphertext = encryptor.update(&data).unwrap();
+            let mut decryptor = sesh2.stream_decryptor();
+            assert!(matches!(decryptor.update(&ciphertext), Err(SessionError::MacMismatch)));
+        }
+    }
+
+    #[test]
+    fn test_streamed_aad() {
+        let (mut sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let aad: Vec<u8> = (0..=255).cycle().take(4 * 1024 * 1024 + 3).collect();
+        let data = vec![5u8; STREAM_CHUNK_LEN + 10];
+
+        let mut encryptor = sesh1.stream_encryptor();
+        for piece in aad.chunks(STREAM_CHUNK_LEN) {
+            encryptor.update_aad(piece).unwrap();
+        }
+        let mut ciphertext = encryptor.update(&data).unwrap();
+        assert!(matches!(encryptor.update_aad(b"late"), Err(SessionError::AadAfterData)));
+        ciphertext.extend(encryptor.finish().unwrap());
+        assert_eq!(ciphertext.len(), STREAM_ID_LEN + data.len() + 2 * (KIND_LEN + ORDERED_OVERHEAD));
+
+        // fed in other pieces, it's the same aad
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        for piece in aad.chunks(1_000_003) {
+            decryptor.update_aad(piece).unwrap();
+        }
+        let mut plain = decryptor.update(&ciphertext).unwrap();
+        assert!(matches!(decryptor.update_aad(b"late"), Err(SessionError::AadAfterData)));
+        plain.extend(decryptor.finish().unwrap());
+        assert_eq!(plain, data);
+
+        // one byte of it different, or none of it given, and nothing opens
+        let mut changed = aad.clone();
+        changed[3 * 1024 * 1024] ^= 1;
+        for given in [&changed[..], &[]] {
+            let mut receiver = sesh2.clone();
+            let mut decryptor = receiver.stream_decryptor();
+            decryptor.update_aad(given).unwrap();
+            assert!(matches!(decryptor.update(&ciphertext), Err(SessionError::MacMismatch)));
+        }
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        let records = split_records(&sesh2, &ciphertext);
+        assert!(matches!(decryptor.next_chunk(&records[0]), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_chunks_dont_move_between_streams() {
+        // unordered, so only the streams' ids tell them apart
+        let (mut sesh1, sesh2) = Session::unordered_pair();
+        let (first, second) = (three_chunks(&mut sesh1), three_chunks(&mut sesh1));
+
+        // a middle chunk, or the terminator, from the other stream
+        for at in [1, 2] {
+            let mut spliced = first.clone();
+            spliced[at] = second[at].clone();
+            let mut receiver = sesh2.clone();
+            let mut decryptor = receiver.stream_decryptor();
+            for record in &spliced[..at] {
+                decryptor.next_chunk(record).unwrap();
+            }
+            assert!(matches!(decryptor.next_chunk(&spliced[at]), Err(SessionError::MacMismatch)));
+
+            let mut receiver = sesh2.clone();
+            let mut decryptor = receiver.stream_decryptor();
+            let opened = decryptor.update(&spliced.concat()).and_then(|_| decryptor.finish());
+            assert!(matches!(opened, Err(SessionError::MacMismatch)));
+        }
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        assert_eq!(decryptor.update(&first.concat()).unwrap().len(), 2 * STREAM_CHUNK_LEN);
+        assert_eq!(decryptor.finish().unwrap(), [9; 5]);
+
+        // the same through the iterators
+        let first: Vec<Vec<u8>> = sesh1.encrypt_iter(chunks()).collect::<Result<_, _>>().unwrap();
+        let second: Vec<Vec<u8>> = sesh1.encrypt_iter(chunks()).collect::<Result<_, _>>().unwrap();
+        for at in [1, 3] {
+            let mut spliced = first.clone();
+            spliced[at] = second[at].clone();
+            let plain: Result<Vec<Vec<u8>>, SessionError> = sesh2.clone().decrypt_iter(spliced).collect();
+            assert!(matches!(plain, Err(SessionError::MacMismatch)));
+        }
+        let plain: Result<Vec<Vec<u8>>, SessionError> = sesh2.clone().decrypt_iter(first).collect();
+        assert_eq!(plain.unwrap(), chunks());
+    }
+
+    fn seeded(session: &Session) -> Session {
+        let mut session = session.clone();
+        session.set_random_source(Arc::new(SharedRng::new(StdRng::seed_from_u64(1))));
+        session
+    }
+
+    #[test]
+    fn test_drained_stream_opens_as_the_oneshot_one() {
+        let (sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let data: Vec<u8> = (0..=255).cycle().take(2 * STREAM_CHUNK_LEN + 300).collect();
+
+        let mut oneshot = seeded(&sesh1);
+        let mut encryptor = oneshot.stream_encryptor();
+        let mut expected = encryptor.update(&data).unwrap();
+        expected.extend(encryptor.finish().unwrap());
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        let mut oneshot_plain = decryptor.update(&expected).unwrap();
+        oneshot_plain.extend(decryptor.finish().unwrap());
+
+        // each drain hands the receiver everything fed so far, and the
+        // stream goes on after it
+        let mut drained = seeded(&sesh1);
+        let mut encryptor = drained.stream_encryptor();
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        let (mut records, mut plain) = (Vec::new(), Vec::new());
+        for (i, piece) in data.chunks(10_000).enumerate() {
+            assert!(encryptor.update(piece).unwrap().is_empty());
+            records.push(encryptor.drain().unwrap());
+            plain.extend(decryptor.update(&records[i]).unwrap());
+            assert_eq!(plain, data[..10_000 * i + piece.len()]);
+        }
+        assert!(encryptor.drain().unwrap().is_empty());
+        records.push(encryptor.finish().unwrap());
+        plain.extend(decryptor.update(records.last().unwrap()).unwrap());
+        plain.extend(decryptor.finish().unwrap());
+        assert_eq!(plain, oneshot_plain);
+        assert_eq!(plain, data);
+
+        // chunk by chunk too
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        let plain: Vec<u8> = records.iter().flat_map(|record| decryptor.next_chunk(record).unwrap().unwrap()).collect();
+        assert!(decryptor.is_finished());
+        assert_eq!(plain, data);
+
+        // draining with nothing buffered changes nothing
+        let mut drained = seeded(&sesh1);
+        let mut encryptor = drained.stream_encryptor();
+        assert!(encryptor.drain().unwrap().is_empty());
+        let mut ciphertext = encryptor.update(&data[..2 * STREAM_CHUNK_LEN]).unwrap();
+        assert!(encryptor.drain().unwrap().is_empty());
+        ciphertext.extend(encryptor.update(&data[2 * STREAM_CHUNK_LEN..]).unwrap());
+        ciphertext.extend(encryptor.finish().unwrap());
+        assert_eq!(ciphertext, expected);
+    }
+
+    #[test]
+    fn test_drained_length_is_authenticated() {
+        let (mut sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let mut encryptor = sesh1.stream_encryptor();
+        encryptor.update(&[3; 100]).unwrap();
+        let drained = encryptor.drain().unwrap();
+        encryptor.update(&[4; 100]).unwrap();
+        let mut ciphertext = [drained.clone(), encryptor.drain().unwrap(), encryptor.finish().unwrap()].concat();
+        assert_eq!(drained.len(), STREAM_ID_LEN + 5 + sesh1.ciphertext_len(100));
+
+        // a byte longer, the chunk runs into the next one
+        ciphertext[STREAM_ID_LEN + 1] += 1;
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        assert!(matches!(decryptor.update(&ciphertext), Err(SessionError::MacMismatch)));
+
+        // and as a record on its own it isn't the length it says
+        let mut drained = drained;
+        drained[STREAM_ID_LEN + 1] += 1;
+        let mut receiver = sesh2.clone();
+        let mut decryptor = receiver.stream_decryptor();
+        assert!(matches!(decryptor.next_chunk(&drained), Err(SessionError::InvalidLength(_))));
+    }
+}
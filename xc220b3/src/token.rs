@@ -0,0 +1,72 @@
+use std::sync::atomic::Ordering;
+
+use crate::kex::KeyExchange;
+use crate::session::{Session, SessionError};
+
+const TOKEN_CONTEXT: &str = "xc220b3 2022-10 session token";
+
+impl<K: KeyExchange> Session<K> {
+    /// `len` bytes bound to this session and `label`, for tokens a higher
+    /// layer needs (CSRF tokens, nonces) without a separate RNG. Every call
+    /// gives a different token, even with the same label: a counter private
+    /// to the session goes into each one.
+    ///
+    /// Tokens come from a key derived from this side's send key, so they
+    /// give nothing away about it, and only someone holding the session's
+    /// keys could work them out. A clone carries on from the same counter,
+    /// so it gives the same tokens as the session it was cloned from.
+    /// [`SessionError::NotReady`] before the session is keyed, and
+    /// [`SessionError::Closed`] once it's closed for sending.
+    pub fn derive_token(&self, label: &[u8], len: usize) -> Result<Vec<u8>, SessionError> {
+        self.require_ready()?;
+        if self.send_closed {
+            return Err(SessionError::Closed);
+        }
+        let counter = self.tokens.fetch_add(1, Ordering::Relaxed);
+        let token_key = blake3::derive_key(TOKEN_CONTEXT, &self.send_key);
+        let mut b3 = blake3::Hasher::new_keyed(&token_key);
+        b3.update(&(label.len() as u64).to_le_bytes());
+        b3.update(label);
+        b3.update(&counter.to_le_bytes());
+        let mut token = vec![0; len];
+        b3.finalize_xof().fill(&mut token);
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use rand::thread_rng;
+
+    use crate::close::CloseReason;
+    use crate::session::{Session, SessionError};
+
+    #[test]
+    fn test_tokens_are_unique() {
+        let (sesh1, sesh2) = Session::pair(&mut thread_rng());
+        let mut seen = HashSet::new();
+        for _ in 0..1000 {
+            assert!(seen.insert(sesh1.derive_token(b"csrf", 16).unwrap()));
+        }
+        assert!(seen.insert(sesh1.derive_token(b"nonce", 16).unwrap()));
+        // the peer's come from its own send key
+        assert!(seen.insert(sesh2.derive_token(b"csrf", 16).unwrap()));
+
+        let token = sesh1.derive_token(b"csrf", 64).unwrap();
+        assert_eq!(token.len(), 64);
+        assert!(!token.windows(32).any(|window| window == sesh1.send_key));
+        assert!(sesh1.derive_token(b"csrf", 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tokens_need_a_keyed_open_session() {
+        let sesh = Session::new(&mut thread_rng());
+        assert!(matches!(sesh.derive_token(b"csrf", 16), Err(SessionError::NotReady)));
+
+        let (mut sesh, _) = Session::pair(&mut thread_rng());
+        sesh.close(CloseReason::Normal).unwrap();
+        assert!(matches!(sesh.derive_token(b"csrf", 16), Err(SessionError::Closed)));
+    }
+}
@@ -0,0 +1,109 @@
+// a running hash over the tags of every record sent and accepted, one chain
+// per direction so both peers agree however their sends interleave:
+//
+//   chain' = BLAKE3 keyed(chain) of tag
+//   hash   = BLAKE3 derive_key(TRANSCRIPT_CONTEXT) of session id
+//              || initiator to responder chain || responder to initiator chain
+//
+// each chain folds records in the order this side sent or accepted them, so
+// a session that accepts reordered records gets a different hash than its
+// peer, as it would for a dropped or injected one
+
+use crate::kex::KeyExchange;
+use crate::session::{Role, Session};
+
+const TRANSCRIPT_CONTEXT: &str = "xc220b3 2022-10 session transcript";
+
+#[derive(Clone, Default)]
+pub(crate) struct Transcript {
+    sent: [u8; 32],
+    received: [u8; 32],
+}
+
+impl Transcript {
+    pub(crate) fn sent(&mut self, tag: &[u8]) {
+        self.sent = *blake3::keyed_hash(&self.sent, tag).as_bytes();
+    }
+
+    // the receive chain with `tag` folded in, worked out before the record is
+    // known to be good so a bad one costs the same up to the verdict
+    pub(crate) fn next_received(&self, tag: &[u8]) -> [u8; 32] {
+        *blake3::keyed_hash(&self.received, tag).as_bytes()
+    }
+
+    pub(crate) fn received(&mut self, next: [u8; 32]) {
+        self.received = next;
+    }
+}
+
+impl<K: KeyExchange> Session<K> {
+    /// A hash of the whole conversation so far: every record this side sent
+    /// and every one it accepted, heartbeats and closes included, in order.
+    /// Two peers that saw the same records get the same hash, and compared
+    /// at the end of a conversation (over a channel of its own, or as a last
+    /// message) it shows whether any record was dropped, injected or, in a
+    /// session that takes them out of order, reordered on the way.
+    pub fn transcript_hash(&self) -> [u8; 32] {
+        let (to_responder, to_initiator) = match self.role() {
+            Some(Role::Responder) => (&self.transcript.received, &self.transcript.sent),
+            _ => (&self.transcript.sent, &self.transcript.received),
+        };
+        let mut b3 = blake3::Hasher::new_derive_key(TRANSCRIPT_CONTEXT);
+        b3.update(&self.session_id);
+        b3.update(to_responder);
+        b3.update(to_initiator);
+        *b3.finalize().as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use crate::session::Session;
+
+    #[test]
+    fn test_peers_agree_on_the_transcript() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        assert_eq!(sesh1.transcript_hash(), sesh2.transcript_hash());
+
+        // each side sends before reading the other's, so they see the
+        // records in different orders
+        for i in 0..5u8 {
+            let from1 = sesh1.encrypt(vec![i]).unwrap();
+            let from2 = sesh2.encrypt(vec![i, i]).unwrap();
+            sesh2.decrypt(from1).unwrap();
+            sesh1.decrypt(from2).unwrap();
+        }
+        let mut record = [0u8; 64];
+        let written = sesh1.encrypt_into(b"last", &mut record).unwrap();
+        sesh2.decrypt_into(&record[..written], &mut [0u8; 64]).unwrap();
+        sesh2.decrypt(sesh1.heartbeat().unwrap()).unwrap_err();
+        assert_eq!(sesh1.transcript_hash(), sesh2.transcript_hash());
+
+        // a refused record isn't part of it
+        let mut tampered = sesh1.encrypt(b"tampered".to_vec()).unwrap();
+        let before = sesh2.transcript_hash();
+        tampered[1] ^= 1;
+        assert!(sesh2.decrypt(tampered).is_err());
+        assert_eq!(sesh2.transcript_hash(), before);
+        assert_ne!(sesh1.transcript_hash(), before);
+    }
+
+    #[test]
+    fn test_dropped_record_diverges() {
+        let (mut sesh1, mut sesh2) = Session::unordered_pair();
+        let records: Vec<Vec<u8>> = (0..3u8).map(|i| sesh1.encrypt(vec![i]).unwrap()).collect();
+        for (i, record) in records.into_iter().enumerate() {
+            if i != 1 {
+                sesh2.decrypt(record).unwrap();
+            }
+        }
+        assert_ne!(sesh1.transcript_hash(), sesh2.transcript_hash());
+
+        // and every pair starts from its own
+        let (sesh3, sesh4) = Session::pair(&mut thread_rng());
+        assert_ne!(sesh3.transcript_hash(), Session::pair(&mut thread_rng()).0.transcript_hash());
+        assert_eq!(sesh3.transcript_hash(), sesh4.transcript_hash());
+    }
+}
@@ -0,0 +1,179 @@
+// Known-answer vectors for whole session records, shared with the language
+// bindings. Each case gives the session key, the aad, the plaintext and the
+// exact record `seal` produces, all in hex. Each case also gives the
+// authenticated data the cipher is handed, laid out as described in
+// record.rs, so an implementation can check its MAC input on its own.
+//
+// session-v1.json holds the same cases as version 1 records, whose headers
+// aren't authenticated, and session-v2.json as version 2 records, laid out
+// as today's but for the version byte. They're only opened here, not
+// regenerated.
+//
+// ecies.json gives, for ecies::seal, the recipient's and the ephemeral
+// secret keys, the plaintext and the sealed payload.
+//
+// To regenerate after a deliberate format change:
+//   XC220B3_WRITE_VECTORS=1 cargo test -p xc220b3 vectors
+
+use serde_json::{json, Value};
+
+use k256::SecretKey;
+
+use crate::ecies;
+use crate::record;
+use crate::session::Session;
+
+const VECTORS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/session.json");
+const V1_VECTORS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/session-v1.json");
+const V2_VECTORS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/session-v2.json");
+const ECIES_VECTORS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/ecies.json");
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    assert!(hex.len().is_multiple_of(2), "odd length hex");
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+fn field(case: &Value, name: &str) -> Vec<u8> {
+    unhex(case[name].as_str().unwrap_or_else(|| panic!("vector is missing {}", name)))
+}
+
+// (description, key, aad, plaintext)
+type Input = (&'static str, [u8; 32], Vec<u8>, Vec<u8>);
+
+fn inputs() -> Vec<Input> {
+    let counting: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    vec![
+        ("empty plaintext", [0; 32], Vec::new(), Vec::new()),
+        ("short plaintext", [0x42; 32], Vec::new(), b"hello".to_vec()),
+        ("one block", [1; 32], Vec::new(), vec![0xaa; 64]),
+        ("multiple blocks", core::array::from_fn(|i| i as u8), Vec::new(), counting.clone()),
+        ("aad only", [2; 32], b"header".to_vec(), Vec::new()),
+        ("aad and plaintext", [2; 32], b"header".to_vec(), b"hello".to_vec()),
+        ("stream chunk aad", [3; 32], vec![1, 0, 0, 0, 0, 0, 0, 0, 1], counting),
+    ]
+}
+
+fn generate() -> Value {
+    let cases: Vec<Value> = inputs()
+        .into_iter()
+        .map(|(description, key, aad, plaintext)| {
+            let record = Session::with_key(key).seal(&plaintext, &aad).unwrap();
+            json!({
+                "description": description,
+                "key": hex(&key),
+                "aad": hex(&aad),
+                "plaintext": hex(&plaintext),
+                "authenticated_data": hex(&record::authenticated_data(&record::header(record::VERSION, 0), &aad, plaintext.len())),
+                "record": hex(&record),
+            })
+        })
+        .collect();
+    json!({ "cipher": "xc220b3-session", "version": record::VERSION, "cases": cases })
+}
+
+// (description, recipient secret, ephemeral secret, plaintext)
+type EciesInput = (&'static str, [u8; 32], [u8; 32], Vec<u8>);
+
+fn ecies_inputs() -> Vec<EciesInput> {
+    vec![
+        ("empty plaintext", [1; 32], [2; 32], Vec::new()),
+        ("short plaintext", [1; 32], [3; 32], b"hello".to_vec()),
+        ("multiple blocks", [4; 32], [5; 32], (0..=255).cycle().take(1000).collect()),
+    ]
+}
+
+fn generate_ecies() -> Value {
+    let cases: Vec<Value> = ecies_inputs()
+        .into_iter()
+        .map(|(description, recipient, ephemeral, plaintext)| {
+            let recipient_pk = SecretKey::from_be_bytes(&recipient).unwrap().public_key();
+            let sealed = ecies::seal_with_ephemeral(&recipient_pk, &plaintext, &SecretKey::from_be_bytes(&ephemeral).unwrap());
+            json!({
+                "description": description,
+                "recipient_secret": hex(&recipient),
+                "ephemeral_secret": hex(&ephemeral),
+                "plaintext": hex(&plaintext),
+                "sealed": hex(&sealed),
+            })
+        })
+        .collect();
+    json!({ "cipher": "xc220b3-ecies", "version": record::V2, "cases": cases })
+}
+
+fn cases(path: &str) -> Vec<Value> {
+    let text = std::fs::read_to_string(path).unwrap();
+    let vectors: Value = serde_json::from_str(&text).unwrap();
+    let cases = vectors["cases"].as_array().unwrap().clone();
+    assert!(!cases.is_empty());
+    cases
+}
+
+#[test]
+fn test_session_vectors() {
+    if std::env::var_os("XC220B3_WRITE_VECTORS").is_some() {
+        let text = serde_json::to_string_pretty(&generate()).unwrap() + "\n";
+        std::fs::write(VECTORS_PATH, text).unwrap();
+    }
+
+    for case in &cases(VECTORS_PATH) {
+        let description = case["description"].as_str().unwrap_or("?");
+        let key: [u8; 32] = field(case, "key").try_into().unwrap();
+        let (aad, plaintext, record) = (field(case, "aad"), field(case, "plaintext"), field(case, "record"));
+
+        let authenticated = record::authenticated_data(&record::header(record::VERSION, 0), &aad, plaintext.len());
+        assert_eq!(hex(&authenticated), hex(&field(case, "authenticated_data")), "{}: authenticated data", description);
+
+        let sealed = Session::with_key(key).seal(&plaintext, &aad).unwrap();
+        assert_eq!(hex(&sealed), hex(&record), "{}: seal", description);
+
+        let opened = Session::with_key(key).open(record, &aad).unwrap();
+        assert_eq!(opened, plaintext, "{}: open", description);
+    }
+}
+
+fn old_vectors_still_open(path: &str, version: u8) {
+    for case in &cases(path) {
+        let description = case["description"].as_str().unwrap_or("?");
+        let key: [u8; 32] = field(case, "key").try_into().unwrap();
+        let (aad, plaintext, record) = (field(case, "aad"), field(case, "plaintext"), field(case, "record"));
+        assert_eq!(record[0], version);
+
+        let mut session = Session::with_key(key);
+        session.set_version(version);
+        let opened = session.open(record, &aad).unwrap();
+        assert_eq!(opened, plaintext, "{}: open", description);
+    }
+}
+
+#[test]
+fn test_v1_session_vectors_still_open() {
+    old_vectors_still_open(V1_VECTORS_PATH, record::V1);
+}
+
+#[test]
+fn test_v2_session_vectors_still_open() {
+    old_vectors_still_open(V2_VECTORS_PATH, record::V2);
+}
+
+#[test]
+fn test_ecies_vectors() {
+    if std::env::var_os("XC220B3_WRITE_VECTORS").is_some() {
+        let text = serde_json::to_string_pretty(&generate_ecies()).unwrap() + "\n";
+        std::fs::write(ECIES_VECTORS_PATH, text).unwrap();
+    }
+
+    for case in &cases(ECIES_VECTORS_PATH) {
+        let description = case["description"].as_str().unwrap_or("?");
+        let recipient = SecretKey::from_be_bytes(&field(case, "recipient_secret")).unwrap();
+        let ephemeral = SecretKey::from_be_bytes(&field(case, "ephemeral_secret")).unwrap();
+        let (plaintext, sealed) = (field(case, "plaintext"), field(case, "sealed"));
+
+        let ours = ecies::seal_with_ephemeral(&recipient.public_key(), &plaintext, &ephemeral);
+        assert_eq!(hex(&ours), hex(&sealed), "{}: seal", description);
+        assert_eq!(ecies::open(&recipient, &sealed).unwrap(), plaintext, "{}: open", description);
+    }
+}
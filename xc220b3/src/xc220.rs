@@ -22,6 +22,17 @@ pub struct XC220 {
 
 impl Clone for XC220 { fn clone(&self) -> XC220 { *self } }
 
+/// [`XC220::try_new`] was given a key or nonce of the wrong length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidCipherParams {
+    pub key_len: usize,
+    pub nonce_len: usize,
+}
+
+/// Most keystream one key and nonce give. The 32-bit block counter starts at
+/// 0 and must not wrap, so the block it would wrap after is never used.
+pub(crate) const MAX_KEYSTREAM_LEN: u64 = 64 * u32::MAX as u64;
+
 macro_rules! swizzle{
     ($b: expr, $c: expr, $d: expr) => {{
         let u32x4(b10, b11, b12, b13) = $b;
@@ -81,8 +92,13 @@ static S7:u32x4 = u32x4(7, 7, 7, 7);
 
 impl XC220 {
     pub fn new(key: &[u8], nonce: &[u8]) -> XC220 {
-        assert!(key.len() == 32);
-        assert!(nonce.len() == 24);
+        XC220::try_new(key, nonce).expect("32 byte key and 24 byte nonce")
+    }
+
+    pub fn try_new(key: &[u8], nonce: &[u8]) -> Result<XC220, InvalidCipherParams> {
+        if key.len() != 32 || nonce.len() != 24 {
+            return Err(InvalidCipherParams { key_len: key.len(), nonce_len: nonce.len() });
+        }
 
         // HChaCha20 produces a 256-bit output block starting from a 512 bit
         // input block where (x0,x1,...,x15) where
@@ -98,7 +114,26 @@ impl XC220 {
         xc220.hchacha20(&mut new_key);
         xc220.state = XC220::expand(&new_key, &nonce[16..24]);
 
-        xc220
+        Ok(xc220)
+    }
+
+    /// Plain ChaCha20 with an 8 or 12 byte nonce, used to check the core
+    /// against the ChaCha20 test vectors.
+    #[cfg(test)]
+    pub fn new_chacha20(key: &[u8], nonce: &[u8]) -> XC220 {
+        assert!(key.len() == 32);
+        assert!(nonce.len() == 8 || nonce.len() == 12);
+
+        XC220{ state: XC220::expand(key, nonce), output: [0u8; 64], offset: 64 }
+    }
+
+    /// Moves to the start of keystream block `block`, for testing the ends
+    /// of the counter without generating everything before them.
+    #[cfg(test)]
+    pub fn seek(&mut self, block: u32) {
+        let u32x4(_, d1, d2, d3) = self.state.d;
+        self.state.d = u32x4(block, d1, d2, d3);
+        self.offset = 64;
     }
 
     fn expand(key: &[u8], nonce: &[u8]) -> ChaChaState {
@@ -161,7 +196,7 @@ impl XC220 {
         }
     }
 
-    fn hchacha20(&mut self, out: &mut [u8]) -> () {
+    fn hchacha20(&mut self, out: &mut [u8]) {
         let mut state = self.state;
 
         // Apply r/2 iterations of the same "double-round" function,
@@ -264,9 +299,7 @@ impl Decryptor for XC220 {
 
 #[cfg(test)]
 mod test {
-    use core::iter::repeat;
-
-    use crate::xc220::XC220;
+    use crate::xc220::{MAX_KEYSTREAM_LEN, XC220};
     use crate::symmetriccipher::SynchronousStreamCipher;
 
     #[test]
@@ -277,7 +310,7 @@ mod test {
             keystream: Vec<u8>,
         }
         // taken from http://tools.ietf.org/html/draft-agl-tls-chacha20poly1305-04
-        let test_vectors = vec!(
+        let test_vectors = [
             TestVector{
                 key: [
                     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -393,12 +426,12 @@ mod test {
                     0x6d, 0xeb, 0x3a, 0xb7, 0x8f, 0xab, 0x78, 0xc9,
                 ),
             },
-        );
+        ];
 
         for tv in test_vectors.iter() {
-            let mut c = XC220::new(&tv.key, &tv.nonce);
-            let input: Vec<u8> = repeat(0).take(tv.keystream.len()).collect();
-            let mut output: Vec<u8> = repeat(0).take(input.len()).collect();
+            let mut c = XC220::new_chacha20(&tv.key, &tv.nonce);
+            let input: Vec<u8> = vec![0; tv.keystream.len()];
+            let mut output: Vec<u8> = vec![0; input.len()];
             c.process(&input[..], &mut output[..]);
             assert_eq!(output, tv.keystream);
         }
@@ -406,9 +439,9 @@ mod test {
 
     #[test]
     fn test_xc220_basic() {
-        // There aren't any convenient test vectors for XChaCha/20,
-        // so, a simple test case was generated using Andrew Moon's
-        // chacha-opt library, with the key/nonce from test_salsa20_cryptopp().
+        // generated using Andrew Moon's chacha-opt library, with the
+        // key/nonce from test_salsa20_cryptopp(), from before there were
+        // published vectors, see test_xchacha20_draft_vectors for those
         let key =
             [0x1b, 0x27, 0x55, 0x64, 0x73, 0xe9, 0x85, 0xd4,
              0x62, 0xcd, 0x51, 0x19, 0x7a, 0x9a, 0x46, 0xc7,
@@ -444,6 +477,104 @@ mod test {
         xc220.process(&input, &mut stream);
         assert!(stream[..] == result[..]);
     }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        let hex: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        hex.chunks(2).map(|pair| u8::from_str_radix(core::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_hchacha20_draft_vector() {
+        // draft-irtf-cfrg-xchacha-03 section 2.2.1, the subkey alone, since
+        // that's where implementations part ways
+        let key = unhex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let nonce = unhex("000000090000004a0000000031415927");
+        let mut xc220 = XC220 { state: XC220::expand(&key, &nonce), output: [0u8; 64], offset: 64 };
+        let mut subkey = [0u8; 32];
+        xc220.hchacha20(&mut subkey);
+        assert_eq!(subkey[..], unhex("82413b4227b27bfed30e42508a877d73a0f9e4d58a74a853c12ec41326d3ecdc"));
+    }
+
+    #[test]
+    fn test_xchacha20_draft_vectors() {
+        // draft-irtf-cfrg-xchacha-03 appendix A.3.2, which starts at block
+        // 1. through XC220::new, so the subkey, the nonce's last 8 bytes and
+        // the counter word are laid out as the draft has them
+        let key = unhex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = unhex("404142434445464748494a4b4c4d4e4f5051525354555658");
+        let plain = b"The dhole (pronounced \"dole\") is also known as the Asiatic wild dog, red dog, and whistling dog. \
+            It is about the size of a German shepherd but looks more like a long-legged fox. This highly elusive and \
+            skilled jumper is classified with wolves, coyotes, jackals, and foxes in the taxonomic family Canidae.";
+        let keystream = unhex(
+            "29624b4b1b140ace53740e405b2168540fd7d630c1f536fecd722fc3cddba7f4
+             cca98cf9e47e5e64d115450f9b125b54449ff76141ca620a1f9cfcab2a1a8a25
+             5e766a5266b878846120ea64ad99aa479471e63befcbd37cd1c22a221fe46221
+             5cf32c74895bf505863ccddd48f62916dc6521f1ec50a5ae08903aa259d9bf60
+             7cd8026fba548604f1b6072d91bc91243a5b845f7fd171b02edc5a0a84cf28dd
+             241146bc376e3f48df5e7fee1d11048c190a3d3deb0feb64b42d9c6fdeee290f
+             a0e6ae2c26c0249ea8c181f7e2ffd100cbe5fd3c4f8271d62b15330cb8fdcf00
+             b3df507ca8c924f7017b7e712d15a2eb5c50484451e54e1b4b995bd8fdd94597
+             bb94d7af0b2c04df10ba0890899ed9293a0f55b8bafa999264035f1d4fbe7fe0
+             aafa109a62372027e50e10cdfecca127",
+        );
+        let ciphertext = unhex(
+            "7d0a2e6b7f7c65a236542630294e063b7ab9b555a5d5149aa21e4ae1e4fbce87
+             ecc8e08a8b5e350abe622b2ffa617b202cfad72032a3037e76ffdcdc4376ee05
+             3a190d7e46ca1de04144850381b9cb29f051915386b8a710b8ac4d027b8b050f
+             7cba5854e028d564e453b8a968824173fc16488b8970cac828f11ae53cabd201
+             12f87107df24ee6183d2274fe4c8b1485534ef2c5fbc1ec24bfc3663efaa08bc
+             047d29d25043532db8391a8a3d776bf4372a6955827ccb0cdd4af403a7ce4c63
+             d595c75a43e045f0cce1f29c8b93bd65afc5974922f214a40b7c402cdb91ae73
+             c0b63615cdad0480680f16515a7ace9d39236464328a37743ffc28f4ddb324f4
+             d0f5bbdc270c65b1749a6efff1fbaa09536175ccd29fb9e6057b307320d31683
+             8a9c71f70b5b5907a66f7ea49aadc409",
+        );
+        assert_eq!(plain.len(), 304);
+
+        let mut xc220 = XC220::new(&key, &nonce);
+        xc220.seek(1);
+        let mut stream = [0u8; 304];
+        xc220.process(&[0; 304], &mut stream);
+        assert_eq!(stream[..], keystream[..]);
+
+        // and from block 0, as records use it, skipping the first block
+        let mut xc220 = XC220::new(&key, &nonce);
+        let mut output = [0u8; 64 + 304];
+        xc220.process(&[&[0; 64][..], plain].concat(), &mut output);
+        assert_eq!(output[64..], ciphertext[..]);
+    }
+
+    #[test]
+    fn test_seek_matches_the_keystream() {
+        let mut xc220 = XC220::new(&[7; 32], &[9; 24]);
+        let mut stream = [0u8; 192];
+        xc220.process(&[0; 192], &mut stream);
+
+        let mut seeked = XC220::new(&[7; 32], &[9; 24]);
+        seeked.seek(2);
+        let mut block = [0u8; 64];
+        seeked.process(&[0; 64], &mut block);
+        assert_eq!(block[..], stream[128..]);
+    }
+
+    #[test]
+    fn test_last_block_before_the_counter_wraps() {
+        // MAX_KEYSTREAM_LEN ends with block u32::MAX - 1
+        assert_eq!(MAX_KEYSTREAM_LEN, 64 * (u32::MAX as u64 - 1) + 64);
+        let mut xc220 = XC220::new(&[7; 32], &[9; 24]);
+        xc220.seek(u32::MAX - 1);
+        let mut block = [0u8; 64];
+        xc220.process(&[0; 64], &mut block);
+        assert_ne!(block, [0; 64]);
+    }
+
+    #[test]
+    #[should_panic(expected = "counter is exhausted")]
+    fn test_keystream_past_the_limit_panics() {
+        let mut xc220 = XC220::new(&[7; 32], &[9; 24]);
+        xc220.seek(u32::MAX);
+        xc220.process(&[0], &mut [0]);
+    }
 }
 
 #[cfg(all(test, feature = "with-bench"))]
@@ -0,0 +1,125 @@
+use bytes::Bytes;
+
+use crate::cipher::{RecordCipher, XCHACHA20_BLAKE3};
+use crate::kex::KeyExchange;
+use crate::record::HEADER_LEN;
+use crate::session::{Session, SessionError};
+
+impl<K: KeyExchange> Session<K> {
+    /// [`Session::encrypt`] for `Bytes`. The plaintext is only read, and the
+    /// record is sealed into a buffer reserved at its final length, header
+    /// and tag included, that becomes the returned `Bytes` without a copy.
+    pub fn encrypt_bytes(&mut self, plain: Bytes) -> Result<Bytes, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.seal_span("data", plain.len());
+                let _enter = span.enter();
+            }
+        );
+
+        self.seal_parts(&[&plain], &[]).map(Bytes::from)
+    }
+
+    /// [`Session::decrypt`] for `Bytes`. The record is read in place, the
+    /// only buffer written is the plaintext's.
+    pub fn decrypt_bytes(&mut self, ciphertext: Bytes) -> Result<Bytes, SessionError> {
+        cfg_if!(
+            if #[cfg(feature = "tracing")] {
+                let span = self.open_span("data", ciphertext.len());
+                let _enter = span.enter();
+            }
+        );
+
+        self.open_slice(&ciphertext, &[]).map(Bytes::from)
+    }
+}
+
+/// A record with a one byte cleartext header split into its fields, each a
+/// view of the `Bytes` it came from. Nothing is copied until it's decrypted,
+/// with `session.decrypt_bytes(message.into_bytes())`.
+///
+/// Obfuscated records (see [`crate::SessionBuilder::obfuscate`]) have no
+/// header to split on, pass those to [`Session::decrypt_bytes`] directly.
+/// In an [ordered](crate::SessionBuilder::ordered) session the ciphertext
+/// starts with the 8 byte sequence number.
+/// The cipher's tag is also its nonce, so there's no separate nonce field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedMessage {
+    record: Bytes,
+}
+
+impl EncryptedMessage {
+    /// Checks `record` is long enough to hold a header and tag. Nothing is
+    /// authenticated yet.
+    pub fn from_bytes(record: Bytes) -> Result<EncryptedMessage, SessionError> {
+        if record.len() < HEADER_LEN + XCHACHA20_BLAKE3.overhead() {
+            return Err(SessionError::InvalidLength(record.len()));
+        }
+        Ok(EncryptedMessage { record })
+    }
+
+    /// The header byte, the version with any flag bits.
+    pub fn header(&self) -> u8 {
+        self.record[0]
+    }
+
+    pub fn ciphertext(&self) -> Bytes {
+        self.record.slice(HEADER_LEN..self.tag_start())
+    }
+
+    pub fn tag(&self) -> Bytes {
+        self.record.slice(self.tag_start()..)
+    }
+
+    /// The whole record.
+    pub fn into_bytes(self) -> Bytes {
+        self.record
+    }
+
+    fn tag_start(&self) -> usize {
+        self.record.len() - XCHACHA20_BLAKE3.tag_len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use rand::thread_rng;
+
+    use crate::session::{Session, SessionError};
+
+    use super::EncryptedMessage;
+
+    #[test]
+    fn test_bytes_records_match_vec_records() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let record = sesh1.clone().encrypt(b"hello".to_vec()).unwrap();
+        let sealed = sesh1.encrypt_bytes(Bytes::from_static(b"hello")).unwrap();
+        assert_eq!(sealed, record);
+        assert_eq!(sesh2.decrypt_bytes(sealed).unwrap(), &b"hello"[..]);
+        assert_eq!(sesh2.stats().received, 1);
+
+        let mut tampered = record;
+        tampered[1] ^= 1;
+        assert!(matches!(sesh2.decrypt_bytes(tampered.into()), Err(SessionError::MacMismatch)));
+    }
+
+    #[test]
+    fn test_encrypted_message_borrows_its_fields() {
+        let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+        let record = sesh1.encrypt_bytes(Bytes::from_static(b"hello")).unwrap();
+        let message = EncryptedMessage::from_bytes(record.clone()).unwrap();
+
+        assert_eq!(message.header(), record[0]);
+        // the sequence number, then the 5 bytes
+        assert_eq!(message.ciphertext().len(), 13);
+        assert_eq!(message.tag().len(), 24);
+        // views of the same buffer, not copies
+        assert_eq!(message.ciphertext().as_ptr(), record[1..].as_ptr());
+        assert_eq!(message.tag().as_ptr(), record[14..].as_ptr());
+        assert_eq!(sesh2.decrypt_bytes(message.into_bytes()).unwrap(), &b"hello"[..]);
+
+        assert!(matches!(EncryptedMessage::from_bytes(record.slice(..24)), Err(SessionError::InvalidLength(24))));
+        assert!(EncryptedMessage::from_bytes(record.slice(..25)).unwrap().ciphertext().is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+// a first message for a responder whose static key the initiator already
+// has, from a certificate say, sent before hearing anything back:
+//
+//   ephemeral public key (33, compressed) || record
+//
+// the session key is ECDH between the ephemeral key and the responder's
+// static key, bound to both keys. the record is an ordinary one, so the
+// session carries on as usual once the payload is opened
+
+use k256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint, EncodedPoint, PublicKey, SecretKey};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::provider::{self, SecretProvider};
+use crate::session::{Role, Session, SessionError};
+
+const ZERO_RTT_CONTEXT: &str = "xc220b3 2022-10 0-rtt";
+
+/// Length of the ephemeral public key opening a
+/// [`Session::initiate_with_payload`] payload.
+pub const ZERO_RTT_KEY_LEN: usize = 33;
+
+impl Session {
+    /// Keys a session to the holder of `responder_static` with a fresh
+    /// ephemeral key and seals `plain` as its first record, giving the
+    /// session and the payload to send: the ephemeral public key followed
+    /// by the record. The responder gets its end with
+    /// [`Session::open_with_ephemeral`], and the session works like any
+    /// other from then on.
+    ///
+    /// This is 0-RTT data, and it's weaker than a handshake in two ways:
+    ///
+    /// - **It can be replayed.** Nothing the responder contributed went into
+    ///   the key, so a captured payload opens again every time it's sent,
+    ///   to the same plaintext. Only send requests that are safe to repeat,
+    ///   or have the responder refuse ephemeral keys (the first
+    ///   [`ZERO_RTT_KEY_LEN`] bytes) it's seen recently, for as long as it
+    ///   would otherwise accept the payload. A per-session
+    ///   [dedup cache](Session::set_dedup_capacity) can't catch it, each
+    ///   replay keys a new session.
+    /// - **It isn't forward secret.** Anyone who later gets the responder's
+    ///   static secret can open the payload and everything after it in the
+    ///   session. [`Session::renegotiate`] once the responder has answered
+    ///   to get fresh ephemeral keys on both sides.
+    pub fn initiate_with_payload(
+        rng: &mut (impl CryptoRng + RngCore),
+        responder_static: &PublicKey,
+        plain: &[u8],
+    ) -> Result<(Session, Vec<u8>), SessionError> {
+        let ephemeral = EphemeralSecret::random(rng);
+        let ephemeral_pk = ephemeral.public_key().to_encoded_point(true);
+        let static_pk = responder_static.to_encoded_point(true);
+        let shared = ephemeral.diffie_hellman(responder_static);
+
+        let mut session = Session::from_secret(None);
+        session.install_keys(Role::Initiator, shared.raw_secret_bytes(), &transcript(&ephemeral_pk, &static_pk));
+        session.peer = Some(static_pk);
+        session.peer_key = Some(static_pk.as_bytes().to_vec());
+
+        let mut payload = ephemeral_pk.as_bytes().to_vec();
+        payload.extend_from_slice(&session.encrypt(plain.to_vec())?);
+        Ok((session, payload))
+    }
+
+    /// The responder's end of [`Session::initiate_with_payload`], giving the
+    /// session and the first message. Read the replay caveats there before
+    /// acting on the message.
+    pub fn open_with_ephemeral(static_secret: &SecretKey, payload: &[u8]) -> Result<(Session, Vec<u8>), SessionError> {
+        Session::open_with_ephemeral_provider(static_secret, payload)
+    }
+
+    /// [`Session::open_with_ephemeral`] with the static key kept by a
+    /// [`SecretProvider`]. A provider that fails is
+    /// [`SessionError::ProviderFailure`], and the payload can be tried again.
+    pub fn open_with_ephemeral_provider(
+        static_secret: &dyn SecretProvider,
+        payload: &[u8],
+    ) -> Result<(Session, Vec<u8>), SessionError> {
+        if payload.len() < ZERO_RTT_KEY_LEN {
+            return Err(SessionError::InvalidLength(payload.len()));
+        }
+        let (ephemeral_pk, record) = payload.split_at(ZERO_RTT_KEY_LEN);
+        let ephemeral = PublicKey::from_sec1_bytes(ephemeral_pk)?;
+        let ephemeral_pk = ephemeral.to_encoded_point(true);
+        let static_pk = EncodedPoint::from_bytes(provider::compressed(static_secret)?).unwrap();
+        let shared = static_secret.ecdh(&ephemeral)?;
+
+        let mut session = Session::from_secret(None);
+        session.install_keys(Role::Responder, shared.as_bytes(), &transcript(&ephemeral_pk, &static_pk));
+        session.peer = Some(ephemeral_pk);
+        session.peer_key = Some(ephemeral_pk.as_bytes().to_vec());
+
+        let plain = session.decrypt(record.to_vec())?;
+        Ok((session, plain))
+    }
+}
+
+// binds the keys to who they're between, so a payload can't be passed off
+// as for another static key
+fn transcript(ephemeral_pk: &EncodedPoint, static_pk: &EncodedPoint) -> [u8; 32] {
+    let mut b3 = blake3::Hasher::new_derive_key(ZERO_RTT_CONTEXT);
+    b3.update(ephemeral_pk.as_bytes());
+    b3.update(static_pk.as_bytes());
+    *b3.finalize().as_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use k256::SecretKey;
+    use rand::thread_rng;
+
+    use crate::session::{Role, Session, SessionError};
+
+    #[test]
+    fn test_zero_rtt_end_to_end() {
+        let responder_static = SecretKey::random(&mut thread_rng());
+        let (mut initiator, payload) = Session::initiate_with_payload(&mut thread_rng(), &responder_static.public_key(), b"GET /").unwrap();
+
+        let (mut responder, first) = Session::open_with_ephemeral(&responder_static, &payload).unwrap();
+        assert_eq!(first, b"GET /");
+        assert_eq!((initiator.role(), responder.role()), (Some(Role::Initiator), Some(Role::Responder)));
+        assert_eq!(initiator.session_id(), responder.session_id());
+
+        let reply = responder.encrypt(b"200".to_vec()).unwrap();
+        assert_eq!(initiator.decrypt(reply).unwrap(), b"200");
+        let next = initiator.encrypt(b"GET /next".to_vec()).unwrap();
+        assert_eq!(responder.decrypt(next).unwrap(), b"GET /next");
+
+        // as documented, a replay opens again
+        assert_eq!(Session::open_with_ephemeral(&responder_static, &payload).unwrap().1, b"GET /");
+    }
+
+    #[test]
+    fn test_zero_rtt_needs_the_right_static_key() {
+        let responder_static = SecretKey::random(&mut thread_rng());
+        let (_, payload) = Session::initiate_with_payload(&mut thread_rng(), &responder_static.public_key(), b"GET /").unwrap();
+
+        let someone_else = SecretKey::random(&mut thread_rng());
+        assert!(matches!(Session::open_with_ephemeral(&someone_else, &payload), Err(SessionError::MacMismatch)));
+
+        let mut tampered = payload.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(Session::open_with_ephemeral(&responder_static, &tampered), Err(SessionError::MacMismatch)));
+        assert!(matches!(Session::open_with_ephemeral(&responder_static, &payload[..10]), Err(SessionError::InvalidLength(10))));
+    }
+}
@@ -0,0 +1,32 @@
+#![cfg(feature = "dangerous-debug")]
+
+use std::process::Command;
+
+use rand::thread_rng;
+use xc220b3::framing::write_frame;
+use xc220b3::Session;
+
+#[test]
+fn test_decryptor_reads_a_logged_capture() {
+    let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+    let dir = std::env::temp_dir().join(format!("xc220b3-decrypt-pcap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (keylog, capture) = (dir.join("keylog"), dir.join("capture"));
+    // someone else's line first, it's skipped
+    let other = Session::pair(&mut thread_rng()).0.export_raw_keys().to_keylog_line();
+    std::fs::write(&keylog, format!("{}\n{}\n", other, sesh2.export_raw_keys().to_keylog_line())).unwrap();
+
+    let mut stream = Vec::new();
+    for message in [&b"hello"[..], b"\x00binary\xff"] {
+        let record = sesh1.encrypt(message.to_vec()).unwrap();
+        sesh2.decrypt(record.clone()).unwrap();
+        write_frame(&mut stream, &record).unwrap();
+    }
+    write_frame(&mut stream, &sesh1.heartbeat().unwrap()).unwrap();
+    std::fs::write(&capture, stream).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_xc220b3-decrypt-pcap")).arg(&keylog).arg(&capture).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0: \"hello\"\n1: \"\\x00binary\\xff\"\n2: heartbeat\n");
+}
@@ -0,0 +1,56 @@
+// its own test binary so the counting allocator is the only one, and only
+// the test's thread is counted, whatever the harness does meanwhile. this
+// crate needs std regardless, xc220b3-core is the one for targets with no
+// allocator, and its tests/stack_buffers.rs is this test without one
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use rand::thread_rng;
+use xc220b3::Session;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn test_into_buffers_dont_allocate() {
+    let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+    let mut record = [0u8; 2048];
+    let mut plain = [0u8; 1024];
+    let message = [0x5a; 1000];
+
+    // tracing registers its callsites the first time through
+    let written = sesh1.encrypt_into(&message, &mut record).unwrap();
+    sesh2.decrypt_into(&record[..written], &mut plain).unwrap();
+
+    let before = allocations();
+    for _ in 0..10 {
+        let written = sesh1.encrypt_into(&message, &mut record).unwrap();
+        let read = sesh2.decrypt_into(&record[..written], &mut plain).unwrap();
+        assert_eq!(plain[..read], message);
+        record[1] ^= 1;
+        assert!(sesh2.decrypt_into(&record[..written], &mut plain).is_err());
+    }
+    assert_eq!(allocations() - before, 0);
+}
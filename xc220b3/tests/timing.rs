@@ -0,0 +1,124 @@
+// dudect-style timing tests of the verification paths: records of each class
+// are opened in random order, timed one at a time, and Welch's t-test says
+// whether any two classes' times are told apart. measurements above a
+// percentile are cropped as dudect does, since preemption only ever adds
+// time, and the largest t over the crops is what's held to the threshold.
+// timing is only meaningful optimised and on a quiet machine, so they're
+// ignored by default:
+//
+//   cargo test --release -- --ignored timing
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use rand::{thread_rng, Rng};
+use xc220b3::{RecordCipher, Session, SessionError, XChaCha20Blake3};
+
+const SAMPLES: usize = 200_000;
+// dudect's: past this the classes are told apart
+const THRESHOLD: f64 = 4.5;
+const CROPS: [f64; 4] = [0.5, 0.75, 0.9, 0.99];
+
+#[derive(Default)]
+struct Welch {
+    n: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welch {
+    fn push(&mut self, x: f64) {
+        self.n += 1.0;
+        let delta = x - self.mean;
+        self.mean += delta / self.n;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn variance(&self) -> f64 {
+        self.m2 / (self.n - 1.0)
+    }
+
+    fn t(&self, other: &Welch) -> f64 {
+        (self.mean - other.mean) / (self.variance() / self.n + other.variance() / other.n).sqrt()
+    }
+}
+
+// times `open` on each class's record, in random order, and gives the
+// largest |t| between any two classes
+fn largest_t<T>(classes: &[Vec<u8>], mut open: impl FnMut(Vec<u8>) -> Result<T, SessionError>) -> f64 {
+    let mut rng = thread_rng();
+    // warm the caches and the branch predictor on all of them
+    for class in classes {
+        for _ in 0..1000 {
+            let _ = black_box(open(class.clone()));
+        }
+    }
+
+    let mut times = vec![Vec::with_capacity(SAMPLES); classes.len()];
+    for _ in 0..SAMPLES * classes.len() {
+        let class = rng.gen_range(0..classes.len());
+        let record = classes[class].clone();
+        let start = Instant::now();
+        let result = black_box(open(black_box(record)));
+        let elapsed = start.elapsed();
+        drop(result);
+        times[class].push(elapsed.as_nanos() as f64);
+    }
+
+    let mut all: Vec<f64> = times.iter().flatten().copied().collect();
+    all.sort_by(f64::total_cmp);
+    let mut largest = 0f64;
+    for crop in CROPS {
+        let below = all[(crop * (all.len() - 1) as f64) as usize];
+        let tests: Vec<Welch> = times
+            .iter()
+            .map(|times| {
+                let mut welch = Welch::default();
+                times.iter().filter(|&&time| time <= below).for_each(|&time| welch.push(time));
+                welch
+            })
+            .collect();
+        for (i, a) in tests.iter().enumerate() {
+            for b in &tests[i + 1..] {
+                largest = largest.max(a.t(b).abs());
+            }
+        }
+    }
+    largest
+}
+
+// a sealed 1000 byte message, and the same with the first and with the last
+// byte of its tag flipped
+fn classes(valid: Vec<u8>) -> Vec<Vec<u8>> {
+    let mut first = valid.clone();
+    first[valid.len() - 24] ^= 1;
+    let mut last = valid.clone();
+    last[valid.len() - 1] ^= 1;
+    vec![valid, first, last]
+}
+
+#[test]
+#[ignore = "statistical, run optimised"]
+fn test_timing_of_the_tag_check() {
+    // good or bad, the check and the verdict cost the same
+    let key = [7u8; 32];
+    let mut valid = Vec::new();
+    XChaCha20Blake3.seal(&key, &[&[0x5a; 1000]], b"aad", &mut valid);
+    let mut out = [0u8; 1000];
+    let t = largest_t(&classes(valid), |sealed| XChaCha20Blake3.open_into(&key, &sealed, b"aad", &mut out));
+    assert!(t < THRESHOLD, "the cipher's time tells good tags from bad, t = {:.2}", t);
+}
+
+#[test]
+#[ignore = "statistical, run optimised"]
+fn test_timing_of_rejected_records() {
+    // only rejected records are compared. one that's accepted is counted,
+    // folded into the transcript and handed back, which a rejected one
+    // isn't, so a session's time says which it was, as what it does next
+    // would anyway; the README says this isn't covered. where in the tag a
+    // bad one is wrong mustn't show
+    let (mut sesh1, mut sesh2) = Session::pair(&mut thread_rng());
+    let records = classes(sesh1.encrypt(vec![0x5a; 1000]).unwrap()).split_off(1);
+    let t = largest_t(&records, |record| sesh2.decrypt(record));
+    assert!(t < THRESHOLD, "decrypt's time gives away where a tag is wrong, t = {:.2}", t);
+}